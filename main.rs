@@ -1,6 +1,9 @@
 use actix_files::Files;
-use actix_session::{CookieSession, Session};
-use actix_web::{http, middleware, web, App, HttpRequest, HttpResponse, HttpServer, Result};
+use actix_session::{Session, UserSession};
+use actix_web::{
+    http, middleware, web, App, FromRequest, HttpRequest, HttpResponse, HttpServer, ResponseError,
+    Result,
+};
 use askama::Template;
 use std::collections::HashMap;
 
@@ -68,18 +71,313 @@ struct VoucherUpdateForm {
     balance: f64,
 }
 
+#[derive(Deserialize)]
+struct ScanSyncUploadJson {
+    redemptions: std::vec::Vec<OfflineRedemptionJson>,
+}
+#[derive(Deserialize)]
+pub struct OfflineRedemptionJson {
+    client_id: String,
+    hash: String,
+    amount: f64,
+    used: bool,
+    redeemed_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize)]
+struct BalanceCheckForm {
+    number_code: String,
+}
+
 #[derive(Deserialize)]
 struct AdminLoginForm {
     admin_username: String,
     admin_password: String,
 }
 
+#[derive(Deserialize)]
+struct VoucherExpirationForm {
+    expiration_date: String,
+}
+
+#[derive(Deserialize)]
+struct VoucherBalanceForm {
+    balance: f64,
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct VoucherBlockForm {
+    blocked: bool,
+    reason: String,
+}
+
+#[derive(Deserialize, Validate)]
+struct VoucherReceiverForm {
+    #[validate(length(min = 1))]
+    receiver_name: String,
+    #[validate(email)]
+    receiver_email: String,
+}
+
+#[derive(Deserialize)]
+struct RemindersUpdateForm {
+    send_reminders: bool,
+    reminder_days: String,
+}
+
+#[derive(Deserialize)]
+struct PasswordChangeForm {
+    current_password: String,
+    new_password: String,
+    new_password_repeat: String,
+}
+
+#[derive(Deserialize)]
+struct BankDetailsForm {
+    bankaccountnr: String,
+    btw_nr: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct ReauthenticateForm {
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct TwoFactorForm {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct TwoFactorDisableForm {
+    password: String,
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct TwoFactorRequireForm {
+    require_2fa: bool,
+}
+
+#[derive(Deserialize, Validate)]
+struct UserInviteForm {
+    #[validate(length(min = 3, max = 64))]
+    username: String,
+    #[validate(length(min = 1))]
+    display_name: String,
+    #[validate(email)]
+    email: String,
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct PlatformLoginForm {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize, Validate)]
+struct PlatformDistributorForm {
+    #[validate(length(min = 1, max = 255))]
+    name: String,
+    #[validate(email)]
+    email: String,
+    tel: String,
+    address: String,
+    #[serde(default = "postcodes::default_country")]
+    country: String,
+    postalcode: String,
+    city: String,
+    subdomain: String,
+    #[serde(default)]
+    description: String,
+    bankaccountnr: String,
+    btw_nr: String,
+    // Only used when creating a distributor, the first owner gets an invitation
+    #[serde(default)]
+    owner_username: String,
+    #[serde(default)]
+    owner_name: String,
+    #[serde(default)]
+    owner_email: String,
+}
+
+#[derive(Deserialize, Validate)]
+struct RegistrationForm {
+    #[validate(length(min = 1, max = 255))]
+    name: String,
+    #[validate(email)]
+    email: String,
+    #[validate(length(min = 1))]
+    tel: String,
+    #[validate(length(min = 1))]
+    address: String,
+    #[serde(default = "postcodes::default_country")]
+    country: String,
+    postalcode: String,
+    city: String,
+    btw_nr: String,
+    bankaccountnr: String,
+    subdomain: String,
+    #[validate(length(min = 1))]
+    owner_name: String,
+    #[validate(length(min = 3, max = 64))]
+    username: String,
+    password: String,
+    password_repeat: String,
+    // Checkbox, only sent when checked
+    accept_terms: Option<String>,
+}
+
+#[derive(Deserialize, Validate)]
+struct ResendVerificationForm {
+    #[validate(email)]
+    email: String,
+}
+
+#[derive(Deserialize)]
+struct DomainForm {
+    domain: String,
+}
+
+#[derive(Deserialize)]
+struct ShopLocationForm {
+    name: String,
+    address: String,
+    #[serde(default = "postcodes::default_country")]
+    country: String,
+    postalcode: String,
+    city: String,
+    maandag: String,
+    dinsdag: String,
+    woensdag: String,
+    donderdag: String,
+    vrijdag: String,
+    zaterdag: String,
+    zondag: String,
+}
+
+impl ShopLocationForm {
+    fn opening_hours(&self) -> [&str; 7] {
+        [
+            &self.maandag,
+            &self.dinsdag,
+            &self.woensdag,
+            &self.donderdag,
+            &self.vrijdag,
+            &self.zaterdag,
+            &self.zondag,
+        ]
+    }
+}
+
+#[derive(Deserialize)]
+struct ScannerDeviceForm {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct PostcodeSearchParams {
+    #[serde(default = "postcodes::default_country")]
+    land: String,
+    q: String,
+}
+
+#[derive(Deserialize, Default)]
+pub struct LocationReportParams {
+    min_date: Option<String>,
+    max_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FaqEntryForm {
+    question: String,
+    answer: String,
+    position: i32,
+}
+
+#[derive(Deserialize)]
+struct ShopPageForm {
+    title: String,
+    // Ignored for the terms and conditions
+    slug: String,
+    body: String,
+    #[serde(default)]
+    published: bool,
+}
+
+#[derive(Deserialize)]
+struct MarkdownPreviewForm {
+    markdown: String,
+}
+
+#[derive(Deserialize)]
+struct BrandingForm {
+    primary_color: String,
+    accent_color: String,
+    font: String,
+}
+
+#[derive(Deserialize)]
+struct SubdomainForm {
+    subdomain: String,
+}
+
+#[derive(Deserialize)]
+struct SubdomainCheckParams {
+    subdomain: String,
+}
+
+#[derive(Deserialize)]
+struct PlatformStatusForm {
+    status: String,
+    reason: String,
+    // Checkbox when suspending, only sent when checked
+    redeem_vouchers: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PlatformReasonForm {
+    reason: String,
+}
+
+#[derive(Deserialize, Default)]
+pub struct PlatformSalesFilterParams {
+    min_date: Option<String>,
+    max_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UserRoleForm {
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct UserActiveForm {
+    active: bool,
+}
+
+#[derive(Deserialize)]
+struct PasswordForgotForm {
+    admin_username: String,
+}
+
+#[derive(Deserialize)]
+struct PasswordResetForm {
+    new_password: String,
+    new_password_repeat: String,
+}
+
 #[derive(Deserialize)]
 struct MijnZaakUpdateForm {
     description: String,
     email: String,
     tel: String,
     address: String,
+    #[serde(default = "postcodes::default_country")]
+    country: String,
     postalcode: String,
     city: String,
 }
@@ -109,11 +407,15 @@ struct LabelVoucherJson {
 
 // Business templates
 
+// Storefront templates include "thema.html" in their <head>, it reads `branding`.
+// "thema-header.html" shows the logo and cover and reads `distributor_name` as well.
+
 #[derive(Template)]
 #[template(path = "index.html")]
 struct Index<'a> {
     distributor: &'a Distributor,
     distributor_vouchers: &'a std::vec::Vec<DistributorVoucher>,
+    branding: &'a Branding,
 }
 
 #[derive(Template)]
@@ -121,11 +423,14 @@ struct Index<'a> {
 struct Bestel<'a> {
     distributor_vouchers: &'a std::vec::Vec<DistributorVoucher>,
     first_distributor_voucher: &'a DistributorVoucher,
+    branding: &'a Branding,
+    csrf_token: String,
 }
 
 #[derive(Template)]
 #[template(path = "bevestig.html")]
 struct Bevestig {
+    branding: Branding,
     payment_url: String,
     voucher_price: String,
     total: String,
@@ -133,13 +438,31 @@ struct Bevestig {
     to_str: String,
 }
 
+// Shown when a distributor has no FAQ entries of its own
 #[derive(Template)]
 #[template(path = "faq.html")]
 struct Faq;
 
+#[derive(Template)]
+#[template(path = "faq-zaak.html")]
+struct FaqDistributor {
+    branding: Branding,
+    distributor_name: String,
+    entries: std::vec::Vec<FaqEntry>,
+}
+
+#[derive(Template)]
+#[template(path = "pagina.html")]
+struct Pagina {
+    branding: Branding,
+    distributor_name: String,
+    page: ShopPage,
+}
+
 #[derive(Template)]
 #[template(path = "bon_mobile.html")]
 struct VoucherPageMobile {
+    branding: Branding,
     number_code: String,
     distributor_name: String,
     balance: String,
@@ -150,6 +473,7 @@ struct VoucherPageMobile {
 #[derive(Template)]
 #[template(path = "bon_desktop.html")]
 struct VoucherPageDesktop {
+    branding: Branding,
     number_code: String,
     distributor_name: String,
     balance: String,
@@ -157,6 +481,36 @@ struct VoucherPageDesktop {
     receiver_name: String,
 }
 
+#[derive(Template)]
+#[template(path = "gesloten.html")]
+struct Gesloten {
+    branding: Branding,
+    distributor_name: String,
+    // Vouchers can still be used in the shop, the balance check stays available
+    redeemable: bool,
+}
+
+#[derive(Template)]
+#[template(path = "saldo.html")]
+struct BalanceCheck {
+    branding: Branding,
+    distributor_name: String,
+    number_code: String,
+    error: String,
+    found: bool,
+    balance: String,
+    expiration_date: String,
+    status: String,
+    redemptions: std::vec::Vec<BalanceCheckRedemption>,
+    csrf_token: String,
+}
+
+struct BalanceCheckRedemption {
+    date: String,
+    amount: String,
+    balance_after: String,
+}
+
 #[derive(Template)]
 #[template(path = "success.html")]
 struct Success {
@@ -170,7 +524,9 @@ struct Failed;
 
 #[derive(Template)]
 #[template(path = "scanner/scanner.html")]
-struct Scanner;
+struct Scanner {
+    csrf_token: String,
+}
 
 #[derive(Template)]
 #[template(path = "scanner/login.html")]
@@ -180,80 +536,415 @@ struct ScannerLogin;
 #[template(path = "404.html")]
 struct Error404;
 
-// Administrator templates
+// Mail templates
 
 #[derive(Template)]
-#[template(path = "admin/login.html")]
-struct AdminLogin {
+#[template(path = "mail/bon.txt")]
+struct VoucherMail {
     distributor_name: String,
-    login_status: String,
+    receiver_name: String,
+    balance: String,
+    expiration_date: String,
+    number_code: String,
+    url: String,
 }
 
 #[derive(Template)]
-#[template(path = "admin/mijn-zaak.html")]
-struct AdminDashboardMijnZaak {
-    distributor: Distributor,
+#[template(path = "mail/bon.html")]
+struct VoucherMailHtml<'a> {
+    mail: &'a VoucherMail,
+    theme: MailTheme,
 }
 
 #[derive(Template)]
-#[template(path = "admin/cadeaubonnen.html")]
-struct AdminDashboardCadeaubonnen<'a> {
-    // Three price vouchers
-    three_price_voucher_a: f64,
-    three_price_voucher_b: f64,
-    three_price_voucher_c: f64,
-    three_price_voucher_max_days: u16,
-    three_price_voucher_one_use_only: bool,
-
-    // Price range vouchers
-    price_range_voucher_min: f64,
-    price_range_voucher_max: f64,
-    price_range_voucher_auto: f64,
-    price_range_voucher_max_days: u16,
-    price_range_voucher_one_use_only: bool,
-
-    // Label vouchers
-    label_vouchers: &'a Vec<LabelVoucherData>,
-    label_voucher_max_days: u16,
+#[template(path = "mail/herinnering.txt")]
+struct ExpiryReminderMail {
+    distributor_name: String,
+    receiver_name: String,
+    balance: String,
+    expiration_date: String,
+    days_left: i64,
+    url: String,
+}
 
-    // Currently active voucher
-    currently_active: Option<VoucherType>,
+#[derive(Template)]
+#[template(path = "mail/herinnering.html")]
+struct ExpiryReminderMailHtml<'a> {
+    mail: &'a ExpiryReminderMail,
+    theme: MailTheme,
 }
 
-struct LabelVoucherData {
-    title: String,
-    amount: u64,
-    description: String,
-    days_valid: u16,
+// Branding of the distributor for the HTML version of mails, images need full urls there
+struct MailTheme {
+    logo_url: String,
+    primary_color: String,
+    accent_color: String,
+    font_family: String,
 }
-impl Default for LabelVoucherData {
-    fn default() -> Self {
-        LabelVoucherData {
-            title: "".to_string(),
-            amount: 10 as u64,
-            description: "".to_string(),
-            days_valid: 90,
+
+impl MailTheme {
+    fn of(distributor: &Distributor) -> MailTheme {
+        MailTheme {
+            logo_url: distributor
+                .branding
+                .logo_path()
+                .map(|p| format!("{}{}", distributor_url(distributor), p))
+                .unwrap_or_default(),
+            primary_color: distributor.branding.primary_color.to_string(),
+            accent_color: distributor.branding.accent_color.to_string(),
+            font_family: distributor.branding.font.css_family(),
         }
     }
 }
 
 #[derive(Template)]
-#[template(path = "admin/bestellingen.html")]
-struct AdminDashboardBestellingen;
+#[template(path = "mail/account-geblokkeerd.txt")]
+struct AccountLockedMail {
+    display_name: String,
+    distributor_name: String,
+    locked_minutes: i64,
+    ip: String,
+    url: String,
+}
 
-#[derive(Deserialize, Serialize)]
-struct AdminOrderTableData {
-    id: u64,
-    purchase_amount: f64,
-    payment_status: i16,
-    purchase_date: String,
-    client_name: String,
+#[derive(Template)]
+#[template(path = "mail/uitnodiging.txt")]
+struct InvitationMail {
+    display_name: String,
+    inviter_name: String,
+    distributor_name: String,
+    url: String,
+    valid_days: i64,
 }
 
-#[derive(Deserialize, Serialize, Template)]
-#[template(path = "admin/bestelling.html")]
-struct AdminOrderData {
-    voucher: Voucher,
+#[derive(Template)]
+#[template(path = "mail/wachtwoord-herstellen.txt")]
+struct PasswordResetMail {
+    display_name: String,
+    distributor_name: String,
+    url: String,
+    valid_minutes: i64,
+}
+
+// Administrator templates
+
+#[derive(Template)]
+#[template(path = "admin/login.html")]
+struct AdminLogin {
+    distributor_name: String,
+    login_status: String,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/login-2fa.html")]
+struct AdminLogin2fa {
+    distributor_name: String,
+    login_status: String,
+    // Set when the user still has to set up 2FA because the distributor requires it
+    enroll: bool,
+    secret: String,
+    qr_code: String,
+    recovery_codes: std::vec::Vec<String>,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/tweestapsverificatie.html")]
+struct AdminDashboardTweestapsverificatie {
+    totp_enabled: bool,
+    require_2fa: bool,
+    secret: String,
+    qr_code: String,
+    recovery_codes_left: i64,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/sessies.html")]
+struct AdminDashboardSessies {
+    sessions: std::vec::Vec<AdminSessionRow>,
+    csrf_token: String,
+}
+
+struct AdminSessionRow {
+    id: u64,
+    ip: String,
+    user_agent: String,
+    create_date: String,
+    last_seen: String,
+    current: bool,
+}
+
+#[derive(Template)]
+#[template(path = "admin/gebruikers.html")]
+struct AdminDashboardGebruikers {
+    users: std::vec::Vec<AdminUserRow>,
+    csrf_token: String,
+}
+
+struct AdminUserRow {
+    id: u64,
+    username: String,
+    display_name: String,
+    email: String,
+    role: String,
+    active: bool,
+    last_login: String,
+    is_self: bool,
+}
+
+#[derive(Template)]
+#[template(path = "registreren.html")]
+struct Registreren {
+    domain: String,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "mail/registratie-bevestigen.txt")]
+struct EmailVerificationMail {
+    display_name: String,
+    distributor_name: String,
+    url: String,
+    valid_hours: i64,
+}
+
+#[derive(Template)]
+#[template(path = "admin/vestigingen.html")]
+struct AdminDashboardVestigingen {
+    locations: std::vec::Vec<ShopLocationRow>,
+    report: std::vec::Vec<LocationReportRow>,
+    total_redemptions: i64,
+    total_amount: String,
+    min_date: String,
+    max_date: String,
+    // Owners manage locations, others with access to orders only see the report
+    can_manage: bool,
+    empty_hours: std::vec::Vec<OpeningHoursRow>,
+    csrf_token: String,
+}
+
+struct ShopLocationRow {
+    location: ShopLocation,
+    hours: std::vec::Vec<OpeningHoursRow>,
+    devices: std::vec::Vec<ScannerDevice>,
+}
+
+struct OpeningHoursRow {
+    label: &'static str,
+    field: &'static str,
+    value: String,
+}
+
+struct LocationReportRow {
+    name: String,
+    redemptions: i64,
+    amount: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/inhoud.html")]
+struct AdminDashboardInhoud {
+    faq_entries: std::vec::Vec<FaqEntry>,
+    pages: std::vec::Vec<ShopPage>,
+    storefront_url: String,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/huisstijl.html")]
+struct AdminDashboardHuisstijl {
+    branding: Branding,
+    fonts: &'static [Font],
+    storefront_url: String,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/domeinen.html")]
+struct AdminDashboardDomeinen {
+    domains: std::vec::Vec<DomainRow>,
+    subdomain: String,
+    subdomain_host: String,
+    platform_domain: &'static str,
+    csrf_token: String,
+}
+
+struct DomainRow {
+    id: u64,
+    domain: String,
+    record_name: String,
+    record_value: String,
+    verified: bool,
+    primary: bool,
+}
+
+#[derive(Template)]
+#[template(path = "admin/aan-de-slag.html")]
+struct AdminDashboardAanDeSlag {
+    distributor: Distributor,
+    storefront_url: String,
+    has_vouchers: bool,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "platform/login.html")]
+struct PlatformLogin {
+    login_status: String,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "platform/dashboard.html")]
+struct PlatformDashboard {
+    admin_name: String,
+    distributors: std::vec::Vec<PlatformDistributorRow>,
+    min_date: String,
+    max_date: String,
+    total_sales: i64,
+    total_amount: String,
+    total_fees: String,
+    audit: std::vec::Vec<PlatformAuditRow>,
+    csrf_token: String,
+}
+
+struct PlatformDistributorRow {
+    id: u64,
+    name: String,
+    subdomain: String,
+    url: String,
+    status: String,
+    sales: i64,
+    amount: String,
+    fees: String,
+}
+
+struct PlatformAuditRow {
+    date: String,
+    admin: String,
+    action: String,
+    distributor: String,
+    after: String,
+    reason: String,
+}
+
+#[derive(Template)]
+#[template(path = "platform/zaak.html")]
+struct PlatformZaak {
+    distributor: Distributor,
+    masked_bankaccountnr: String,
+    status: String,
+    users: std::vec::Vec<AdminUserRow>,
+    audit: std::vec::Vec<PlatformAuditRow>,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "platform/zaak-nieuw.html")]
+struct PlatformZaakNieuw {
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/uitnodiging.html")]
+struct AdminUitnodiging {
+    distributor_name: String,
+    display_name: String,
+    token: String,
+    status: String,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/wachtwoord-vergeten.html")]
+struct AdminWachtwoordVergeten {
+    distributor_name: String,
+    status: String,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/wachtwoord-herstellen.html")]
+struct AdminWachtwoordHerstellen {
+    distributor_name: String,
+    token: String,
+    status: String,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/mijn-zaak.html")]
+struct AdminDashboardMijnZaak {
+    distributor: Distributor,
+    // The full IBAN is only sent after entering the password again
+    masked_bankaccountnr: String,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/cadeaubonnen.html")]
+struct AdminDashboardCadeaubonnen<'a> {
+    // Three price vouchers
+    three_price_voucher_a: f64,
+    three_price_voucher_b: f64,
+    three_price_voucher_c: f64,
+    three_price_voucher_max_days: u16,
+    three_price_voucher_one_use_only: bool,
+
+    // Price range vouchers
+    price_range_voucher_min: f64,
+    price_range_voucher_max: f64,
+    price_range_voucher_auto: f64,
+    price_range_voucher_max_days: u16,
+    price_range_voucher_one_use_only: bool,
+
+    // Label vouchers
+    label_vouchers: &'a Vec<LabelVoucherData>,
+    label_voucher_max_days: u16,
+
+    // Currently active voucher
+    currently_active: Option<VoucherType>,
+    csrf_token: String,
+}
+
+struct LabelVoucherData {
+    title: String,
+    amount: u64,
+    description: String,
+    days_valid: u16,
+}
+impl Default for LabelVoucherData {
+    fn default() -> Self {
+        LabelVoucherData {
+            title: "".to_string(),
+            amount: 10 as u64,
+            description: "".to_string(),
+            days_valid: 90,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/bestellingen.html")]
+struct AdminDashboardBestellingen;
+
+#[derive(Deserialize, Serialize)]
+struct AdminOrderTableData {
+    id: u64,
+    purchase_amount: f64,
+    payment_status: i16,
+    purchase_date: String,
+    client_name: String,
+}
+
+#[derive(Deserialize, Serialize, Template)]
+#[template(path = "admin/bestelling.html")]
+struct AdminOrderData {
+    voucher: Voucher,
+    history: std::vec::Vec<AuditEntry>,
+    csrf_token: String,
 }
 
 #[derive(Deserialize, Debug)]
@@ -267,9 +958,53 @@ pub struct AdminOrderFilterParams {
     statusses: Option<String>,
 }
 
+#[derive(Deserialize, Default)]
+pub struct AuditLogFilterParams {
+    page: Option<u64>,
+    action: Option<String>,
+    entity: Option<String>,
+    distributoruser: Option<String>,
+    min_date: Option<String>,
+    max_date: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/logboek.html")]
+struct AdminDashboardLogboek {
+    entries: std::vec::Vec<AuditLogRow>,
+    users: std::vec::Vec<AuditLogUser>,
+    action: String,
+    entity: String,
+    distributoruser: String,
+    min_date: String,
+    max_date: String,
+    page: u64,
+    has_next_page: bool,
+    query: String,
+}
+
+struct AuditLogRow {
+    date: String,
+    user: String,
+    action: String,
+    entity: String,
+    entity_id: u64,
+    before: String,
+    after: String,
+    reason: String,
+    ip: String,
+}
+
+struct AuditLogUser {
+    id: String,
+    display_name: String,
+}
+
 #[derive(Template)]
 #[template(path = "admin/wachtwoord.html")]
-struct AdminDashboardWachtwoord;
+struct AdminDashboardWachtwoord {
+    csrf_token: String,
+}
 
 #[derive(Template)]
 #[template(path = "admin/help.html")]
@@ -294,10 +1029,208 @@ pub struct Distributor {
     address: String,
     location: Location,
     subdomain: String,
+    // Markdown, description_html is its sanitized rendering made on save
     description: String,
-    //#[serde(skip_serializing)]
+    #[serde(default)]
+    description_html: String,
+    // IBAN encrypted with bankdetails::encrypt, use iban() or masked_iban()
     bankaccountnr: String,
     btw_nr: String,
+    // Comma separated amount of days before expiration to remind receivers, e.g. "30,7"
+    reminder_days: String,
+    send_reminders: bool,
+    // All admins of this distributor have to use two-factor authentication
+    require_2fa: bool,
+    status: DistributorStatus,
+    // Whether cashiers can still redeem vouchers while the distributor is suspended
+    #[serde(default)]
+    redeem_when_suspended: bool,
+    // Verified own domain the storefront redirects to, None to use the subdomain
+    primary_domain: Option<String>,
+    #[serde(default)]
+    branding: Branding,
+}
+
+// Look of the storefront, voucher pages and mails of a distributor
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Branding {
+    // File names in branding::UPLOAD_DIR
+    logo: Option<String>,
+    cover: Option<String>,
+    // Hex colors, e.g. "#1f3a5f"
+    primary_color: String,
+    accent_color: String,
+    font: Font,
+}
+
+impl Default for Branding {
+    fn default() -> Branding {
+        Branding {
+            logo: None,
+            cover: None,
+            primary_color: "#1f3a5f".to_string(),
+            accent_color: "#f2a541".to_string(),
+            font: Font::System,
+        }
+    }
+}
+
+impl Branding {
+    pub fn logo_path(&self) -> Option<String> {
+        self.logo.as_ref().map(|f| format!("/uploads/{}", f))
+    }
+
+    pub fn cover_path(&self) -> Option<String> {
+        self.cover.as_ref().map(|f| format!("/uploads/{}", f))
+    }
+}
+
+// Fonts a distributor can choose from, all but System are loaded from Google Fonts
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum Font {
+    System,
+    Lato,
+    Merriweather,
+    Montserrat,
+    OpenSans,
+    PlayfairDisplay,
+    Poppins,
+    Roboto,
+}
+
+impl Font {
+    pub fn all() -> &'static [Font] {
+        &[
+            Font::System,
+            Font::Lato,
+            Font::Merriweather,
+            Font::Montserrat,
+            Font::OpenSans,
+            Font::PlayfairDisplay,
+            Font::Poppins,
+            Font::Roboto,
+        ]
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Font::System => "Standaard",
+            Font::Lato => "Lato",
+            Font::Merriweather => "Merriweather",
+            Font::Montserrat => "Montserrat",
+            Font::OpenSans => "Open Sans",
+            Font::PlayfairDisplay => "Playfair Display",
+            Font::Poppins => "Poppins",
+            Font::Roboto => "Roboto",
+        }
+    }
+
+    pub fn css_family(&self) -> String {
+        match self {
+            Font::System => {
+                "system-ui, -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif".to_string()
+            }
+            Font::Merriweather | Font::PlayfairDisplay => {
+                format!("'{}', Georgia, serif", self.label())
+            }
+            _ => format!("'{}', Helvetica, Arial, sans-serif", self.label()),
+        }
+    }
+
+    // Empty for the system font
+    pub fn stylesheet_url(&self) -> String {
+        match self {
+            Font::System => "".to_string(),
+            _ => format!(
+                "https://fonts.googleapis.com/css2?family={}:wght@400;700&display=swap",
+                self.label().replace(" ", "+")
+            ),
+        }
+    }
+}
+
+impl FromStr for Font {
+    type Err = ();
+
+    fn from_str(input: &str) -> std::result::Result<Font, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "system" => Ok(Font::System),
+            "lato" => Ok(Font::Lato),
+            "merriweather" => Ok(Font::Merriweather),
+            "montserrat" => Ok(Font::Montserrat),
+            "opensans" => Ok(Font::OpenSans),
+            "playfairdisplay" => Ok(Font::PlayfairDisplay),
+            "poppins" => Ok(Font::Poppins),
+            "roboto" => Ok(Font::Roboto),
+            _ => Err(()),
+        }
+    }
+}
+impl std::fmt::Display for Font {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FaqEntry {
+    id: u64,
+    distributor_id: u64,
+    question: String,
+    // Markdown, answer_html is its sanitized rendering made on save
+    answer: String,
+    answer_html: String,
+    position: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ShopPage {
+    id: u64,
+    distributor_id: u64,
+    kind: PageKind,
+    // Address of custom pages, /pagina/{slug}
+    slug: String,
+    title: String,
+    // Markdown, body_html is its sanitized rendering made on save
+    body: String,
+    body_html: String,
+    published: bool,
+    update_date: Option<DateTime<Utc>>,
+}
+
+// Terms and conditions are a page like the others, shown on /voorwaarden
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum PageKind {
+    Terms,
+    Custom,
+}
+
+impl FromStr for PageKind {
+    type Err = ();
+
+    fn from_str(input: &str) -> std::result::Result<PageKind, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "terms" => Ok(PageKind::Terms),
+            "custom" => Ok(PageKind::Custom),
+            _ => Err(()),
+        }
+    }
+}
+impl std::fmt::Display for PageKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DistributorDomain {
+    id: u64,
+    distributor_id: u64,
+    domain: String,
+    // Has to be published in a TXT record to prove the distributor owns the domain
+    verification_token: String,
+    verified: bool,
+    primary: bool,
 }
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Client {
@@ -331,18 +1264,130 @@ pub struct Voucher {
     hash_code: String,
     number_code: String,
     version: i64,
+    status: VoucherStatus,
 }
 
-#[derive(Deserialize, Serialize, PartialEq, Debug)]
-enum VoucherType {
-    ThreeOptionVoucher,
-    RangeVoucher,
-    LabelVoucher,
-}
+#[derive(Deserialize, Serialize)]
+pub struct Redemption {
+    id: u64,
+    voucher_id: u64,
+    // ID generated by the scanner for offline redemptions, None for online scans
+    client_id: Option<String>,
+    amount: f64,
+    balance_after: f64,
+    redeemed_at: chrono::DateTime<chrono::Utc>,
+    // None for redemptions from before shop locations, or from an unpaired scanner
+    // when the distributor has several locations
+    shoplocation_id: Option<u64>,
+    scannerdevice_id: Option<u64>,
+}
+
+// Where a scan happened, see redemption_origin
+#[derive(Clone, Copy, Default)]
+pub struct RedemptionOrigin {
+    shoplocation_id: Option<u64>,
+    scannerdevice_id: Option<u64>,
+}
+
+// A shop of a distributor, chains have several. The address of the distributor itself is
+// its business address.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ShopLocation {
+    id: u64,
+    distributor_id: u64,
+    name: String,
+    address: String,
+    location: Location,
+    // One entry per weekday from Monday, e.g. "09:00-12:00, 13:00-18:00", empty when closed
+    opening_hours: std::vec::Vec<String>,
+    active: bool,
+}
+
+// A browser paired with a shop location, it's recognized by a cookie with the token
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScannerDevice {
+    id: u64,
+    distributor_id: u64,
+    shoplocation_id: u64,
+    name: String,
+    paired: bool,
+    last_seen: Option<DateTime<Utc>>,
+    active: bool,
+}
+
+// Redemptions of one shop location in a period, shoplocation_id None for unknown
+pub struct LocationRedemptions {
+    shoplocation_id: Option<u64>,
+    name: Option<String>,
+    redemptions: i64,
+    amount: f64,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum Role {
+    Owner,
+    Manager,
+    Cashier,
+    Accountant,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Permission {
+    // Business details, bank account and security settings of the distributor
+    ManageBusiness,
+    ManageUsers,
+    ManageVouchers,
+    ViewOrders,
+    // Changing vouchers of orders: balance, expiration, blocking, receiver
+    ManageOrders,
+    Export,
+    ViewAuditLog,
+    Scan,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum TokenPurpose {
+    PasswordReset,
+    Invitation,
+    // Short-lived handover of a platform admin to the dashboard of a distributor
+    Impersonation,
+    EmailVerification,
+}
+
+// Self-registered distributors go from Unverified over Onboarding to Active, the storefront
+// is only open when Active. Deleted distributors are kept for the audit log and bookkeeping.
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum DistributorStatus {
+    Unverified,
+    Onboarding,
+    Active,
+    Suspended,
+    Deleted,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+enum VoucherType {
+    ThreeOptionVoucher,
+    RangeVoucher,
+    LabelVoucher,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum VoucherStatus {
+    PendingPayment,
+    Active,
+    PartiallyUsed,
+    Used,
+    Expired,
+    Refunded,
+    Blocked,
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Location {
     id: u64,
+    // ISO code, see postcodes::COUNTRIES
+    country: String,
     postalcode: String,
     city: String,
 }
@@ -369,21 +1414,226 @@ pub struct DistributorUser {
     id: u64,
     //#[serde(skip_serializing)]
     username: String,
-    //#[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     password: String,
     distributor: Distributor,
     display_name: String,
+    email: String,
+    // The TOTP secret itself is never loaded into this struct, it ends up in the session
+    totp_enabled: bool,
+    role: Role,
+    // Deactivated users can't log in, they're kept for the audit log
+    active: bool,
+    last_login: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// Extractor for admin handlers, fails when nobody is logged in
+pub struct AdminUser(DistributorUser);
+
+// Staff of Kaddo itself, they manage the distributors from the main domain
+#[derive(Deserialize, Serialize, Clone)]
+pub struct PlatformAdmin {
+    id: u64,
+    username: String,
+    #[serde(skip_serializing, default)]
+    password: String,
+    display_name: String,
+    email: String,
+    active: bool,
+    last_login: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// Extractor for platform handlers, only on the main domain
+pub struct PlatformUser(PlatformAdmin);
+
+#[derive(Debug)]
+pub enum AdminError {
+    NotLoggedIn { redirect: bool },
+    NotLoggedInPlatform,
+    Forbidden,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct PlatformAuditEntry {
+    id: u64,
+    platformadmin_id: u64,
+    distributor_id: Option<u64>,
+    action: String,
+    entity: String,
+    entity_id: u64,
+    before: String,
+    after: String,
+    reason: String,
+    ip: String,
+    create_date: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// Paid sales of one distributor in a period
+pub struct DistributorSales {
+    distributor_id: u64,
+    sales: i64,
+    amount: f64,
+}
+
+pub struct AdminSession {
+    id: u64,
+    distributoruser_id: Option<u64>,
+    state: HashMap<String, String>,
+    ip: String,
+    user_agent: String,
+    create_date: chrono::DateTime<chrono::Utc>,
+    last_seen: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AuditEntry {
+    id: u64,
+    distributor_id: u64,
+    distributoruser_id: Option<u64>,
+    // Set when a platform admin acts as the user through an impersonation link
+    platformadmin_id: Option<u64>,
+    action: String,
+    entity: String,
+    entity_id: u64,
+    before: String,
+    after: String,
+    reason: String,
+    ip: String,
+    create_date: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Distributor {
+    pub fn tenant(&self) -> data::Tenant {
+        data::Tenant::of(self.id)
+    }
+
+    pub fn iban(&self) -> Option<String> {
+        bankdetails::decrypt(&self.bankaccountnr)
+    }
+
+    // For overviews and logs, e.g. "BE** **** **** 7034"
+    pub fn masked_iban(&self) -> String {
+        self.iban()
+            .map(|iban| bankdetails::mask_iban(&iban))
+            .unwrap_or_default()
+    }
+
+    // Whether customers can order in the storefront
+    pub fn is_live(&self) -> bool {
+        self.status == DistributorStatus::Active
+    }
+
+    // Whether vouchers of this distributor can be scanned
+    pub fn accepts_redemptions(&self) -> bool {
+        match self.status {
+            DistributorStatus::Active | DistributorStatus::Onboarding => true,
+            DistributorStatus::Suspended => self.redeem_when_suspended,
+            _ => false,
+        }
+    }
+
+    // Reminder offsets in days, largest first, invalid entries are skipped
+    pub fn reminder_offsets(&self) -> std::vec::Vec<i64> {
+        parse_reminder_days(&self.reminder_days).unwrap_or_default()
+    }
+}
+
+// The session only says who logged in. The user is loaded again on every request, so a
+// deactivated user or a changed role takes effect right away, and a session only counts on
+// the host of its own distributor.
+impl FromRequest for AdminUser {
+    type Error = AdminError;
+    type Future =
+        futures::future::LocalBoxFuture<'static, std::result::Result<AdminUser, AdminError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let session_user = req
+            .get_session()
+            .get::<DistributorUser>("distributoruser")
+            .unwrap_or(None);
+        let distributor = tenancy::current(req);
+        let mysql = req.app_data::<web::Data<MySQL>>().cloned();
+        // The scanner calls its endpoints with fetch, a redirect makes no sense there
+        let not_logged_in = AdminError::NotLoggedIn {
+            redirect: !req.path().starts_with("/scan/"),
+        };
+
+        Box::pin(async move {
+            let (session_user, distributor, mysql) = match (session_user, distributor, mysql) {
+                (Some(user), Some(distributor), Some(mysql))
+                    if user.distributor.id == distributor.id =>
+                {
+                    (user, distributor, mysql)
+                }
+                _ => return Err(not_logged_in),
+            };
+
+            match DistributorUser::get_by_id(session_user.id, &mysql).await {
+                Some(user) if user.active && user.distributor.id == distributor.id => {
+                    Ok(AdminUser(user))
+                }
+                _ => Err(not_logged_in),
+            }
+        })
+    }
+}
+
+impl FromRequest for PlatformUser {
+    type Error = AdminError;
+    type Future = futures::future::Ready<std::result::Result<PlatformUser, AdminError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        if !is_platform_host(req) {
+            return futures::future::err(AdminError::Forbidden);
+        }
+
+        match req
+            .get_session()
+            .get::<PlatformAdmin>("platformadmin")
+            .unwrap_or(None)
+        {
+            Some(admin) => futures::future::ok(PlatformUser(admin)),
+            None => futures::future::err(AdminError::NotLoggedInPlatform),
+        }
+    }
+}
+
+impl std::fmt::Display for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl ResponseError for AdminError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AdminError::NotLoggedIn { redirect: true } => HttpResponse::Found()
+                .header(http::header::LOCATION, "/admin/login")
+                .finish(),
+            AdminError::NotLoggedIn { redirect: false } => HttpResponse::Unauthorized().finish(),
+            AdminError::NotLoggedInPlatform => HttpResponse::Found()
+                .header(http::header::LOCATION, "/platform/login")
+                .finish(),
+            AdminError::Forbidden => HttpResponse::Forbidden()
+                .content_type("text/plain")
+                .body("forbidden"),
+        }
+    }
 }
 
 impl DistributorUser {
     pub async fn create(user: &mut DistributorUser, mysql: &web::Data<MySQL>) -> u64 {
         user.hash_password();
 
-        let result = sqlx::query("INSERT INTO distributoruser (username, password, distributor, display_name) VALUES (?,?,?,?)")
+        let result = sqlx::query("INSERT INTO distributoruser (username, password, distributor, display_name, email, role) VALUES (?,?,?,?,?,?)")
         .bind(&user.username)
         .bind(&user.password)
         .bind(&user.distributor.id)
         .bind(&user.display_name)
+        .bind(&user.email)
+        .bind(&user.role.to_string())
         .execute(&mysql.conn).await;
 
         match result {
@@ -395,6 +1645,37 @@ impl DistributorUser {
         }
     }
 
+    pub fn tenant(&self) -> data::Tenant {
+        self.distributor.tenant()
+    }
+
+    // Users of a suspended distributor can only scan, and only when its policy allows it
+    pub fn can(&self, permission: Permission) -> bool {
+        match self.distributor.status {
+            DistributorStatus::Suspended => {
+                permission == Permission::Scan
+                    && self.distributor.accepts_redemptions()
+                    && self.role.can(permission)
+            }
+            _ => self.role.can(permission),
+        }
+    }
+
+    // First page after logging in
+    pub fn home(&self) -> &'static str {
+        match self.distributor.status {
+            DistributorStatus::Suspended => "/scanner",
+            _ => self.role.home(),
+        }
+    }
+
+    pub fn require(&self, permission: Permission) -> std::result::Result<(), AdminError> {
+        match self.can(permission) {
+            true => Ok(()),
+            false => Err(AdminError::Forbidden),
+        }
+    }
+
     pub fn hash_password(&mut self) -> Result<(), argon2::Error> {
         let salt: [u8; 32] = rand::thread_rng().gen();
         let config = Config::default();
@@ -410,11 +1691,68 @@ impl DistributorUser {
         argon2::verify_encoded(&self.password, password).map_err(|e| argon2::Error::from(e))
     }
 
+    // Returns the reason why a new password isn't accepted
+    pub fn check_password_strength(&self, password: &str) -> Result<(), &'static str> {
+        if password.chars().count() < 10 {
+            return Err("password_too_short");
+        }
+        if !password.chars().any(|c| c.is_alphabetic())
+            || !password.chars().any(|c| !c.is_alphabetic())
+        {
+            return Err("password_too_simple");
+        }
+        if password
+            .to_lowercase()
+            .contains(&self.username.to_lowercase())
+        {
+            return Err("password_contains_username");
+        }
+
+        Ok(())
+    }
+
+    pub async fn update_password(&mut self, password: &str, mysql: &web::Data<MySQL>) -> bool {
+        self.password = password.to_string();
+        self.hash_password();
+
+        let result = sqlx::query("UPDATE distributoruser SET password=? WHERE ID = ?")
+            .bind(&self.password)
+            .bind(&self.id)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    // Accepts a code of the authenticator app or an unused recovery code
+    pub async fn verify_second_factor(&self, code: &str, mysql: &web::Data<MySQL>) -> bool {
+        let secret = match data::get_totp_secret(mysql, self.id).await {
+            Some(secret) => secret,
+            None => return false,
+        };
+
+        if let Some(step) = totp::verify(&secret, code, Utc::now().timestamp()) {
+            // A code can only be used once, even within its time window
+            return data::use_totp_step(mysql, self.id, step).await;
+        }
+
+        data::use_recovery_code(mysql, self.id, code).await
+    }
+
+    // Usernames are only unique within a distributor
     pub async fn get_by_username(
         username: &String,
+        tenant: data::Tenant,
         mysql: &web::Data<MySQL>,
     ) -> Option<DistributorUser> {
-        let mut result = sqlx::query("SELECT ID, username, password, distributor, display_name FROM distributoruser WHERE username = ?")
+        let mut result = sqlx::query("SELECT ID, username, password, distributor, display_name, email, totp_enabled, role, active, last_login FROM distributoruser WHERE distributor = ? AND username = ?")
+        .bind(&tenant.distributor_id)
         .bind(&username)
         .fetch_one(&mysql.conn).await;
 
@@ -431,54 +1769,593 @@ impl DistributorUser {
                     .await
                     .unwrap(),
                 display_name: r.try_get("display_name").unwrap(),
+                email: r.try_get("email").unwrap(),
+                totp_enabled: r.try_get("totp_enabled").unwrap(),
+                role: Role::from_str(r.try_get("role").unwrap()).unwrap(),
+                active: r.try_get("active").unwrap(),
+                last_login: r.try_get("last_login").unwrap(),
+            }),
+        }
+    }
+
+    pub async fn get_by_id(id: u64, mysql: &web::Data<MySQL>) -> Option<DistributorUser> {
+        let mut result = sqlx::query("SELECT username, password, distributor, display_name, email, totp_enabled, role, active, last_login FROM distributoruser WHERE ID = ?")
+        .bind(&id)
+        .fetch_one(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(DistributorUser {
+                id: id,
+                username: r.try_get("username").unwrap(),
+                password: r.try_get("password").unwrap(),
+                distributor: get_distributor(mysql, r.try_get("distributor").unwrap())
+                    .await
+                    .unwrap(),
+                display_name: r.try_get("display_name").unwrap(),
+                email: r.try_get("email").unwrap(),
+                totp_enabled: r.try_get("totp_enabled").unwrap(),
+                role: Role::from_str(r.try_get("role").unwrap()).unwrap(),
+                active: r.try_get("active").unwrap(),
+                last_login: r.try_get("last_login").unwrap(),
             }),
         }
     }
 }
 
-impl FromStr for VoucherType {
+impl PlatformAdmin {
+    pub async fn get_by_username(
+        username: &String,
+        mysql: &web::Data<MySQL>,
+    ) -> Option<PlatformAdmin> {
+        let mut result = sqlx::query("SELECT ID, username, password, display_name, email, active, last_login FROM platformadmin WHERE username = ?")
+        .bind(&username)
+        .fetch_one(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(PlatformAdmin {
+                id: r.try_get("ID").unwrap(),
+                username: r.try_get("username").unwrap(),
+                password: r.try_get("password").unwrap(),
+                display_name: r.try_get("display_name").unwrap(),
+                email: r.try_get("email").unwrap(),
+                active: r.try_get("active").unwrap(),
+                last_login: r.try_get("last_login").unwrap(),
+            }),
+        }
+    }
+
+    pub async fn create(admin: &mut PlatformAdmin, mysql: &web::Data<MySQL>) -> u64 {
+        let salt: [u8; 32] = rand::thread_rng().gen();
+        let config = Config::default();
+
+        admin.password = argon2::hash_encoded(admin.password.as_bytes(), &salt, &config).unwrap();
+
+        let result = sqlx::query(
+            "INSERT INTO platformadmin (username, password, display_name, email) VALUES (?,?,?,?)",
+        )
+        .bind(&admin.username)
+        .bind(&admin.password)
+        .bind(&admin.display_name)
+        .bind(&admin.email)
+        .execute(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+}
+
+impl PlatformAuditEntry {
+    pub fn new(
+        admin: &PlatformAdmin,
+        req: &HttpRequest,
+        action: &str,
+        distributor_id: Option<u64>,
+        entity: &str,
+        entity_id: u64,
+    ) -> PlatformAuditEntry {
+        PlatformAuditEntry {
+            id: 0,
+            platformadmin_id: admin.id,
+            distributor_id: distributor_id,
+            action: action.to_string(),
+            entity: entity.to_string(),
+            entity_id: entity_id,
+            before: "{}".to_string(),
+            after: "{}".to_string(),
+            reason: "".to_string(),
+            ip: client_ip(req),
+            create_date: None,
+        }
+    }
+}
+
+impl AuditEntry {
+    pub fn new(
+        user: &DistributorUser,
+        req: &HttpRequest,
+        action: &str,
+        entity: &str,
+        entity_id: u64,
+    ) -> AuditEntry {
+        let mut entry = AuditEntry::for_tenant(user.tenant(), req, action, entity, entity_id);
+        entry.distributoruser_id = Some(user.id);
+        entry
+    }
+
+    // Entry without actor, e.g. a failed login of an unknown user
+    pub fn for_tenant(
+        tenant: data::Tenant,
+        req: &HttpRequest,
+        action: &str,
+        entity: &str,
+        entity_id: u64,
+    ) -> AuditEntry {
+        AuditEntry {
+            id: 0,
+            distributor_id: tenant.distributor_id,
+            distributoruser_id: None,
+            platformadmin_id: req
+                .get_session()
+                .get::<u64>("impersonated_by")
+                .unwrap_or(None),
+            action: action.to_string(),
+            entity: entity.to_string(),
+            entity_id: entity_id,
+            before: "{}".to_string(),
+            after: "{}".to_string(),
+            reason: "".to_string(),
+            ip: client_ip(req),
+            create_date: None,
+        }
+    }
+}
+
+impl Role {
+    pub fn permissions(&self) -> &'static [Permission] {
+        use Permission::*;
+
+        match self {
+            Role::Owner => &[
+                ManageBusiness,
+                ManageUsers,
+                ManageVouchers,
+                ViewOrders,
+                ManageOrders,
+                Export,
+                ViewAuditLog,
+                Scan,
+            ],
+            Role::Manager => &[ManageVouchers, ViewOrders, ManageOrders, Export, Scan],
+            Role::Cashier => &[Scan],
+            Role::Accountant => &[ViewOrders, Export, ViewAuditLog],
+        }
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+
+    // First page after logging in
+    pub fn home(&self) -> &'static str {
+        match self {
+            Role::Owner => "/admin/dashboard/mijn-zaak",
+            Role::Manager | Role::Accountant => "/admin/dashboard/bestellingen",
+            Role::Cashier => "/scanner",
+        }
+    }
+}
+
+impl FromStr for Role {
     type Err = ();
 
-    fn from_str(input: &str) -> Result<VoucherType, Self::Err> {
+    fn from_str(input: &str) -> Result<Role, Self::Err> {
         match &*input.to_lowercase() {
-            "threeoptionvoucher" => Ok(VoucherType::ThreeOptionVoucher),
-            "rangevoucher" => Ok(VoucherType::RangeVoucher),
-            "labelvoucher" => Ok(VoucherType::LabelVoucher),
+            "owner" => Ok(Role::Owner),
+            "manager" => Ok(Role::Manager),
+            "cashier" => Ok(Role::Cashier),
+            "accountant" => Ok(Role::Accountant),
             _ => Err(()),
         }
     }
 }
-impl std::fmt::Display for VoucherType {
+impl std::fmt::Display for Role {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
-impl FromStr for OrderStatus {
+impl std::fmt::Display for TokenPurpose {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for DistributorStatus {
     type Err = ();
 
-    fn from_str(input: &str) -> Result<OrderStatus, Self::Err> {
+    fn from_str(input: &str) -> Result<DistributorStatus, Self::Err> {
         match &*input.to_lowercase() {
-            "failed" => Ok(OrderStatus::Failed),
-            "paid" => Ok(OrderStatus::Paid),
+            "unverified" => Ok(DistributorStatus::Unverified),
+            "onboarding" => Ok(DistributorStatus::Onboarding),
+            "active" => Ok(DistributorStatus::Active),
+            "suspended" => Ok(DistributorStatus::Suspended),
+            "deleted" => Ok(DistributorStatus::Deleted),
             _ => Err(()),
         }
     }
 }
-impl std::fmt::Display for OrderStatus {
+impl std::fmt::Display for DistributorStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
-fn get_subdomain_part(full_domain: &str) -> &str {
-    full_domain.split(".").next().unwrap()
-}
-
-pub mod data {
-    use crate::data::Selector::*;
-    use crate::*;
-    use futures::TryStreamExt;
+impl FromStr for VoucherType {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<VoucherType, Self::Err> {
+        match &*input.to_lowercase() {
+            "threeoptionvoucher" => Ok(VoucherType::ThreeOptionVoucher),
+            "rangevoucher" => Ok(VoucherType::RangeVoucher),
+            "labelvoucher" => Ok(VoucherType::LabelVoucher),
+            _ => Err(()),
+        }
+    }
+}
+impl std::fmt::Display for VoucherType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Voucher {
+    // Blocked and Refunded are set by hand, every other status follows from the voucher itself
+    pub fn derive_status(&self) -> VoucherStatus {
+        match self.status {
+            VoucherStatus::Blocked | VoucherStatus::Refunded => self.status,
+            _ if !self.sale.paid => VoucherStatus::PendingPayment,
+            _ if self.used || self.balance <= 0.0 => VoucherStatus::Used,
+            _ if self.expiration_date < Utc::now() => VoucherStatus::Expired,
+            _ if self.balance < self.sale.amount => VoucherStatus::PartiallyUsed,
+            _ => VoucherStatus::Active,
+        }
+    }
+
+    pub fn is_redeemable(&self) -> bool {
+        match self.derive_status() {
+            VoucherStatus::Active | VoucherStatus::PartiallyUsed => true,
+            _ => false,
+        }
+    }
+}
+
+impl VoucherStatus {
+    // Message shown on the scanner when a voucher can't be redeemed
+    pub fn description(&self) -> &'static str {
+        match self {
+            VoucherStatus::PendingPayment => "Bon is niet betaald",
+            VoucherStatus::Active => "Bon is actief",
+            VoucherStatus::PartiallyUsed => "Bon is gedeeltelijk gebruikt",
+            VoucherStatus::Used => "Bon is al gebruikt",
+            VoucherStatus::Expired => "Bon is vervallen",
+            VoucherStatus::Refunded => "Bon werd terugbetaald",
+            VoucherStatus::Blocked => "Bon is geblokkeerd",
+        }
+    }
+}
+
+impl FromStr for VoucherStatus {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<VoucherStatus, Self::Err> {
+        match &*input.to_lowercase() {
+            "pendingpayment" => Ok(VoucherStatus::PendingPayment),
+            "active" => Ok(VoucherStatus::Active),
+            "partiallyused" => Ok(VoucherStatus::PartiallyUsed),
+            "used" => Ok(VoucherStatus::Used),
+            "expired" => Ok(VoucherStatus::Expired),
+            "refunded" => Ok(VoucherStatus::Refunded),
+            "blocked" => Ok(VoucherStatus::Blocked),
+            _ => Err(()),
+        }
+    }
+}
+impl std::fmt::Display for VoucherStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for OrderStatus {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<OrderStatus, Self::Err> {
+        match &*input.to_lowercase() {
+            "failed" => Ok(OrderStatus::Failed),
+            "paid" => Ok(OrderStatus::Paid),
+            _ => Err(()),
+        }
+    }
+}
+impl std::fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+// Host of the request without port, e.g. "bakkerij.kaddo.test" or "bonnen.bakkerij.be"
+fn request_host(req: &HttpRequest) -> String {
+    req.headers()
+        .get("Host")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("")
+        .split(":")
+        .next()
+        .unwrap()
+        .to_lowercase()
+}
+
+// Own domain of a distributor as typed by the user, e.g. "https://Bonnen.Bakkerij.be/"
+fn normalize_custom_domain(input: &str) -> Result<String, &'static str> {
+    let domain = input
+        .trim()
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_end_matches("/")
+        .trim_end_matches(".")
+        .to_lowercase();
+
+    let labels: std::vec::Vec<&str> = domain.split(".").collect();
+    if domain.len() > 253 || labels.len() < 2 {
+        return Err("domain_invalid");
+    }
+    if labels.iter().any(|l| {
+        l.is_empty()
+            || l.len() > 63
+            || l.starts_with("-")
+            || l.ends_with("-")
+            || !l
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    }) {
+        return Err("domain_invalid");
+    }
+    // IP addresses can't be verified with a TXT record
+    if labels.last().unwrap().chars().all(|c| c.is_ascii_digit()) {
+        return Err("domain_invalid");
+    }
+    if domain == PLATFORM_DOMAIN || domain.ends_with(&format!(".{}", PLATFORM_DOMAIN)) {
+        return Err("domain_reserved");
+    }
+
+    Ok(domain)
+}
+
+// Labels and form fields of the opening hours, from Monday
+const WEEKDAYS: [(&str, &str); 7] = [
+    ("Maandag", "maandag"),
+    ("Dinsdag", "dinsdag"),
+    ("Woensdag", "woensdag"),
+    ("Donderdag", "donderdag"),
+    ("Vrijdag", "vrijdag"),
+    ("Zaterdag", "zaterdag"),
+    ("Zondag", "zondag"),
+];
+
+// Opening hours of one day, e.g. "9:00-12:00, 13:00 - 18:00", normalized to
+// "09:00-12:00, 13:00-18:00". Empty means closed.
+fn parse_opening_hours(input: &str) -> Option<String> {
+    let mut periods: std::vec::Vec<String> = std::vec::Vec::new();
+    for period in input.split(",").map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let mut times = period.split("-").map(|t| t.trim());
+        let open = chrono::NaiveTime::parse_from_str(times.next()?, "%H:%M").ok()?;
+        let close = chrono::NaiveTime::parse_from_str(times.next()?, "%H:%M").ok()?;
+        if times.next().is_some() || close <= open {
+            return None;
+        }
+        periods.push(format!(
+            "{}-{}",
+            open.format("%H:%M"),
+            close.format("%H:%M")
+        ));
+    }
+
+    Some(periods.join(", "))
+}
+
+const DEFAULT_REMINDER_DAYS: &str = "30,7";
+
+fn parse_reminder_days(input: &str) -> Option<std::vec::Vec<i64>> {
+    let mut days: std::vec::Vec<i64> = std::vec::Vec::new();
+    for part in input.split(",").map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        match part.parse::<i64>() {
+            Ok(d) if d > 0 && d <= 365 => days.push(d),
+            _ => return None,
+        }
+    }
+
+    days.sort_unstable_by(|a, b| b.cmp(a));
+    days.dedup();
+
+    if days.len() > 5 {
+        return None;
+    }
+
+    Some(days)
+}
+
+// Random token for links in mails, only its hash is stored
+fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    sync::to_hex(&bytes)
+}
+
+fn hash_token(token: &str) -> String {
+    let mut sha256 = Sha256::new();
+    sha256.input_str(token);
+    sha256.result_str()
+}
+
+fn generate_recovery_codes() -> std::vec::Vec<String> {
+    (0..RECOVERY_CODE_AMOUNT)
+        .map(|_| {
+            let bytes: [u8; 5] = rand::thread_rng().gen();
+            let code = sync::to_hex(&bytes);
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+// Recovery codes are compared without dashes, spaces or casing
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+// Only the last characters of e.g. a bank account number, for logs and overviews
+fn mask_tail(value: &str, visible: usize) -> String {
+    let chars: std::vec::Vec<char> = value.chars().collect();
+    if chars.len() <= visible {
+        return "*".repeat(chars.len());
+    }
+
+    format!(
+        "{}{}",
+        "*".repeat(chars.len() - visible),
+        chars[chars.len() - visible..].iter().collect::<String>()
+    )
+}
+
+// Spreadsheets run a value starting with one of these as a formula, a quote keeps it text
+fn csv_field(value: &str) -> String {
+    let value = match value.starts_with(&['=', '+', '-', '@', '\t', '\r'][..]) {
+        true => format!("'{}", value),
+        false => value.to_string(),
+    };
+
+    format!("\"{}\"", value.replace("\"", "\"\""))
+}
+
+// Proxies in front of Kaddo, comma separated, only their forwarded headers are trusted
+static TRUSTED_PROXIES: Lazy<std::vec::Vec<String>> = Lazy::new(|| {
+    env::var("KADDO_TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| ip != "")
+        .collect()
+});
+
+// Address of the client. Anyone can send X-Forwarded-For, so it only counts when the
+// connection comes from a trusted proxy, and then only the address that proxy added.
+fn client_ip(req: &HttpRequest) -> String {
+    let peer = req
+        .peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default();
+
+    if TRUSTED_PROXIES.contains(&peer) {
+        let forwarded = req
+            .headers()
+            .get("X-Forwarded-For")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.rsplit(',').next())
+            .map(|ip| ip.trim())
+            .unwrap_or("");
+        if forwarded != "" {
+            return forwarded.to_string();
+        }
+    }
+
+    peer
+}
+
+const PLATFORM_DOMAIN: &str = "kaddo.test";
+
+// Added by Kaddo to every paid order, this is the platform's income
+const TRANSACTION_FEE: f64 = 1.5;
+
+// Subdomains that can't be given to a distributor
+const RESERVED_SUBDOMAINS: &[&str] = &[
+    "www", "kaddo", "admin", "platform", "api", "mail", "assets", "static", "scanner",
+];
+
+// The main domain hosts the platform itself, every subdomain is a distributor
+fn is_platform_host(req: &HttpRequest) -> bool {
+    let host = req
+        .headers()
+        .get("Host")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("");
+    let host = host.split(":").next().unwrap();
+
+    host == PLATFORM_DOMAIN || host == format!("www.{}", PLATFORM_DOMAIN)
+}
+
+fn check_subdomain(subdomain: &str) -> Result<(), &'static str> {
+    if subdomain.len() < 3 || subdomain.len() > 63 {
+        return Err("subdomain_length");
+    }
+    if !subdomain
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        || subdomain.starts_with("-")
+        || subdomain.ends_with("-")
+    {
+        return Err("subdomain_invalid");
+    }
+    // Deleted distributors and users are renamed to verwijderd-<ID>
+    if RESERVED_SUBDOMAINS.contains(&subdomain) || subdomain.starts_with("verwijderd-") {
+        return Err("subdomain_reserved");
+    }
+
+    Ok(())
+}
+
+// Validated and normalized VAT number and IBAN, the IBAN still has to be encrypted before
+// storing it
+fn check_registration_numbers(
+    btw_nr: &str,
+    bankaccountnr: &str,
+) -> Result<(String, String), &'static str> {
+    Ok((
+        bankdetails::check_vat(btw_nr)?,
+        bankdetails::check_iban(bankaccountnr)?,
+    ))
+}
+
+fn platform_url() -> String {
+    format!("http://{}:8080", PLATFORM_DOMAIN)
+}
+
+// Public URL of the storefront of a distributor, used in mails
+fn distributor_url(distributor: &Distributor) -> String {
+    match &distributor.primary_domain {
+        Some(domain) => format!("https://{}", domain),
+        None => format!("http://{}.{}:8080", distributor.subdomain, PLATFORM_DOMAIN),
+    }
+}
+
+pub mod data {
+    use crate::data::Selector::*;
+    use crate::*;
+    use futures::TryStreamExt;
 
     pub enum Selector {
         ById(u64),
@@ -487,11 +2364,45 @@ pub mod data {
         ByNumberCode(String),
     }
 
+    // Distributor context required by every query on orders, vouchers, sales and clients.
+    // Rows of other distributors are never returned or modified.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Tenant {
+        pub distributor_id: u64,
+    }
+
+    impl Tenant {
+        pub fn of(distributor_id: u64) -> Tenant {
+            Tenant {
+                distributor_id: distributor_id,
+            }
+        }
+    }
+
+    // The payment provider calls back without a host, so the tenant is looked up from the sale
+    pub async fn get_tenant_by_payment_id(
+        mysql: &web::Data<MySQL>,
+        payment_id: &str,
+    ) -> Option<Tenant> {
+        let mut result = sqlx::query("SELECT distributor FROM sale WHERE payment_id = ?")
+            .bind(&payment_id)
+            .fetch_one(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(Tenant::of(r.try_get("distributor").unwrap())),
+        }
+    }
+
     pub async fn get_distributor_by_subdomain(
         mysql: &web::Data<MySQL>,
         subdomain: &str,
     ) -> Option<Distributor> {
-        let mut result = sqlx::query("SELECT ID, name, email, tel, address, location, description, bankaccountnr, btw_nr FROM distributor WHERE subdomain = ?")
+        let mut result = sqlx::query("SELECT distributor.ID, name, email, tel, address, location, location.country, location.postalcode, location.city, description, description_html, bankaccountnr, btw_nr, reminder_days, send_reminders, require_2fa, status, redeem_when_suspended, (SELECT domain FROM distributordomain WHERE distributordomain.distributor = distributor.ID AND is_primary = 1 AND verified_date IS NOT NULL) AS primary_domain, logo, cover, primary_color, accent_color, font FROM distributor JOIN location ON location.ID = distributor.location LEFT JOIN distributorbranding ON distributorbranding.distributor = distributor.ID WHERE subdomain = ? AND status != 'Deleted'")
         .bind(&subdomain)
         .fetch_one(&mysql.conn).await;
 
@@ -506,19 +2417,60 @@ pub mod data {
                 email: r.try_get("email").unwrap(),
                 tel: r.try_get("tel").unwrap(),
                 address: r.try_get("address").unwrap(),
-                location: get_location(mysql, r.try_get("location").unwrap())
-                    .await
-                    .unwrap(),
+                location: Location {
+                    id: r.try_get("location").unwrap(),
+                    country: r.try_get("country").unwrap(),
+                    postalcode: r.try_get("postalcode").unwrap(),
+                    city: r.try_get("city").unwrap(),
+                },
                 subdomain: subdomain.to_string(),
                 description: r.try_get("description").unwrap(),
+                description_html: r.try_get("description_html").unwrap(),
                 bankaccountnr: r.try_get("bankaccountnr").unwrap(),
                 btw_nr: r.try_get("btw_nr").unwrap(),
+                reminder_days: r.try_get("reminder_days").unwrap(),
+                send_reminders: r.try_get("send_reminders").unwrap(),
+                require_2fa: r.try_get("require_2fa").unwrap(),
+                status: DistributorStatus::from_str(r.try_get("status").unwrap())
+                    .unwrap_or(DistributorStatus::Active),
+                redeem_when_suspended: r.try_get("redeem_when_suspended").unwrap(),
+                primary_domain: r.try_get("primary_domain").unwrap(),
+                branding: branding_from_row(&r),
             }),
         }
     }
 
+    // Own domains first, then subdomains of the platform domain
+    pub async fn get_distributor_by_host(
+        mysql: &web::Data<MySQL>,
+        host: &str,
+    ) -> Option<Distributor> {
+        let mut result = sqlx::query("SELECT distributor FROM distributordomain WHERE domain = ? AND verified_date IS NOT NULL")
+            .bind(&host)
+            .fetch_optional(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                return None;
+            }
+            Ok(Some(r)) => {
+                return get_distributor(mysql, r.try_get("distributor").unwrap())
+                    .await
+                    .filter(|d| d.status != DistributorStatus::Deleted)
+            }
+            Ok(None) => {}
+        }
+
+        match host.strip_suffix(&format!(".{}", PLATFORM_DOMAIN)) {
+            Some(subdomain) => get_distributor_by_subdomain(mysql, subdomain).await,
+            None => None,
+        }
+    }
+
     pub async fn get_distributor(mysql: &web::Data<MySQL>, id: u64) -> Option<Distributor> {
-        let mut result = sqlx::query("SELECT name, email, tel, address, location, subdomain, description, bankaccountnr, btw_nr FROM distributor WHERE ID = ?")
+        let mut result = sqlx::query("SELECT name, email, tel, address, location, subdomain, description, description_html, bankaccountnr, btw_nr, reminder_days, send_reminders, require_2fa, status, redeem_when_suspended, (SELECT domain FROM distributordomain WHERE distributordomain.distributor = distributor.ID AND is_primary = 1 AND verified_date IS NOT NULL) AS primary_domain, logo, cover, primary_color, accent_color, font FROM distributor LEFT JOIN distributorbranding ON distributorbranding.distributor = distributor.ID WHERE distributor.ID = ?")
         .bind(&id)
         .fetch_one(&mysql.conn).await;
 
@@ -538,19 +2490,79 @@ pub mod data {
                     .unwrap(),
                 subdomain: r.try_get("subdomain").unwrap(),
                 description: r.try_get("description").unwrap(),
+                description_html: r.try_get("description_html").unwrap(),
                 bankaccountnr: r.try_get("bankaccountnr").unwrap(),
                 btw_nr: r.try_get("btw_nr").unwrap(),
+                reminder_days: r.try_get("reminder_days").unwrap(),
+                send_reminders: r.try_get("send_reminders").unwrap(),
+                require_2fa: r.try_get("require_2fa").unwrap(),
+                status: DistributorStatus::from_str(r.try_get("status").unwrap())
+                    .unwrap_or(DistributorStatus::Active),
+                redeem_when_suspended: r.try_get("redeem_when_suspended").unwrap(),
+                primary_domain: r.try_get("primary_domain").unwrap(),
+                branding: branding_from_row(&r),
             }),
         }
     }
 
+    // Distributors that never changed their branding have no row and get the default
+    fn branding_from_row(r: &sqlx::mysql::MySqlRow) -> Branding {
+        let default = Branding::default();
+
+        Branding {
+            logo: r.try_get("logo").unwrap_or(None),
+            cover: r.try_get("cover").unwrap_or(None),
+            primary_color: r
+                .try_get::<Option<String>, _>("primary_color")
+                .unwrap_or(None)
+                .unwrap_or(default.primary_color),
+            accent_color: r
+                .try_get::<Option<String>, _>("accent_color")
+                .unwrap_or(None)
+                .unwrap_or(default.accent_color),
+            font: r
+                .try_get::<Option<String>, _>("font")
+                .unwrap_or(None)
+                .and_then(|f| Font::from_str(&f).ok())
+                .unwrap_or(default.font),
+        }
+    }
+
+    pub async fn update_distributor_branding(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        branding: &Branding,
+    ) -> bool {
+        let result = sqlx::query("INSERT INTO distributorbranding (distributor, logo, cover, primary_color, accent_color, font) VALUES (?,?,?,?,?,?) ON DUPLICATE KEY UPDATE logo=VALUES(logo), cover=VALUES(cover), primary_color=VALUES(primary_color), accent_color=VALUES(accent_color), font=VALUES(font)")
+            .bind(&tenant.distributor_id)
+            .bind(&branding.logo)
+            .bind(&branding.cover)
+            .bind(&branding.primary_color)
+            .bind(&branding.accent_color)
+            .bind(&branding.font.to_string())
+            .execute(&mysql.conn)
+            .await;
+        tenancy::forget(tenant.distributor_id).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(_) => true,
+        }
+    }
+
     pub async fn update_distributor(mysql: &web::Data<MySQL>, distributor: &Distributor) -> bool {
         let location = get_id_of_location(mysql, &distributor.location).await;
         if location.is_none() {
             return false;
         }
+        let previous_subdomain = get_distributor(mysql, distributor.id)
+            .await
+            .map(|d| d.subdomain);
 
-        let result = sqlx::query("UPDATE distributor SET name=?, email=?, tel=?, address=?, location=?, subdomain=?, description=?, bankaccountnr=?, btw_nr=? WHERE ID = ?")
+        let result = sqlx::query("UPDATE distributor SET name=?, email=?, tel=?, address=?, location=?, subdomain=?, description=?, description_html=?, bankaccountnr=?, btw_nr=? WHERE ID = ?")
         .bind(&distributor.name)
         .bind(&distributor.email)
         .bind(&distributor.tel)
@@ -558,150 +2570,198 @@ pub mod data {
         .bind(&location.unwrap())
         .bind(&distributor.subdomain)
         .bind(&distributor.description)
+        .bind(&content::render_markdown(&distributor.description))
         .bind(&distributor.bankaccountnr)
         .bind(&distributor.btw_nr)
         .bind(&distributor.id)
         .execute(&mysql.conn).await;
+        tenancy::forget(distributor.id).await;
 
-        match result {
+        let updated = match result {
             Err(e) => {
                 println!("Error: {}", e);
                 false
             }
             Ok(r) => r.rows_affected() > 0,
+        };
+
+        // Links to the old subdomain keep working
+        if let Some(previous) = previous_subdomain {
+            if updated && previous != distributor.subdomain {
+                add_subdomain_history(
+                    mysql,
+                    distributor.tenant(),
+                    &previous,
+                    &distributor.subdomain,
+                )
+                .await;
+            }
         }
+
+        updated
     }
 
-    pub async fn add_client(mysql: &web::Data<MySQL>, client: &Client) -> u64 {
+    async fn add_subdomain_history(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        previous: &str,
+        current: &str,
+    ) -> bool {
+        // Going back to an earlier name, it's no longer an old one
+        let result =
+            sqlx::query("DELETE FROM subdomainhistory WHERE subdomain = ? AND distributor = ?")
+                .bind(&current)
+                .bind(&tenant.distributor_id)
+                .execute(&mysql.conn)
+                .await;
+
+        if let Err(e) = result {
+            println!("Error: {}", e);
+            return false;
+        }
+
         let result =
-            sqlx::query("INSERT INTO client (firstname, lastname, email, tel) VALUES (?,?,?,?)")
-                .bind(&client.firstname)
-                .bind(&client.lastname)
-                .bind(&client.email)
-                .bind(&client.tel)
+            sqlx::query("INSERT INTO subdomainhistory (distributor, subdomain) VALUES (?,?)")
+                .bind(&tenant.distributor_id)
+                .bind(&previous)
                 .execute(&mysql.conn)
                 .await;
 
         match result {
             Err(e) => {
                 println!("Error: {}", e);
-                0
+                false
             }
-            Ok(r) => r.last_insert_id(),
+            Ok(r) => r.rows_affected() > 0,
         }
     }
 
-    pub async fn get_client(mysql: &web::Data<MySQL>, id: u64) -> Option<Client> {
-        let mut result = sqlx::query(
-            "SELECT firstname, lastname, email, tel, saved_account FROM client WHERE ID = ?",
-        )
-        .bind(&id)
-        .fetch_one(&mysql.conn)
-        .await;
+    // Distributor that used this subdomain before it was renamed
+    pub async fn get_distributor_by_previous_subdomain(
+        mysql: &web::Data<MySQL>,
+        subdomain: &str,
+    ) -> Option<Distributor> {
+        let mut result =
+            sqlx::query("SELECT distributor FROM subdomainhistory WHERE subdomain = ?")
+                .bind(&subdomain)
+                .fetch_optional(&mysql.conn)
+                .await;
 
         match result {
             Err(e) => {
                 println!("error: {:?}", e);
                 None
             }
-            Ok(r) => Some(Client {
-                id: id,
-                firstname: r.try_get("firstname").unwrap(),
-                lastname: r.try_get("lastname").unwrap(),
-                email: r.try_get("email").unwrap(),
-                tel: r.try_get("tel").unwrap(),
-                saved_account: r.try_get("saved_account").unwrap(),
-            }),
+            Ok(Some(r)) => get_distributor(mysql, r.try_get("distributor").unwrap())
+                .await
+                .filter(|d| d.status != DistributorStatus::Deleted),
+            Ok(None) => None,
         }
     }
 
-    pub async fn add_sale(mysql: &web::Data<MySQL>, sale: &Sale) -> u64 {
-        let result = sqlx::query("INSERT INTO sale (client, amount, payment_id) VALUES (?,?,?)")
-            .bind(&sale.client.id)
-            .bind(&sale.amount)
-            .bind(&sale.payment_id)
-            .execute(&mysql.conn)
-            .await;
+    // A sign-up or a shop added by the platform creates the distributor, its first shop, the
+    // owner and the token of the verification or invitation mail at once, so a failure halfway
+    // can't leave a shop without owner
+    pub async fn add_registration(
+        mysql: &web::Data<MySQL>,
+        distributor: &Distributor,
+        owner: &DistributorUser,
+        purpose: TokenPurpose,
+        token: &str,
+        expires: chrono::DateTime<chrono::Utc>,
+    ) -> Option<(u64, u64)> {
+        let location = get_id_of_location(mysql, &distributor.location).await?;
+
+        let mut tx = match mysql.conn.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                println!("Error: {}", e);
+                return None;
+            }
+        };
 
-        match result {
+        let result = sqlx::query("INSERT INTO distributor (name, email, tel, address, location, subdomain, description, description_html, bankaccountnr, btw_nr, reminder_days, send_reminders, require_2fa, status) VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?)")
+        .bind(&distributor.name)
+        .bind(&distributor.email)
+        .bind(&distributor.tel)
+        .bind(&distributor.address)
+        .bind(&location)
+        .bind(&distributor.subdomain)
+        .bind(&distributor.description)
+        .bind(&content::render_markdown(&distributor.description))
+        .bind(&distributor.bankaccountnr)
+        .bind(&distributor.btw_nr)
+        .bind(&distributor.reminder_days)
+        .bind(&distributor.send_reminders)
+        .bind(&distributor.require_2fa)
+        .bind(&distributor.status.to_string())
+        .execute(&mut tx).await;
+
+        let distributor_id = match result {
             Err(e) => {
                 println!("Error: {}", e);
-                0
+                return None;
             }
             Ok(r) => r.last_insert_id(),
-        }
-    }
-
-    pub async fn get_sale(mysql: &web::Data<MySQL>, selector: Selector) -> Option<Sale> {
-        let (where_column, where_value) = match selector {
-            ById(id) => ("ID", id.to_string()),
-            ByPaymentId(payment_id) => ("payment_id", payment_id),
-            _ => ("", "".to_string()),
         };
 
-        let sql = format!(
-            "SELECT ID, client, amount, payment_id, paid, purchase_date FROM sale WHERE {} = ?",
-            where_column
-        );
-        let mut result = sqlx::query(&sql)
-            .bind(&where_value)
-            .fetch_one(&mysql.conn)
+        let result = sqlx::query("INSERT INTO shoplocation (distributor, name, address, location, opening_hours) VALUES (?,?,?,?,?)")
+            .bind(&distributor_id)
+            .bind(&distributor.name)
+            .bind(&distributor.address)
+            .bind(&location)
+            .bind(&serde_json::to_string(&vec![""; 7]).unwrap())
+            .execute(&mut tx)
             .await;
 
-        match result {
-            Err(e) => {
-                println!("error: {:?}", e);
-                None
-            }
-            Ok(r) => Some(Sale {
-                id: r.try_get("ID").unwrap(),
-                client: get_client(&mysql, r.try_get("client").unwrap())
-                    .await
-                    .unwrap(),
-                amount: r.try_get("amount").unwrap(),
-                payment_id: r.try_get("payment_id").unwrap(),
-                paid: r.try_get("paid").unwrap(),
-                purchase_date: r.try_get("purchase_date").unwrap(),
-            }),
+        if let Err(e) = result {
+            println!("Error: {}", e);
+            return None;
         }
-    }
 
-    pub async fn update_sale(mysql: &web::Data<MySQL>, sale: &Sale, selector: Selector) -> bool {
-        let (where_column, where_value) = match selector {
-            ById(id) => ("ID", id.to_string()),
-            ByPaymentId(payment_id) => ("payment_id", payment_id),
-            _ => ("", "".to_string()),
+        // The password of the owner is hashed by the caller
+        let result = sqlx::query("INSERT INTO distributoruser (username, password, distributor, display_name, email, role) VALUES (?,?,?,?,?,?)")
+        .bind(&owner.username)
+        .bind(&owner.password)
+        .bind(&distributor_id)
+        .bind(&owner.display_name)
+        .bind(&owner.email)
+        .bind(&owner.role.to_string())
+        .execute(&mut tx).await;
+
+        let owner_id = match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                return None;
+            }
+            Ok(r) => r.last_insert_id(),
         };
 
-        let sql = format!(
-            "UPDATE sale SET client=?, amount=?, payment_id=?, paid=? WHERE {} = ?",
-            where_column
-        );
+        let result = sqlx::query("INSERT INTO usertoken (distributoruser, purpose, token_hash, expires) VALUES (?,?,?,?)")
+        .bind(&owner_id)
+        .bind(&purpose.to_string())
+        .bind(&hash_token(token))
+        .bind(&expires)
+        .execute(&mut tx).await;
 
-        let mut result = sqlx::query(&sql)
-            .bind(&sale.client.id)
-            .bind(&sale.amount)
-            .bind(&sale.payment_id)
-            .bind(&sale.paid)
-            .bind(&where_value)
-            .execute(&mysql.conn)
-            .await;
+        if let Err(e) = result {
+            println!("Error: {}", e);
+            return None;
+        }
 
-        match result {
+        match tx.commit().await {
             Err(e) => {
                 println!("Error: {}", e);
-                false
+                None
             }
-            Ok(r) => r.rows_affected() > 0,
+            Ok(_) => Some((distributor_id, owner_id)),
         }
     }
 
-    pub async fn get_distributor_voucher(
-        mysql: &web::Data<MySQL>,
-        id: u64,
-    ) -> Option<DistributorVoucher> {
-        let mut result = sqlx::query("SELECT distributor, voucher_type, amount, min_amount, max_amount, label, description, days_valid, active, one_use_only, create_date FROM distributorvoucher WHERE ID = ?")
-        .bind(&id)
+    // Owner of a sign-up that still waits for the confirmation of its email address
+    pub async fn get_unverified_owner_id(mysql: &web::Data<MySQL>, email: &str) -> Option<u64> {
+        let mut result = sqlx::query("SELECT distributoruser.ID FROM distributoruser INNER JOIN distributor ON distributor.ID=distributoruser.distributor WHERE distributoruser.email = ? AND distributoruser.role = 'Owner' AND distributor.status = 'Unverified' ORDER BY distributoruser.ID DESC LIMIT 1")
+        .bind(&email)
         .fetch_one(&mysql.conn).await;
 
         match result {
@@ -709,218 +2769,215 @@ pub mod data {
                 println!("error: {:?}", e);
                 None
             }
-            Ok(r) => Some(DistributorVoucher {
-                id: id,
-                distributor: get_distributor(&mysql, r.try_get("distributor").unwrap())
-                    .await
-                    .unwrap(),
-                voucher_type: VoucherType::from_str(r.try_get("voucher_type").unwrap()).unwrap(),
-                amount: r.try_get("amount").unwrap(),
-                min_amount: r.try_get("min_amount").unwrap(),
-                max_amount: r.try_get("max_amount").unwrap(),
-                label: match r.try_get("label").unwrap() {
-                    None => "".to_string(),
-                    Some(v) => v,
-                },
-                description: match r.try_get("description").unwrap() {
-                    None => "".to_string(),
-                    Some(v) => v,
-                },
-                days_valid: r.try_get("days_valid").unwrap(),
-                active: r.try_get("active").unwrap(),
-                one_use_only: r.try_get("one_use_only").unwrap(),
-                create_date: r.try_get("create_date").unwrap(),
-            }),
+            Ok(r) => Some(r.try_get("ID").unwrap()),
         }
     }
 
-    pub async fn get_distributor_vouchers_by_distributor(
+    // Sign-ups that were never confirmed since the given time
+    pub async fn get_expired_registrations(
         mysql: &web::Data<MySQL>,
-        id: u64,
-    ) -> Option<std::vec::Vec<DistributorVoucher>> {
-        let mut result = sqlx::query(
-            "SELECT ID FROM distributorvoucher WHERE distributor = ? AND most_recent_of_Type=1",
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Option<std::vec::Vec<u64>> {
+        let result = sqlx::query(
+            "SELECT ID FROM distributor WHERE status = 'Unverified' AND create_date < ?",
         )
-        .bind(&id)
-        .fetch(&mysql.conn);
-
-        let mut distributor_vouchers: std::vec::Vec<DistributorVoucher> = std::vec::Vec::new();
+        .bind(&since)
+        .fetch_all(&mysql.conn)
+        .await;
 
-        while let Some(row) = result.try_next().await.unwrap() {
-            distributor_vouchers.push(
-                get_distributor_voucher(&mysql, row.try_get("ID").unwrap())
-                    .await
-                    .unwrap(),
-            );
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(rows.iter().map(|r| r.try_get("ID").unwrap()).collect()),
         }
-
-        Some(distributor_vouchers)
     }
-    pub async fn get_active_distributor_vouchers_by_distributor(
-        mysql: &web::Data<MySQL>,
-        id: u64,
-    ) -> Option<std::vec::Vec<DistributorVoucher>> {
+
+    // Every distributor that isn't deleted, for the platform admin
+    pub async fn get_distributors(mysql: &web::Data<MySQL>) -> Option<std::vec::Vec<Distributor>> {
         let mut result =
-            sqlx::query("SELECT ID FROM distributorvoucher WHERE distributor = ? AND active=1")
-                .bind(&id)
+            sqlx::query("SELECT ID FROM distributor WHERE status != 'Deleted' ORDER BY name")
                 .fetch(&mysql.conn);
 
-        let mut distributor_vouchers: std::vec::Vec<DistributorVoucher> = std::vec::Vec::new();
+        let mut distributors: std::vec::Vec<Distributor> = std::vec::Vec::new();
 
         while let Some(row) = result.try_next().await.unwrap() {
-            distributor_vouchers.push(
-                get_distributor_voucher(&mysql, row.try_get("ID").unwrap())
+            distributors.push(
+                get_distributor(&mysql, row.try_get("ID").unwrap())
                     .await
                     .unwrap(),
             );
         }
 
-        Some(distributor_vouchers)
+        Some(distributors)
     }
-    pub async fn add_active_distributor_vouchers(
+
+    pub async fn update_distributor_status(
         mysql: &web::Data<MySQL>,
-        distributor_vouchers: std::vec::Vec<DistributorVoucher>,
+        id: u64,
+        status: DistributorStatus,
     ) -> bool {
-        if distributor_vouchers.len() == 0 {
-            return false;
-        }
-
-        let mut result = sqlx::query("UPDATE distributorvoucher SET most_recent_of_type=0 WHERE distributor=? AND upper(voucher_type)=upper(?)")
-        .bind(distributor_vouchers[0].distributor.id)
-        .bind(distributor_vouchers[0].voucher_type.to_string())
-        .execute(&mysql.conn).await;
-
-        let mut result = sqlx::query("UPDATE distributorvoucher SET active=0 WHERE distributor=?")
-            .bind(distributor_vouchers[0].distributor.id)
+        let result = sqlx::query("UPDATE distributor SET status=? WHERE ID = ?")
+            .bind(&status.to_string())
+            .bind(&id)
             .execute(&mysql.conn)
             .await;
+        tenancy::forget(id).await;
 
-        for distributor_voucher in distributor_vouchers {
-            let mut result = sqlx::query("INSERT INTO distributorvoucher (distributor, voucher_type, amount, min_amount, max_amount, label, description, days_valid, active, one_use_only, most_recent_of_type) VALUES (?,?,?,?,?,?,?,?,?,?,1)")
-            .bind(distributor_voucher.distributor.id)
-            .bind(distributor_voucher.voucher_type.to_string())
-            .bind(distributor_voucher.amount)
-            .bind(distributor_voucher.min_amount)
-            .bind(distributor_voucher.max_amount)
-            .bind(distributor_voucher.label.to_string())
-            .bind(distributor_voucher.description.to_string())
-            .bind(distributor_voucher.days_valid)
-            .bind(distributor_voucher.active)
-            .bind(distributor_voucher.one_use_only)
-            .execute(&mysql.conn).await;
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
         }
-
-        true
     }
 
-    pub async fn get_distributor_vouchers_by_sale(
+    pub async fn update_redeem_when_suspended(
         mysql: &web::Data<MySQL>,
         id: u64,
-    ) -> Option<std::vec::Vec<DistributorVoucher>> {
-        let sql = format!("SELECT distributorvoucher FROM voucher WHERE sale = ?");
-        let mut result = sqlx::query(&sql).bind(&id).fetch(&mysql.conn);
-
-        let mut distributor_vouchers: std::vec::Vec<DistributorVoucher> = std::vec::Vec::new();
+        redeem_when_suspended: bool,
+    ) -> bool {
+        let result = sqlx::query("UPDATE distributor SET redeem_when_suspended=? WHERE ID = ?")
+            .bind(&redeem_when_suspended)
+            .bind(&id)
+            .execute(&mysql.conn)
+            .await;
+        tenancy::forget(id).await;
 
-        while let Some(row) = result.try_next().await.unwrap() {
-            distributor_vouchers.push(
-                get_distributor_voucher(&mysql, row.try_get("distributorvoucher").unwrap())
-                    .await
-                    .unwrap(),
-            );
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(_) => true,
         }
-
-        Some(distributor_vouchers)
     }
 
-    pub async fn get_voucher(mysql: &web::Data<MySQL>, selector: Selector) -> Option<Voucher> {
-        let (where_column, where_value) = match selector {
-            ById(id) => ("ID", id.to_string()),
-            ByHash(hash) => ("hash_code", hash),
-            ByNumberCode(number_code) => ("number_code", number_code),
-            _ => ("", "".to_string()),
+    // The row stays for sales and bookkeeping, the subdomain becomes available again. Personal
+    // data of the distributor, its customers, receivers and users is wiped in one transaction.
+    // Of the distributor only the VAT number is kept, the settlements and invoices have to be
+    // traceable to the company for as long as the bookkeeping is kept; the name is replaced by
+    // a placeholder. The audit log is append-only, so its before/after values still hold the
+    // emails and usernames of the time; a distributor.anonymize entry marks from when the other
+    // tables are anonymized.
+    pub async fn delete_distributor(mysql: &web::Data<MySQL>, id: u64, reason: &str) -> bool {
+        let mut tx = match mysql.conn.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                println!("Error: {}", e);
+                return false;
+            }
         };
 
-        let sql = format!("SELECT ID, sale, receiver_email, receiver_name, distributorvoucher, balance, used, expiration_date, hash_code, number_code, version FROM voucher WHERE {} = ?", where_column);
-        let mut result = sqlx::query(&sql)
-            .bind(&where_value)
-            .fetch_one(&mysql.conn)
+        let result = sqlx::query("UPDATE distributor SET status='Deleted', subdomain=CONCAT('verwijderd-', ID), send_reminders=0, name=CONCAT('Verwijderde zaak ', ID), email='', tel='', address='', bankaccountnr='', description='', description_html='' WHERE ID = ? AND status != 'Deleted'")
+            .bind(&id)
+            .execute(&mut tx)
             .await;
 
         match result {
             Err(e) => {
-                println!("error: {:?}", e);
-                None
+                println!("Error: {}", e);
+                return false;
             }
-            Ok(r) => Some(Voucher {
-                id: r.try_get("ID").unwrap(),
-                sale: data::get_sale(&mysql, data::Selector::ById(r.try_get("sale").unwrap()))
-                    .await
-                    .unwrap(),
-                receiver_email: r.try_get("receiver_email").unwrap(),
-                receiver_name: r.try_get("receiver_name").unwrap(),
-                distributorvoucher: get_distributor_voucher(
-                    &mysql,
-                    r.try_get("distributorvoucher").unwrap(),
-                )
-                .await
-                .unwrap(),
-                balance: r.try_get("balance").unwrap(),
-                used: r.try_get("used").unwrap(),
-                expiration_date: r.try_get("expiration_date").unwrap(),
-                hash_code: r.try_get("hash_code").unwrap(),
-                number_code: r.try_get("number_code").unwrap(),
-                version: r.try_get("version").unwrap(),
-            }),
+            Ok(r) if r.rows_affected() == 0 => return false,
+            Ok(_) => {}
         }
-    }
 
-    pub async fn add_voucher(mysql: &web::Data<MySQL>, voucher: &Voucher) -> u64 {
-        let result = sqlx::query("INSERT INTO voucher (sale, receiver_email, receiver_name, distributorvoucher, balance, used, expiration_date, hash_code, number_code, version) VALUES (?,?,?,?,?,?,?,?,?,?)")
-        .bind(&voucher.sale.id)
-        .bind(&voucher.receiver_email)
-        .bind(&voucher.receiver_name)
-        .bind(&voucher.distributorvoucher.id)
-        .bind(&voucher.balance)
-        .bind(&voucher.used)
-        .bind(&voucher.expiration_date)
-        .bind(&voucher.hash_code)
-        .bind(&voucher.number_code)
-        .bind(&voucher.version)
-        .execute(&mysql.conn).await;
+        let queries = [
+            "DELETE FROM subdomainhistory WHERE distributor = ?",
+            "DELETE FROM distributordomain WHERE distributor = ?",
+            "UPDATE client SET firstname='', lastname='', email='', tel='' WHERE distributor = ?",
+            "UPDATE voucher SET receiver_email='', receiver_name='' WHERE sale IN (SELECT ID FROM sale WHERE distributor = ?)",
+            "DELETE FROM recoverycode WHERE distributoruser IN (SELECT ID FROM distributoruser WHERE distributor = ?)",
+            "DELETE FROM usertoken WHERE distributoruser IN (SELECT ID FROM distributoruser WHERE distributor = ?)",
+            "DELETE FROM adminsession WHERE distributoruser IN (SELECT ID FROM distributoruser WHERE distributor = ?)",
+            "UPDATE loginattempt SET username='', ip='' WHERE distributor = ?",
+            "UPDATE scannerdevice SET is_active=0 WHERE distributor = ?",
+            "UPDATE shoplocation SET is_active=0, name='', address='' WHERE distributor = ?",
+            "UPDATE distributoruser SET active=0, username=CONCAT('verwijderd-', ID), display_name='', email='', password='', totp_secret=NULL, totp_pending_secret=NULL, totp_enabled=0 WHERE distributor = ?",
+        ];
+        for query in queries.iter() {
+            let result = sqlx::query(query).bind(&id).execute(&mut tx).await;
+
+            if let Err(e) = result {
+                println!("Error: {}", e);
+                return false;
+            }
+        }
+
+        let result = sqlx::query("INSERT INTO auditlog (distributor, distributoruser, action, entity, entity_id, before_value, after_value, reason, ip) SELECT ID, NULL, 'distributor.anonymize', 'distributor', ID, '', '', ?, '' FROM distributor WHERE ID = ?")
+            .bind(&reason)
+            .bind(&id)
+            .execute(&mut tx)
+            .await;
+
+        if let Err(e) = result {
+            println!("Error: {}", e);
+            return false;
+        }
+
+        let result = tx.commit().await;
+        tenancy::forget(id).await;
 
         match result {
             Err(e) => {
                 println!("Error: {}", e);
-                0
+                false
             }
-            Ok(r) => r.last_insert_id(),
+            Ok(_) => true,
         }
     }
 
-    pub async fn update_voucher(mysql: &web::Data<MySQL>, voucher: &Voucher) -> bool {
-        let sql = format!("UPDATE voucher SET balance=?, used=? WHERE ID = ?");
+    // Refunds the remaining balance of every usable voucher when a distributor leaves. The
+    // settlement rows are what the distributor owes its customers. Both happen in one
+    // transaction, so a retry after a failure can't settle a voucher twice.
+    pub async fn settle_open_vouchers(mysql: &web::Data<MySQL>, tenant: Tenant) -> bool {
+        let mut tx = match mysql.conn.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                println!("Error: {}", e);
+                return false;
+            }
+        };
 
-        let mut result = sqlx::query(&sql)
-            .bind(&voucher.balance)
-            .bind(&voucher.used)
-            .bind(&voucher.id)
-            .execute(&mysql.conn)
+        let result = sqlx::query("INSERT INTO settlement (distributor, voucher, amount) SELECT distributorvoucher.distributor, voucher.ID, voucher.balance FROM voucher INNER JOIN distributorvoucher ON distributorvoucher.ID=voucher.distributorvoucher WHERE distributorvoucher.distributor = ? AND voucher.status IN ('Active', 'PartiallyUsed') AND voucher.balance > 0 AND voucher.expiration_date > UTC_TIMESTAMP()")
+            .bind(&tenant.distributor_id)
+            .execute(&mut tx)
             .await;
 
-        match result {
+        if let Err(e) = result {
+            println!("Error: {}", e);
+            return false;
+        }
+
+        let result = sqlx::query("UPDATE voucher SET status='Refunded', balance=0, version=version+1 WHERE ID IN (SELECT voucher FROM settlement WHERE distributor = ?) AND status != 'Refunded'")
+            .bind(&tenant.distributor_id)
+            .execute(&mut tx)
+            .await;
+
+        if let Err(e) = result {
+            println!("Error: {}", e);
+            return false;
+        }
+
+        match tx.commit().await {
             Err(e) => {
                 println!("Error: {}", e);
                 false
             }
-            Ok(r) => r.rows_affected() > 0,
+            Ok(_) => true,
         }
     }
 
-    pub async fn get_location(mysql: &web::Data<MySQL>, id: u64) -> Option<Location> {
-        let mut result = sqlx::query("SELECT postalcode, city FROM location WHERE ID = ?")
-            .bind(&id)
-            .fetch_one(&mysql.conn)
+    pub async fn get_settlements(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+    ) -> Option<std::vec::Vec<serde_json::Value>> {
+        let mut result = sqlx::query("SELECT settlement.voucher, settlement.amount, settlement.settled_date, voucher.number_code, client.email FROM settlement INNER JOIN voucher ON voucher.ID=settlement.voucher INNER JOIN sale ON sale.ID=voucher.sale INNER JOIN client ON client.ID=sale.client WHERE settlement.distributor = ? ORDER BY settlement.ID")
+            .bind(&tenant.distributor_id)
+            .fetch_all(&mysql.conn)
             .await;
 
         match result {
@@ -928,19 +2985,30 @@ pub mod data {
                 println!("error: {:?}", e);
                 None
             }
-            Ok(r) => Some(Location {
-                id: id,
-                postalcode: r.try_get("postalcode").unwrap(),
-                city: r.try_get("city").unwrap(),
-            }),
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| {
+                        serde_json::json!({
+                            "voucher": r.try_get::<u64, _>("voucher").unwrap(),
+                            "number_code": r.try_get::<String, _>("number_code").unwrap(),
+                            "amount": r.try_get::<f64, _>("amount").unwrap(),
+                            "buyer_email": r.try_get::<String, _>("email").unwrap(),
+                            "settled_date": r.try_get::<DateTime<Utc>, _>("settled_date").unwrap(),
+                        })
+                    })
+                    .collect(),
+            ),
         }
     }
 
-    pub async fn get_id_of_location(mysql: &web::Data<MySQL>, location: &Location) -> Option<u64> {
-        let mut result = sqlx::query("SELECT ID FROM location WHERE postalcode = ? AND city = ?")
-            .bind(&location.postalcode)
-            .bind(&location.city)
-            .fetch_one(&mysql.conn)
+    // Every sold voucher with its buyer, for the export when a distributor leaves
+    pub async fn get_export_vouchers(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+    ) -> Option<std::vec::Vec<serde_json::Value>> {
+        let mut result = sqlx::query("SELECT voucher.ID, voucher.number_code, voucher.receiver_name, voucher.receiver_email, voucher.balance, voucher.status, voucher.expiration_date, sale.ID AS sale, sale.amount, sale.paid, sale.purchase_date, client.firstname, client.lastname, client.email, client.tel FROM voucher INNER JOIN sale ON sale.ID=voucher.sale INNER JOIN client ON client.ID=sale.client WHERE sale.distributor = ? ORDER BY voucher.ID")
+            .bind(&tenant.distributor_id)
+            .fetch_all(&mysql.conn)
             .await;
 
         match result {
@@ -948,884 +3016,10666 @@ pub mod data {
                 println!("error: {:?}", e);
                 None
             }
-            Ok(r) => Some(r.try_get("ID").unwrap()),
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| {
+                        serde_json::json!({
+                            "id": r.try_get::<u64, _>("ID").unwrap(),
+                            "number_code": r.try_get::<String, _>("number_code").unwrap(),
+                            "receiver_name": r.try_get::<String, _>("receiver_name").unwrap(),
+                            "receiver_email": r.try_get::<String, _>("receiver_email").unwrap(),
+                            "balance": r.try_get::<f64, _>("balance").unwrap(),
+                            "status": r.try_get::<String, _>("status").unwrap(),
+                            "expiration_date": r.try_get::<DateTime<Utc>, _>("expiration_date").unwrap(),
+                            "sale": {
+                                "id": r.try_get::<u64, _>("sale").unwrap(),
+                                "amount": r.try_get::<f64, _>("amount").unwrap(),
+                                "paid": r.try_get::<bool, _>("paid").unwrap(),
+                                "purchase_date": r.try_get::<Option<DateTime<Utc>>, _>("purchase_date").unwrap(),
+                            },
+                            "buyer": {
+                                "firstname": r.try_get::<String, _>("firstname").unwrap(),
+                                "lastname": r.try_get::<String, _>("lastname").unwrap(),
+                                "email": r.try_get::<String, _>("email").unwrap(),
+                                "tel": r.try_get::<String, _>("tel").unwrap(),
+                            },
+                        })
+                    })
+                    .collect(),
+            ),
         }
     }
 
-    pub async fn get_all_orders(
+    pub async fn get_export_redemptions(
         mysql: &web::Data<MySQL>,
-        start: u64,
-        amount: u64,
-        mut filters: AdminOrderFilterParams,
-        distributor_id: u64,
-    ) -> Vec<AdminOrderTableData> {
-        let mut statusses: std::vec::Vec<u16> = std::vec::Vec::new();
-
-        let mut where_str: String = "".to_string();
-        // Needed when filter for date is PREV_HOUR or MOST_RECENT_ACTIVATION
-        let current_date = chrono::offset::Utc::now();
-        let mut cmp_min_date: Option<String> = None;
-        let mut cmp_max_date: Option<String> = None;
+        tenant: Tenant,
+    ) -> Option<std::vec::Vec<Redemption>> {
+        let mut result = sqlx::query("SELECT ID, voucher, client_id, amount, balance_after, redeemed_at, shoplocation, scannerdevice FROM redemption WHERE distributor = ? ORDER BY redeemed_at")
+            .bind(&tenant.distributor_id)
+            .fetch_all(&mysql.conn)
+            .await;
 
-        if filters.min_amount.is_some() {
-            where_str = format!("{}{}", where_str, " (sale.amount >= ?) ");
-        } else {
-            where_str = format!("{}{}", where_str, " (? IS NULL) ");
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| Redemption {
+                        id: r.try_get("ID").unwrap(),
+                        voucher_id: r.try_get("voucher").unwrap(),
+                        client_id: r.try_get("client_id").unwrap(),
+                        amount: r.try_get("amount").unwrap(),
+                        balance_after: r.try_get("balance_after").unwrap(),
+                        redeemed_at: r.try_get("redeemed_at").unwrap(),
+                        shoplocation_id: r.try_get("shoplocation").unwrap(),
+                        scannerdevice_id: r.try_get("scannerdevice").unwrap(),
+                    })
+                    .collect(),
+            ),
         }
-        if filters.max_amount.is_some() {
-            where_str = format!("{}{}", where_str, " AND (sale.amount < ?) ");
-        } else {
-            where_str = format!("{}{}", where_str, " AND (? IS NULL) ");
-        }
-        if filters.min_date.is_some() {
-            where_str = format!("{}{}", where_str, " AND (sale.purchase_date >= ?) ");
+    }
 
-            match filters.min_date.as_deref() {
-                Some("PREV_HOUR") => {
-                    cmp_min_date = Some(
-                        (current_date - chrono::Duration::hours(1))
-                            .format("%Y-%m-%d %H:%M")
-                            .to_string(),
-                    );
-                }
-                Some("MOST_RECENT_ACTIVATION") => {
-                    cmp_min_date = Some(
-                        get_active_distributor_vouchers_by_distributor(mysql, distributor_id)
-                            .await
-                            .unwrap()[0]
-                            .create_date
-                            .unwrap()
-                            .format("%Y-%m-%d %H:%M:%S")
-                            .to_string(),
-                    );
-                }
-                _ => {
-                    cmp_min_date = Some(format!("{} 00:00:00", filters.min_date.unwrap()));
-                }
+    pub async fn is_subdomain_taken(
+        mysql: &web::Data<MySQL>,
+        subdomain: &str,
+        except_distributor: u64,
+    ) -> bool {
+        // Previous subdomains of others stay reserved, they redirect to the renamed shop
+        let mut result = sqlx::query("SELECT (SELECT COUNT(*) FROM distributor WHERE subdomain = ? AND ID != ?) + (SELECT COUNT(*) FROM subdomainhistory WHERE subdomain = ? AND distributor != ?) AS amount")
+        .bind(&subdomain)
+        .bind(&except_distributor)
+        .bind(&subdomain)
+        .bind(&except_distributor)
+        .fetch_one(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                true
             }
-        } else {
-            where_str = format!("{}{}", where_str, " AND (? IS NULL) ");
+            Ok(r) => r.try_get::<i64, _>("amount").unwrap() > 0,
         }
-        if filters.max_date.is_some() {
-            where_str = format!("{}{}", where_str, " AND (sale.purchase_date <= ?) ");
+    }
 
-            match filters.max_date.as_deref() {
-                Some("PREV_HOUR") => {
-                    cmp_max_date = Some(
-                        (current_date + chrono::Duration::seconds(120))
-                            .format("%Y-%m-%d %H:%M")
-                            .to_string(),
-                    );
-                }
-                Some("MOST_RECENT_ACTIVATION") => {
-                    cmp_max_date = Some("3000-01-01 23:59:59".to_string());
-                }
-                _ => {
-                    cmp_max_date = Some(format!("{} 23:59:59", filters.max_date.unwrap()));
-                }
+    // Paid vouchers that can still be used in the shop
+    pub async fn count_open_vouchers(mysql: &web::Data<MySQL>, tenant: Tenant) -> i64 {
+        let mut result = sqlx::query("SELECT COUNT(*) AS amount FROM voucher INNER JOIN distributorvoucher ON distributorvoucher.ID=voucher.distributorvoucher WHERE distributorvoucher.distributor = ? AND voucher.status IN ('Active', 'PartiallyUsed') AND voucher.balance > 0 AND voucher.expiration_date > UTC_TIMESTAMP()")
+            .bind(&tenant.distributor_id)
+            .fetch_one(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                0
             }
-        } else {
-            where_str = format!("{}{}", where_str, " AND (? IS NULL) ");
-        }
-        if filters.search_query.is_some() {
-            where_str = format!(
-                "{}{}",
-                where_str, " AND UPPER(CONCAT(client.firstname, ' ', client.lastname, '$', client.email, '$', client.tel, '$', voucher.number_code, '$', voucher.receiver_name, '$', voucher.receiver_email, '$', DATE_FORMAT(DATE(CONVERT_TZ(sale.purchase_date, '+00:00', '+01:00')), '%d-%m-%Y'), '$', sale.payment_id)) LIKE ? "
-            );
-            filters.search_query = Some(format!(
-                "%{}%",
-                filters.search_query.unwrap().to_uppercase()
-            ));
-        } else {
-            where_str = format!("{}{}", where_str, " AND (? IS NULL) ");
+            Ok(r) => r.try_get("amount").unwrap(),
         }
+    }
 
-        if filters.statusses.is_some() {
-            statusses = filters
-                .statusses
-                .unwrap()
-                .split(",")
-                .map(|x| x.parse::<u16>().unwrap())
-                .collect();
+    // Paid sales per distributor between two dates, for the platform overview
+    pub async fn get_sales_per_distributor(
+        mysql: &web::Data<MySQL>,
+        from: chrono::DateTime<chrono::Utc>,
+        until: chrono::DateTime<chrono::Utc>,
+    ) -> Option<std::vec::Vec<DistributorSales>> {
+        let mut result = sqlx::query("SELECT distributor, COUNT(*) AS sales, CAST(SUM(amount) AS DOUBLE) AS amount FROM sale WHERE paid = 1 AND purchase_date >= ? AND purchase_date < ? GROUP BY distributor")
+            .bind(&from)
+            .bind(&until)
+            .fetch_all(&mysql.conn)
+            .await;
 
-            let mut in_str: String = "".to_string();
-            for i in &statusses {
-                in_str = format!("{}?,", in_str);
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
             }
-            in_str.pop();
-
-            where_str = format!("{} AND sale.paid IN ({}) ", where_str, in_str);
-        } else {
-            where_str = format!("{}{}", where_str, " AND 1=1 ");
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| DistributorSales {
+                        distributor_id: r.try_get("distributor").unwrap(),
+                        sales: r.try_get("sales").unwrap(),
+                        amount: r.try_get("amount").unwrap(),
+                    })
+                    .collect(),
+            ),
         }
+    }
 
-        if where_str.chars().count() > 0 {
-            where_str = format!("{}{}", " WHERE ", where_str);
+    pub async fn update_platform_admin_last_login(mysql: &web::Data<MySQL>, id: u64) -> bool {
+        let result =
+            sqlx::query("UPDATE platformadmin SET last_login=UTC_TIMESTAMP() WHERE ID = ?")
+                .bind(&id)
+                .execute(&mysql.conn)
+                .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
         }
+    }
 
-        let sql = format!("SELECT voucher.ID, sale.amount, sale.paid, sale.purchase_date, CONCAT(client.firstname, ' ', client.lastname) as client_name FROM voucher INNER JOIN sale ON sale.ID=voucher.sale INNER JOIN client ON client.ID=sale.client {} ORDER BY sale.purchase_date DESC, voucher.id DESC LIMIT ? OFFSET ?", where_str);
+    pub async fn add_platform_audit_entry(
+        mysql: &web::Data<MySQL>,
+        entry: &PlatformAuditEntry,
+    ) -> u64 {
+        let result = sqlx::query("INSERT INTO platformauditlog (platformadmin, distributor, action, entity, entity_id, before_value, after_value, reason, ip) VALUES (?,?,?,?,?,?,?,?,?)")
+        .bind(&entry.platformadmin_id)
+        .bind(&entry.distributor_id)
+        .bind(&entry.action)
+        .bind(&entry.entity)
+        .bind(&entry.entity_id)
+        .bind(&entry.before)
+        .bind(&entry.after)
+        .bind(&entry.reason)
+        .bind(&entry.ip)
+        .execute(&mysql.conn).await;
 
-        let mut query = sqlx::query(&sql)
-            .bind(filters.min_amount)
-            .bind(filters.max_amount)
-            .bind(cmp_min_date)
-            .bind(cmp_max_date)
-            .bind(filters.search_query);
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
 
-        for status in statusses {
-            query = query.bind(status);
+    // Newest first, optionally only the entries about one distributor
+    pub async fn get_platform_audit_entries(
+        mysql: &web::Data<MySQL>,
+        distributor: Option<u64>,
+        amount: u64,
+    ) -> Option<std::vec::Vec<PlatformAuditEntry>> {
+        let mut result = sqlx::query("SELECT ID, platformadmin, distributor, action, entity, entity_id, before_value, after_value, reason, ip, create_date FROM platformauditlog WHERE (? IS NULL OR distributor = ?) ORDER BY create_date DESC, ID DESC LIMIT ?")
+        .bind(&distributor)
+        .bind(&distributor)
+        .bind(&amount)
+        .fetch_all(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| PlatformAuditEntry {
+                        id: r.try_get("ID").unwrap(),
+                        platformadmin_id: r.try_get("platformadmin").unwrap(),
+                        distributor_id: r.try_get("distributor").unwrap(),
+                        action: r.try_get("action").unwrap(),
+                        entity: r.try_get("entity").unwrap(),
+                        entity_id: r.try_get("entity_id").unwrap(),
+                        before: r.try_get("before_value").unwrap(),
+                        after: r.try_get("after_value").unwrap(),
+                        reason: r.try_get("reason").unwrap(),
+                        ip: r.try_get("ip").unwrap(),
+                        create_date: r.try_get("create_date").unwrap(),
+                    })
+                    .collect(),
+            ),
         }
+    }
 
-        let mut result = query.bind(amount).bind(start).fetch_all(&mysql.conn).await;
+    pub async fn get_platform_admin_names(mysql: &web::Data<MySQL>) -> HashMap<u64, String> {
+        let mut result = sqlx::query("SELECT ID, display_name FROM platformadmin")
+            .fetch_all(&mysql.conn)
+            .await;
 
-        let mut order_data: std::vec::Vec<AdminOrderTableData> = std::vec::Vec::new();
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                HashMap::new()
+            }
+            Ok(rows) => rows
+                .iter()
+                .map(|r| (r.try_get("ID").unwrap(), r.try_get("display_name").unwrap()))
+                .collect(),
+        }
+    }
 
-        for r in result.unwrap().iter() {
-            let date: chrono::DateTime<chrono::Utc> = r.try_get("purchase_date").unwrap();
+    pub async fn get_descriptions_without_html(
+        mysql: &web::Data<MySQL>,
+    ) -> Option<std::vec::Vec<(u64, String)>> {
+        let mut result = sqlx::query("SELECT ID, description FROM distributor WHERE description != '' AND description_html = ''")
+            .fetch_all(&mysql.conn)
+            .await;
 
-            order_data.push(AdminOrderTableData {
-                id: r.try_get("ID").unwrap(),
-                purchase_amount: r.try_get("amount").unwrap(),
-                payment_status: r.try_get("paid").unwrap(),
-                purchase_date: date
-                    .with_timezone(&chrono::Local)
-                    .format("%d-%m-%Y %H:%M")
-                    .to_string(),
-                client_name: r.try_get("client_name").unwrap(),
-            });
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| (r.try_get("ID").unwrap(), r.try_get("description").unwrap()))
+                    .collect(),
+            ),
         }
+    }
 
-        order_data
+    pub async fn update_description_html(mysql: &web::Data<MySQL>, id: u64, html: &str) -> bool {
+        let result = sqlx::query("UPDATE distributor SET description_html=? WHERE ID = ?")
+            .bind(&html)
+            .bind(&id)
+            .execute(&mysql.conn)
+            .await;
+        tenancy::forget(id).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
     }
-}
 
-pub mod mollie {
-    use crate::*;
-    use serde::{Deserialize, Serialize};
-    use serde_json::json;
-    use serde_json::value;
+    // Bank account numbers that aren't encrypted with the current key, the prefix holds its id
+    pub async fn get_bankaccountnrs_to_encrypt(
+        mysql: &web::Data<MySQL>,
+        current_prefix: &str,
+    ) -> Option<std::vec::Vec<(u64, String)>> {
+        let mut result = sqlx::query(
+            "SELECT ID, bankaccountnr FROM distributor WHERE bankaccountnr != '' AND LEFT(bankaccountnr, CHAR_LENGTH(?)) != ?",
+        )
+        .bind(&current_prefix)
+        .bind(&current_prefix)
+        .fetch_all(&mysql.conn)
+        .await;
 
-    #[derive(Clone)]
-    pub struct Mollie {
-        pub api_key: String,
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| {
+                        (
+                            r.try_get("ID").unwrap(),
+                            r.try_get("bankaccountnr").unwrap(),
+                        )
+                    })
+                    .collect(),
+            ),
+        }
     }
 
-    #[derive(Serialize, Deserialize)]
-    #[serde(rename_all = "camelCase")]
-    pub struct Amount {
-        currency: String,
-        value: String,
+    pub async fn update_bankaccountnr(mysql: &web::Data<MySQL>, id: u64, value: &str) -> bool {
+        let result = sqlx::query("UPDATE distributor SET bankaccountnr=? WHERE ID = ?")
+            .bind(&value)
+            .bind(&id)
+            .execute(&mysql.conn)
+            .await;
+        tenancy::forget(id).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
     }
 
-    #[derive(Serialize, Deserialize)]
-    #[serde(rename_all = "camelCase")]
-    pub struct Link {
-        pub href: String,
-        r#type: String,
+    pub async fn get_faq_entries(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+    ) -> Option<std::vec::Vec<FaqEntry>> {
+        let mut result = sqlx::query("SELECT ID, distributor, question, answer, answer_html, position FROM distributorfaq WHERE distributor = ? ORDER BY position, ID")
+            .bind(&tenant.distributor_id)
+            .fetch_all(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| FaqEntry {
+                        id: r.try_get("ID").unwrap(),
+                        distributor_id: r.try_get("distributor").unwrap(),
+                        question: r.try_get("question").unwrap(),
+                        answer: r.try_get("answer").unwrap(),
+                        answer_html: r.try_get("answer_html").unwrap(),
+                        position: r.try_get("position").unwrap(),
+                    })
+                    .collect(),
+            ),
+        }
     }
 
-    #[derive(Serialize, Deserialize)]
-    #[serde(rename_all = "camelCase")]
-    pub struct Payment {
-        #[serde(skip_serializing)]
-        id: String,
-        description: String,
-        amount: Amount,
-        redirectUrl: String,
-        webhookUrl: String,
-        #[serde(rename = "_links")]
-        pub links: HashMap<String, Link>,
-        #[serde(skip_serializing)]
-        pub status: String,
+    pub async fn get_faq_entry(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        id: u64,
+    ) -> Option<FaqEntry> {
+        get_faq_entries(mysql, tenant)
+            .await?
+            .into_iter()
+            .find(|e| e.id == id)
     }
 
-    impl Mollie {
+    pub async fn add_faq_entry(mysql: &web::Data<MySQL>, tenant: Tenant, entry: &FaqEntry) -> u64 {
+        let result = sqlx::query("INSERT INTO distributorfaq (distributor, question, answer, answer_html, position) VALUES (?,?,?,?,?)")
+            .bind(&tenant.distributor_id)
+            .bind(&entry.question)
+            .bind(&entry.answer)
+            .bind(&entry.answer_html)
+            .bind(&entry.position)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+
+    pub async fn update_faq_entry(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        entry: &FaqEntry,
+    ) -> bool {
+        let result = sqlx::query("UPDATE distributorfaq SET question=?, answer=?, answer_html=?, position=? WHERE ID = ? AND distributor = ?")
+            .bind(&entry.question)
+            .bind(&entry.answer)
+            .bind(&entry.answer_html)
+            .bind(&entry.position)
+            .bind(&entry.id)
+            .bind(&tenant.distributor_id)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(_) => true,
+        }
+    }
+
+    pub async fn delete_faq_entry(mysql: &web::Data<MySQL>, tenant: Tenant, id: u64) -> bool {
+        let result = sqlx::query("DELETE FROM distributorfaq WHERE ID = ? AND distributor = ?")
+            .bind(&id)
+            .bind(&tenant.distributor_id)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn get_shop_pages(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+    ) -> Option<std::vec::Vec<ShopPage>> {
+        let mut result = sqlx::query("SELECT ID, distributor, kind, slug, title, body, body_html, is_published, update_date FROM distributorpage WHERE distributor = ? ORDER BY kind = 'Terms' DESC, title")
+            .bind(&tenant.distributor_id)
+            .fetch_all(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| ShopPage {
+                        id: r.try_get("ID").unwrap(),
+                        distributor_id: r.try_get("distributor").unwrap(),
+                        kind: PageKind::from_str(r.try_get("kind").unwrap())
+                            .unwrap_or(PageKind::Custom),
+                        slug: r.try_get("slug").unwrap(),
+                        title: r.try_get("title").unwrap(),
+                        body: r.try_get("body").unwrap(),
+                        body_html: r.try_get("body_html").unwrap(),
+                        published: r.try_get("is_published").unwrap(),
+                        update_date: r.try_get("update_date").unwrap(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    pub async fn get_shop_page(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        id: u64,
+    ) -> Option<ShopPage> {
+        get_shop_pages(mysql, tenant)
+            .await?
+            .into_iter()
+            .find(|p| p.id == id)
+    }
+
+    // Only published pages, for the storefront
+    pub async fn get_published_shop_page(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        kind: PageKind,
+        slug: &str,
+    ) -> Option<ShopPage> {
+        get_shop_pages(mysql, tenant)
+            .await?
+            .into_iter()
+            .find(|p| p.published && p.kind == kind && (kind == PageKind::Terms || p.slug == slug))
+    }
+
+    pub async fn is_page_slug_taken(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        slug: &str,
+        except_page: u64,
+    ) -> bool {
+        let mut result = sqlx::query("SELECT COUNT(*) AS amount FROM distributorpage WHERE distributor = ? AND slug = ? AND ID != ?")
+            .bind(&tenant.distributor_id)
+            .bind(&slug)
+            .bind(&except_page)
+            .fetch_one(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                true
+            }
+            Ok(r) => r.try_get::<i64, _>("amount").unwrap() > 0,
+        }
+    }
+
+    pub async fn add_shop_page(mysql: &web::Data<MySQL>, tenant: Tenant, page: &ShopPage) -> u64 {
+        let result = sqlx::query("INSERT INTO distributorpage (distributor, kind, slug, title, body, body_html, is_published) VALUES (?,?,?,?,?,?,?)")
+            .bind(&tenant.distributor_id)
+            .bind(&page.kind.to_string())
+            .bind(&page.slug)
+            .bind(&page.title)
+            .bind(&page.body)
+            .bind(&page.body_html)
+            .bind(&page.published)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+
+    pub async fn update_shop_page(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        page: &ShopPage,
+    ) -> bool {
+        let result = sqlx::query("UPDATE distributorpage SET slug=?, title=?, body=?, body_html=?, is_published=?, update_date=UTC_TIMESTAMP() WHERE ID = ? AND distributor = ?")
+            .bind(&page.slug)
+            .bind(&page.title)
+            .bind(&page.body)
+            .bind(&page.body_html)
+            .bind(&page.published)
+            .bind(&page.id)
+            .bind(&tenant.distributor_id)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn delete_shop_page(mysql: &web::Data<MySQL>, tenant: Tenant, id: u64) -> bool {
+        let result = sqlx::query("DELETE FROM distributorpage WHERE ID = ? AND distributor = ?")
+            .bind(&id)
+            .bind(&tenant.distributor_id)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn get_distributor_domains(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+    ) -> Option<std::vec::Vec<DistributorDomain>> {
+        let mut result = sqlx::query("SELECT ID, distributor, domain, verification_token, verified_date, is_primary FROM distributordomain WHERE distributor = ? ORDER BY domain")
+            .bind(&tenant.distributor_id)
+            .fetch_all(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| DistributorDomain {
+                        id: r.try_get("ID").unwrap(),
+                        distributor_id: r.try_get("distributor").unwrap(),
+                        domain: r.try_get("domain").unwrap(),
+                        verification_token: r.try_get("verification_token").unwrap(),
+                        verified: r
+                            .try_get::<Option<chrono::DateTime<chrono::Utc>>, _>("verified_date")
+                            .unwrap()
+                            .is_some(),
+                        primary: r.try_get("is_primary").unwrap(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    pub async fn get_distributor_domain(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        id: u64,
+    ) -> Option<DistributorDomain> {
+        get_distributor_domains(mysql, tenant)
+            .await?
+            .into_iter()
+            .find(|d| d.id == id)
+    }
+
+    // Verified by any distributor, or already added by this one. An unverified claim of
+    // another distributor doesn't count, whoever verifies first gets the domain.
+    pub async fn is_domain_taken(mysql: &web::Data<MySQL>, tenant: Tenant, domain: &str) -> bool {
+        let mut result = sqlx::query("SELECT COUNT(*) AS amount FROM distributordomain WHERE domain = ? AND (verified_date IS NOT NULL OR distributor = ?)")
+                .bind(&domain)
+                .bind(&tenant.distributor_id)
+                .fetch_one(&mysql.conn)
+                .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                true
+            }
+            Ok(r) => r.try_get::<i64, _>("amount").unwrap() > 0,
+        }
+    }
+
+    pub async fn add_distributor_domain(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        domain: &str,
+        verification_token: &str,
+    ) -> u64 {
+        let result = sqlx::query(
+            "INSERT INTO distributordomain (distributor, domain, verification_token) VALUES (?,?,?)",
+        )
+        .bind(&tenant.distributor_id)
+        .bind(&domain)
+        .bind(&verification_token)
+        .execute(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+
+    // Fails when another distributor verified the domain first, otherwise the unverified
+    // claims of other distributors on the same domain are removed
+    pub async fn verify_distributor_domain(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        id: u64,
+        domain: &str,
+    ) -> bool {
+        let mut tx = match mysql.conn.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                println!("Error: {}", e);
+                return false;
+            }
+        };
+
+        let result = sqlx::query("UPDATE distributordomain SET verified_date=UTC_TIMESTAMP() WHERE ID = ? AND distributor = ? AND domain = ?")
+            .bind(&id)
+            .bind(&tenant.distributor_id)
+            .bind(&domain)
+            .execute(&mut tx)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                return false;
+            }
+            Ok(r) if r.rows_affected() == 0 => return false,
+            Ok(_) => {}
+        }
+
+        let result = sqlx::query("DELETE FROM distributordomain WHERE domain = ? AND distributor != ? AND verified_date IS NULL")
+            .bind(&domain)
+            .bind(&tenant.distributor_id)
+            .execute(&mut tx)
+            .await;
+
+        if let Err(e) = result {
+            println!("Error: {}", e);
+            return false;
+        }
+
+        let result = tx.commit().await;
+        tenancy::forget(tenant.distributor_id).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(_) => true,
+        }
+    }
+
+    // Only verified domains can become primary, the others of the distributor lose the flag
+    pub async fn set_primary_distributor_domain(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        id: Option<u64>,
+    ) -> bool {
+        let result = sqlx::query("UPDATE distributordomain SET is_primary = (ID = ? AND verified_date IS NOT NULL) WHERE distributor = ?")
+            .bind(&id.unwrap_or(0))
+            .bind(&tenant.distributor_id)
+            .execute(&mysql.conn)
+            .await;
+        tenancy::forget(tenant.distributor_id).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(_) => true,
+        }
+    }
+
+    pub async fn delete_distributor_domain(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        id: u64,
+    ) -> bool {
+        let result = sqlx::query("DELETE FROM distributordomain WHERE ID = ? AND distributor = ?")
+            .bind(&id)
+            .bind(&tenant.distributor_id)
+            .execute(&mysql.conn)
+            .await;
+        tenancy::forget(tenant.distributor_id).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn update_distributor_reminders(
+        mysql: &web::Data<MySQL>,
+        distributor: &Distributor,
+    ) -> bool {
+        let result =
+            sqlx::query("UPDATE distributor SET reminder_days=?, send_reminders=? WHERE ID = ?")
+                .bind(&distributor.reminder_days)
+                .bind(&distributor.send_reminders)
+                .bind(&distributor.id)
+                .execute(&mysql.conn)
+                .await;
+        tenancy::forget(distributor.id).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn get_distributors_with_reminders(
+        mysql: &web::Data<MySQL>,
+    ) -> Option<std::vec::Vec<Distributor>> {
+        let result = sqlx::query(
+            "SELECT ID FROM distributor WHERE send_reminders = 1 AND status = 'Active'",
+        )
+        .fetch_all(&mysql.conn)
+        .await;
+
+        let rows = match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                return None;
+            }
+            Ok(rows) => rows,
+        };
+
+        let mut distributors: std::vec::Vec<Distributor> = std::vec::Vec::new();
+
+        for row in rows {
+            let id: u64 = row.try_get("ID").unwrap();
+            match get_distributor(&mysql, id).await {
+                Some(distributor) => distributors.push(distributor),
+                None => println!("error: distributor {} could not be loaded", id),
+            }
+        }
+
+        Some(distributors)
+    }
+
+    pub async fn add_client(mysql: &web::Data<MySQL>, tenant: Tenant, client: &Client) -> u64 {
+        let result = sqlx::query(
+            "INSERT INTO client (distributor, firstname, lastname, email, tel) VALUES (?,?,?,?,?)",
+        )
+        .bind(&tenant.distributor_id)
+        .bind(&client.firstname)
+        .bind(&client.lastname)
+        .bind(&client.email)
+        .bind(&client.tel)
+        .execute(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+
+    pub async fn get_client(mysql: &web::Data<MySQL>, tenant: Tenant, id: u64) -> Option<Client> {
+        let mut result = sqlx::query("SELECT firstname, lastname, email, tel, saved_account FROM client WHERE ID = ? AND distributor = ?")
+        .bind(&id)
+        .bind(&tenant.distributor_id)
+        .fetch_one(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(Client {
+                id: id,
+                firstname: r.try_get("firstname").unwrap(),
+                lastname: r.try_get("lastname").unwrap(),
+                email: r.try_get("email").unwrap(),
+                tel: r.try_get("tel").unwrap(),
+                saved_account: r.try_get("saved_account").unwrap(),
+            }),
+        }
+    }
+
+    pub async fn add_sale(mysql: &web::Data<MySQL>, tenant: Tenant, sale: &Sale) -> u64 {
+        let result = sqlx::query(
+            "INSERT INTO sale (distributor, client, amount, payment_id) VALUES (?,?,?,?)",
+        )
+        .bind(&tenant.distributor_id)
+        .bind(&sale.client.id)
+        .bind(&sale.amount)
+        .bind(&sale.payment_id)
+        .execute(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+
+    pub async fn get_sale(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        selector: Selector,
+    ) -> Option<Sale> {
+        let (where_column, where_value) = match selector {
+            ById(id) => ("ID", id.to_string()),
+            ByPaymentId(payment_id) => ("payment_id", payment_id),
+            _ => ("", "".to_string()),
+        };
+
+        let sql = format!("SELECT ID, client, amount, payment_id, paid, purchase_date FROM sale WHERE {} = ? AND distributor = ?", where_column);
+        let mut result = sqlx::query(&sql)
+            .bind(&where_value)
+            .bind(&tenant.distributor_id)
+            .fetch_one(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(Sale {
+                id: r.try_get("ID").unwrap(),
+                client: get_client(&mysql, tenant, r.try_get("client").unwrap())
+                    .await
+                    .unwrap(),
+                amount: r.try_get("amount").unwrap(),
+                payment_id: r.try_get("payment_id").unwrap(),
+                paid: r.try_get("paid").unwrap(),
+                purchase_date: r.try_get("purchase_date").unwrap(),
+            }),
+        }
+    }
+
+    pub async fn update_sale(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        sale: &Sale,
+        selector: Selector,
+    ) -> bool {
+        let (where_column, where_value) = match selector {
+            ById(id) => ("ID", id.to_string()),
+            ByPaymentId(payment_id) => ("payment_id", payment_id),
+            _ => ("", "".to_string()),
+        };
+
+        let sql = format!(
+            "UPDATE sale SET client=?, amount=?, payment_id=?, paid=? WHERE {} = ? AND distributor = ?",
+            where_column
+        );
+
+        let mut result = sqlx::query(&sql)
+            .bind(&sale.client.id)
+            .bind(&sale.amount)
+            .bind(&sale.payment_id)
+            .bind(&sale.paid)
+            .bind(&where_value)
+            .bind(&tenant.distributor_id)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn get_distributor_voucher(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        id: u64,
+    ) -> Option<DistributorVoucher> {
+        let mut result = sqlx::query("SELECT distributor, voucher_type, amount, min_amount, max_amount, label, description, days_valid, active, one_use_only, create_date FROM distributorvoucher WHERE ID = ? AND distributor = ?")
+        .bind(&id)
+        .bind(&tenant.distributor_id)
+        .fetch_one(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(DistributorVoucher {
+                id: id,
+                distributor: get_distributor(&mysql, r.try_get("distributor").unwrap())
+                    .await
+                    .unwrap(),
+                voucher_type: VoucherType::from_str(r.try_get("voucher_type").unwrap()).unwrap(),
+                amount: r.try_get("amount").unwrap(),
+                min_amount: r.try_get("min_amount").unwrap(),
+                max_amount: r.try_get("max_amount").unwrap(),
+                label: match r.try_get("label").unwrap() {
+                    None => "".to_string(),
+                    Some(v) => v,
+                },
+                description: match r.try_get("description").unwrap() {
+                    None => "".to_string(),
+                    Some(v) => v,
+                },
+                days_valid: r.try_get("days_valid").unwrap(),
+                active: r.try_get("active").unwrap(),
+                one_use_only: r.try_get("one_use_only").unwrap(),
+                create_date: r.try_get("create_date").unwrap(),
+            }),
+        }
+    }
+
+    pub async fn get_distributor_vouchers_by_distributor(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+    ) -> Option<std::vec::Vec<DistributorVoucher>> {
+        let mut result = sqlx::query(
+            "SELECT ID FROM distributorvoucher WHERE distributor = ? AND most_recent_of_Type=1",
+        )
+        .bind(&tenant.distributor_id)
+        .fetch(&mysql.conn);
+
+        let mut distributor_vouchers: std::vec::Vec<DistributorVoucher> = std::vec::Vec::new();
+
+        while let Some(row) = result.try_next().await.unwrap() {
+            distributor_vouchers.push(
+                get_distributor_voucher(&mysql, tenant, row.try_get("ID").unwrap())
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        Some(distributor_vouchers)
+    }
+    pub async fn get_active_distributor_vouchers_by_distributor(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+    ) -> Option<std::vec::Vec<DistributorVoucher>> {
+        let mut result =
+            sqlx::query("SELECT ID FROM distributorvoucher WHERE distributor = ? AND active=1")
+                .bind(&tenant.distributor_id)
+                .fetch(&mysql.conn);
+
+        let mut distributor_vouchers: std::vec::Vec<DistributorVoucher> = std::vec::Vec::new();
+
+        while let Some(row) = result.try_next().await.unwrap() {
+            distributor_vouchers.push(
+                get_distributor_voucher(&mysql, tenant, row.try_get("ID").unwrap())
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        Some(distributor_vouchers)
+    }
+    pub async fn add_active_distributor_vouchers(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        distributor_vouchers: std::vec::Vec<DistributorVoucher>,
+    ) -> bool {
+        if distributor_vouchers.len() == 0 {
+            return false;
+        }
+        if distributor_vouchers
+            .iter()
+            .any(|v| v.distributor.id != tenant.distributor_id)
+        {
+            println!(
+                "error: distributor vouchers do not belong to distributor {}",
+                tenant.distributor_id
+            );
+            return false;
+        }
+
+        let mut result = sqlx::query("UPDATE distributorvoucher SET most_recent_of_type=0 WHERE distributor=? AND upper(voucher_type)=upper(?)")
+        .bind(tenant.distributor_id)
+        .bind(distributor_vouchers[0].voucher_type.to_string())
+        .execute(&mysql.conn).await;
+
+        let mut result = sqlx::query("UPDATE distributorvoucher SET active=0 WHERE distributor=?")
+            .bind(tenant.distributor_id)
+            .execute(&mysql.conn)
+            .await;
+
+        for distributor_voucher in distributor_vouchers {
+            let mut result = sqlx::query("INSERT INTO distributorvoucher (distributor, voucher_type, amount, min_amount, max_amount, label, description, days_valid, active, one_use_only, most_recent_of_type) VALUES (?,?,?,?,?,?,?,?,?,?,1)")
+            .bind(tenant.distributor_id)
+            .bind(distributor_voucher.voucher_type.to_string())
+            .bind(distributor_voucher.amount)
+            .bind(distributor_voucher.min_amount)
+            .bind(distributor_voucher.max_amount)
+            .bind(distributor_voucher.label.to_string())
+            .bind(distributor_voucher.description.to_string())
+            .bind(distributor_voucher.days_valid)
+            .bind(distributor_voucher.active)
+            .bind(distributor_voucher.one_use_only)
+            .execute(&mysql.conn).await;
+        }
+
+        true
+    }
+
+    pub async fn get_distributor_vouchers_by_sale(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        id: u64,
+    ) -> Option<std::vec::Vec<DistributorVoucher>> {
+        let sql = format!("SELECT voucher.distributorvoucher FROM voucher INNER JOIN sale ON sale.ID=voucher.sale WHERE voucher.sale = ? AND sale.distributor = ?");
+        let mut result = sqlx::query(&sql)
+            .bind(&id)
+            .bind(&tenant.distributor_id)
+            .fetch(&mysql.conn);
+
+        let mut distributor_vouchers: std::vec::Vec<DistributorVoucher> = std::vec::Vec::new();
+
+        while let Some(row) = result.try_next().await.unwrap() {
+            distributor_vouchers.push(
+                get_distributor_voucher(&mysql, tenant, row.try_get("distributorvoucher").unwrap())
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        Some(distributor_vouchers)
+    }
+
+    pub async fn get_voucher(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        selector: Selector,
+    ) -> Option<Voucher> {
+        let (where_column, where_value) = match selector {
+            ById(id) => ("ID", id.to_string()),
+            ByHash(hash) => ("hash_code", hash),
+            ByNumberCode(number_code) => ("number_code", number_code),
+            _ => ("", "".to_string()),
+        };
+
+        let sql = format!("SELECT voucher.ID, voucher.sale, voucher.receiver_email, voucher.receiver_name, voucher.distributorvoucher, voucher.balance, voucher.used, voucher.expiration_date, voucher.hash_code, voucher.number_code, voucher.version, voucher.status FROM voucher INNER JOIN distributorvoucher ON distributorvoucher.ID=voucher.distributorvoucher WHERE voucher.{} = ? AND distributorvoucher.distributor = ?", where_column);
+        let mut result = sqlx::query(&sql)
+            .bind(&where_value)
+            .bind(&tenant.distributor_id)
+            .fetch_one(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => {
+                // An unknown status is an error, the voucher isn't guessed into a state
+                let status = match VoucherStatus::from_str(r.try_get("status").unwrap()) {
+                    Ok(status) => status,
+                    Err(_) => {
+                        println!(
+                            "error: voucher {} has an unknown status",
+                            r.try_get::<u64, _>("ID").unwrap()
+                        );
+                        return None;
+                    }
+                };
+
+                Some(Voucher {
+                    id: r.try_get("ID").unwrap(),
+                    sale: get_sale(&mysql, tenant, ById(r.try_get("sale").unwrap()))
+                        .await
+                        .unwrap(),
+                    receiver_email: r.try_get("receiver_email").unwrap(),
+                    receiver_name: r.try_get("receiver_name").unwrap(),
+                    distributorvoucher: get_distributor_voucher(
+                        &mysql,
+                        tenant,
+                        r.try_get("distributorvoucher").unwrap(),
+                    )
+                    .await
+                    .unwrap(),
+                    balance: r.try_get("balance").unwrap(),
+                    used: r.try_get("used").unwrap(),
+                    expiration_date: r.try_get("expiration_date").unwrap(),
+                    hash_code: r.try_get("hash_code").unwrap(),
+                    number_code: r.try_get("number_code").unwrap(),
+                    version: r.try_get("version").unwrap(),
+                    status: status,
+                })
+            }
+        }
+    }
+
+    pub async fn add_voucher(mysql: &web::Data<MySQL>, tenant: Tenant, voucher: &Voucher) -> u64 {
+        if voucher.distributorvoucher.distributor.id != tenant.distributor_id {
+            println!(
+                "error: voucher does not belong to distributor {}",
+                tenant.distributor_id
+            );
+            return 0;
+        }
+
+        let result = sqlx::query("INSERT INTO voucher (sale, receiver_email, receiver_name, distributorvoucher, balance, used, expiration_date, hash_code, number_code, version, status) VALUES (?,?,?,?,?,?,?,?,?,?,?)")
+        .bind(&voucher.sale.id)
+        .bind(&voucher.receiver_email)
+        .bind(&voucher.receiver_name)
+        .bind(&voucher.distributorvoucher.id)
+        .bind(&voucher.balance)
+        .bind(&voucher.used)
+        .bind(&voucher.expiration_date)
+        .bind(&voucher.hash_code)
+        .bind(&voucher.number_code)
+        .bind(&voucher.version)
+        .bind(&voucher.status.to_string())
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+
+    // Only succeeds if the voucher wasn't modified since it was loaded (optimistic locking on version)
+    pub async fn update_voucher(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        voucher: &Voucher,
+    ) -> bool {
+        let sql = format!("UPDATE voucher INNER JOIN distributorvoucher ON distributorvoucher.ID=voucher.distributorvoucher SET voucher.receiver_email=?, voucher.receiver_name=?, voucher.balance=?, voucher.used=?, voucher.expiration_date=?, voucher.status=?, voucher.version=voucher.version+1 WHERE voucher.ID = ? AND voucher.version = ? AND distributorvoucher.distributor = ?");
+
+        let mut result = sqlx::query(&sql)
+            .bind(&voucher.receiver_email)
+            .bind(&voucher.receiver_name)
+            .bind(&voucher.balance)
+            .bind(&voucher.used)
+            .bind(&voucher.expiration_date)
+            .bind(&voucher.derive_status().to_string())
+            .bind(&voucher.id)
+            .bind(&voucher.version)
+            .bind(&tenant.distributor_id)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn update_voucher_status_by_sale(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        sale_id: u64,
+        from: VoucherStatus,
+        to: VoucherStatus,
+    ) -> bool {
+        let result = sqlx::query("UPDATE voucher INNER JOIN sale ON sale.ID=voucher.sale SET voucher.status=?, voucher.version=voucher.version+1 WHERE voucher.sale = ? AND voucher.status = ? AND sale.distributor = ?")
+        .bind(&to.to_string())
+        .bind(&sale_id)
+        .bind(&from.to_string())
+        .bind(&tenant.distributor_id)
+        .execute(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    // Returns the amount of vouchers that expired since the last run.
+    // Maintenance job over all distributors, not reachable from any request.
+    pub async fn expire_vouchers(mysql: &web::Data<MySQL>) -> u64 {
+        let result = sqlx::query("UPDATE voucher SET status='Expired', version=version+1 WHERE expiration_date < UTC_TIMESTAMP() AND status IN ('Active', 'PartiallyUsed')")
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.rows_affected(),
+        }
+    }
+
+    pub async fn get_snapshot_vouchers_by_distributor(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+    ) -> Option<std::vec::Vec<sync::SnapshotVoucher>> {
+        let mut result = sqlx::query("SELECT voucher.ID, voucher.hash_code, voucher.number_code, voucher.receiver_name, voucher.balance, voucher.used, voucher.expiration_date, voucher.version, distributorvoucher.one_use_only FROM voucher INNER JOIN sale ON sale.ID=voucher.sale INNER JOIN distributorvoucher ON distributorvoucher.ID=voucher.distributorvoucher WHERE distributorvoucher.distributor = ? AND voucher.status IN ('Active', 'PartiallyUsed') AND voucher.expiration_date > UTC_TIMESTAMP()")
+        .bind(&tenant.distributor_id)
+        .fetch_all(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| sync::SnapshotVoucher {
+                        id: r.try_get("ID").unwrap(),
+                        hash_code: r.try_get("hash_code").unwrap(),
+                        number_code: r.try_get("number_code").unwrap(),
+                        receiver_name: r.try_get("receiver_name").unwrap(),
+                        balance: r.try_get("balance").unwrap(),
+                        used: r.try_get("used").unwrap(),
+                        one_use_only: r.try_get("one_use_only").unwrap(),
+                        expiration_date: r.try_get("expiration_date").unwrap(),
+                        version: r.try_get("version").unwrap(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    pub enum RedeemOutcome {
+        Applied,
+        // The client_id was already used, the first upload won
+        Duplicate,
+        // The voucher changed since it was read, reload and try again
+        Changed,
+        Failed,
+    }
+
+    // Records the redemption, debits the voucher and writes the audit entry in one
+    // transaction. The client_id of an offline redemption can only be claimed once, so a
+    // redemption uploaded twice at once can't be applied twice.
+    pub async fn redeem_voucher(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        voucher: &Voucher,
+        redemption: &Redemption,
+        entry: &AuditEntry,
+    ) -> RedeemOutcome {
+        let mut tx = match mysql.conn.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                println!("Error: {}", e);
+                return RedeemOutcome::Failed;
+            }
+        };
+
+        let result = sqlx::query("INSERT INTO redemption (distributor, voucher, client_id, amount, balance_after, redeemed_at, shoplocation, scannerdevice) VALUES (?,?,?,?,?,?,?,?)")
+        .bind(&tenant.distributor_id)
+        .bind(&redemption.voucher_id)
+        .bind(&redemption.client_id)
+        .bind(&redemption.amount)
+        .bind(&redemption.balance_after)
+        .bind(&redemption.redeemed_at)
+        .bind(&redemption.shoplocation_id)
+        .bind(&redemption.scannerdevice_id)
+        .execute(&mut tx).await;
+
+        match result {
+            Err(sqlx::Error::Database(e))
+                if e.try_downcast_ref::<sqlx::mysql::MySqlDatabaseError>()
+                    .map(|e| e.number() == 1062)
+                    .unwrap_or(false) =>
+            {
+                return RedeemOutcome::Duplicate
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                return RedeemOutcome::Failed;
+            }
+            Ok(_) => {}
+        }
+
+        let result = sqlx::query("UPDATE voucher INNER JOIN distributorvoucher ON distributorvoucher.ID=voucher.distributorvoucher SET voucher.balance=?, voucher.used=?, voucher.status=?, voucher.version=voucher.version+1 WHERE voucher.ID = ? AND voucher.version = ? AND distributorvoucher.distributor = ?")
+            .bind(&voucher.balance)
+            .bind(&voucher.used)
+            .bind(&voucher.derive_status().to_string())
+            .bind(&voucher.id)
+            .bind(&voucher.version)
+            .bind(&tenant.distributor_id)
+            .execute(&mut tx)
+            .await;
+
+        // Dropping the transaction rolls the claimed client_id back
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                return RedeemOutcome::Failed;
+            }
+            Ok(r) if r.rows_affected() == 0 => return RedeemOutcome::Changed,
+            Ok(_) => {}
+        }
+
+        let result = sqlx::query("INSERT INTO auditlog (distributor, distributoruser, platformadmin, action, entity, entity_id, before_value, after_value, reason, ip) VALUES (?,?,?,?,?,?,?,?,?,?)")
+        .bind(&entry.distributor_id)
+        .bind(&entry.distributoruser_id)
+        .bind(&entry.platformadmin_id)
+        .bind(&entry.action)
+        .bind(&entry.entity)
+        .bind(&entry.entity_id)
+        .bind(&entry.before)
+        .bind(&entry.after)
+        .bind(&entry.reason)
+        .bind(&entry.ip)
+        .execute(&mut tx).await;
+
+        if let Err(e) = result {
+            println!("Error: {}", e);
+            return RedeemOutcome::Failed;
+        }
+
+        match tx.commit().await {
+            Err(e) => {
+                println!("Error: {}", e);
+                RedeemOutcome::Failed
+            }
+            Ok(_) => RedeemOutcome::Applied,
+        }
+    }
+
+    pub async fn get_shop_locations(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+    ) -> Option<std::vec::Vec<ShopLocation>> {
+        let mut result = sqlx::query("SELECT shoplocation.ID, distributor, name, address, location, location.country, location.postalcode, location.city, opening_hours, is_active FROM shoplocation JOIN location ON location.ID = shoplocation.location WHERE distributor = ? AND is_active = 1 ORDER BY name")
+            .bind(&tenant.distributor_id)
+            .fetch_all(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| ShopLocation {
+                        id: r.try_get("ID").unwrap(),
+                        distributor_id: r.try_get("distributor").unwrap(),
+                        name: r.try_get("name").unwrap(),
+                        address: r.try_get("address").unwrap(),
+                        location: Location {
+                            id: r.try_get("location").unwrap(),
+                            country: r.try_get("country").unwrap(),
+                            postalcode: r.try_get("postalcode").unwrap(),
+                            city: r.try_get("city").unwrap(),
+                        },
+                        opening_hours: serde_json::from_str(r.try_get("opening_hours").unwrap())
+                            .unwrap_or_default(),
+                        active: r.try_get("is_active").unwrap(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    pub async fn get_shop_location(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        id: u64,
+    ) -> Option<ShopLocation> {
+        get_shop_locations(mysql, tenant)
+            .await?
+            .into_iter()
+            .find(|l| l.id == id)
+    }
+
+    pub async fn add_shop_location(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        shop: &ShopLocation,
+    ) -> u64 {
+        let location = match get_id_of_location(mysql, &shop.location).await {
+            Some(location) => location,
+            None => return 0,
+        };
+
+        let result = sqlx::query("INSERT INTO shoplocation (distributor, name, address, location, opening_hours) VALUES (?,?,?,?,?)")
+            .bind(&tenant.distributor_id)
+            .bind(&shop.name)
+            .bind(&shop.address)
+            .bind(&location)
+            .bind(&serde_json::to_string(&shop.opening_hours).unwrap())
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+
+    pub async fn update_shop_location(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        shop: &ShopLocation,
+    ) -> bool {
+        let location = match get_id_of_location(mysql, &shop.location).await {
+            Some(location) => location,
+            None => return false,
+        };
+
+        let result = sqlx::query("UPDATE shoplocation SET name=?, address=?, location=?, opening_hours=? WHERE ID = ? AND distributor = ?")
+            .bind(&shop.name)
+            .bind(&shop.address)
+            .bind(&location)
+            .bind(&serde_json::to_string(&shop.opening_hours).unwrap())
+            .bind(&shop.id)
+            .bind(&tenant.distributor_id)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(_) => true,
+        }
+    }
+
+    // Redemptions keep pointing to the location, so it's only closed. Its scanners stop working.
+    pub async fn close_shop_location(mysql: &web::Data<MySQL>, tenant: Tenant, id: u64) -> bool {
+        let result =
+            sqlx::query("UPDATE shoplocation SET is_active=0 WHERE ID = ? AND distributor = ?")
+                .bind(&id)
+                .bind(&tenant.distributor_id)
+                .execute(&mysql.conn)
+                .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                return false;
+            }
+            Ok(r) if r.rows_affected() == 0 => return false,
+            Ok(_) => {}
+        }
+
+        let result = sqlx::query(
+            "UPDATE scannerdevice SET is_active=0 WHERE shoplocation = ? AND distributor = ?",
+        )
+        .bind(&id)
+        .bind(&tenant.distributor_id)
+        .execute(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(_) => true,
+        }
+    }
+
+    pub async fn get_scanner_devices(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+    ) -> Option<std::vec::Vec<ScannerDevice>> {
+        let mut result = sqlx::query("SELECT ID, distributor, shoplocation, name, paired_date, last_seen, is_active FROM scannerdevice WHERE distributor = ? AND is_active = 1 ORDER BY name")
+            .bind(&tenant.distributor_id)
+            .fetch_all(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(rows.iter().map(|r| scanner_device_from_row(r)).collect()),
+        }
+    }
+
+    fn scanner_device_from_row(r: &sqlx::mysql::MySqlRow) -> ScannerDevice {
+        ScannerDevice {
+            id: r.try_get("ID").unwrap(),
+            distributor_id: r.try_get("distributor").unwrap(),
+            shoplocation_id: r.try_get("shoplocation").unwrap(),
+            name: r.try_get("name").unwrap(),
+            paired: r
+                .try_get::<Option<DateTime<Utc>>, _>("paired_date")
+                .unwrap()
+                .is_some(),
+            last_seen: r.try_get("last_seen").unwrap(),
+            active: r.try_get("is_active").unwrap(),
+        }
+    }
+
+    pub async fn get_scanner_device_by_token(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        token_hash: &str,
+    ) -> Option<ScannerDevice> {
+        let mut result = sqlx::query("SELECT ID, distributor, shoplocation, name, paired_date, last_seen, is_active FROM scannerdevice WHERE distributor = ? AND token_hash = ? AND is_active = 1")
+            .bind(&tenant.distributor_id)
+            .bind(&token_hash)
+            .fetch_optional(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(row) => row.map(|r| scanner_device_from_row(&r)),
+        }
+    }
+
+    pub async fn add_scanner_device(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        shoplocation_id: u64,
+        name: &str,
+        token_hash: &str,
+    ) -> u64 {
+        let result = sqlx::query("INSERT INTO scannerdevice (distributor, shoplocation, name, token_hash) VALUES (?,?,?,?)")
+            .bind(&tenant.distributor_id)
+            .bind(&shoplocation_id)
+            .bind(&name)
+            .bind(&token_hash)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+
+    // The pairing link works once
+    pub async fn pair_scanner_device(mysql: &web::Data<MySQL>, tenant: Tenant, id: u64) -> bool {
+        let result = sqlx::query("UPDATE scannerdevice SET paired_date=UTC_TIMESTAMP(), last_seen=UTC_TIMESTAMP() WHERE ID = ? AND distributor = ? AND paired_date IS NULL")
+            .bind(&id)
+            .bind(&tenant.distributor_id)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn touch_scanner_device(mysql: &web::Data<MySQL>, tenant: Tenant, id: u64) -> bool {
+        let result = sqlx::query(
+            "UPDATE scannerdevice SET last_seen=UTC_TIMESTAMP() WHERE ID = ? AND distributor = ?",
+        )
+        .bind(&id)
+        .bind(&tenant.distributor_id)
+        .execute(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn revoke_scanner_device(mysql: &web::Data<MySQL>, tenant: Tenant, id: u64) -> bool {
+        let result =
+            sqlx::query("UPDATE scannerdevice SET is_active=0 WHERE ID = ? AND distributor = ?")
+                .bind(&id)
+                .bind(&tenant.distributor_id)
+                .execute(&mysql.conn)
+                .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn get_redemptions_per_location(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        from: chrono::DateTime<chrono::Utc>,
+        until: chrono::DateTime<chrono::Utc>,
+    ) -> Option<std::vec::Vec<LocationRedemptions>> {
+        let mut result = sqlx::query("SELECT redemption.shoplocation, shoplocation.name, COUNT(*) AS redemptions, CAST(SUM(redemption.amount) AS DOUBLE) AS amount FROM redemption LEFT JOIN shoplocation ON shoplocation.ID = redemption.shoplocation WHERE redemption.distributor = ? AND redemption.redeemed_at >= ? AND redemption.redeemed_at < ? GROUP BY redemption.shoplocation, shoplocation.name ORDER BY amount DESC")
+            .bind(&tenant.distributor_id)
+            .bind(&from)
+            .bind(&until)
+            .fetch_all(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| LocationRedemptions {
+                        shoplocation_id: r.try_get("shoplocation").unwrap(),
+                        name: r.try_get("name").unwrap(),
+                        redemptions: r.try_get("redemptions").unwrap(),
+                        amount: r.try_get("amount").unwrap(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    pub async fn get_redemption_by_client_id(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        client_id: &str,
+    ) -> Option<Redemption> {
+        let mut result = sqlx::query("SELECT ID, voucher, client_id, amount, balance_after, redeemed_at, shoplocation, scannerdevice FROM redemption WHERE distributor = ? AND client_id = ?")
+        .bind(&tenant.distributor_id)
+        .bind(&client_id)
+        .fetch_one(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(Redemption {
+                id: r.try_get("ID").unwrap(),
+                voucher_id: r.try_get("voucher").unwrap(),
+                client_id: r.try_get("client_id").unwrap(),
+                amount: r.try_get("amount").unwrap(),
+                balance_after: r.try_get("balance_after").unwrap(),
+                redeemed_at: r.try_get("redeemed_at").unwrap(),
+                shoplocation_id: r.try_get("shoplocation").unwrap(),
+                scannerdevice_id: r.try_get("scannerdevice").unwrap(),
+            }),
+        }
+    }
+
+    // Vouchers with balance expiring within days_before but not within next_days_before,
+    // that weren't reminded for this offset yet
+    pub async fn get_voucher_ids_to_remind(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        days_before: i64,
+        next_days_before: i64,
+    ) -> Option<std::vec::Vec<u64>> {
+        let mut result = sqlx::query("SELECT voucher.ID FROM voucher INNER JOIN sale ON sale.ID=voucher.sale INNER JOIN distributorvoucher ON distributorvoucher.ID=voucher.distributorvoucher WHERE distributorvoucher.distributor = ? AND voucher.status IN ('Active', 'PartiallyUsed') AND voucher.balance > 0 AND voucher.expiration_date <= UTC_TIMESTAMP() + INTERVAL ? DAY AND voucher.expiration_date > UTC_TIMESTAMP() + INTERVAL ? DAY AND DATEDIFF(voucher.expiration_date, sale.purchase_date) > ? AND NOT EXISTS (SELECT 1 FROM voucherreminder WHERE voucherreminder.voucher = voucher.ID AND voucherreminder.days_before = ?)")
+        .bind(&tenant.distributor_id)
+        .bind(&days_before)
+        .bind(&next_days_before)
+        .bind(&days_before)
+        .bind(&days_before)
+        .fetch_all(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(rows.iter().map(|r| r.try_get("ID").unwrap()).collect()),
+        }
+    }
+
+    pub async fn add_voucher_reminder(
+        mysql: &web::Data<MySQL>,
+        voucher_id: u64,
+        days_before: i64,
+    ) -> bool {
+        let result = sqlx::query("INSERT INTO voucherreminder (voucher, days_before) VALUES (?,?)")
+            .bind(&voucher_id)
+            .bind(&days_before)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn get_redemptions_by_voucher(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        voucher_id: u64,
+    ) -> Option<std::vec::Vec<Redemption>> {
+        let mut result = sqlx::query("SELECT ID, voucher, client_id, amount, balance_after, redeemed_at, shoplocation, scannerdevice FROM redemption WHERE voucher = ? AND distributor = ? ORDER BY redeemed_at DESC, ID DESC")
+        .bind(&voucher_id)
+        .bind(&tenant.distributor_id)
+        .fetch_all(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| Redemption {
+                        id: r.try_get("ID").unwrap(),
+                        voucher_id: r.try_get("voucher").unwrap(),
+                        client_id: r.try_get("client_id").unwrap(),
+                        amount: r.try_get("amount").unwrap(),
+                        balance_after: r.try_get("balance_after").unwrap(),
+                        redeemed_at: r.try_get("redeemed_at").unwrap(),
+                        shoplocation_id: r.try_get("shoplocation").unwrap(),
+                        scannerdevice_id: r.try_get("scannerdevice").unwrap(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    pub async fn add_audit_entry(mysql: &web::Data<MySQL>, entry: &AuditEntry) -> u64 {
+        let result = sqlx::query("INSERT INTO auditlog (distributor, distributoruser, platformadmin, action, entity, entity_id, before_value, after_value, reason, ip) VALUES (?,?,?,?,?,?,?,?,?,?)")
+        .bind(&entry.distributor_id)
+        .bind(&entry.distributoruser_id)
+        .bind(&entry.platformadmin_id)
+        .bind(&entry.action)
+        .bind(&entry.entity)
+        .bind(&entry.entity_id)
+        .bind(&entry.before)
+        .bind(&entry.after)
+        .bind(&entry.reason)
+        .bind(&entry.ip)
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+
+    pub async fn get_audit_entries_by_entity(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        entity: &str,
+        entity_id: u64,
+    ) -> Option<std::vec::Vec<AuditEntry>> {
+        let mut result = sqlx::query("SELECT ID, distributor, distributoruser, platformadmin, action, entity, entity_id, before_value, after_value, reason, ip, create_date FROM auditlog WHERE distributor = ? AND entity = ? AND entity_id = ? ORDER BY create_date DESC, ID DESC")
+        .bind(&tenant.distributor_id)
+        .bind(&entity)
+        .bind(&entity_id)
+        .fetch_all(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| AuditEntry {
+                        id: r.try_get("ID").unwrap(),
+                        distributor_id: r.try_get("distributor").unwrap(),
+                        distributoruser_id: r.try_get("distributoruser").unwrap(),
+                        platformadmin_id: r.try_get("platformadmin").unwrap(),
+                        action: r.try_get("action").unwrap(),
+                        entity: r.try_get("entity").unwrap(),
+                        entity_id: r.try_get("entity_id").unwrap(),
+                        before: r.try_get("before_value").unwrap(),
+                        after: r.try_get("after_value").unwrap(),
+                        reason: r.try_get("reason").unwrap(),
+                        ip: r.try_get("ip").unwrap(),
+                        create_date: r.try_get("create_date").unwrap(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    // Filtered audit log of a distributor, newest first
+    pub async fn get_audit_entries(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        filters: &AuditLogFilterParams,
+        start: u64,
+        amount: u64,
+    ) -> Option<std::vec::Vec<AuditEntry>> {
+        let distributoruser = filters
+            .distributoruser
+            .as_deref()
+            .and_then(|u| u.parse::<u64>().ok());
+        let min_date = filters
+            .min_date
+            .as_deref()
+            .map(|d| format!("{} 00:00:00", d));
+        let max_date = filters
+            .max_date
+            .as_deref()
+            .map(|d| format!("{} 23:59:59", d));
+
+        let mut result = sqlx::query("SELECT ID, distributor, distributoruser, platformadmin, action, entity, entity_id, before_value, after_value, reason, ip, create_date FROM auditlog WHERE distributor = ? AND (? IS NULL OR action = ?) AND (? IS NULL OR entity = ?) AND (? IS NULL OR distributoruser = ?) AND (? IS NULL OR create_date >= ?) AND (? IS NULL OR create_date <= ?) ORDER BY create_date DESC, ID DESC LIMIT ? OFFSET ?")
+        .bind(&tenant.distributor_id)
+        .bind(&filters.action)
+        .bind(&filters.action)
+        .bind(&filters.entity)
+        .bind(&filters.entity)
+        .bind(&distributoruser)
+        .bind(&distributoruser)
+        .bind(&min_date)
+        .bind(&min_date)
+        .bind(&max_date)
+        .bind(&max_date)
+        .bind(&amount)
+        .bind(&start)
+        .fetch_all(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|r| AuditEntry {
+                        id: r.try_get("ID").unwrap(),
+                        distributor_id: r.try_get("distributor").unwrap(),
+                        distributoruser_id: r.try_get("distributoruser").unwrap(),
+                        platformadmin_id: r.try_get("platformadmin").unwrap(),
+                        action: r.try_get("action").unwrap(),
+                        entity: r.try_get("entity").unwrap(),
+                        entity_id: r.try_get("entity_id").unwrap(),
+                        before: r.try_get("before_value").unwrap(),
+                        after: r.try_get("after_value").unwrap(),
+                        reason: r.try_get("reason").unwrap(),
+                        ip: r.try_get("ip").unwrap(),
+                        create_date: r.try_get("create_date").unwrap(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    pub async fn add_user_token(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+        purpose: TokenPurpose,
+        token: &str,
+        expires: chrono::DateTime<chrono::Utc>,
+    ) -> u64 {
+        let result = sqlx::query("INSERT INTO usertoken (distributoruser, purpose, token_hash, expires) VALUES (?,?,?,?)")
+        .bind(&distributoruser_id)
+        .bind(&purpose.to_string())
+        .bind(&hash_token(token))
+        .bind(&expires)
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+
+    // Impersonation link, it remembers which platform admin asked for it
+    pub async fn add_impersonation_token(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+        platformadmin_id: u64,
+        token: &str,
+        expires: chrono::DateTime<chrono::Utc>,
+    ) -> u64 {
+        let result = sqlx::query("INSERT INTO usertoken (distributoruser, platformadmin, purpose, token_hash, expires) VALUES (?,?,?,?,?)")
+        .bind(&distributoruser_id)
+        .bind(&platformadmin_id)
+        .bind(&TokenPurpose::Impersonation.to_string())
+        .bind(&hash_token(token))
+        .bind(&expires)
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+
+    pub async fn get_impersonation_admin(mysql: &web::Data<MySQL>, token: &str) -> Option<u64> {
+        let mut result = sqlx::query("SELECT platformadmin FROM usertoken WHERE token_hash = ? AND purpose = ? AND platformadmin IS NOT NULL AND used_date IS NULL AND expires > UTC_TIMESTAMP()")
+        .bind(&hash_token(token))
+        .bind(&TokenPurpose::Impersonation.to_string())
+        .fetch_one(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(r.try_get("platformadmin").unwrap()),
+        }
+    }
+
+    // ID of the user a valid (unused and unexpired) token belongs to
+    pub async fn get_user_token_user(
+        mysql: &web::Data<MySQL>,
+        purpose: TokenPurpose,
+        token: &str,
+    ) -> Option<u64> {
+        let mut result = sqlx::query("SELECT distributoruser FROM usertoken WHERE token_hash = ? AND purpose = ? AND used_date IS NULL AND expires > UTC_TIMESTAMP()")
+        .bind(&hash_token(token))
+        .bind(&purpose.to_string())
+        .fetch_one(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(r.try_get("distributoruser").unwrap()),
+        }
+    }
+
+    // Marks a valid token as used, only the first caller gets the user ID back
+    pub async fn use_user_token(
+        mysql: &web::Data<MySQL>,
+        purpose: TokenPurpose,
+        token: &str,
+    ) -> Option<u64> {
+        let distributoruser_id = get_user_token_user(mysql, purpose, token).await?;
+
+        let result = sqlx::query("UPDATE usertoken SET used_date=UTC_TIMESTAMP() WHERE token_hash = ? AND purpose = ? AND used_date IS NULL AND expires > UTC_TIMESTAMP()")
+        .bind(&hash_token(token))
+        .bind(&purpose.to_string())
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                None
+            }
+            Ok(r) if r.rows_affected() > 0 => Some(distributoruser_id),
+            Ok(_) => None,
+        }
+    }
+
+    pub async fn revoke_user_tokens(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+        purpose: TokenPurpose,
+    ) -> bool {
+        let result = sqlx::query("UPDATE usertoken SET used_date=UTC_TIMESTAMP() WHERE distributoruser = ? AND purpose = ? AND used_date IS NULL")
+        .bind(&distributoruser_id)
+        .bind(&purpose.to_string())
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn update_distributor_require_2fa(
+        mysql: &web::Data<MySQL>,
+        distributor: &Distributor,
+    ) -> bool {
+        let result = sqlx::query("UPDATE distributor SET require_2fa=? WHERE ID = ?")
+            .bind(&distributor.require_2fa)
+            .bind(&distributor.id)
+            .execute(&mysql.conn)
+            .await;
+        tenancy::forget(distributor.id).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn get_totp_secret(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+    ) -> Option<String> {
+        let mut result = sqlx::query(
+            "SELECT totp_secret FROM distributoruser WHERE ID = ? AND totp_enabled = 1 AND totp_secret IS NOT NULL",
+        )
+        .bind(&distributoruser_id)
+        .fetch_one(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(r.try_get("totp_secret").unwrap()),
+        }
+    }
+
+    pub async fn get_totp_pending_secret(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+    ) -> Option<String> {
+        let mut result = sqlx::query(
+            "SELECT totp_pending_secret FROM distributoruser WHERE ID = ? AND totp_pending_secret IS NOT NULL",
+        )
+        .bind(&distributoruser_id)
+        .fetch_one(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(r.try_get("totp_pending_secret").unwrap()),
+        }
+    }
+
+    // Never touches an enabled secret, so showing the setup page can't turn 2FA off
+    pub async fn set_totp_pending_secret(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+        secret: &str,
+    ) -> bool {
+        let result = sqlx::query(
+            "UPDATE distributoruser SET totp_pending_secret=? WHERE ID = ? AND totp_enabled = 0",
+        )
+        .bind(&secret)
+        .bind(&distributoruser_id)
+        .execute(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    // Promotes the pending secret once the user confirmed it with a code
+    pub async fn enable_totp(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+        secret: &str,
+    ) -> bool {
+        let result = sqlx::query("UPDATE distributoruser SET totp_secret=totp_pending_secret, totp_pending_secret=NULL, totp_enabled=1, totp_last_step=0 WHERE ID = ? AND totp_enabled = 0 AND totp_pending_secret = ?")
+        .bind(&distributoruser_id)
+        .bind(&secret)
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    // Stores a (new) secret, it only counts for logging in once enabled is set
+    pub async fn update_totp(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+        secret: Option<&str>,
+        enabled: bool,
+    ) -> bool {
+        let result = sqlx::query("UPDATE distributoruser SET totp_secret=?, totp_enabled=?, totp_pending_secret=NULL, totp_last_step=0 WHERE ID = ?")
+        .bind(&secret)
+        .bind(&enabled)
+        .bind(&distributoruser_id)
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    // Fails when this or a later time step was already used
+    pub async fn use_totp_step(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+        step: u64,
+    ) -> bool {
+        let result = sqlx::query(
+            "UPDATE distributoruser SET totp_last_step=? WHERE ID = ? AND totp_last_step < ?",
+        )
+        .bind(&step)
+        .bind(&distributoruser_id)
+        .bind(&step)
+        .execute(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    // Replaces all recovery codes of a user
+    pub async fn set_recovery_codes(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+        codes: &std::vec::Vec<String>,
+    ) -> bool {
+        let result = sqlx::query("DELETE FROM recoverycode WHERE distributoruser = ?")
+            .bind(&distributoruser_id)
+            .execute(&mysql.conn)
+            .await;
+
+        if let Err(e) = result {
+            println!("Error: {}", e);
+            return false;
+        }
+
+        for code in codes {
+            let result =
+                sqlx::query("INSERT INTO recoverycode (distributoruser, code_hash) VALUES (?,?)")
+                    .bind(&distributoruser_id)
+                    .bind(&hash_token(&normalize_recovery_code(code)))
+                    .execute(&mysql.conn)
+                    .await;
+
+            if let Err(e) = result {
+                println!("Error: {}", e);
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub async fn use_recovery_code(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+        code: &str,
+    ) -> bool {
+        let result = sqlx::query("UPDATE recoverycode SET used_date=UTC_TIMESTAMP() WHERE distributoruser = ? AND code_hash = ? AND used_date IS NULL")
+        .bind(&distributoruser_id)
+        .bind(&hash_token(&normalize_recovery_code(code)))
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn count_recovery_codes(mysql: &web::Data<MySQL>, distributoruser_id: u64) -> i64 {
+        let mut result = sqlx::query("SELECT COUNT(*) AS amount FROM recoverycode WHERE distributoruser = ? AND used_date IS NULL")
+        .bind(&distributoruser_id)
+        .fetch_one(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                0
+            }
+            Ok(r) => r.try_get("amount").unwrap(),
+        }
+    }
+
+    pub async fn add_login_attempt(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        username: &str,
+        distributoruser_id: Option<u64>,
+        ip: &str,
+        success: bool,
+    ) -> u64 {
+        let result = sqlx::query("INSERT INTO loginattempt (distributor, username, distributoruser, ip, success) VALUES (?,?,?,?,?)")
+        .bind(&tenant.distributor_id)
+        .bind(&username)
+        .bind(&distributoruser_id)
+        .bind(&ip)
+        .bind(&success)
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+
+    // Failed attempts on a username since the given time, a successful login resets the count
+    pub async fn count_failed_login_attempts_by_username(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        username: &str,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> i64 {
+        let mut result = sqlx::query("SELECT COUNT(*) AS amount FROM loginattempt WHERE distributor = ? AND username = ? AND success = 0 AND create_date > ? AND create_date > IFNULL((SELECT MAX(create_date) FROM loginattempt WHERE distributor = ? AND username = ? AND success = 1), '1970-01-01')")
+        .bind(&tenant.distributor_id)
+        .bind(&username)
+        .bind(&since)
+        .bind(&tenant.distributor_id)
+        .bind(&username)
+        .fetch_one(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                0
+            }
+            Ok(r) => r.try_get("amount").unwrap(),
+        }
+    }
+
+    pub async fn get_last_failed_login_attempt_by_username(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        username: &str,
+    ) -> Option<chrono::DateTime<chrono::Utc>> {
+        let mut result = sqlx::query("SELECT MAX(create_date) AS last_failure FROM loginattempt WHERE distributor = ? AND username = ? AND success = 0")
+        .bind(&tenant.distributor_id)
+        .bind(&username)
+        .fetch_one(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => r.try_get("last_failure").unwrap(),
+        }
+    }
+
+    // Counted over all distributors, one IP guessing at several shops is still one attacker
+    pub async fn count_failed_login_attempts_by_ip(
+        mysql: &web::Data<MySQL>,
+        ip: &str,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> i64 {
+        let mut result = sqlx::query("SELECT COUNT(*) AS amount FROM loginattempt WHERE ip = ? AND success = 0 AND create_date > ?")
+        .bind(&ip)
+        .bind(&since)
+        .fetch_one(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                0
+            }
+            Ok(r) => r.try_get("amount").unwrap(),
+        }
+    }
+
+    pub async fn is_distributor_user_locked(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+    ) -> bool {
+        let mut result = sqlx::query(
+            "SELECT ID FROM distributoruser WHERE ID = ? AND locked_until > UTC_TIMESTAMP()",
+        )
+        .bind(&distributoruser_id)
+        .fetch_one(&mysql.conn)
+        .await;
+
+        result.is_ok()
+    }
+
+    pub async fn lock_distributor_user(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+        until: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> bool {
+        let result = sqlx::query("UPDATE distributoruser SET locked_until=? WHERE ID = ?")
+            .bind(&until)
+            .bind(&distributoruser_id)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    // Only the first caller since the given time may send the lock mail
+    pub async fn claim_lock_mail(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> bool {
+        let result = sqlx::query("UPDATE distributoruser SET lock_mail_date=UTC_TIMESTAMP() WHERE ID = ? AND (lock_mail_date IS NULL OR lock_mail_date < ?)")
+            .bind(&distributoruser_id)
+            .bind(&since)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn add_admin_session(
+        mysql: &web::Data<MySQL>,
+        token: &str,
+        distributoruser_id: Option<u64>,
+        state: &HashMap<String, String>,
+        ip: &str,
+        user_agent: &str,
+        expires: chrono::DateTime<chrono::Utc>,
+    ) -> u64 {
+        let result = sqlx::query("INSERT INTO adminsession (session_hash, distributoruser, state, ip, user_agent, expires) VALUES (?,?,?,?,?,?)")
+        .bind(&hash_token(token))
+        .bind(&distributoruser_id)
+        .bind(&serde_json::to_string(state).unwrap())
+        .bind(&ip)
+        .bind(&user_agent)
+        .bind(&expires)
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.last_insert_id(),
+        }
+    }
+
+    // Only returns sessions that aren't revoked, expired or idle for too long
+    pub async fn get_admin_session(
+        mysql: &web::Data<MySQL>,
+        token: &str,
+        idle_since: chrono::DateTime<chrono::Utc>,
+    ) -> Option<AdminSession> {
+        let mut result = sqlx::query("SELECT ID, distributoruser, state, ip, user_agent, create_date, last_seen FROM adminsession WHERE session_hash = ? AND revoked_date IS NULL AND expires > UTC_TIMESTAMP() AND last_seen > ?")
+        .bind(&hash_token(token))
+        .bind(&idle_since)
+        .fetch_one(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(AdminSession {
+                id: r.try_get("ID").unwrap(),
+                distributoruser_id: r.try_get("distributoruser").unwrap(),
+                state: serde_json::from_str(r.try_get("state").unwrap()).unwrap_or_default(),
+                ip: r.try_get("ip").unwrap(),
+                user_agent: r.try_get("user_agent").unwrap(),
+                create_date: r.try_get("create_date").unwrap(),
+                last_seen: r.try_get("last_seen").unwrap(),
+            }),
+        }
+    }
+
+    pub async fn get_admin_sessions_by_user(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+    ) -> Option<std::vec::Vec<AdminSession>> {
+        let mut result = sqlx::query("SELECT ID, distributoruser, ip, user_agent, create_date, last_seen FROM adminsession WHERE distributoruser = ? AND revoked_date IS NULL AND expires > UTC_TIMESTAMP() ORDER BY last_seen DESC")
+        .bind(&distributoruser_id)
+        .fetch(&mysql.conn);
+
+        let mut sessions: std::vec::Vec<AdminSession> = std::vec::Vec::new();
+
+        while let Some(row) = result.try_next().await.unwrap() {
+            sessions.push(AdminSession {
+                id: row.try_get("ID").unwrap(),
+                distributoruser_id: row.try_get("distributoruser").unwrap(),
+                state: HashMap::new(),
+                ip: row.try_get("ip").unwrap(),
+                user_agent: row.try_get("user_agent").unwrap(),
+                create_date: row.try_get("create_date").unwrap(),
+                last_seen: row.try_get("last_seen").unwrap(),
+            });
+        }
+
+        Some(sessions)
+    }
+
+    pub async fn update_admin_session(
+        mysql: &web::Data<MySQL>,
+        id: u64,
+        distributoruser_id: Option<u64>,
+        state: &HashMap<String, String>,
+    ) -> bool {
+        let result = sqlx::query("UPDATE adminsession SET distributoruser=?, state=?, last_seen=UTC_TIMESTAMP() WHERE ID = ?")
+        .bind(&distributoruser_id)
+        .bind(&serde_json::to_string(state).unwrap())
+        .bind(&id)
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn touch_admin_session(mysql: &web::Data<MySQL>, id: u64) -> bool {
+        let result = sqlx::query("UPDATE adminsession SET last_seen=UTC_TIMESTAMP() WHERE ID = ?")
+            .bind(&id)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn revoke_admin_session(mysql: &web::Data<MySQL>, id: u64) -> bool {
+        let result = sqlx::query("UPDATE adminsession SET revoked_date=UTC_TIMESTAMP() WHERE ID = ? AND revoked_date IS NULL")
+        .bind(&id)
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    // Revokes one session, but only when it belongs to the given user
+    pub async fn revoke_admin_session_of_user(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+        id: u64,
+    ) -> bool {
+        let result = sqlx::query("UPDATE adminsession SET revoked_date=UTC_TIMESTAMP() WHERE ID = ? AND distributoruser = ? AND revoked_date IS NULL")
+        .bind(&id)
+        .bind(&distributoruser_id)
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    // Revokes all sessions of a user, optionally keeping the one making the request
+    pub async fn revoke_admin_sessions_by_user(
+        mysql: &web::Data<MySQL>,
+        distributoruser_id: u64,
+        except: Option<u64>,
+    ) -> u64 {
+        let result = sqlx::query("UPDATE adminsession SET revoked_date=UTC_TIMESTAMP() WHERE distributoruser = ? AND ID != ? AND revoked_date IS NULL")
+        .bind(&distributoruser_id)
+        .bind(&except.unwrap_or(0))
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.rows_affected(),
+        }
+    }
+
+    pub async fn delete_expired_admin_sessions(
+        mysql: &web::Data<MySQL>,
+        idle_since: chrono::DateTime<chrono::Utc>,
+    ) -> u64 {
+        let result = sqlx::query("DELETE FROM adminsession WHERE expires < UTC_TIMESTAMP() OR last_seen < ? OR revoked_date IS NOT NULL")
+        .bind(&idle_since)
+        .execute(&mysql.conn).await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                0
+            }
+            Ok(r) => r.rows_affected(),
+        }
+    }
+
+    pub async fn get_distributor_users(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+    ) -> Option<std::vec::Vec<DistributorUser>> {
+        let mut result = sqlx::query(
+            "SELECT ID FROM distributoruser WHERE distributor = ? ORDER BY active DESC, display_name",
+        )
+        .bind(&tenant.distributor_id)
+        .fetch(&mysql.conn);
+
+        let mut users: std::vec::Vec<DistributorUser> = std::vec::Vec::new();
+
+        while let Some(row) = result.try_next().await.unwrap() {
+            users.push(
+                DistributorUser::get_by_id(row.try_get("ID").unwrap(), mysql)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        Some(users)
+    }
+
+    pub async fn get_distributor_user(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        id: u64,
+    ) -> Option<DistributorUser> {
+        match DistributorUser::get_by_id(id, mysql).await {
+            Some(user) if user.distributor.id == tenant.distributor_id => Some(user),
+            _ => None,
+        }
+    }
+
+    pub async fn count_active_owners(mysql: &web::Data<MySQL>, tenant: Tenant) -> i64 {
+        let mut result = sqlx::query("SELECT COUNT(*) AS amount FROM distributoruser WHERE distributor = ? AND role = 'Owner' AND active = 1")
+            .bind(&tenant.distributor_id)
+            .fetch_one(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                0
+            }
+            Ok(r) => r.try_get("amount").unwrap(),
+        }
+    }
+
+    pub async fn update_distributor_user_role(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        id: u64,
+        role: Role,
+    ) -> bool {
+        let result =
+            sqlx::query("UPDATE distributoruser SET role=? WHERE ID = ? AND distributor = ?")
+                .bind(&role.to_string())
+                .bind(&id)
+                .bind(&tenant.distributor_id)
+                .execute(&mysql.conn)
+                .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn update_distributor_user_active(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        id: u64,
+        active: bool,
+    ) -> bool {
+        let result =
+            sqlx::query("UPDATE distributoruser SET active=? WHERE ID = ? AND distributor = ?")
+                .bind(&active)
+                .bind(&id)
+                .bind(&tenant.distributor_id)
+                .execute(&mysql.conn)
+                .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn update_last_login(mysql: &web::Data<MySQL>, distributoruser_id: u64) -> bool {
+        let result =
+            sqlx::query("UPDATE distributoruser SET last_login=UTC_TIMESTAMP() WHERE ID = ?")
+                .bind(&distributoruser_id)
+                .execute(&mysql.conn)
+                .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn get_location(mysql: &web::Data<MySQL>, id: u64) -> Option<Location> {
+        let mut result = sqlx::query("SELECT country, postalcode, city FROM location WHERE ID = ?")
+            .bind(&id)
+            .fetch_one(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(Location {
+                id: id,
+                country: r.try_get("country").unwrap(),
+                postalcode: r.try_get("postalcode").unwrap(),
+                city: r.try_get("city").unwrap(),
+            }),
+        }
+    }
+
+    pub async fn get_id_of_location(mysql: &web::Data<MySQL>, location: &Location) -> Option<u64> {
+        let mut result = sqlx::query(
+            "SELECT ID FROM location WHERE country = ? AND postalcode = ? AND city = ?",
+        )
+        .bind(&location.country)
+        .bind(&location.postalcode)
+        .bind(&location.city)
+        .fetch_one(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(r) => Some(r.try_get("ID").unwrap()),
+        }
+    }
+
+    pub async fn get_locations_by_postalcode(
+        mysql: &web::Data<MySQL>,
+        country: &str,
+        postalcode: &str,
+    ) -> Option<std::vec::Vec<Location>> {
+        let mut result = sqlx::query(
+            "SELECT ID, country, postalcode, city FROM location WHERE country = ? AND postalcode = ?",
+        )
+        .bind(&country)
+        .bind(&postalcode)
+        .fetch_all(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(rows.iter().map(|r| location_from_row(r)).collect()),
+        }
+    }
+
+    // For the autocomplete, query is the start of a postal code or a city
+    pub async fn search_locations(
+        mysql: &web::Data<MySQL>,
+        country: &str,
+        query: &str,
+        limit: u64,
+    ) -> Option<std::vec::Vec<Location>> {
+        let pattern = format!(
+            "{}%",
+            query
+                .replace("\\", "\\\\")
+                .replace("%", "\\%")
+                .replace("_", "\\_")
+        );
+        let mut result = sqlx::query("SELECT ID, country, postalcode, city FROM location WHERE country = ? AND (postalcode LIKE ? OR city LIKE ?) ORDER BY postalcode, city LIMIT ?")
+            .bind(&country)
+            .bind(&pattern)
+            .bind(&pattern)
+            .bind(&limit)
+            .fetch_all(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                None
+            }
+            Ok(rows) => Some(rows.iter().map(|r| location_from_row(r)).collect()),
+        }
+    }
+
+    fn location_from_row(r: &sqlx::mysql::MySqlRow) -> Location {
+        Location {
+            id: r.try_get("ID").unwrap(),
+            country: r.try_get("country").unwrap(),
+            postalcode: r.try_get("postalcode").unwrap(),
+            city: r.try_get("city").unwrap(),
+        }
+    }
+
+    // Locations of a country that come from an imported postal code list
+    pub async fn count_reference_locations(mysql: &web::Data<MySQL>, country: &str) -> i64 {
+        let mut result = sqlx::query(
+            "SELECT COUNT(*) AS amount FROM location WHERE country = ? AND reference = 1",
+        )
+        .bind(&country)
+        .fetch_one(&mysql.conn)
+        .await;
+
+        match result {
+            Err(e) => {
+                println!("error: {:?}", e);
+                0
+            }
+            Ok(r) => r.try_get("amount").unwrap(),
+        }
+    }
+
+    // Returns false when the location already exists, the importer marks it as reference data
+    pub async fn add_location_if_missing(
+        mysql: &web::Data<MySQL>,
+        location: &Location,
+        reference: bool,
+    ) -> bool {
+        if reference {
+            let result = sqlx::query("UPDATE location SET reference = 1 WHERE country = ? AND postalcode = ? AND city = ?")
+                .bind(&location.country)
+                .bind(&location.postalcode)
+                .bind(&location.city)
+                .execute(&mysql.conn)
+                .await;
+
+            if let Err(e) = result {
+                println!("Error: {}", e);
+            }
+        }
+
+        let result = sqlx::query("INSERT INTO location (country, postalcode, city, reference) SELECT ?, ?, ?, ? FROM DUAL WHERE NOT EXISTS (SELECT ID FROM location WHERE country = ? AND postalcode = ? AND city = ?)")
+            .bind(&location.country)
+            .bind(&location.postalcode)
+            .bind(&location.city)
+            .bind(&reference)
+            .bind(&location.country)
+            .bind(&location.postalcode)
+            .bind(&location.city)
+            .execute(&mysql.conn)
+            .await;
+
+        match result {
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+            Ok(r) => r.rows_affected() > 0,
+        }
+    }
+
+    pub async fn get_all_orders(
+        mysql: &web::Data<MySQL>,
+        tenant: Tenant,
+        start: u64,
+        amount: u64,
+        mut filters: AdminOrderFilterParams,
+    ) -> Vec<AdminOrderTableData> {
+        let mut statusses: std::vec::Vec<u16> = std::vec::Vec::new();
+
+        let mut where_str: String = " (sale.distributor = ?) AND ".to_string();
+        // Needed when filter for date is PREV_HOUR or MOST_RECENT_ACTIVATION
+        let current_date = chrono::offset::Utc::now();
+        let mut cmp_min_date: Option<String> = None;
+        let mut cmp_max_date: Option<String> = None;
+
+        if filters.min_amount.is_some() {
+            where_str = format!("{}{}", where_str, " (sale.amount >= ?) ");
+        } else {
+            where_str = format!("{}{}", where_str, " (? IS NULL) ");
+        }
+        if filters.max_amount.is_some() {
+            where_str = format!("{}{}", where_str, " AND (sale.amount < ?) ");
+        } else {
+            where_str = format!("{}{}", where_str, " AND (? IS NULL) ");
+        }
+        if filters.min_date.is_some() {
+            where_str = format!("{}{}", where_str, " AND (sale.purchase_date >= ?) ");
+
+            match filters.min_date.as_deref() {
+                Some("PREV_HOUR") => {
+                    cmp_min_date = Some(
+                        (current_date - chrono::Duration::hours(1))
+                            .format("%Y-%m-%d %H:%M")
+                            .to_string(),
+                    );
+                }
+                Some("MOST_RECENT_ACTIVATION") => {
+                    cmp_min_date = Some(
+                        get_active_distributor_vouchers_by_distributor(mysql, tenant)
+                            .await
+                            .unwrap()[0]
+                            .create_date
+                            .unwrap()
+                            .format("%Y-%m-%d %H:%M:%S")
+                            .to_string(),
+                    );
+                }
+                _ => {
+                    cmp_min_date = Some(format!("{} 00:00:00", filters.min_date.unwrap()));
+                }
+            }
+        } else {
+            where_str = format!("{}{}", where_str, " AND (? IS NULL) ");
+        }
+        if filters.max_date.is_some() {
+            where_str = format!("{}{}", where_str, " AND (sale.purchase_date <= ?) ");
+
+            match filters.max_date.as_deref() {
+                Some("PREV_HOUR") => {
+                    cmp_max_date = Some(
+                        (current_date + chrono::Duration::seconds(120))
+                            .format("%Y-%m-%d %H:%M")
+                            .to_string(),
+                    );
+                }
+                Some("MOST_RECENT_ACTIVATION") => {
+                    cmp_max_date = Some("3000-01-01 23:59:59".to_string());
+                }
+                _ => {
+                    cmp_max_date = Some(format!("{} 23:59:59", filters.max_date.unwrap()));
+                }
+            }
+        } else {
+            where_str = format!("{}{}", where_str, " AND (? IS NULL) ");
+        }
+        if filters.search_query.is_some() {
+            where_str = format!(
+                "{}{}",
+                where_str, " AND UPPER(CONCAT(client.firstname, ' ', client.lastname, '$', client.email, '$', client.tel, '$', voucher.number_code, '$', voucher.receiver_name, '$', voucher.receiver_email, '$', DATE_FORMAT(DATE(CONVERT_TZ(sale.purchase_date, '+00:00', '+01:00')), '%d-%m-%Y'), '$', sale.payment_id)) LIKE ? "
+            );
+            filters.search_query = Some(format!(
+                "%{}%",
+                filters.search_query.unwrap().to_uppercase()
+            ));
+        } else {
+            where_str = format!("{}{}", where_str, " AND (? IS NULL) ");
+        }
+
+        if filters.statusses.is_some() {
+            statusses = filters
+                .statusses
+                .unwrap()
+                .split(",")
+                .map(|x| x.parse::<u16>().unwrap())
+                .collect();
+
+            let mut in_str: String = "".to_string();
+            for i in &statusses {
+                in_str = format!("{}?,", in_str);
+            }
+            in_str.pop();
+
+            where_str = format!("{} AND sale.paid IN ({}) ", where_str, in_str);
+        } else {
+            where_str = format!("{}{}", where_str, " AND 1=1 ");
+        }
+
+        if where_str.chars().count() > 0 {
+            where_str = format!("{}{}", " WHERE ", where_str);
+        }
+
+        let sql = format!("SELECT voucher.ID, sale.amount, sale.paid, sale.purchase_date, CONCAT(client.firstname, ' ', client.lastname) as client_name FROM voucher INNER JOIN sale ON sale.ID=voucher.sale INNER JOIN client ON client.ID=sale.client {} ORDER BY sale.purchase_date DESC, voucher.id DESC LIMIT ? OFFSET ?", where_str);
+
+        let mut query = sqlx::query(&sql)
+            .bind(tenant.distributor_id)
+            .bind(filters.min_amount)
+            .bind(filters.max_amount)
+            .bind(cmp_min_date)
+            .bind(cmp_max_date)
+            .bind(filters.search_query);
+
+        for status in statusses {
+            query = query.bind(status);
+        }
+
+        let mut result = query.bind(amount).bind(start).fetch_all(&mysql.conn).await;
+
+        let mut order_data: std::vec::Vec<AdminOrderTableData> = std::vec::Vec::new();
+
+        for r in result.unwrap().iter() {
+            let date: chrono::DateTime<chrono::Utc> = r.try_get("purchase_date").unwrap();
+
+            order_data.push(AdminOrderTableData {
+                id: r.try_get("ID").unwrap(),
+                purchase_amount: r.try_get("amount").unwrap(),
+                payment_status: r.try_get("paid").unwrap(),
+                purchase_date: date
+                    .with_timezone(&chrono::Local)
+                    .format("%d-%m-%Y %H:%M")
+                    .to_string(),
+                client_name: r.try_get("client_name").unwrap(),
+            });
+        }
+
+        order_data
+    }
+}
+
+pub mod mollie {
+    use crate::*;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use serde_json::value;
+
+    #[derive(Clone)]
+    pub struct Mollie {
+        pub api_key: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Amount {
+        currency: String,
+        value: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Link {
+        pub href: String,
+        r#type: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Payment {
+        #[serde(skip_serializing)]
+        id: String,
+        description: String,
+        amount: Amount,
+        redirectUrl: String,
+        webhookUrl: String,
+        #[serde(rename = "_links")]
+        pub links: HashMap<String, Link>,
+        #[serde(skip_serializing)]
+        pub status: String,
+    }
+
+    impl Mollie {
         pub async fn make_payment(&self, sale: &Sale, payment_id: &mut String) -> Payment {
             let mut payment = Payment {
                 id: "".to_string(),
                 description: format!("My Description for {}", sale.client.email),
                 amount: Amount {
                     currency: "EUR".to_string(),
-                    value: format!("{:.2}", sale.amount + 1.5),
+                    value: format!("{:.2}", sale.amount + TRANSACTION_FEE),
+                },
+                redirectUrl: "http://demok.kaddo.test:8080".to_string(),
+                webhookUrl:
+                    "https://b27b-2a02-1810-3807-8a00-2453-bf80-2799-ff4b.ngrok.io/payment/hook"
+                        .to_string(),
+                status: "".to_string(),
+                links: HashMap::new(),
+            };
+
+            let http = reqwest::Client::new();
+            let res = http
+                .post("https://api.mollie.com/v2/payments")
+                .json(&payment)
+                .header(
+                    "Authorization",
+                    "Bearer test_8v3dnuvdFp7hj6U9TCvtCpr8MsNeHx",
+                )
+                .send()
+                .await
+                .unwrap();
+
+            let json: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+
+            *payment_id = str::replace(&json["id"].to_string(), "\"", "");
+
+            payment.redirectUrl = format!("http://demok.kaddo.test:8080/check/{}", payment_id);
+
+            payment.id = payment_id.to_string();
+            self.update_payment(&payment).await;
+
+            payment
+        }
+
+        pub async fn update_payment(&self, payment: &Payment) -> bool {
+            #[derive(Serialize)]
+            struct Parameters {
+                redirectUrl: String,
+            }
+
+            let params = Parameters {
+                redirectUrl: (*payment.redirectUrl).to_string(),
+            };
+
+            let http = reqwest::Client::new();
+            let res = http
+                .patch(&format!(
+                    "https://api.mollie.com/v2/payments/{}",
+                    payment.id
+                ))
+                .json(&params)
+                .header(
+                    "Authorization",
+                    "Bearer test_8v3dnuvdFp7hj6U9TCvtCpr8MsNeHx",
+                )
+                .send()
+                .await
+                .unwrap();
+
+            true
+        }
+
+        pub async fn get_payment(&self, id: &String) -> Payment {
+            let http = reqwest::Client::new();
+            let res = http
+                .patch(&format!("https://api.mollie.com/v2/payments/{}", id))
+                .header(
+                    "Authorization",
+                    "Bearer test_8v3dnuvdFp7hj6U9TCvtCpr8MsNeHx",
+                )
+                .send()
+                .await
+                .unwrap();
+
+            let payment: Payment = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+
+            payment
+        }
+    }
+}
+
+pub mod mail {
+    use crate::*;
+    use lettre::message::MultiPart;
+    use lettre::transport::smtp::authentication::Credentials;
+    use lettre::{Message, SmtpTransport, Transport};
+    use lettre_email::EmailBuilder;
+
+    #[derive(Clone)]
+    pub struct Creds {
+        pub username: String,
+        pub password: String,
+    }
+
+    #[derive(Clone)]
+    pub struct Mail {
+        pub creds: Creds,
+        pub smtp_server: String,
+    }
+
+    impl Mail {
+        // Fails on an address that can't be parsed or when the mail server refuses the mail
+        pub async fn send_plain_mail(
+            &self,
+            from: String,
+            to: String,
+            subject: String,
+            text: String,
+        ) -> Result<(), String> {
+            let email = Message::builder()
+                .from(
+                    from.parse()
+                        .map_err(|e| format!("Invalid sender {}: {}", from, e))?,
+                )
+                .reply_to(
+                    to.parse()
+                        .map_err(|e| format!("Invalid receiver {}: {}", to, e))?,
+                )
+                .to(to
+                    .parse()
+                    .map_err(|e| format!("Invalid receiver {}: {}", to, e))?)
+                .subject(subject)
+                .body(text)
+                .map_err(|e| format!("Could not build email: {}", e))?;
+
+            let mailer = SmtpTransport::unencrypted_localhost();
+
+            match mailer.send(&email) {
+                Ok(_) => {
+                    println!("Email sent successfully!");
+                    Ok(())
+                }
+                Err(e) => Err(format!("Could not send email: {:?}", e)),
+            }
+        }
+
+        // Plain text with an HTML alternative, for mails in the branding of a distributor
+        pub async fn send_html_mail(
+            &self,
+            from: String,
+            to: String,
+            subject: String,
+            text: String,
+            html: String,
+        ) -> Result<(), String> {
+            let email = Message::builder()
+                .from(
+                    from.parse()
+                        .map_err(|e| format!("Invalid sender {}: {}", from, e))?,
+                )
+                .reply_to(
+                    to.parse()
+                        .map_err(|e| format!("Invalid receiver {}: {}", to, e))?,
+                )
+                .to(to
+                    .parse()
+                    .map_err(|e| format!("Invalid receiver {}: {}", to, e))?)
+                .subject(subject)
+                .multipart(MultiPart::alternative_plain_html(text, html))
+                .map_err(|e| format!("Could not build email: {}", e))?;
+
+            let mailer = SmtpTransport::unencrypted_localhost();
+
+            match mailer.send(&email) {
+                Ok(_) => {
+                    println!("Email sent successfully!");
+                    Ok(())
+                }
+                Err(e) => Err(format!("Could not send email: {:?}", e)),
+            }
+        }
+
+        pub async fn send_template_mail<T: Template>(
+            &self,
+            from: String,
+            to: String,
+            subject: String,
+            template: &T,
+        ) -> Result<(), String> {
+            self.send_plain_mail(from, to, subject, template.render().unwrap())
+                .await
+        }
+
+        pub async fn send_voucher_mail(&self, voucher: &Voucher) -> Result<(), String> {
+            let distributor = &voucher.distributorvoucher.distributor;
+            let mail = VoucherMail {
+                distributor_name: distributor.name.to_string(),
+                receiver_name: voucher.receiver_name.to_string(),
+                balance: format!("{:.2}", voucher.balance).replace(".", ","),
+                expiration_date: voucher.expiration_date.format("%d-%m-%Y").to_string(),
+                number_code: voucher.number_code.to_string(),
+                url: format!("{}/bon/{}", distributor_url(distributor), voucher.hash_code),
+            };
+            let html = VoucherMailHtml {
+                mail: &mail,
+                theme: MailTheme::of(distributor),
+            };
+
+            self.send_html_mail(
+                "Kaddo. <noreply@kaddo.be>".to_string(),
+                format!("{} <{}>", voucher.receiver_name, voucher.receiver_email),
+                format!("Je cadeaubon van {}", distributor.name),
+                mail.render().unwrap(),
+                html.render().unwrap(),
+            )
+            .await
+        }
+
+        pub async fn send_invitation_mail(
+            &self,
+            user: &DistributorUser,
+            inviter_name: &str,
+            token: &str,
+            valid_days: i64,
+        ) -> Result<(), String> {
+            self.send_template_mail(
+                "Kaddo. <noreply@kaddo.be>".to_string(),
+                format!("{} <{}>", user.display_name, user.email),
+                format!("Uitnodiging voor {}", user.distributor.name),
+                &InvitationMail {
+                    display_name: user.display_name.to_string(),
+                    inviter_name: inviter_name.to_string(),
+                    distributor_name: user.distributor.name.to_string(),
+                    url: format!(
+                        "{}/admin/uitnodiging/{}",
+                        distributor_url(&user.distributor),
+                        token
+                    ),
+                    valid_days: valid_days,
+                },
+            )
+            .await
+        }
+
+        pub async fn send_email_verification_mail(
+            &self,
+            user: &DistributorUser,
+            token: &str,
+            valid_hours: i64,
+        ) -> Result<(), String> {
+            self.send_template_mail(
+                "Kaddo. <noreply@kaddo.be>".to_string(),
+                format!("{} <{}>", user.display_name, user.email),
+                "Bevestig je registratie bij Kaddo.".to_string(),
+                &EmailVerificationMail {
+                    display_name: user.display_name.to_string(),
+                    distributor_name: user.distributor.name.to_string(),
+                    url: format!("{}/registreren/bevestigen/{}", platform_url(), token),
+                    valid_hours: valid_hours,
+                },
+            )
+            .await
+        }
+
+        pub async fn send_password_reset_mail(
+            &self,
+            user: &DistributorUser,
+            token: &str,
+            valid_minutes: i64,
+        ) -> Result<(), String> {
+            self.send_template_mail(
+                "Kaddo. <noreply@kaddo.be>".to_string(),
+                format!("{} <{}>", user.display_name, user.email),
+                "Wachtwoord herstellen".to_string(),
+                &PasswordResetMail {
+                    display_name: user.display_name.to_string(),
+                    distributor_name: user.distributor.name.to_string(),
+                    url: format!(
+                        "{}/admin/wachtwoord-herstellen/{}",
+                        distributor_url(&user.distributor),
+                        token
+                    ),
+                    valid_minutes: valid_minutes,
+                },
+            )
+            .await
+        }
+
+        pub async fn send_account_locked_mail(
+            &self,
+            user: &DistributorUser,
+            locked_minutes: i64,
+            ip: &str,
+        ) -> Result<(), String> {
+            self.send_template_mail(
+                "Kaddo. <noreply@kaddo.be>".to_string(),
+                format!("{} <{}>", user.display_name, user.email),
+                "Je account werd tijdelijk geblokkeerd".to_string(),
+                &AccountLockedMail {
+                    display_name: user.display_name.to_string(),
+                    distributor_name: user.distributor.name.to_string(),
+                    locked_minutes: locked_minutes,
+                    ip: ip.to_string(),
+                    url: format!(
+                        "{}/admin/wachtwoord-vergeten",
+                        distributor_url(&user.distributor)
+                    ),
+                },
+            )
+            .await
+        }
+
+        pub async fn send_expiry_reminder_mail(&self, voucher: &Voucher) -> Result<(), String> {
+            let distributor = &voucher.distributorvoucher.distributor;
+            let days_left = (voucher.expiration_date - Utc::now()).num_days().max(0);
+
+            let mail = ExpiryReminderMail {
+                distributor_name: distributor.name.to_string(),
+                receiver_name: voucher.receiver_name.to_string(),
+                balance: format!("{:.2}", voucher.balance).replace(".", ","),
+                expiration_date: voucher.expiration_date.format("%d-%m-%Y").to_string(),
+                days_left: days_left,
+                url: format!("{}/bon/{}", distributor_url(distributor), voucher.hash_code),
+            };
+            let html = ExpiryReminderMailHtml {
+                mail: &mail,
+                theme: MailTheme::of(distributor),
+            };
+
+            self.send_html_mail(
+                "Kaddo. <noreply@kaddo.be>".to_string(),
+                format!("{} <{}>", voucher.receiver_name, voucher.receiver_email),
+                format!("Je cadeaubon van {} vervalt binnenkort", distributor.name),
+                mail.render().unwrap(),
+                html.render().unwrap(),
+            )
+            .await
+        }
+    }
+}
+
+pub mod sync {
+    use crate::*;
+
+    // Signs voucher snapshots with ed25519 so the scanner can verify them offline
+    #[derive(Clone)]
+    pub struct Signer {
+        secret_key: std::vec::Vec<u8>,
+        pub public_key: std::vec::Vec<u8>,
+    }
+
+    #[derive(Serialize)]
+    pub struct SnapshotVoucher {
+        pub id: u64,
+        pub hash_code: String,
+        pub number_code: String,
+        pub receiver_name: String,
+        pub balance: f64,
+        pub used: bool,
+        pub one_use_only: bool,
+        pub expiration_date: chrono::DateTime<chrono::Utc>,
+        pub version: i64,
+    }
+
+    #[derive(Serialize)]
+    pub struct Snapshot {
+        pub distributor_id: u64,
+        pub generated_at: chrono::DateTime<chrono::Utc>,
+        pub vouchers: std::vec::Vec<SnapshotVoucher>,
+    }
+
+    // The payload is sent as a string so the signature can be verified over the exact bytes
+    #[derive(Serialize)]
+    pub struct SignedSnapshot {
+        pub payload: String,
+        pub signature: String,
+    }
+
+    #[derive(Serialize, PartialEq, Debug)]
+    #[serde(rename_all = "snake_case")]
+    pub enum RedemptionStatus {
+        Applied,
+        Duplicate,
+        Conflict,
+        Rejected,
+    }
+
+    #[derive(Serialize)]
+    pub struct RedemptionResult {
+        pub client_id: String,
+        pub status: RedemptionStatus,
+        pub msg: String,
+        pub balance: Option<f64>,
+    }
+
+    impl Signer {
+        pub fn from_seed(seed: &str) -> Signer {
+            let mut sha256 = Sha256::new();
+            sha256.input_str(seed);
+            let mut seed_bytes = [0u8; 32];
+            sha256.result(&mut seed_bytes);
+
+            let (secret_key, public_key) = crypto::ed25519::keypair(&seed_bytes);
+
+            Signer {
+                secret_key: secret_key.to_vec(),
+                public_key: public_key.to_vec(),
+            }
+        }
+
+        pub fn sign(&self, snapshot: &Snapshot) -> SignedSnapshot {
+            let payload = serde_json::to_string(snapshot).unwrap();
+            let signature = crypto::ed25519::signature(payload.as_bytes(), &self.secret_key);
+
+            SignedSnapshot {
+                payload: payload,
+                signature: to_hex(&signature),
+            }
+        }
+    }
+
+    pub fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    // How long a scanner may stay offline before its queued redemptions are uploaded
+    pub const MAX_OFFLINE_HOURS: i64 = 72;
+
+    // The time a redemption is taken to have happened. The scanner's clock can't move it
+    // past the upload or further back than the offline window, so a backdated upload can't
+    // redeem a voucher that expired long ago.
+    pub fn effective_redeemed_at(
+        redeemed_at: DateTime<Utc>,
+        received_at: DateTime<Utc>,
+    ) -> DateTime<Utc> {
+        let earliest = received_at - Duration::hours(MAX_OFFLINE_HOURS);
+
+        if redeemed_at > received_at {
+            received_at
+        } else if redeemed_at < earliest {
+            earliest
+        } else {
+            redeemed_at
+        }
+    }
+
+    // Applies one queued redemption. Replaying the same client_id returns the original outcome.
+    pub async fn apply_redemption(
+        mysql: &web::Data<MySQL>,
+        tenant: data::Tenant,
+        redemption: &OfflineRedemptionJson,
+        audit: &AuditEntry,
+        origin: RedemptionOrigin,
+    ) -> RedemptionResult {
+        let mut result = RedemptionResult {
+            client_id: redemption.client_id.to_string(),
+            status: RedemptionStatus::Rejected,
+            msg: "".to_string(),
+            balance: None,
+        };
+
+        if let Some(existing) =
+            data::get_redemption_by_client_id(mysql, tenant, &redemption.client_id).await
+        {
+            result.status = RedemptionStatus::Duplicate;
+            result.balance = Some(existing.balance_after);
+            return result;
+        }
+
+        if redemption.amount < 0.0 {
+            result.msg = "Ongeldig bedrag".to_string();
+            return result;
+        }
+
+        let redeemed_at = effective_redeemed_at(redemption.redeemed_at, Utc::now());
+
+        // A concurrent scan bumps the version, in that case reload and try again
+        for _ in 0..3 {
+            let voucher = data::get_voucher(
+                mysql,
+                tenant,
+                data::Selector::ByHash(redemption.hash.to_string()),
+            )
+            .await;
+
+            let mut v = match voucher {
+                Some(v) => v,
+                None => {
+                    result.msg = "Geen voucher gevonden".to_string();
+                    return result;
+                }
+            };
+
+            // Vouchers that were valid when scanned offline are still accepted after expiring
+            let status = match v.derive_status() {
+                VoucherStatus::Expired if redeemed_at <= v.expiration_date => {
+                    VoucherStatus::PartiallyUsed
+                }
+                status => status,
+            };
+            match status {
+                VoucherStatus::Active | VoucherStatus::PartiallyUsed => {}
+                VoucherStatus::Used => {
+                    result.status = RedemptionStatus::Conflict;
+                    result.msg = status.description().to_string();
+                    result.balance = Some(v.balance);
+                    return result;
+                }
+                _ => {
+                    result.msg = status.description().to_string();
+                    return result;
+                }
+            }
+            if redemption.amount > v.balance {
+                result.status = RedemptionStatus::Conflict;
+                result.msg = "Onvoldoende saldo".to_string();
+                result.balance = Some(v.balance);
+                return result;
+            }
+
+            let mut entry = audit.clone();
+            entry.entity_id = v.id;
+            entry.before = serde_json::json!({ "balance": v.balance, "used": v.used }).to_string();
+
+            v.balance = ((v.balance - redemption.amount) * 100.0).round() / 100.0;
+            v.used = redemption.used || v.distributorvoucher.one_use_only || v.balance <= 0.0;
+
+            let row = Redemption {
+                id: 0,
+                voucher_id: v.id,
+                client_id: Some(redemption.client_id.to_string()),
+                amount: redemption.amount,
+                balance_after: v.balance,
+                redeemed_at: redeemed_at,
+                shoplocation_id: origin.shoplocation_id,
+                scannerdevice_id: origin.scannerdevice_id,
+            };
+            entry.after = serde_json::json!({ "balance": v.balance, "used": v.used, "client_id": redemption.client_id, "redeemed_at": redeemed_at }).to_string();
+
+            match data::redeem_voucher(mysql, tenant, &v, &row, &entry).await {
+                data::RedeemOutcome::Applied => {}
+                data::RedeemOutcome::Changed => continue,
+                // Another upload of the same redemption got there first
+                data::RedeemOutcome::Duplicate => {
+                    result.status = RedemptionStatus::Duplicate;
+                    result.balance =
+                        data::get_redemption_by_client_id(mysql, tenant, &redemption.client_id)
+                            .await
+                            .map(|existing| existing.balance_after);
+                    return result;
+                }
+                data::RedeemOutcome::Failed => {
+                    result.msg = "Inwisselen is mislukt, probeer opnieuw".to_string();
+                    return result;
+                }
+            }
+
+            result.status = RedemptionStatus::Applied;
+            result.balance = Some(v.balance);
+            return result;
+        }
+
+        result.status = RedemptionStatus::Conflict;
+        result.msg = "Bon werd ondertussen gewijzigd".to_string();
+        result
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn redemption_within_the_offline_window_keeps_its_time() {
+            let received_at = Utc::now();
+            let redeemed_at = received_at - Duration::hours(5);
+
+            assert_eq!(effective_redeemed_at(redeemed_at, received_at), redeemed_at);
+        }
+
+        #[test]
+        fn backdated_redemption_is_clamped_to_the_offline_window() {
+            let received_at = Utc::now();
+            let redeemed_at = received_at - Duration::days(400);
+
+            assert_eq!(
+                effective_redeemed_at(redeemed_at, received_at),
+                received_at - Duration::hours(MAX_OFFLINE_HOURS)
+            );
+        }
+
+        #[test]
+        fn backdated_redemption_does_not_revive_an_expired_voucher() {
+            // Expired a month ago, the scanner claims it was redeemed the day before
+            let received_at = Utc::now();
+            let expiration_date = received_at - Duration::days(30);
+            let redeemed_at = expiration_date - Duration::days(1);
+
+            assert!(effective_redeemed_at(redeemed_at, received_at) > expiration_date);
+        }
+
+        #[test]
+        fn redemption_in_the_future_is_moved_to_the_upload() {
+            let received_at = Utc::now();
+
+            assert_eq!(
+                effective_redeemed_at(received_at + Duration::days(2), received_at),
+                received_at
+            );
+        }
+    }
+}
+
+pub mod ratelimit {
+    use crate::*;
+
+    // In-memory sliding window limiter, counts attempts per key (e.g. an IP address)
+    pub struct RateLimiter {
+        max_attempts: usize,
+        window: Duration,
+        attempts: Mutex<HashMap<String, std::vec::Vec<DateTime<Utc>>>>,
+    }
+
+    impl RateLimiter {
+        pub fn new(max_attempts: usize, window: Duration) -> RateLimiter {
+            RateLimiter {
+                max_attempts: max_attempts,
+                window: window,
+                attempts: Mutex::new(HashMap::new()),
+            }
+        }
+
+        // Registers an attempt, returns false when the key is over the limit
+        pub async fn check(&self, key: &str) -> bool {
+            let now = Utc::now();
+            let window_start = now - self.window;
+            let mut attempts = self.attempts.lock().await;
+
+            if attempts.len() > 10000 {
+                attempts.retain(|_, times| times.iter().any(|t| *t > window_start));
+            }
+
+            let times = attempts
+                .entry(key.to_string())
+                .or_insert_with(std::vec::Vec::new);
+            times.retain(|t| *t > window_start);
+
+            if times.len() >= self.max_attempts {
+                return false;
+            }
+
+            times.push(now);
+            true
+        }
+    }
+}
+
+pub mod sessions {
+    use crate::*;
+    use actix_session::SessionStatus;
+    use actix_web::cookie::{Cookie, SameSite};
+    use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+    use actix_web::HttpMessage;
+    use futures::future::{ok, LocalBoxFuture, Ready};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::task::{Context, Poll};
+
+    pub const COOKIE_NAME: &str = "kaddo-session";
+    // A session ends after this time, even when it's in use
+    pub const MAX_AGE_HOURS: i64 = 12;
+    pub const IDLE_TIMEOUT_MINUTES: i64 = 30;
+    // last_seen is only written when it's older than this, not on every request
+    const TOUCH_INTERVAL_SECONDS: i64 = 60;
+
+    // Placed in the request extensions so handlers know which stored session they run in
+    #[derive(Clone, Copy)]
+    pub struct CurrentSession {
+        pub id: u64,
+    }
+
+    pub fn current_session_id(req: &HttpRequest) -> Option<u64> {
+        req.extensions().get::<CurrentSession>().map(|s| s.id)
+    }
+
+    pub fn idle_since() -> DateTime<Utc> {
+        Utc::now() - Duration::minutes(IDLE_TIMEOUT_MINUTES)
+    }
+
+    // Session backend that keeps the state in the adminsession table, the cookie only
+    // holds a random token of which the database stores the hash
+    #[derive(Clone)]
+    pub struct DbSession {
+        mysql: web::Data<MySQL>,
+        secure: bool,
+    }
+
+    impl DbSession {
+        pub fn new(mysql: MySQL) -> DbSession {
+            DbSession {
+                mysql: web::Data::new(mysql),
+                secure: true,
+            }
+        }
+
+        pub fn secure(mut self, secure: bool) -> DbSession {
+            self.secure = secure;
+            self
+        }
+
+        fn cookie(&self, token: String) -> Cookie<'static> {
+            Cookie::build(COOKIE_NAME, token)
+                .path("/")
+                .http_only(true)
+                .secure(self.secure)
+                .same_site(SameSite::Lax)
+                .finish()
+        }
+
+        fn removal_cookie(&self) -> Cookie<'static> {
+            let mut cookie = Cookie::build(COOKIE_NAME, "").path("/").finish();
+            cookie.make_removal();
+            cookie
+        }
+    }
+
+    impl<S, B> Transform<S> for DbSession
+    where
+        S: Service<
+                Request = ServiceRequest,
+                Response = ServiceResponse<B>,
+                Error = actix_web::Error,
+            > + 'static,
+        S::Future: 'static,
+        B: 'static,
+    {
+        type Request = ServiceRequest;
+        type Response = ServiceResponse<B>;
+        type Error = actix_web::Error;
+        type InitError = ();
+        type Transform = DbSessionMiddleware<S>;
+        type Future = Ready<std::result::Result<Self::Transform, Self::InitError>>;
+
+        fn new_transform(&self, service: S) -> Self::Future {
+            ok(DbSessionMiddleware {
+                service: Rc::new(RefCell::new(service)),
+                inner: Rc::new(self.clone()),
+            })
+        }
+    }
+
+    pub struct DbSessionMiddleware<S> {
+        service: Rc<RefCell<S>>,
+        inner: Rc<DbSession>,
+    }
+
+    impl<S, B> Service for DbSessionMiddleware<S>
+    where
+        S: Service<
+                Request = ServiceRequest,
+                Response = ServiceResponse<B>,
+                Error = actix_web::Error,
+            > + 'static,
+        S::Future: 'static,
+        B: 'static,
+    {
+        type Request = ServiceRequest;
+        type Response = ServiceResponse<B>;
+        type Error = actix_web::Error;
+        type Future = LocalBoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, cx: &mut Context) -> Poll<std::result::Result<(), Self::Error>> {
+            self.service.borrow_mut().poll_ready(cx)
+        }
+
+        fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+            let service = self.service.clone();
+            let inner = self.inner.clone();
+
+            Box::pin(async move {
+                let mysql = inner.mysql.clone();
+                let token = req.cookie(COOKIE_NAME).map(|c| c.value().to_string());
+                let stored = match &token {
+                    Some(token) => data::get_admin_session(&mysql, token, idle_since()).await,
+                    None => None,
+                };
+
+                if let Some(stored) = &stored {
+                    req.extensions_mut()
+                        .insert(CurrentSession { id: stored.id });
+                    Session::set_session(stored.state.clone().into_iter(), &mut req);
+                }
+
+                let ip = client_ip(req.request());
+                let user_agent = req
+                    .headers()
+                    .get("User-Agent")
+                    .and_then(|h| h.to_str().ok())
+                    .unwrap_or("")
+                    .chars()
+                    .take(255)
+                    .collect::<String>();
+
+                let fut = service.borrow_mut().call(req);
+                let mut res = fut.await?;
+
+                let (status, state) = Session::get_changes(&mut res);
+                let state: HashMap<String, String> = state.map(|s| s.collect()).unwrap_or_default();
+                let distributoruser_id = state
+                    .get("distributoruser")
+                    .and_then(|u| serde_json::from_str::<DistributorUser>(u).ok())
+                    .map(|u| u.id);
+
+                let cookie = match (status, stored) {
+                    (SessionStatus::Purged, Some(stored)) => {
+                        data::revoke_admin_session(&mysql, stored.id).await;
+                        Some(inner.removal_cookie())
+                    }
+                    (SessionStatus::Changed, Some(stored)) => {
+                        data::update_admin_session(&mysql, stored.id, distributoruser_id, &state)
+                            .await;
+                        None
+                    }
+                    // New token on login, so a token known before it can't be reused
+                    (SessionStatus::Changed, None) | (SessionStatus::Renewed, _) => {
+                        if let Some(stored) = stored_id(&res) {
+                            data::revoke_admin_session(&mysql, stored).await;
+                        }
+
+                        let new_token = generate_token();
+                        data::add_admin_session(
+                            &mysql,
+                            &new_token,
+                            distributoruser_id,
+                            &state,
+                            &ip,
+                            &user_agent,
+                            Utc::now() + Duration::hours(MAX_AGE_HOURS),
+                        )
+                        .await;
+                        Some(inner.cookie(new_token))
+                    }
+                    (_, Some(stored)) => {
+                        if stored.last_seen < Utc::now() - Duration::seconds(TOUCH_INTERVAL_SECONDS)
+                        {
+                            data::touch_admin_session(&mysql, stored.id).await;
+                        }
+                        None
+                    }
+                    // Unknown, revoked or expired token
+                    (_, None) if token.is_some() => Some(inner.removal_cookie()),
+                    (_, None) => None,
+                };
+
+                if let Some(cookie) = cookie {
+                    res.response_mut().add_cookie(&cookie)?;
+                }
+
+                Ok(res)
+            })
+        }
+    }
+
+    fn stored_id<B>(res: &ServiceResponse<B>) -> Option<u64> {
+        res.request()
+            .extensions()
+            .get::<CurrentSession>()
+            .map(|s| s.id)
+    }
+}
+
+pub mod content {
+    use crate::*;
+    use pulldown_cmark::{html, Options, Parser};
+
+    pub const MAX_MARKDOWN_LENGTH: usize = 20000;
+    pub const MAX_TITLE_LENGTH: usize = 200;
+
+    // Slugs of custom pages that would clash with routes of the storefront
+    const RESERVED_SLUGS: &[&str] = &["voorwaarden", "faq"];
+
+    // Markdown of a distributor to HTML that is safe to show in the storefront. Raw HTML in
+    // the Markdown goes through the same sanitizer, scripts and event handlers are dropped.
+    pub fn render_markdown(input: &str) -> String {
+        let parser = Parser::new_ext(
+            input,
+            Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+        );
+        let mut unsafe_html = String::new();
+        html::push_html(&mut unsafe_html, parser);
+
+        ammonia::Builder::default()
+            .link_rel(Some("noopener noreferrer nofollow"))
+            .clean(&unsafe_html)
+            .to_string()
+    }
+
+    // Descriptions saved before they were Markdown don't have a rendering yet
+    pub async fn render_missing_descriptions(mysql: MySQL) {
+        let mysql = web::Data::new(mysql);
+
+        for (id, description) in data::get_descriptions_without_html(&mysql)
+            .await
+            .unwrap_or_default()
+        {
+            data::update_description_html(&mysql, id, &render_markdown(&description)).await;
+        }
+    }
+
+    pub fn check_slug(slug: &str) -> std::result::Result<(), &'static str> {
+        if slug.is_empty() || slug.len() > 60 {
+            return Err("slug_length");
+        }
+        if !slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            || slug.starts_with("-")
+            || slug.ends_with("-")
+        {
+            return Err("slug_invalid");
+        }
+        if RESERVED_SLUGS.contains(&slug) {
+            return Err("slug_reserved");
+        }
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn valid_slugs() {
+            assert_eq!(check_slug("over-ons"), Ok(()));
+            assert_eq!(check_slug("a"), Ok(()));
+            assert_eq!(check_slug("actie-2024"), Ok(()));
+            assert_eq!(check_slug(&"a".repeat(60)), Ok(()));
+        }
+
+        #[test]
+        fn slug_length() {
+            assert_eq!(check_slug(""), Err("slug_length"));
+            assert_eq!(check_slug(&"a".repeat(61)), Err("slug_length"));
+        }
+
+        #[test]
+        fn invalid_slugs() {
+            assert_eq!(check_slug("Over-ons"), Err("slug_invalid"));
+            assert_eq!(check_slug("over ons"), Err("slug_invalid"));
+            assert_eq!(check_slug("over_ons"), Err("slug_invalid"));
+            assert_eq!(check_slug("-over-ons"), Err("slug_invalid"));
+            assert_eq!(check_slug("over-ons-"), Err("slug_invalid"));
+            assert_eq!(check_slug("../admin"), Err("slug_invalid"));
+            assert_eq!(check_slug("café"), Err("slug_invalid"));
+        }
+
+        #[test]
+        fn reserved_slugs() {
+            assert_eq!(check_slug("voorwaarden"), Err("slug_reserved"));
+            assert_eq!(check_slug("faq"), Err("slug_reserved"));
+        }
+    }
+}
+
+pub mod postcodes {
+    use crate::*;
+
+    pub const COUNTRIES: [&str; 3] = ["BE", "NL", "LU"];
+    const MAX_SUGGESTIONS: u64 = 10;
+
+    pub fn default_country() -> String {
+        "BE".to_string()
+    }
+
+    // Postal code in the notation stored in the location table: 4 digits for Belgium and
+    // Luxembourg ("L-1234" is accepted), "1234 AB" for the Netherlands
+    pub fn normalize_postalcode(country: &str, input: &str) -> Option<String> {
+        if !input.is_ascii() {
+            return None;
+        }
+        let compact: String = input
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_uppercase();
+        let compact = compact
+            .strip_prefix(&format!("{}-", country))
+            .or(compact.strip_prefix(&format!("{}-", country.get(..1).unwrap_or(""))))
+            .unwrap_or(&compact);
+
+        match country {
+            "BE" | "LU" => match compact.len() == 4
+                && compact.chars().all(|c| c.is_ascii_digit())
+                && !compact.starts_with("0")
+            {
+                true => Some(compact.to_string()),
+                false => None,
+            },
+            "NL" => {
+                let (digits, letters) = compact.split_at(compact.len().min(4));
+                match digits.len() == 4
+                    && digits.chars().all(|c| c.is_ascii_digit())
+                    && !digits.starts_with("0")
+                    && letters.len() == 2
+                    && letters.chars().all(|c| c.is_ascii_uppercase())
+                    // Not handed out by PostNL
+                    && !["SA", "SD", "SS"].contains(&letters)
+                {
+                    true => Some(format!("{} {}", digits, letters)),
+                    false => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // Compares city names without case, accents and punctuation, "Liège" matches "LIEGE"
+    fn fold_city(city: &str) -> String {
+        city.to_lowercase()
+            .chars()
+            .map(|c| match c {
+                'à' | 'á' | 'â' | 'ä' => 'a',
+                'è' | 'é' | 'ê' | 'ë' => 'e',
+                'ì' | 'í' | 'î' | 'ï' => 'i',
+                'ò' | 'ó' | 'ô' | 'ö' => 'o',
+                'ù' | 'ú' | 'û' | 'ü' => 'u',
+                'ç' => 'c',
+                c => c,
+            })
+            .filter(|c| c.is_alphanumeric())
+            .collect()
+    }
+
+    // Looks the address up in the reference data, with the error code for the form when it
+    // can't be found. The returned location has the stored spelling of the city.
+    pub async fn resolve(
+        mysql: &web::Data<MySQL>,
+        country: &str,
+        postalcode: &str,
+        city: &str,
+    ) -> std::result::Result<Location, &'static str> {
+        let country = country.trim().to_uppercase();
+        if !COUNTRIES.contains(&country.as_str()) {
+            return Err("country_unsupported");
+        }
+        let postalcode = normalize_postalcode(&country, postalcode).ok_or("postalcode_invalid")?;
+        if city.trim().is_empty() {
+            return Err("city_missing");
+        }
+
+        let mut locations = data::get_locations_by_postalcode(mysql, &country, &postalcode)
+            .await
+            .unwrap_or_default();
+
+        // Until the list of a country is imported its addresses can't be checked, the
+        // location is added as it was entered
+        if locations.is_empty() && data::count_reference_locations(mysql, &country).await == 0 {
+            data::add_location_if_missing(
+                mysql,
+                &Location {
+                    id: 0,
+                    country: country.to_string(),
+                    postalcode: postalcode.to_string(),
+                    city: city.trim().to_string(),
+                },
+                false,
+            )
+            .await;
+            locations = data::get_locations_by_postalcode(mysql, &country, &postalcode)
+                .await
+                .unwrap_or_default();
+        }
+        if locations.is_empty() {
+            return Err("postalcode_unknown");
+        }
+
+        let city = fold_city(city);
+        locations
+            .into_iter()
+            .find(|l| fold_city(&l.city) == city)
+            .ok_or("city_mismatch")
+    }
+
+    pub async fn suggest(
+        mysql: &web::Data<MySQL>,
+        country: &str,
+        query: &str,
+    ) -> std::vec::Vec<Location> {
+        let country = country.trim().to_uppercase();
+        let query = query.trim();
+        if !COUNTRIES.contains(&country.as_str()) || query.len() < 2 || query.len() > 50 {
+            return std::vec::Vec::new();
+        }
+
+        data::search_locations(mysql, &country, query, MAX_SUGGESTIONS)
+            .await
+            .unwrap_or_default()
+    }
+
+    // Imports a CSV with the postal code in the first column and the city in the second,
+    // separated by ; or , like the lists published by bpost, POST Luxembourg and the BAG
+    // extracts for the Netherlands. The header and lines without a valid postal code are
+    // skipped, existing locations are kept so the import can be rerun with a newer list.
+    //
+    //     kaddo import-postcodes BE zipcodes.csv
+    pub async fn import(mysql: MySQL, country: &str, path: &str) -> std::io::Result<(u64, u64)> {
+        let mysql = web::Data::new(mysql);
+        let country = country.trim().to_uppercase();
+        if !COUNTRIES.contains(&country.as_str()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("unsupported country {}", country),
+            ));
+        }
+
+        let bytes = std::fs::read(path)?;
+        // bpost still publishes Latin-1 files
+        let contents = match String::from_utf8(bytes) {
+            Ok(contents) => contents,
+            Err(e) => e.into_bytes().iter().map(|b| *b as char).collect(),
+        };
+
+        let (mut added, mut skipped) = (0, 0);
+        for line in contents.lines() {
+            let separator = if line.contains(";") { ';' } else { ',' };
+            let mut columns = line
+                .split(separator)
+                .map(|c| c.trim().trim_matches('"').trim());
+            let postalcode = columns
+                .next()
+                .and_then(|p| normalize_postalcode(&country, p));
+            let city = columns.next().unwrap_or("");
+
+            match postalcode {
+                Some(postalcode) if !city.is_empty() => {
+                    let location = Location {
+                        id: 0,
+                        country: country.to_string(),
+                        postalcode: postalcode,
+                        city: city.to_string(),
+                    };
+                    match data::add_location_if_missing(&mysql, &location, true).await {
+                        true => added += 1,
+                        false => skipped += 1,
+                    }
+                }
+                _ => skipped += 1,
+            }
+        }
+
+        Ok((added, skipped))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn belgian_and_luxembourg_postal_codes() {
+            assert_eq!(normalize_postalcode("BE", "9000"), Some("9000".to_string()));
+            assert_eq!(
+                normalize_postalcode("BE", " 90 00 "),
+                Some("9000".to_string())
+            );
+            assert_eq!(
+                normalize_postalcode("BE", "B-9000"),
+                Some("9000".to_string())
+            );
+            assert_eq!(
+                normalize_postalcode("BE", "be-9000"),
+                Some("9000".to_string())
+            );
+            assert_eq!(
+                normalize_postalcode("LU", "L-1234"),
+                Some("1234".to_string())
+            );
+            assert_eq!(normalize_postalcode("LU", "1234"), Some("1234".to_string()));
+        }
+
+        #[test]
+        fn invalid_belgian_and_luxembourg_postal_codes() {
+            assert_eq!(normalize_postalcode("BE", "0900"), None);
+            assert_eq!(normalize_postalcode("BE", "900"), None);
+            assert_eq!(normalize_postalcode("BE", "90000"), None);
+            assert_eq!(normalize_postalcode("BE", "90a0"), None);
+            assert_eq!(normalize_postalcode("BE", "L-9000"), None);
+            assert_eq!(normalize_postalcode("BE", ""), None);
+            assert_eq!(normalize_postalcode("BE", "９０００"), None);
+        }
+
+        #[test]
+        fn dutch_postal_codes() {
+            assert_eq!(
+                normalize_postalcode("NL", "1234AB"),
+                Some("1234 AB".to_string())
+            );
+            assert_eq!(
+                normalize_postalcode("NL", "1234 ab"),
+                Some("1234 AB".to_string())
+            );
+            assert_eq!(
+                normalize_postalcode("NL", "NL-1234 AB"),
+                Some("1234 AB".to_string())
+            );
+        }
+
+        #[test]
+        fn invalid_dutch_postal_codes() {
+            assert_eq!(normalize_postalcode("NL", "1234"), None);
+            assert_eq!(normalize_postalcode("NL", "0123AB"), None);
+            assert_eq!(normalize_postalcode("NL", "1234ABC"), None);
+            assert_eq!(normalize_postalcode("NL", "1234 SA"), None);
+            assert_eq!(normalize_postalcode("NL", "1234 SS"), None);
+            assert_eq!(normalize_postalcode("NL", "12"), None);
+            assert_eq!(normalize_postalcode("NL", ""), None);
+        }
+
+        #[test]
+        fn unsupported_country() {
+            assert_eq!(normalize_postalcode("DE", "10115"), None);
+        }
+
+        #[test]
+        fn city_names_are_compared_without_case_accents_and_punctuation() {
+            assert_eq!(fold_city("Liège"), "liege");
+            assert_eq!(fold_city("LIÈGE"), "liege");
+            assert_eq!(fold_city("Sint-Niklaas"), "sintniklaas");
+            assert_eq!(fold_city("'s-Hertogenbosch"), "shertogenbosch");
+            assert_eq!(fold_city("Esch-sur-Alzette"), "eschsuralzette");
+            assert_eq!(fold_city(""), "");
+        }
+    }
+}
+
+pub mod bankdetails {
+    use crate::*;
+    use crypto::aead::{AeadDecryptor, AeadEncryptor};
+    use crypto::aes::KeySize;
+    use crypto::aes_gcm::AesGcm;
+
+    // Raw 256-bit keys from KADDO_ENCRYPTION_KEYS as "<id>:<64 hex chars>", comma separated.
+    // New values are encrypted with the first key, the others are only read. Every value
+    // stores the id of its key, so a key is rotated by putting a new one in front; the values
+    // of the older keys are re-encrypted at startup, after which those can be removed.
+    static KEYS: Lazy<std::vec::Vec<(String, [u8; 32])>> = Lazy::new(|| {
+        let keys = env::var("KADDO_ENCRYPTION_KEYS").expect("KADDO_ENCRYPTION_KEYS not set");
+        parse_keys(&keys).expect("KADDO_ENCRYPTION_KEYS is invalid")
+    });
+    // The old passphrase, a single SHA-256 of it was the key of values stored as v1. Only
+    // needed until those are re-encrypted.
+    static LEGACY_KEY: Lazy<Option<[u8; 32]>> = Lazy::new(|| {
+        let secret = env::var("KADDO_ENCRYPTION_KEY").ok()?;
+        let mut sha256 = Sha256::new();
+        sha256.input_str(&secret);
+        let mut key = [0u8; 32];
+        sha256.result(&mut key);
+        Some(key)
+    });
+    const LEGACY_PREFIX: &str = "v1:";
+    const PREFIX: &str = "v2:";
+    const AAD: &[u8] = b"kaddo-bankaccountnr";
+
+    fn parse_keys(
+        value: &str,
+    ) -> std::result::Result<std::vec::Vec<(String, [u8; 32])>, &'static str> {
+        let mut keys: std::vec::Vec<(String, [u8; 32])> = std::vec::Vec::new();
+        for entry in value.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            let mut parts = entry.splitn(2, ':');
+            let id = parts.next().unwrap_or("");
+            if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err("key_id");
+            }
+            if keys.iter().any(|(existing, _)| existing == id) {
+                return Err("key_id_duplicate");
+            }
+            let bytes = parts.next().and_then(from_hex).ok_or("key_hex")?;
+            if bytes.len() != 32 {
+                return Err("key_length");
+            }
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&bytes);
+            keys.push((id.to_string(), key));
+        }
+
+        match keys.is_empty() {
+            true => Err("key_missing"),
+            false => Ok(keys),
+        }
+    }
+
+    fn current_key() -> &'static (String, [u8; 32]) {
+        &KEYS[0]
+    }
+
+    // IBAN lengths of the SEPA countries
+    const IBAN_LENGTHS: [(&str, usize); 36] = [
+        ("AD", 24),
+        ("AT", 20),
+        ("BE", 16),
+        ("BG", 22),
+        ("CH", 21),
+        ("CY", 28),
+        ("CZ", 24),
+        ("DE", 22),
+        ("DK", 18),
+        ("EE", 20),
+        ("ES", 24),
+        ("FI", 18),
+        ("FR", 27),
+        ("GB", 22),
+        ("GI", 23),
+        ("GR", 27),
+        ("HR", 21),
+        ("HU", 28),
+        ("IE", 22),
+        ("IS", 26),
+        ("IT", 27),
+        ("LI", 21),
+        ("LT", 20),
+        ("LU", 20),
+        ("LV", 21),
+        ("MC", 27),
+        ("MT", 31),
+        ("NL", 18),
+        ("NO", 15),
+        ("PL", 28),
+        ("PT", 25),
+        ("RO", 24),
+        ("SE", 24),
+        ("SI", 19),
+        ("SK", 24),
+        ("SM", 27),
+    ];
+
+    // Makes sure the keys are set before the server starts instead of at the first save
+    pub fn init() {
+        Lazy::force(&KEYS);
+    }
+
+    fn compact(input: &str) -> String {
+        input
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '.' && *c != '-')
+            .collect::<String>()
+            .to_uppercase()
+    }
+
+    // Remainder of a number with letters as 10..35, as used by IBAN and Dutch VAT numbers
+    fn mod97(input: &str) -> u32 {
+        input.chars().fold(0, |rest, c| match c.to_digit(36) {
+            Some(v) if v >= 10 => (rest * 100 + v) % 97,
+            Some(v) => (rest * 10 + v) % 97,
+            None => rest,
+        })
+    }
+
+    pub fn check_iban(input: &str) -> std::result::Result<String, &'static str> {
+        let iban = compact(input);
+        if iban.len() < 5
+            || !iban.chars().all(|c| c.is_ascii_alphanumeric())
+            || !iban[..2].chars().all(|c| c.is_ascii_alphabetic())
+            || !iban[2..4].chars().all(|c| c.is_ascii_digit())
+        {
+            return Err("iban_format");
+        }
+
+        match IBAN_LENGTHS
+            .iter()
+            .find(|(country, _)| *country == &iban[..2])
+        {
+            None => return Err("iban_country"),
+            Some((_, length)) if *length != iban.len() => return Err("iban_length"),
+            Some(_) => {}
+        }
+
+        match mod97(&format!("{}{}", &iban[4..], &iban[..4])) {
+            1 => Ok(iban),
+            _ => Err("iban_checksum"),
+        }
+    }
+
+    pub fn format_iban(iban: &str) -> String {
+        iban.chars()
+            .collect::<std::vec::Vec<char>>()
+            .chunks(4)
+            .map(|c| c.iter().collect::<String>())
+            .collect::<std::vec::Vec<String>>()
+            .join(" ")
+    }
+
+    pub fn mask_iban(iban: &str) -> String {
+        if iban.chars().count() < 8 {
+            return mask_tail(iban, 0);
+        }
+        let country: String = iban.chars().take(2).collect();
+        let rest: String = iban.chars().skip(2).collect();
+        format_iban(&format!("{}{}", country, mask_tail(&rest, 4)))
+    }
+
+    fn digits(value: &str) -> bool {
+        !value.is_empty() && value.chars().all(|c| c.is_ascii_digit())
+    }
+
+    fn number(value: &str) -> u64 {
+        value.parse().unwrap_or(0)
+    }
+
+    // Format of the number after the country code of EU VAT numbers, with the checksum for
+    // the countries around us. Greece uses EL instead of its ISO code.
+    pub fn check_vat(input: &str) -> std::result::Result<String, &'static str> {
+        let vat = compact(input);
+        if vat.len() < 4 || !vat.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err("btw_format");
+        }
+        let (country, rest) = vat.split_at(2);
+        let len = rest.len();
+
+        let format_ok = match country {
+            "AT" => len == 9 && rest.starts_with("U") && digits(&rest[1..]),
+            "BE" => len == 10 && digits(rest) && (rest.starts_with("0") || rest.starts_with("1")),
+            "BG" => (len == 9 || len == 10) && digits(rest),
+            "CY" => {
+                len == 9 && digits(&rest[..8]) && rest[8..].chars().all(|c| c.is_ascii_alphabetic())
+            }
+            "CZ" => (8..=10).contains(&len) && digits(rest),
+            "DE" | "EE" | "EL" | "PT" => len == 9 && digits(rest),
+            "DK" | "FI" | "HU" | "LU" | "MT" | "SI" => len == 8 && digits(rest),
+            "ES" => len == 9,
+            "FR" => len == 11 && digits(&rest[2..]),
+            "HR" | "IT" | "LV" => len == 11 && digits(rest),
+            "IE" => (len == 8 || len == 9) && digits(&rest[..1]),
+            "LT" => (len == 9 || len == 12) && digits(rest),
+            "NL" => len == 12 && digits(&rest[..9]) && &rest[9..10] == "B" && digits(&rest[10..]),
+            "PL" | "SK" => len == 10 && digits(rest),
+            "RO" => (2..=10).contains(&len) && digits(rest),
+            "SE" => len == 12 && digits(rest) && rest.ends_with("01"),
+            _ => return Err("btw_country"),
+        };
+        if !format_ok {
+            return Err("btw_format");
+        }
+
+        let checksum_ok = match country {
+            "BE" => 97 - number(&rest[..8]) % 97 == number(&rest[8..]),
+            // Since 2020 sole proprietors get numbers that only pass the mod 97 check
+            "NL" => {
+                let weighted: u64 = rest[..8]
+                    .chars()
+                    .zip((2..=9).rev())
+                    .map(|(c, w)| c.to_digit(10).unwrap() as u64 * w)
+                    .sum();
+                weighted % 11 == number(&rest[8..9]) || mod97(&vat) == 1
+            }
+            "LU" => number(&rest[..6]) % 89 == number(&rest[6..]),
+            "DE" => {
+                let mut product = 10;
+                for c in rest[..8].chars() {
+                    let mut sum = (c.to_digit(10).unwrap() + product) % 10;
+                    if sum == 0 {
+                        sum = 10;
+                    }
+                    product = (2 * sum) % 11;
+                }
+                (11 - product) % 10 == rest[8..].parse::<u32>().unwrap()
+            }
+            // The key is numeric for companies with a SIREN number
+            "FR" if digits(&rest[..2]) => {
+                (12 + 3 * (number(&rest[2..]) % 97)) % 97 == number(&rest[..2])
+            }
+            _ => true,
+        };
+        match checksum_ok {
+            true => Ok(vat),
+            false => Err("btw_checksum"),
+        }
+    }
+
+    pub fn encrypt(plaintext: &str) -> String {
+        let (id, key) = current_key();
+        encrypt_with(id, key, plaintext)
+    }
+
+    fn encrypt_with(id: &str, key: &[u8; 32], plaintext: &str) -> String {
+        let nonce: [u8; 12] = rand::thread_rng().gen();
+        let mut ciphertext = vec![0u8; plaintext.len()];
+        let mut tag = [0u8; 16];
+        AesGcm::new(KeySize::KeySize256, key, &nonce, AAD).encrypt(
+            plaintext.as_bytes(),
+            &mut ciphertext,
+            &mut tag,
+        );
+
+        format!(
+            "{}{}:{}:{}:{}",
+            PREFIX,
+            id,
+            sync::to_hex(&nonce),
+            sync::to_hex(&ciphertext),
+            sync::to_hex(&tag)
+        )
+    }
+
+    fn from_hex(value: &str) -> Option<std::vec::Vec<u8>> {
+        if value.len() % 2 != 0 {
+            return None;
+        }
+        (0..value.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+            .collect()
+    }
+
+    // None when the key of the value isn't configured or the value was tampered with
+    pub fn decrypt(stored: &str) -> Option<String> {
+        match stored.strip_prefix(LEGACY_PREFIX) {
+            Some(rest) => decrypt_with(&(*LEGACY_KEY)?, rest),
+            None => decrypt_with_keys(&KEYS, stored),
+        }
+    }
+
+    fn decrypt_with_keys(keys: &[(String, [u8; 32])], stored: &str) -> Option<String> {
+        let mut parts = stored.strip_prefix(PREFIX)?.splitn(2, ':');
+        let id = parts.next()?;
+        let (_, key) = keys.iter().find(|(key_id, _)| key_id == id)?;
+        decrypt_with(key, parts.next()?)
+    }
+
+    fn decrypt_with(key: &[u8; 32], rest: &str) -> Option<String> {
+        let mut parts = rest.split(":");
+        let nonce = from_hex(parts.next()?)?;
+        let ciphertext = from_hex(parts.next()?)?;
+        let tag = from_hex(parts.next()?)?;
+        if nonce.len() != 12 || tag.len() != 16 || parts.next().is_some() {
+            return None;
+        }
+
+        let mut plaintext = vec![0u8; ciphertext.len()];
+        match AesGcm::new(KeySize::KeySize256, key, &nonce, AAD).decrypt(
+            &ciphertext,
+            &mut plaintext,
+            &tag,
+        ) {
+            true => String::from_utf8(plaintext).ok(),
+            false => None,
+        }
+    }
+
+    // Bank account numbers saved before encryption, or with a key that's no longer the
+    // current one, are encrypted with the current key at startup
+    pub async fn reencrypt(mysql: MySQL) {
+        let mysql = web::Data::new(mysql);
+        let current_prefix = format!("{}{}:", PREFIX, current_key().0);
+
+        for (id, bankaccountnr) in data::get_bankaccountnrs_to_encrypt(&mysql, &current_prefix)
+            .await
+            .unwrap_or_default()
+        {
+            let plaintext = match bankaccountnr.starts_with(LEGACY_PREFIX)
+                || bankaccountnr.starts_with(PREFIX)
+            {
+                true => match decrypt(&bankaccountnr) {
+                    Some(plaintext) => plaintext,
+                    None => {
+                        println!(
+                            "error: bank account of distributor {} can't be decrypted",
+                            id
+                        );
+                        continue;
+                    }
+                },
+                false => check_iban(&bankaccountnr).unwrap_or(compact(&bankaccountnr)),
+            };
+            data::update_bankaccountnr(&mysql, id, &encrypt(&plaintext)).await;
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn mod97_of_an_iban() {
+            assert_eq!(mod97("539007547034BE68"), 1);
+            assert_eq!(mod97("123"), 26);
+        }
+
+        #[test]
+        fn valid_ibans() {
+            assert_eq!(
+                check_iban("BE68539007547034"),
+                Ok("BE68539007547034".to_string())
+            );
+            assert_eq!(
+                check_iban("NL91ABNA0417164300"),
+                Ok("NL91ABNA0417164300".to_string())
+            );
+            assert_eq!(
+                check_iban("LU280019400644750000"),
+                Ok("LU280019400644750000".to_string())
+            );
+        }
+
+        #[test]
+        fn iban_with_spaces_and_lowercase() {
+            assert_eq!(
+                check_iban(" be68 5390 0754 7034 "),
+                Ok("BE68539007547034".to_string())
+            );
+            assert_eq!(
+                check_iban("nl91-abna-0417-1643-00"),
+                Ok("NL91ABNA0417164300".to_string())
+            );
+        }
+
+        #[test]
+        fn invalid_ibans() {
+            assert_eq!(check_iban("BE68539007547035"), Err("iban_checksum"));
+            assert_eq!(check_iban("NL91ABNA0417164301"), Err("iban_checksum"));
+            assert_eq!(check_iban("LU280019400644750001"), Err("iban_checksum"));
+            assert_eq!(check_iban("BE6853900754703"), Err("iban_length"));
+            assert_eq!(check_iban("NL91ABNA04171643000"), Err("iban_length"));
+            assert_eq!(check_iban("XX68539007547034"), Err("iban_country"));
+            assert_eq!(check_iban("68BE539007547034"), Err("iban_format"));
+            assert_eq!(check_iban("BE68"), Err("iban_format"));
+            assert_eq!(check_iban(""), Err("iban_format"));
+            assert_eq!(check_iban("BE68é39007547034"), Err("iban_format"));
+        }
+
+        #[test]
+        fn belgian_vat_numbers() {
+            assert_eq!(check_vat("BE0417497106"), Ok("BE0417497106".to_string()));
+            assert_eq!(check_vat("be 0417.497.106"), Ok("BE0417497106".to_string()));
+            assert_eq!(check_vat("BE0417497107"), Err("btw_checksum"));
+            assert_eq!(check_vat("BE2417497106"), Err("btw_format"));
+            assert_eq!(check_vat("BE041749710"), Err("btw_format"));
+        }
+
+        #[test]
+        fn dutch_and_luxembourg_vat_numbers() {
+            assert_eq!(
+                check_vat("NL004495445B01"),
+                Ok("NL004495445B01".to_string())
+            );
+            // Sole proprietor number, only passes the mod 97 check
+            assert_eq!(
+                check_vat("NL000099998B57"),
+                Ok("NL000099998B57".to_string())
+            );
+            assert_eq!(check_vat("NL004495446B01"), Err("btw_checksum"));
+            assert_eq!(check_vat("LU15027442"), Ok("LU15027442".to_string()));
+            assert_eq!(check_vat("LU15027443"), Err("btw_checksum"));
+        }
+
+        #[test]
+        fn invalid_vat_numbers() {
+            assert_eq!(check_vat("XX0417497106"), Err("btw_country"));
+            assert_eq!(check_vat("BE"), Err("btw_format"));
+            assert_eq!(check_vat(""), Err("btw_format"));
+        }
+
+        #[test]
+        fn masked_iban_shows_country_and_last_digits() {
+            assert_eq!(mask_iban("BE68539007547034"), "BE** **** **** 7034");
+        }
+
+        #[test]
+        fn masked_short_input_shows_nothing() {
+            assert_eq!(mask_iban(""), "");
+            assert_eq!(mask_iban("BE68"), "****");
+            assert_eq!(mask_iban("BE68539"), "*******");
+            assert_eq!(mask_iban("é1234567"), "é1** 4567");
+        }
+
+        fn test_keys() -> std::vec::Vec<(String, [u8; 32])> {
+            parse_keys(&format!("2:{}, 1:{}", "ab".repeat(32), "01".repeat(32))).unwrap()
+        }
+
+        #[test]
+        fn keys_are_read_in_order() {
+            let keys = test_keys();
+            assert_eq!(keys.len(), 2);
+            assert_eq!(keys[0].0, "2");
+            assert_eq!(keys[0].1, [0xab; 32]);
+            assert_eq!(keys[1].0, "1");
+            assert_eq!(keys[1].1, [0x01; 32]);
+        }
+
+        #[test]
+        fn invalid_keys() {
+            assert_eq!(parse_keys(""), Err("key_missing"));
+            assert_eq!(parse_keys(&"ab".repeat(32)), Err("key_hex"));
+            assert_eq!(parse_keys(&format!(":{}", "ab".repeat(32))), Err("key_id"));
+            assert_eq!(
+                parse_keys(&format!("a-1:{}", "ab".repeat(32))),
+                Err("key_id")
+            );
+            assert_eq!(parse_keys("1"), Err("key_hex"));
+            assert_eq!(
+                parse_keys(&format!("1:{}", "zz".repeat(32))),
+                Err("key_hex")
+            );
+            assert_eq!(
+                parse_keys(&format!("1:{}", "ab".repeat(16))),
+                Err("key_length")
+            );
+            assert_eq!(
+                parse_keys(&format!("1:{},1:{}", "ab".repeat(32), "cd".repeat(32))),
+                Err("key_id_duplicate")
+            );
+        }
+
+        #[test]
+        fn encrypted_value_holds_its_key_id() {
+            let keys = test_keys();
+            let stored = encrypt_with(&keys[0].0, &keys[0].1, "BE68539007547034");
+
+            assert!(stored.starts_with("v2:2:"));
+            assert_eq!(
+                decrypt_with_keys(&keys, &stored),
+                Some("BE68539007547034".to_string())
+            );
+        }
+
+        #[test]
+        fn older_key_still_decrypts() {
+            let keys = test_keys();
+            let stored = encrypt_with(&keys[1].0, &keys[1].1, "BE68539007547034");
+
+            assert_eq!(
+                decrypt_with_keys(&keys, &stored),
+                Some("BE68539007547034".to_string())
+            );
+            // Once the old key is removed
+            assert_eq!(decrypt_with_keys(&keys[..1], &stored), None);
+        }
+
+        #[test]
+        fn tampered_or_unknown_values_are_not_decrypted() {
+            let keys = test_keys();
+            let stored = encrypt_with(&keys[0].0, &keys[0].1, "BE68539007547034");
+
+            assert_eq!(
+                decrypt_with_keys(&keys, &stored.replacen("v2:2:", "v2:3:", 1)),
+                None
+            );
+            // Another last digit of the tag
+            let last = match stored.ends_with('0') {
+                true => "1",
+                false => "0",
+            };
+            let tampered = format!("{}{}", &stored[..stored.len() - 1], last);
+            assert_eq!(decrypt_with_keys(&keys, &tampered), None);
+            assert_eq!(decrypt_with_keys(&keys, "BE68539007547034"), None);
+            assert_eq!(decrypt_with_keys(&keys, "v2:2:00"), None);
+        }
+    }
+}
+
+pub mod branding {
+    use crate::*;
+    use image::imageops::FilterType;
+    use image::{GenericImageView, ImageFormat, ImageOutputFormat};
+    use std::io::Cursor;
+
+    // Served under /uploads, outside of the templates
+    pub const UPLOAD_DIR: &str = "./uploads";
+    pub const MAX_UPLOAD_BYTES: usize = 5 * 1024 * 1024;
+    // Larger images are refused before decoding them
+    const MAX_SOURCE_PIXELS: u64 = 40_000_000;
+
+    #[derive(PartialEq, Debug, Clone, Copy)]
+    pub enum BrandingImage {
+        Logo,
+        Cover,
+    }
+
+    impl BrandingImage {
+        // Path segment in the admin, e.g. /admin/dashboard/huisstijl/omslag
+        pub fn from_path(segment: &str) -> Option<BrandingImage> {
+            match segment {
+                "logo" => Some(BrandingImage::Logo),
+                "omslag" => Some(BrandingImage::Cover),
+                _ => None,
+            }
+        }
+
+        fn max_size(&self) -> (u32, u32) {
+            match self {
+                BrandingImage::Logo => (600, 300),
+                BrandingImage::Cover => (1920, 640),
+            }
+        }
+
+        fn extension(&self) -> &'static str {
+            match self {
+                BrandingImage::Logo => "png",
+                BrandingImage::Cover => "jpg",
+            }
+        }
+    }
+
+    // Hex color from a color input, e.g. "#1F3A5F"
+    pub fn check_color(input: &str) -> Option<String> {
+        let color = input.trim().to_lowercase();
+        if color.len() == 7
+            && color.starts_with("#")
+            && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+        {
+            Some(color)
+        } else {
+            None
+        }
+    }
+
+    // Checks the declared type against the contents and re-encodes the image, so only
+    // pixels of the upload end up on disk. Logos stay PNG for transparency.
+    fn process_image(
+        image: BrandingImage,
+        content_type: &str,
+        bytes: &[u8],
+    ) -> std::result::Result<std::vec::Vec<u8>, &'static str> {
+        let format = match content_type {
+            "image/png" => ImageFormat::Png,
+            "image/jpeg" => ImageFormat::Jpeg,
+            "image/webp" => ImageFormat::WebP,
+            _ => return Err("invalid_content_type"),
+        };
+        if image::guess_format(bytes).ok() != Some(format) {
+            return Err("invalid_image");
+        }
+
+        let mut reader = image::io::Reader::new(Cursor::new(bytes));
+        reader.set_format(format);
+        let (width, height) = reader.into_dimensions().map_err(|_| "invalid_image")?;
+        if width as u64 * height as u64 > MAX_SOURCE_PIXELS {
+            return Err("image_too_large");
+        }
+
+        let mut decoded =
+            image::load_from_memory_with_format(bytes, format).map_err(|_| "invalid_image")?;
+        let (max_width, max_height) = image.max_size();
+        if decoded.width() > max_width || decoded.height() > max_height {
+            decoded = decoded.resize(max_width, max_height, FilterType::Lanczos3);
+        }
+
+        let mut output = std::vec::Vec::new();
+        let written = match image {
+            BrandingImage::Logo => decoded.write_to(&mut output, ImageOutputFormat::Png),
+            BrandingImage::Cover => image::DynamicImage::ImageRgb8(decoded.to_rgb8())
+                .write_to(&mut output, ImageOutputFormat::Jpeg(85)),
+        };
+        written.map_err(|_| "invalid_image")?;
+
+        Ok(output)
+    }
+
+    // Returns the file name, a new one on every upload so browsers don't show the old image
+    pub async fn save_image(
+        tenant: data::Tenant,
+        image: BrandingImage,
+        content_type: String,
+        bytes: web::Bytes,
+    ) -> std::result::Result<String, &'static str> {
+        let file_name = format!(
+            "{}-{}-{}.{}",
+            tenant.distributor_id,
+            format!("{:?}", image).to_lowercase(),
+            &generate_token()[..16],
+            image.extension()
+        );
+        let path = std::path::Path::new(UPLOAD_DIR).join(&file_name);
+
+        web::block(move || -> std::result::Result<(), &'static str> {
+            let output = process_image(image, &content_type, &bytes)?;
+            std::fs::create_dir_all(UPLOAD_DIR).map_err(|_| "upload_failed")?;
+            std::fs::write(&path, output).map_err(|_| "upload_failed")
+        })
+        .await
+        .map_err(|e| match e {
+            actix_web::error::BlockingError::Error(e) => e,
+            actix_web::error::BlockingError::Canceled => "upload_failed",
+        })?;
+
+        Ok(file_name)
+    }
+
+    pub async fn remove_image(file_name: String) {
+        // Only names made by save_image, never a path
+        if file_name.contains("/") || file_name.contains("..") {
+            return;
+        }
+
+        let _ = web::block(move || {
+            std::fs::remove_file(std::path::Path::new(UPLOAD_DIR).join(file_name))
+        })
+        .await;
+    }
+}
+
+pub mod tenancy {
+    use crate::*;
+    use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+    use actix_web::HttpMessage;
+    use futures::future::{ok, LocalBoxFuture, Ready};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::task::{Context, Poll};
+
+    // A resolved host is served from memory for this long
+    const CACHE_TTL_SECONDS: i64 = 60;
+    const CACHE_MAX_ENTRIES: usize = 10000;
+
+    // Not bound to a distributor: Mollie calls the hook on our own host and assets are shared
+    const EXEMPT_PATHS: &[&str] = &["/payment/hook", "/assets/", "/uploads/"];
+
+    #[derive(Clone)]
+    pub enum HostTenant {
+        Current(Distributor),
+        // Previous subdomain of a distributor that was renamed
+        Renamed(Distributor),
+        // Never cached, anyone can send requests for endless made up hosts
+        Unknown,
+    }
+
+    impl HostTenant {
+        fn distributor_id(&self) -> Option<u64> {
+            match self {
+                HostTenant::Current(d) | HostTenant::Renamed(d) => Some(d.id),
+                HostTenant::Unknown => None,
+            }
+        }
+    }
+
+    struct CachedHost {
+        tenant: HostTenant,
+        fetched: DateTime<Utc>,
+    }
+
+    static CACHE: Lazy<Mutex<HashMap<String, CachedHost>>> =
+        Lazy::new(|| Mutex::new(HashMap::new()));
+
+    // Raised by every invalidation, a lookup that started before it doesn't store its result
+    static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+    pub async fn lookup(mysql: &web::Data<MySQL>, host: &str) -> HostTenant {
+        let fresh_after = Utc::now() - Duration::seconds(CACHE_TTL_SECONDS);
+
+        if let Some(cached) = CACHE.lock().await.get(host) {
+            if cached.fetched > fresh_after {
+                return cached.tenant.clone();
+            }
+        }
+
+        let generation = GENERATION.load(Ordering::SeqCst);
+        let tenant = match data::get_distributor_by_host(mysql, host).await {
+            Some(distributor) => HostTenant::Current(distributor),
+            None => match host.strip_suffix(&format!(".{}", PLATFORM_DOMAIN)) {
+                Some(subdomain) => data::get_distributor_by_previous_subdomain(mysql, subdomain)
+                    .await
+                    .map(HostTenant::Renamed)
+                    .unwrap_or(HostTenant::Unknown),
+                None => HostTenant::Unknown,
+            },
+        };
+
+        if let HostTenant::Unknown = tenant {
+            return tenant;
+        }
+
+        let mut cache = CACHE.lock().await;
+        if generation == GENERATION.load(Ordering::SeqCst) {
+            if cache.len() >= CACHE_MAX_ENTRIES {
+                cache.retain(|_, c| c.fetched > fresh_after);
+            }
+            // Still full of fresh entries, this host is looked up again next time
+            if cache.len() >= CACHE_MAX_ENTRIES {
+                return tenant;
+            }
+            cache.insert(
+                host.to_string(),
+                CachedHost {
+                    tenant: tenant.clone(),
+                    fetched: Utc::now(),
+                },
+            );
+        }
+
+        tenant
+    }
+
+    // Called by every data function that changes a distributor or its domains
+    pub async fn forget(distributor_id: u64) {
+        GENERATION.fetch_add(1, Ordering::SeqCst);
+        CACHE
+            .lock()
+            .await
+            .retain(|_, c| c.tenant.distributor_id() != Some(distributor_id));
+    }
+
+    // Distributor of the storefront or admin this request is for, None on the platform domain
+    pub fn current(req: &HttpRequest) -> Option<Distributor> {
+        req.extensions().get::<Distributor>().cloned()
+    }
+
+    // Resolves the distributor of the host once per request and places it in the
+    // request extensions. Old subdomains are redirected, unknown hosts get the 404 page.
+    #[derive(Clone)]
+    pub struct ResolveTenant {
+        mysql: web::Data<MySQL>,
+    }
+
+    impl ResolveTenant {
+        pub fn new(mysql: MySQL) -> ResolveTenant {
+            ResolveTenant {
+                mysql: web::Data::new(mysql),
+            }
+        }
+    }
+
+    impl<S, B> Transform<S> for ResolveTenant
+    where
+        S: Service<
+                Request = ServiceRequest,
+                Response = ServiceResponse<B>,
+                Error = actix_web::Error,
+            > + 'static,
+        S::Future: 'static,
+        B: 'static,
+    {
+        type Request = ServiceRequest;
+        type Response = ServiceResponse<B>;
+        type Error = actix_web::Error;
+        type InitError = ();
+        type Transform = ResolveTenantMiddleware<S>;
+        type Future = Ready<std::result::Result<Self::Transform, Self::InitError>>;
+
+        fn new_transform(&self, service: S) -> Self::Future {
+            ok(ResolveTenantMiddleware {
+                service: Rc::new(RefCell::new(service)),
+                inner: Rc::new(self.clone()),
+            })
+        }
+    }
+
+    pub struct ResolveTenantMiddleware<S> {
+        service: Rc<RefCell<S>>,
+        inner: Rc<ResolveTenant>,
+    }
+
+    impl<S, B> Service for ResolveTenantMiddleware<S>
+    where
+        S: Service<
+                Request = ServiceRequest,
+                Response = ServiceResponse<B>,
+                Error = actix_web::Error,
+            > + 'static,
+        S::Future: 'static,
+        B: 'static,
+    {
+        type Request = ServiceRequest;
+        type Response = ServiceResponse<B>;
+        type Error = actix_web::Error;
+        type Future = LocalBoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, cx: &mut Context) -> Poll<std::result::Result<(), Self::Error>> {
+            self.service.borrow_mut().poll_ready(cx)
+        }
+
+        fn call(&mut self, req: ServiceRequest) -> Self::Future {
+            let service = self.service.clone();
+            let inner = self.inner.clone();
+
+            Box::pin(async move {
+                let exempt = EXEMPT_PATHS.iter().any(|p| req.path().starts_with(p));
+
+                if !exempt && !is_platform_host(req.request()) {
+                    match lookup(&inner.mysql, &request_host(req.request())).await {
+                        HostTenant::Current(distributor) => {
+                            req.extensions_mut().insert(distributor);
+                        }
+                        HostTenant::Renamed(distributor) => {
+                            let location = format!(
+                                "{}{}",
+                                distributor_url(&distributor),
+                                req.uri()
+                                    .path_and_query()
+                                    .map(|p| p.as_str())
+                                    .unwrap_or("/")
+                            );
+                            // 308 keeps the method and body, e.g. for scanners posting to the old host
+                            let mut response = match *req.method() {
+                                http::Method::GET | http::Method::HEAD => {
+                                    HttpResponse::MovedPermanently()
+                                }
+                                _ => HttpResponse::PermanentRedirect(),
+                            };
+                            return Ok(req.into_response(
+                                response
+                                    .header(http::header::LOCATION, location)
+                                    .finish()
+                                    .into_body(),
+                            ));
+                        }
+                        HostTenant::Unknown => {
+                            let body = Error404.render().unwrap();
+                            return Ok(req.into_response(
+                                HttpResponse::NotFound()
+                                    .content_type("text/html")
+                                    .body(body)
+                                    .into_body(),
+                            ));
+                        }
+                    }
+                }
+
+                let fut = service.borrow_mut().call(req);
+                fut.await
+            })
+        }
+    }
+}
+
+pub mod domains {
+    use crate::*;
+    use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+    use futures::future::{ok, LocalBoxFuture, Ready};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::task::{Context, Poll};
+    use trust_dns_resolver::Resolver;
+
+    // The distributor proves ownership of a domain with a TXT record on this name
+    pub fn verification_record_name(domain: &str) -> String {
+        format!("_kaddo-verificatie.{}", domain)
+    }
+
+    pub fn verification_record_value(token: &str) -> String {
+        format!("kaddo-verificatie={}", token)
+    }
+
+    // The resolver blocks, so it runs on the thread pool
+    async fn lookup_txt(name: String) -> std::vec::Vec<String> {
+        web::block(move || -> std::result::Result<std::vec::Vec<String>, ()> {
+            let resolver = Resolver::from_system_conf().map_err(|_| ())?;
+            let response = resolver.txt_lookup(name.as_str()).map_err(|_| ())?;
+
+            Ok(response
+                .iter()
+                .map(|txt| {
+                    txt.txt_data()
+                        .iter()
+                        .map(|d| String::from_utf8_lossy(d).to_string())
+                        .collect::<String>()
+                })
+                .collect())
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    pub async fn has_verification_record(domain: &DistributorDomain) -> bool {
+        let expected = verification_record_value(&domain.verification_token);
+
+        lookup_txt(verification_record_name(&domain.domain))
+            .await
+            .iter()
+            .any(|value| value.trim() == expected)
+    }
+
+    // Sends visitors of a storefront to its primary domain, so links and sessions
+    // always end up on one host. Runs after tenancy::ResolveTenant.
+    #[derive(Clone)]
+    pub struct CanonicalHost;
+
+    impl CanonicalHost {
+        pub fn new() -> CanonicalHost {
+            CanonicalHost
+        }
+    }
+
+    impl<S, B> Transform<S> for CanonicalHost
+    where
+        S: Service<
+                Request = ServiceRequest,
+                Response = ServiceResponse<B>,
+                Error = actix_web::Error,
+            > + 'static,
+        S::Future: 'static,
+        B: 'static,
+    {
+        type Request = ServiceRequest;
+        type Response = ServiceResponse<B>;
+        type Error = actix_web::Error;
+        type InitError = ();
+        type Transform = CanonicalHostMiddleware<S>;
+        type Future = Ready<std::result::Result<Self::Transform, Self::InitError>>;
+
+        fn new_transform(&self, service: S) -> Self::Future {
+            ok(CanonicalHostMiddleware {
+                service: Rc::new(RefCell::new(service)),
+            })
+        }
+    }
+
+    pub struct CanonicalHostMiddleware<S> {
+        service: Rc<RefCell<S>>,
+    }
+
+    impl<S, B> Service for CanonicalHostMiddleware<S>
+    where
+        S: Service<
+                Request = ServiceRequest,
+                Response = ServiceResponse<B>,
+                Error = actix_web::Error,
+            > + 'static,
+        S::Future: 'static,
+        B: 'static,
+    {
+        type Request = ServiceRequest;
+        type Response = ServiceResponse<B>;
+        type Error = actix_web::Error;
+        type Future = LocalBoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, cx: &mut Context) -> Poll<std::result::Result<(), Self::Error>> {
+            self.service.borrow_mut().poll_ready(cx)
+        }
+
+        fn call(&mut self, req: ServiceRequest) -> Self::Future {
+            let service = self.service.clone();
+
+            Box::pin(async move {
+                // Forms are never redirected, the body would get lost
+                let safe_method = match *req.method() {
+                    http::Method::GET | http::Method::HEAD => true,
+                    _ => false,
+                };
+
+                if safe_method {
+                    let host = request_host(req.request());
+                    let primary = tenancy::current(req.request()).and_then(|d| d.primary_domain);
+
+                    if let Some(primary) = primary {
+                        if primary != host {
+                            let location = format!(
+                                "https://{}{}",
+                                primary,
+                                req.uri()
+                                    .path_and_query()
+                                    .map(|p| p.as_str())
+                                    .unwrap_or("/")
+                            );
+
+                            return Ok(req.into_response(
+                                HttpResponse::MovedPermanently()
+                                    .header(http::header::LOCATION, location)
+                                    .finish()
+                                    .into_body(),
+                            ));
+                        }
+                    }
+                }
+
+                let fut = service.borrow_mut().call(req);
+                fut.await
+            })
+        }
+    }
+}
+
+pub mod csrf {
+    use crate::*;
+    use actix_web::cookie::{Cookie, SameSite};
+    use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+    use actix_web::HttpMessage;
+    use futures::future::{ok, LocalBoxFuture, Ready};
+    use futures::StreamExt;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::task::{Context, Poll};
+
+    // Double-submit token: the cookie holds the token and every state-changing request
+    // has to repeat it in the X-CSRF-Token header or a csrf_token form field
+    pub const COOKIE_NAME: &str = "kaddo-csrf";
+    pub const HEADER_NAME: &str = "X-CSRF-Token";
+    pub const FIELD_NAME: &str = "csrf_token";
+
+    // Called by Mollie, which can't know the token
+    const EXEMPT_PATHS: &[&str] = &["/payment/hook"];
+
+    // The default limit of web::Form, a larger form body would be refused by the handler anyway
+    const FORM_LIMIT: usize = 16_384;
+
+    #[derive(Clone)]
+    struct CsrfToken(String);
+
+    // Token for the hidden csrf_token field of forms in templates
+    pub fn token(req: &HttpRequest) -> String {
+        req.extensions()
+            .get::<CsrfToken>()
+            .map(|t| t.0.to_string())
+            .unwrap_or_default()
+    }
+
+    #[derive(Clone)]
+    pub struct Csrf {
+        secure: bool,
+    }
+
+    impl Csrf {
+        pub fn new() -> Csrf {
+            Csrf { secure: true }
+        }
+
+        pub fn secure(mut self, secure: bool) -> Csrf {
+            self.secure = secure;
+            self
+        }
+    }
+
+    impl<S, B> Transform<S> for Csrf
+    where
+        S: Service<
+                Request = ServiceRequest,
+                Response = ServiceResponse<B>,
+                Error = actix_web::Error,
+            > + 'static,
+        S::Future: 'static,
+        B: 'static,
+    {
+        type Request = ServiceRequest;
+        type Response = ServiceResponse<B>;
+        type Error = actix_web::Error;
+        type InitError = ();
+        type Transform = CsrfMiddleware<S>;
+        type Future = Ready<std::result::Result<Self::Transform, Self::InitError>>;
+
+        fn new_transform(&self, service: S) -> Self::Future {
+            ok(CsrfMiddleware {
+                service: Rc::new(RefCell::new(service)),
+                inner: Rc::new(self.clone()),
+            })
+        }
+    }
+
+    pub struct CsrfMiddleware<S> {
+        service: Rc<RefCell<S>>,
+        inner: Rc<Csrf>,
+    }
+
+    impl<S, B> Service for CsrfMiddleware<S>
+    where
+        S: Service<
+                Request = ServiceRequest,
+                Response = ServiceResponse<B>,
+                Error = actix_web::Error,
+            > + 'static,
+        S::Future: 'static,
+        B: 'static,
+    {
+        type Request = ServiceRequest;
+        type Response = ServiceResponse<B>;
+        type Error = actix_web::Error;
+        type Future = LocalBoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, cx: &mut Context) -> Poll<std::result::Result<(), Self::Error>> {
+            self.service.borrow_mut().poll_ready(cx)
+        }
+
+        fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+            let service = self.service.clone();
+            let inner = self.inner.clone();
+
+            Box::pin(async move {
+                let cookie_token = req.cookie(COOKIE_NAME).map(|c| c.value().to_string());
+                let token = cookie_token.clone().unwrap_or_else(generate_token);
+                req.extensions_mut().insert(CsrfToken(token.to_string()));
+
+                let safe_method = match *req.method() {
+                    http::Method::GET | http::Method::HEAD | http::Method::OPTIONS => true,
+                    _ => false,
+                };
+
+                if !safe_method && !EXEMPT_PATHS.contains(&req.path()) {
+                    let submitted = match req.headers().get(HEADER_NAME) {
+                        Some(header) => header.to_str().ok().map(|h| h.to_string()),
+                        None if req.content_type() == "application/x-www-form-urlencoded" => {
+                            form_token(&mut req).await?
+                        }
+                        None => None,
+                    };
+
+                    let valid = match (&cookie_token, &submitted) {
+                        (Some(expected), Some(submitted)) => {
+                            crypto::util::fixed_time_eq(expected.as_bytes(), submitted.as_bytes())
+                        }
+                        _ => false,
+                    };
+
+                    if !valid {
+                        return Ok(req.into_response(
+                            HttpResponse::Forbidden()
+                                .content_type("text/plain")
+                                .body("invalid_csrf_token")
+                                .into_body(),
+                        ));
+                    }
+                }
+
+                let fut = service.borrow_mut().call(req);
+                let mut res = fut.await?;
+
+                if cookie_token.is_none() {
+                    // Readable by scripts on purpose, they send it back in the header
+                    let cookie = Cookie::build(COOKIE_NAME, token)
+                        .path("/")
+                        .secure(inner.secure)
+                        .same_site(SameSite::Strict)
+                        .finish();
+                    res.response_mut().add_cookie(&cookie)?;
+                }
+
+                Ok(res)
+            })
+        }
+    }
+
+    // Reads the token from a form body and puts the body back for the handler. Stops reading
+    // at FORM_LIMIT so an unauthenticated request can't make us buffer an unbounded body.
+    async fn form_token(
+        req: &mut ServiceRequest,
+    ) -> std::result::Result<Option<String>, actix_web::Error> {
+        let declared_length = req
+            .headers()
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|l| l.to_str().ok())
+            .and_then(|l| l.parse::<usize>().ok());
+        if declared_length.map(|l| l > FORM_LIMIT).unwrap_or(false) {
+            return Err(actix_web::error::ErrorPayloadTooLarge("payload_too_large"));
+        }
+
+        let mut payload = req.take_payload();
+        let mut body = web::BytesMut::new();
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk?;
+            if body.len() + chunk.len() > FORM_LIMIT {
+                return Err(actix_web::error::ErrorPayloadTooLarge("payload_too_large"));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        let fields: HashMap<String, String> =
+            serde_urlencoded::from_bytes(&body).unwrap_or_default();
+
+        let (_, mut restored) = actix_http::h1::Payload::create(true);
+        restored.unread_data(body.freeze());
+        req.set_payload(restored.into());
+
+        Ok(fields.get(FIELD_NAME).cloned())
+    }
+}
+
+pub mod totp {
+    use crate::*;
+    use crypto::hmac::Hmac;
+    use crypto::mac::Mac;
+    use crypto::sha1::Sha1;
+
+    // RFC 6238 defaults, these are what every authenticator app supports
+    const PERIOD: i64 = 30;
+    const DIGITS: u32 = 6;
+    // Accept the previous and next code as well to allow for clock drift
+    const ALLOWED_DRIFT: i64 = 1;
+
+    const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    pub fn generate_secret() -> String {
+        let bytes: [u8; 20] = rand::thread_rng().gen();
+        base32_encode(&bytes)
+    }
+
+    pub fn base32_encode(bytes: &[u8]) -> String {
+        let mut encoded = String::new();
+        let mut buffer: u32 = 0;
+        let mut bits = 0;
+
+        for byte in bytes {
+            buffer = (buffer << 8) | *byte as u32;
+            bits += 8;
+            while bits >= 5 {
+                encoded.push(BASE32_ALPHABET[((buffer >> (bits - 5)) & 31) as usize] as char);
+                bits -= 5;
+            }
+        }
+        if bits > 0 {
+            encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+        }
+
+        encoded
+    }
+
+    pub fn base32_decode(input: &str) -> Option<std::vec::Vec<u8>> {
+        let mut decoded = std::vec::Vec::new();
+        let mut buffer: u32 = 0;
+        let mut bits = 0;
+
+        for c in input.trim_end_matches('=').chars() {
+            let value = BASE32_ALPHABET
+                .iter()
+                .position(|a| *a as char == c.to_ascii_uppercase())?;
+            buffer = (buffer << 5) | value as u32;
+            bits += 5;
+            if bits >= 8 {
+                decoded.push(((buffer >> (bits - 8)) & 255) as u8);
+                bits -= 8;
+            }
+        }
+
+        Some(decoded)
+    }
+
+    pub fn code_at(secret: &[u8], step: u64) -> u32 {
+        let mut hmac = Hmac::new(Sha1::new(), secret);
+        hmac.input(&step.to_be_bytes());
+        let result = hmac.result();
+        let digest = result.code();
+
+        let offset = (digest[digest.len() - 1] & 15) as usize;
+        let binary = ((digest[offset] as u32 & 127) << 24)
+            | ((digest[offset + 1] as u32) << 16)
+            | ((digest[offset + 2] as u32) << 8)
+            | (digest[offset + 3] as u32);
+
+        binary % 10u32.pow(DIGITS)
+    }
+
+    // Returns the time step the code belongs to, so it can be marked as used
+    pub fn verify(secret: &str, code: &str, timestamp: i64) -> Option<u64> {
+        let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+        if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let code = code.parse::<u32>().ok()?;
+        let secret = base32_decode(secret)?;
+
+        let current_step = timestamp / PERIOD;
+        for step in (current_step - ALLOWED_DRIFT)..=(current_step + ALLOWED_DRIFT) {
+            if step >= 0 && code_at(&secret, step as u64) == code {
+                return Some(step as u64);
+            }
+        }
+
+        None
+    }
+
+    // otpauth:// URI that authenticator apps read from the QR code
+    pub fn provisioning_uri(issuer: &str, account: &str, secret: &str) -> String {
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            uri_encode(issuer),
+            uri_encode(account),
+            secret,
+            uri_encode(issuer),
+            DIGITS,
+            PERIOD
+        )
+    }
+
+    pub fn qr_code_svg(uri: &str) -> String {
+        qrcode::QrCode::new(uri.as_bytes())
+            .unwrap()
+            .render::<qrcode::render::svg::Color>()
+            .min_dimensions(200, 200)
+            .build()
+    }
+
+    fn uri_encode(input: &str) -> String {
+        input
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    (b as char).to_string()
+                }
+                _ => format!("%{:02X}", b),
+            })
+            .collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // The SHA1 seed of the RFC 6238 test vectors, "12345678901234567890"
+        const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+        #[test]
+        fn base32_matches_rfc_4648() {
+            assert_eq!(base32_encode(b""), "");
+            assert_eq!(base32_encode(b"f"), "MY");
+            assert_eq!(base32_encode(b"fo"), "MZXQ");
+            assert_eq!(base32_encode(b"foo"), "MZXW6");
+            assert_eq!(base32_encode(b"foob"), "MZXW6YQ");
+            assert_eq!(base32_encode(b"fooba"), "MZXW6YTB");
+            assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+            assert_eq!(base32_encode(b"12345678901234567890"), RFC_SECRET);
+        }
+
+        #[test]
+        fn base32_decodes_padding_and_lowercase() {
+            assert_eq!(base32_decode("MZXW6YTBOI"), Some(b"foobar".to_vec()));
+            assert_eq!(base32_decode("mzxw6ytboi======"), Some(b"foobar".to_vec()));
+            assert_eq!(
+                base32_decode(RFC_SECRET),
+                Some(b"12345678901234567890".to_vec())
+            );
+            assert_eq!(base32_decode("MZXW1"), None);
+        }
+
+        #[test]
+        fn generated_secret_round_trips() {
+            let secret = generate_secret();
+            assert_eq!(base32_decode(&secret).map(|s| s.len()), Some(20));
+        }
+
+        #[test]
+        fn codes_match_rfc_6238() {
+            let secret = b"12345678901234567890";
+            // The 8 digit codes of the RFC, cut to our 6 digits
+            for (time, code) in [
+                (59, 287082),
+                (1111111109, 81804),
+                (1111111111, 50471),
+                (1234567890, 5924),
+                (2000000000, 279037),
+                (20000000000, 353130),
+            ]
+            .iter()
+            {
+                assert_eq!(code_at(secret, (*time / PERIOD) as u64), *code);
+            }
+        }
+
+        #[test]
+        fn verify_returns_the_step_of_the_code() {
+            assert_eq!(verify(RFC_SECRET, "287082", 59), Some(1));
+            assert_eq!(verify(RFC_SECRET, "081804", 1111111109), Some(37037036));
+            assert_eq!(verify(RFC_SECRET, "005924", 1234567890), Some(41152263));
+        }
+
+        #[test]
+        fn verify_accepts_one_step_of_drift() {
+            // The code of the previous and of the next period
+            assert_eq!(verify(RFC_SECRET, "081804", 1111111111), Some(37037036));
+            assert_eq!(verify(RFC_SECRET, "050471", 1111111109), Some(37037037));
+            assert_eq!(verify(RFC_SECRET, "287082", 89), Some(1));
+            assert_eq!(verify(RFC_SECRET, "287082", 29), Some(1));
+        }
+
+        #[test]
+        fn verify_rejects_codes_outside_the_window() {
+            assert_eq!(verify(RFC_SECRET, "081804", 1111111109 + 2 * PERIOD), None);
+            assert_eq!(verify(RFC_SECRET, "050471", 1111111111 - 2 * PERIOD), None);
+            assert_eq!(verify(RFC_SECRET, "287082", 119), None);
+        }
+
+        #[test]
+        fn verify_cleans_and_checks_the_input() {
+            assert_eq!(verify(RFC_SECRET, " 287 082 ", 59), Some(1));
+            assert_eq!(verify(RFC_SECRET, "28708", 59), None);
+            assert_eq!(verify(RFC_SECRET, "2870820", 59), None);
+            assert_eq!(verify(RFC_SECRET, "28708a", 59), None);
+            assert_eq!(verify(RFC_SECRET, "287083", 59), None);
+            assert_eq!(verify("not base32!", "287082", 59), None);
+        }
+    }
+}
+
+static BALANCE_CHECK_LIMITER: Lazy<ratelimit::RateLimiter> =
+    Lazy::new(|| ratelimit::RateLimiter::new(10, Duration::minutes(15)));
+static PASSWORD_RESET_LIMITER: Lazy<ratelimit::RateLimiter> =
+    Lazy::new(|| ratelimit::RateLimiter::new(5, Duration::hours(1)));
+
+static TWO_FACTOR_LIMITER: Lazy<ratelimit::RateLimiter> =
+    Lazy::new(|| ratelimit::RateLimiter::new(5, Duration::minutes(5)));
+
+const PASSWORD_RESET_VALID_MINUTES: i64 = 60;
+const INVITATION_VALID_DAYS: i64 = 7;
+
+// Failed logins are counted over this window
+const LOGIN_ATTEMPT_WINDOW_MINUTES: i64 = 15;
+// Failures on one account before every next attempt has to wait, up to the max delay
+const LOGIN_DELAY_AFTER: i64 = 3;
+const LOGIN_MAX_DELAY_SECONDS: i64 = 16;
+const LOGIN_LOCK_AFTER: i64 = 10;
+const LOGIN_LOCK_MINUTES: i64 = 15;
+// At most one mail about a lock per account in this time
+const LOCK_MAIL_INTERVAL_HOURS: i64 = 24;
+const LOGIN_MAX_FAILURES_PER_IP: i64 = 50;
+
+// Verified against when the user doesn't exist, so both cases take as long
+static DUMMY_PASSWORD_HASH: Lazy<String> = Lazy::new(|| {
+    let salt: [u8; 32] = rand::thread_rng().gen();
+    argon2::hash_encoded(b"dummy password", &salt, &Config::default()).unwrap()
+});
+const RECOVERY_CODE_AMOUNT: usize = 10;
+
+pub mod jobs {
+    use crate::*;
+
+    // Runs the periodic maintenance jobs in the background of the actix system
+    pub fn start(mysql: MySQL, mail: mail::Mail) {
+        let mysql = web::Data::new(mysql);
+
+        actix_web::rt::spawn(async move {
+            loop {
+                expire_vouchers(&mysql).await;
+                send_expiry_reminders(&mysql, &mail).await;
+                delete_expired_sessions(&mysql).await;
+                expire_registrations(&mysql).await;
+                actix_web::rt::time::delay_for(std::time::Duration::from_secs(60 * 60)).await;
+            }
+        });
+    }
+
+    pub async fn expire_vouchers(mysql: &web::Data<MySQL>) {
+        let expired = data::expire_vouchers(mysql).await;
+        if expired > 0 {
+            println!("[+] Marked {} vouchers as expired", expired);
+        }
+    }
+
+    pub async fn delete_expired_sessions(mysql: &web::Data<MySQL>) {
+        data::delete_expired_admin_sessions(mysql, sessions::idle_since()).await;
+    }
+
+    // Frees the subdomain and username of sign-ups that were never confirmed
+    pub async fn expire_registrations(mysql: &web::Data<MySQL>) {
+        let since = Utc::now() - Duration::days(UNVERIFIED_REGISTRATION_VALID_DAYS);
+        let ids = data::get_expired_registrations(mysql, since)
+            .await
+            .unwrap_or_default();
+
+        for id in ids {
+            if data::delete_distributor(mysql, id, "registration_expired").await {
+                println!("[+] Removed unconfirmed registration {}", id);
+            }
+        }
+    }
+
+    pub async fn send_expiry_reminders(mysql: &web::Data<MySQL>, mail: &mail::Mail) {
+        let distributors = data::get_distributors_with_reminders(mysql)
+            .await
+            .unwrap_or_default();
+
+        for distributor in distributors {
+            let offsets = distributor.reminder_offsets();
+
+            // Each offset covers the days up to the next (smaller) offset, so a voucher
+            // only gets the reminder that matches the time it has left
+            for (i, days_before) in offsets.iter().enumerate() {
+                let next_days_before = offsets.get(i + 1).cloned().unwrap_or(0);
+
+                let voucher_ids = data::get_voucher_ids_to_remind(
+                    mysql,
+                    distributor.tenant(),
+                    *days_before,
+                    next_days_before,
+                )
+                .await
+                .unwrap_or_default();
+
+                for voucher_id in voucher_ids {
+                    let voucher = data::get_voucher(
+                        mysql,
+                        distributor.tenant(),
+                        data::Selector::ById(voucher_id),
+                    )
+                    .await;
+                    if voucher.is_none() {
+                        continue;
+                    }
+
+                    // Mark first so a failing mail server doesn't cause repeated reminders, a
+                    // mail that can't be sent is skipped and the job goes on with the next one
+                    if data::add_voucher_reminder(mysql, voucher_id, *days_before).await {
+                        if let Err(e) = mail.send_expiry_reminder_mail(&voucher.unwrap()).await {
+                            println!("Error: reminder for voucher {}: {}", voucher_id, e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/* ROUTE FUNCTIONS */
+
+// Business routes
+
+async fn index(mysql: web::Data<MySQL>, req: HttpRequest) -> Result<HttpResponse> {
+    let distributor = tenancy::current(&req);
+
+    if distributor.is_none() {
+        return error404().await;
+    }
+
+    let d = distributor.unwrap();
+    if !d.is_live() {
+        return storefront_closed(&d).await;
+    }
+
+    let s = Index {
+        distributor: &d,
+        branding: &d.branding,
+        distributor_vouchers: &data::get_active_distributor_vouchers_by_distributor(
+            &mysql,
+            d.tenant(),
+        )
+        .await
+        .unwrap(),
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn bestel(mysql: web::Data<MySQL>, req: HttpRequest) -> Result<HttpResponse> {
+    let distributor = tenancy::current(&req);
+
+    if distributor.is_none() {
+        return error404().await;
+    }
+
+    let d = distributor.unwrap();
+    if !d.is_live() {
+        return storefront_closed(&d).await;
+    }
+
+    let distributor_vouchers =
+        &data::get_active_distributor_vouchers_by_distributor(&mysql, d.tenant())
+            .await
+            .unwrap();
+    let s = Bestel {
+        csrf_token: csrf::token(&req),
+        distributor_vouchers: &distributor_vouchers,
+        first_distributor_voucher: &distributor_vouchers[0],
+        branding: &d.branding,
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn faq(mysql: web::Data<MySQL>, req: HttpRequest) -> Result<HttpResponse> {
+    let distributor = match tenancy::current(&req) {
+        Some(d) => d,
+        None => return error404().await,
+    };
+    if !distributor.is_live() {
+        return storefront_closed(&distributor).await;
+    }
+
+    let entries = data::get_faq_entries(&mysql, distributor.tenant())
+        .await
+        .unwrap_or_default();
+    let s = match entries.is_empty() {
+        true => Faq.render().unwrap(),
+        false => FaqDistributor {
+            branding: distributor.branding,
+            distributor_name: distributor.name,
+            entries: entries,
+        }
+        .render()
+        .unwrap(),
+    };
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn shop_page(
+    mysql: &web::Data<MySQL>,
+    req: &HttpRequest,
+    kind: PageKind,
+    slug: &str,
+) -> Result<HttpResponse> {
+    let distributor = match tenancy::current(req) {
+        Some(d) => d,
+        None => return error404().await,
+    };
+    if !distributor.is_live() {
+        return storefront_closed(&distributor).await;
+    }
+
+    let page = match data::get_published_shop_page(mysql, distributor.tenant(), kind, slug).await {
+        Some(page) => page,
+        None => return error404().await,
+    };
+
+    let s = Pagina {
+        branding: distributor.branding,
+        distributor_name: distributor.name,
+        page: page,
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn voorwaarden(mysql: web::Data<MySQL>, req: HttpRequest) -> Result<HttpResponse> {
+    shop_page(&mysql, &req, PageKind::Terms, "").await
+}
+
+async fn pagina(
+    web::Path(slug): web::Path<String>,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    shop_page(&mysql, &req, PageKind::Custom, &slug).await
+}
+
+async fn order_form(
+    mysql: web::Data<MySQL>,
+    mut form: web::Form<OrderForm>,
+    req: HttpRequest,
+    mollie: web::Data<mollie::Mollie>,
+) -> String {
+    // Make client of this voucher
+    let mut client = Client {
+        id: 0,
+        firstname: form.firstname.to_string(),
+        lastname: form.lastname.to_string(),
+        email: form.from_email.to_string(),
+        tel: form.tel.to_string(),
+        saved_account: false,
+    };
+
+    // Get distributor of this subpage and the voucher being purchased
+    let distributor = match tenancy::current(&req) {
+        Some(d) => d,
+        None => return "/niet-gelukt".to_string(),
+    };
+    if !distributor.is_live() {
+        return "/niet-gelukt".to_string();
+    }
+    let tenant = distributor.tenant();
+
+    // Vouchers of other distributors aren't found
+    let distributor_voucher =
+        match data::get_distributor_voucher(&mysql, tenant, form.voucher).await {
+            Some(v) => v,
+            None => return "/niet-gelukt".to_string(),
+        };
+
+    // Add client to database
+    let client_id = data::add_client(&mysql, tenant, &client).await;
+    client.id = client_id;
+
+    // Check if amount is not altered on the client-side
+    if distributor_voucher.voucher_type != VoucherType::RangeVoucher
+        && form.amount != distributor_voucher.amount
+    {
+        return "/niet-gelukt".to_string();
+    }
+
+    // Make Sale object with default values
+    let amount: f64 = form.amount;
+    let mut sale = Sale {
+        id: 0,
+        client: client,
+        amount: amount,
+        payment_id: "".to_string(),
+        paid: false,
+        purchase_date: None,
+    };
+
+    // Add sale to database and get ID of sale
+    let sale_id = data::add_sale(&mysql, tenant, &sale).await;
+    sale.id = sale_id;
+
+    // Generate voucher being purchased
+    let expiration_date = Utc::now()
+        .with_timezone(&chrono::Local)
+        .with_timezone(&chrono::Utc)
+        + Duration::days(distributor_voucher.days_valid.into());
+    let random_number: u16 = rand::thread_rng().gen();
+    let random_number_str = format!("{}-{}", client_id, random_number);
+
+    let mut sha256 = Sha256::new();
+    sha256.input_str(&random_number_str);
+    let hash = &sha256.result_str();
+
+    if form.to_email == "" {
+        form.to_email = form.from_email.to_string();
+    }
+    if form.to_firstname == "" {
+        form.to_firstname = form.firstname.to_string();
+    }
+    if form.to_lastname == "" {
+        form.to_lastname = form.lastname.to_string();
+    }
+
+    let mut voucher = Voucher {
+        id: 0,
+        sale: sale.clone(),
+        receiver_email: form.to_email.to_string(),
+        receiver_name: format!("{} {}", form.to_firstname, form.to_lastname),
+        balance: form.amount,
+        distributorvoucher: distributor_voucher,
+        used: false,
+        expiration_date: expiration_date,
+        hash_code: hash.to_string(),
+        number_code: random_number_str,
+        version: 1,
+        status: VoucherStatus::PendingPayment,
+    };
+
+    // Add voucher to database
+    data::add_voucher(&mysql, tenant, &voucher).await;
+
+    // Make payment and retrieve payment_id
+    let mut payment_id = "".to_string(); // payment_id is passed by reference and gets a new value
+    mollie.make_payment(&sale, &mut payment_id).await;
+    sale.payment_id = payment_id;
+
+    // Update sale
+    data::update_sale(&mysql, tenant, &sale, data::Selector::ById(sale_id)).await;
+
+    format!("/bevestig/{}", hash)
+}
+
+async fn payment_hook(
+    mollie: web::Data<mollie::Mollie>,
+    mysql: web::Data<MySQL>,
+    mail: web::Data<mail::Mail>,
+    data: web::Form<PaymentHook>,
+) -> Result<HttpResponse> {
+    let payment = mollie.get_payment(&data.id).await;
+
+    let tenant = data::get_tenant_by_payment_id(&mysql, &data.id).await;
+    if tenant.is_none() {
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    let tenant = tenant.unwrap();
+
+    if payment.status == "paid" {
+        let mut sale = data::get_sale(&mysql, tenant, data::Selector::ByPaymentId(data.id.clone()))
+            .await
+            .unwrap();
+
+        sale.paid = true;
+        data::update_sale(
+            &mysql,
+            tenant,
+            &sale,
+            data::Selector::ByPaymentId(data.id.clone()),
+        )
+        .await;
+        data::update_voucher_status_by_sale(
+            &mysql,
+            tenant,
+            sale.id,
+            VoucherStatus::PendingPayment,
+            VoucherStatus::Active,
+        )
+        .await;
+
+        mail.send_plain_mail(
+            "Kaddo. <noreply@kaddo.be>".to_string(),
+            format!(
+                "{} {} <{}>",
+                sale.client.firstname, sale.client.lastname, sale.client.email
+            ),
+            "Betaling ontvangen!".to_string(),
+            "Rofl".to_string(),
+        )
+        .await
+        .unwrap_or_else(|e| println!("Error: {}", e));
+
+        println!("Paid, updating ID {}", data.id.clone());
+        println!("{}", serde_json::to_string(&sale).unwrap());
+
+        return Ok(HttpResponse::Ok().finish());
+    } else {
+        return Ok(HttpResponse::Ok().finish());
+    }
+}
+
+async fn check(
+    mollie: web::Data<mollie::Mollie>,
+    web::Path(payment_id): web::Path<(String)>,
+) -> Result<HttpResponse> {
+    let payment = mollie.get_payment(&payment_id).await;
+
+    match payment.status == "paid" {
+        true => Ok(HttpResponse::Found()
+            .header(http::header::LOCATION, "/succes/aankoop")
+            .finish()),
+        _ => Ok(HttpResponse::Found()
+            .header(http::header::LOCATION, "/niet-gelukt")
+            .finish()),
+    }
+}
+
+async fn confirm_order(
+    web::Path(hash): web::Path<String>,
+    mollie: web::Data<mollie::Mollie>,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let distributor = tenancy::current(&req);
+
+    if distributor.is_none() {
+        return error404().await;
+    }
+
+    let distributor = distributor.unwrap();
+    let voucher_get =
+        data::get_voucher(&mysql, distributor.tenant(), data::Selector::ByHash(hash)).await;
+    let voucher: Voucher;
+
+    if voucher_get.is_none() {
+        return Ok(HttpResponse::Found()
+            .header(http::header::LOCATION, "/niet-gelukt")
+            .finish());
+    } else {
+        voucher = voucher_get.unwrap();
+    }
+
+    if voucher.sale.paid {
+        return Ok(HttpResponse::Found()
+            .header(http::header::LOCATION, "/niet-gelukt")
+            .finish());
+    }
+
+    let payment_id = voucher.sale.payment_id;
+    let payment = mollie.get_payment(&payment_id).await;
+
+    let transaction_fee = TRANSACTION_FEE;
+    let s = Bevestig {
+        branding: distributor.branding,
+        payment_url: payment.links.get("checkout").unwrap().href.to_string(),
+        voucher_price: format!("{:.2}", voucher.sale.amount).replace(".", ","),
+        total: format!("{:.2}", voucher.sale.amount + transaction_fee).replace(".", ","),
+        from_str: format!(
+            "{} {} <{}>",
+            uppercase_first_letter(&voucher.sale.client.firstname),
+            uppercase_first_letter(&voucher.sale.client.lastname),
+            voucher.sale.client.email,
+        ),
+        to_str: format!(
+            "{} <{}>",
+            uppercase_first_letter(&voucher.receiver_name),
+            voucher.receiver_email
+        ),
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn success(web::Path(action): web::Path<String>) -> Result<HttpResponse> {
+    let (title, message) = match &*action {
+        "aankoop" => (
+            "Aankoop succesvol!",
+            "We hebben je aankoop goed ontvangen en gaan direct voor je aan de slag!",
+        ),
+        "registratie" => (
+            "Welkom bij Kaddo.",
+            "Je bent succesvol geregistreerd! Bevestig je e-mailadres via de link in je mailbox om verder te gaan.",
+        ),
+        _ => ("Success", "Goed gedaan!"),
+    };
+
+    let s = Success {
+        title: title.to_string(),
+        message: message.to_string(),
+    }
+    .render()
+    .unwrap();
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn failed() -> Result<HttpResponse> {
+    let s = Failed.render().unwrap();
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn voucher_desktop(
+    web::Path(hash): web::Path<String>,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let distributor = match tenancy::current(&req) {
+        Some(d) => d,
+        None => return error404().await,
+    };
+    // Vouchers stay viewable as long as they can still be used
+    if !distributor.accepts_redemptions() {
+        return storefront_closed(&distributor).await;
+    }
+
+    let voucher =
+        data::get_voucher(&mysql, distributor.tenant(), data::Selector::ByHash(hash)).await;
+
+    if voucher.is_none() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let v = voucher.unwrap();
+
+    let s = VoucherPageDesktop {
+        branding: v.distributorvoucher.distributor.branding,
+        distributor_name: v.distributorvoucher.distributor.name,
+        balance: v.balance.to_string(),
+        number_code: v.number_code,
+        expiration_date: v.expiration_date.format("%d-%m-%Y").to_string(),
+        receiver_name: v.receiver_name,
+    }
+    .render()
+    .unwrap();
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn voucher_mobile(
+    web::Path(hash): web::Path<String>,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let distributor = match tenancy::current(&req) {
+        Some(d) => d,
+        None => return error404().await,
+    };
+    // Vouchers stay viewable as long as they can still be used
+    if !distributor.accepts_redemptions() {
+        return storefront_closed(&distributor).await;
+    }
+
+    let voucher =
+        data::get_voucher(&mysql, distributor.tenant(), data::Selector::ByHash(hash)).await;
+
+    if voucher.is_none() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let v = voucher.unwrap();
+
+    let s = VoucherPageMobile {
+        branding: v.distributorvoucher.distributor.branding,
+        distributor_name: v.distributorvoucher.distributor.name,
+        balance: v.balance.to_string(),
+        number_code: v.number_code,
+        expiration_date: v.expiration_date.format("%d-%m-%Y").to_string(),
+        receiver_name: v.receiver_name,
+    }
+    .render()
+    .unwrap();
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn balance_check(req: HttpRequest) -> Result<HttpResponse> {
+    let distributor = tenancy::current(&req);
+
+    if distributor.is_none() {
+        return error404().await;
+    }
+
+    let distributor = distributor.unwrap();
+    if !distributor.accepts_redemptions() {
+        return storefront_closed(&distributor).await;
+    }
+
+    let s = BalanceCheck {
+        csrf_token: csrf::token(&req),
+        branding: distributor.branding,
+        distributor_name: distributor.name,
+        number_code: "".to_string(),
+        error: "".to_string(),
+        found: false,
+        balance: "".to_string(),
+        expiration_date: "".to_string(),
+        status: "".to_string(),
+        redemptions: std::vec::Vec::new(),
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn balance_check_form(
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<BalanceCheckForm>,
+) -> Result<HttpResponse> {
+    let distributor = tenancy::current(&req);
+
+    if distributor.is_none() {
+        return error404().await;
+    }
+
+    let d = distributor.unwrap();
+    if !d.accepts_redemptions() {
+        return storefront_closed(&d).await;
+    }
+
+    let mut s = BalanceCheck {
+        csrf_token: csrf::token(&req),
+        branding: d.branding.clone(),
+        distributor_name: d.name.to_string(),
+        number_code: form.number_code.trim().to_string(),
+        error: "".to_string(),
+        found: false,
+        balance: "".to_string(),
+        expiration_date: "".to_string(),
+        status: "".to_string(),
+        redemptions: std::vec::Vec::new(),
+    };
+
+    // Number codes are short, limit guessing per IP
+    let ip = client_ip(&req);
+    if !BALANCE_CHECK_LIMITER.check(&ip).await {
+        s.error = "Te veel pogingen, probeer het later opnieuw.".to_string();
+        return Ok(HttpResponse::TooManyRequests()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
+    }
+
+    let voucher = data::get_voucher(
+        &mysql,
+        d.tenant(),
+        data::Selector::ByNumberCode(form.number_code.trim().to_string()),
+    )
+    .await;
+
+    let v = match voucher {
+        Some(v) if v.sale.paid => v,
+        _ => {
+            s.error = "Geen bon gevonden met deze code.".to_string();
+            return Ok(HttpResponse::NotFound()
+                .content_type("text/html")
+                .body(s.render().unwrap()));
+        }
+    };
+
+    s.found = true;
+    s.balance = format!("{:.2}", v.balance).replace(".", ",");
+    s.expiration_date = v.expiration_date.format("%d-%m-%Y").to_string();
+    s.status = v.derive_status().description().to_string();
+    s.redemptions = data::get_redemptions_by_voucher(&mysql, d.tenant(), v.id)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(|r| BalanceCheckRedemption {
+            date: r
+                .redeemed_at
+                .with_timezone(&chrono::Local)
+                .format("%d-%m-%Y %H:%M")
+                .to_string(),
+            amount: format!("{:.2}", r.amount).replace(".", ","),
+            balance_after: format!("{:.2}", r.balance_after).replace(".", ","),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(s.render().unwrap()))
+}
+
+async fn scanner(mysql: web::Data<MySQL>, req: HttpRequest) -> Result<HttpResponse> {
+    let s = Scanner {
+        csrf_token: csrf::token(&req),
+    }
+    .render()
+    .unwrap();
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn scanner_login(mysql: web::Data<MySQL>) -> Result<HttpResponse> {
+    let s = ScannerLogin.render().unwrap();
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+async fn scanner_login_authenticate(mysql: web::Data<MySQL>) -> Result<HttpResponse> {
+    unimplemented!()
+}
+
+async fn scan_get(
+    web::Path((method, identifier)): web::Path<(String, String)>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> String {
+    let mut voucher: Option<Voucher> = None;
+
+    let distributor = tenancy::current(&req);
+
+    // Only users of this distributor that are allowed to scan
+    match distributor {
+        Some(d)
+            if d.id == distributor_user.distributor.id
+                && distributor_user.can(Permission::Scan) => {}
+        _ => {
+            return serde_json::json!({ "success": false, "msg": "Geen voucher gevonden", "voucher": null })
+                .to_string();
+        }
+    }
+
+    let tenant = distributor_user.tenant();
+
+    // = data::get_voucher(&mysql, data::Selector::ByHash(hash)).await;
+
+    match &*method {
+        "hash" => {
+            voucher = data::get_voucher(&mysql, tenant, data::Selector::ByHash(identifier)).await;
+        }
+        "number_code" => {
+            voucher =
+                data::get_voucher(&mysql, tenant, data::Selector::ByNumberCode(identifier)).await;
+        }
+        _ => {
+            voucher = None;
+        }
+    }
+
+    match voucher {
+        None => {
+            serde_json::json!({ "success": false, "msg": "Geen voucher gevonden", "voucher": null })
+        }
+        Some(v) if !v.is_redeemable() => serde_json::json!({
+            "success": false,
+            "msg": v.derive_status().description(),
+            "voucher": null,
+        }),
+        Some(v) => serde_json::json!({
+            "success": true,
+            "msg": "",
+            "voucher": {
+                "id": v.id,
+                "paid": v.sale.paid,
+                "one_use_only": v.distributorvoucher.one_use_only,
+                "used": v.used,
+                "balance": v.balance,
+                "receiver_name": v.receiver_name,
+                "hash_code": v.hash_code,
+                "status": v.derive_status().to_string(),
+                "expiration_date": v.expiration_date.format("%d-%m-%Y").to_string(),
+            }
+        }),
+    }
+    .to_string()
+}
+
+async fn scan_update(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<VoucherUpdateForm>,
+) -> String {
+    let distributor = tenancy::current(&req);
+
+    match distributor {
+        Some(d)
+            if d.id == distributor_user.distributor.id
+                && distributor_user.can(Permission::Scan) => {}
+        _ => return "error".to_string(),
+    }
+
+    let tenant = distributor_user.tenant();
+
+    let mut voucher = data::get_voucher(
+        &mysql,
+        tenant,
+        data::Selector::ByHash(form.hash.to_string()),
+    )
+    .await;
+
+    match voucher {
+        None => "error".to_string(),
+        Some(v) if !v.is_redeemable() => "not_redeemable".to_string(),
+        // The scanner can only lower the balance
+        Some(v) if form.balance < 0.0 || form.balance > v.balance => "invalid_balance".to_string(),
+        Some(mut v) => {
+            let amount = v.balance - form.balance;
+
+            let mut entry =
+                AuditEntry::new(&distributor_user, &req, "voucher.redeem", "voucher", v.id);
+            entry.before = serde_json::json!({ "balance": v.balance, "used": v.used }).to_string();
+
+            v.balance = form.balance;
+            v.used = form.used || v.distributorvoucher.one_use_only || v.balance <= 0.0;
+            entry.after = serde_json::json!({ "balance": v.balance, "used": v.used }).to_string();
+
+            let origin = redemption_origin(&mysql, &req, tenant).await;
+            let row = Redemption {
+                id: 0,
+                voucher_id: v.id,
+                client_id: None,
+                amount: amount,
+                balance_after: v.balance,
+                redeemed_at: Utc::now(),
+                shoplocation_id: origin.shoplocation_id,
+                scannerdevice_id: origin.scannerdevice_id,
+            };
+
+            match data::redeem_voucher(&mysql, tenant, &v, &row, &entry).await {
+                data::RedeemOutcome::Applied => "success".to_string(),
+                // Scanned elsewhere in the meantime, the scanner has to reload the voucher
+                data::RedeemOutcome::Changed => "changed".to_string(),
+                data::RedeemOutcome::Duplicate | data::RedeemOutcome::Failed => "false".to_string(),
+            }
+        }
+    }
+}
+
+const SCANNER_COOKIE_NAME: &str = "kaddo-scanner";
+
+// Scanner device this browser was paired as, it's marked as seen
+async fn scanner_device(
+    mysql: &web::Data<MySQL>,
+    req: &HttpRequest,
+    tenant: data::Tenant,
+) -> Option<ScannerDevice> {
+    use actix_web::HttpMessage;
+
+    let token = req.cookie(SCANNER_COOKIE_NAME)?.value().to_string();
+    let device = data::get_scanner_device_by_token(mysql, tenant, &hash_token(&token)).await?;
+    data::touch_scanner_device(mysql, tenant, device.id).await;
+
+    Some(device)
+}
+
+// The location of the paired scanner. Without one the location is only known when the
+// distributor has a single shop.
+async fn redemption_origin(
+    mysql: &web::Data<MySQL>,
+    req: &HttpRequest,
+    tenant: data::Tenant,
+) -> RedemptionOrigin {
+    if let Some(device) = scanner_device(mysql, req, tenant).await {
+        return RedemptionOrigin {
+            shoplocation_id: Some(device.shoplocation_id),
+            scannerdevice_id: Some(device.id),
+        };
+    }
+
+    match &data::get_shop_locations(mysql, tenant)
+        .await
+        .unwrap_or_default()[..]
+    {
+        [only] => RedemptionOrigin {
+            shoplocation_id: Some(only.id),
+            scannerdevice_id: None,
+        },
+        _ => RedemptionOrigin::default(),
+    }
+}
+
+// Opened on the scanner itself with the link shown once when the device was added
+async fn scanner_koppelen(
+    web::Path(token): web::Path<String>,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let distributor = match tenancy::current(&req) {
+        Some(d) => d,
+        None => return error404().await,
+    };
+
+    let device =
+        match data::get_scanner_device_by_token(&mysql, distributor.tenant(), &hash_token(&token))
+            .await
+        {
+            Some(device) if !device.paired => device,
+            _ => return error404().await,
+        };
+    if !data::pair_scanner_device(&mysql, distributor.tenant(), device.id).await {
+        return error404().await;
+    }
+
+    let cookie = actix_web::cookie::Cookie::build(SCANNER_COOKIE_NAME, token)
+        .path("/")
+        .http_only(true)
+        .same_site(actix_web::cookie::SameSite::Strict)
+        .permanent()
+        .finish();
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, "/scanner")
+        .cookie(cookie)
+        .finish())
+}
+
+// Suggestions for the postal code and city fields
+async fn postcodes_zoeken(mysql: web::Data<MySQL>, req: HttpRequest) -> Result<HttpResponse> {
+    let params = match web::Query::<PostcodeSearchParams>::from_query(req.query_string()) {
+        Ok(params) => params.into_inner(),
+        Err(_) => return Ok(HttpResponse::Ok().json(serde_json::json!([]))),
+    };
+
+    Ok(HttpResponse::Ok().json(
+        postcodes::suggest(&mysql, &params.land, &params.q)
+            .await
+            .iter()
+            .map(|l| serde_json::json!({ "country": l.country, "postalcode": l.postalcode, "city": l.city }))
+            .collect::<std::vec::Vec<serde_json::Value>>(),
+    ))
+}
+
+async fn scan_sync_key(signer: web::Data<sync::Signer>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body(sync::to_hex(&signer.public_key)))
+}
+
+async fn scan_sync_snapshot(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    signer: web::Data<sync::Signer>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::Scan)?;
+
+    let tenant = distributor_user.tenant();
+    let snapshot = sync::Snapshot {
+        distributor_id: tenant.distributor_id,
+        generated_at: Utc::now(),
+        vouchers: data::get_snapshot_vouchers_by_distributor(&mysql, tenant)
+            .await
+            .unwrap_or_default(),
+    };
+
+    Ok(HttpResponse::Ok().json(&signer.sign(&snapshot)))
+}
+
+async fn scan_sync_upload(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    json: web::Json<ScanSyncUploadJson>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::Scan)?;
+
+    let tenant = distributor_user.tenant();
+
+    // Apply in the order the scanner queued them so balances follow the real sequence
+    let mut results: std::vec::Vec<sync::RedemptionResult> = std::vec::Vec::new();
+    let entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "voucher.redeem_offline",
+        "voucher",
+        0,
+    );
+    let origin = redemption_origin(&mysql, &req, tenant).await;
+    for redemption in &json.redemptions {
+        results.push(sync::apply_redemption(&mysql, tenant, redemption, &entry, origin).await);
+    }
+
+    Ok(HttpResponse::Ok().json(&results))
+}
+
+async fn test(mail: web::Data<mail::Mail>) -> Result<HttpResponse> {
+    //mail.send_plain_mail().await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// Administrator routes
+
+async fn admin_login(req: HttpRequest, session: Session) -> Result<HttpResponse> {
+    if session
+        .get::<DistributorUser>("distributoruser")
+        .unwrap()
+        .is_some()
+    {
+        return Ok(HttpResponse::Found()
+            .header(http::header::LOCATION, "/admin/dashboard")
+            .finish());
+    }
+
+    let distributor = match tenancy::current(&req) {
+        Some(d) => d,
+        None => return error404().await,
+    };
+
+    let s = AdminLogin {
+        csrf_token: csrf::token(&req),
+        distributor_name: distributor.name,
+        login_status: "".to_string(),
+    }
+    .render()
+    .unwrap();
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn admin_login_form(
+    mysql: web::Data<MySQL>,
+    mail: web::Data<mail::Mail>,
+    req: HttpRequest,
+    form: web::Form<AdminLoginForm>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let distributor = match tenancy::current(&req) {
+        Some(d) => d,
+        None => return error404().await,
+    };
+    let tenant = distributor.tenant();
+
+    let ip = client_ip(&req);
+    let window_start = Utc::now() - Duration::minutes(LOGIN_ATTEMPT_WINDOW_MINUTES);
+
+    let mut s = AdminLogin {
+        csrf_token: csrf::token(&req),
+        distributor_name: distributor.name.to_string(),
+        login_status: "".to_string(),
+    };
+
+    match distributor.status {
+        // Cashiers can keep scanning when the policy of the suspension allows it
+        DistributorStatus::Suspended if !distributor.accepts_redemptions() => {
+            s.login_status =
+                "Deze zaak is momenteel geschorst, neem contact op met Kaddo.".to_string();
+            return Ok(HttpResponse::Forbidden()
+                .content_type("text/html")
+                .body(s.render().unwrap()));
+        }
+        DistributorStatus::Unverified => {
+            s.login_status =
+                "Bevestig eerst je e-mailadres via de link die we je stuurden.".to_string();
+            return Ok(HttpResponse::Forbidden()
+                .content_type("text/html")
+                .body(s.render().unwrap()));
+        }
+        _ => {}
+    }
+
+    if data::count_failed_login_attempts_by_ip(&mysql, &ip, window_start).await
+        >= LOGIN_MAX_FAILURES_PER_IP
+    {
+        s.login_status = "Te veel mislukte pogingen, probeer het later opnieuw.".to_string();
+        return Ok(HttpResponse::TooManyRequests()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
+    }
+
+    // An account that's being guessed at only takes a next attempt after a growing wait.
+    // The request is refused instead of held, so waiting attempts don't tie up workers.
+    let failed_attempts = data::count_failed_login_attempts_by_username(
+        &mysql,
+        tenant,
+        &form.admin_username,
+        window_start,
+    )
+    .await;
+    if failed_attempts >= LOGIN_DELAY_AFTER {
+        let delay = 2i64
+            .saturating_pow((failed_attempts - LOGIN_DELAY_AFTER) as u32)
+            .min(LOGIN_MAX_DELAY_SECONDS);
+        let last_failure =
+            data::get_last_failed_login_attempt_by_username(&mysql, tenant, &form.admin_username)
+                .await;
+        let retry_after = last_failure
+            .map(|last| (last + Duration::seconds(delay) - Utc::now()).num_seconds())
+            .unwrap_or(0);
+        if retry_after > 0 {
+            s.login_status = "Te veel mislukte pogingen, probeer het later opnieuw.".to_string();
+            return Ok(HttpResponse::TooManyRequests()
+                .header(http::header::RETRY_AFTER, (retry_after + 1).to_string())
+                .content_type("text/html")
+                .body(s.render().unwrap()));
+        }
+    }
+
+    let distributor_user =
+        match DistributorUser::get_by_username(&form.admin_username, tenant, &mysql).await {
+            Some(user) if user.active => Some(user),
+            _ => None,
+        };
+
+    // Same message for unknown users, wrong passwords and locked accounts
+    s.login_status = "Gebruikersnaam of wachtwoord niet correct.".to_string();
+
+    let pass_check = match &distributor_user {
+        Some(user) => user
+            .verify_password(form.admin_password.as_bytes())
+            .unwrap_or(false),
+        None => {
+            argon2::verify_encoded(&DUMMY_PASSWORD_HASH, form.admin_password.as_bytes()).ok();
+            false
+        }
+    };
+    let locked = match &distributor_user {
+        Some(user) => data::is_distributor_user_locked(&mysql, user.id).await,
+        None => false,
+    };
+
+    // Attempts during a lock aren't counted, or a correct password entered while locked
+    // would count towards the next lock
+    if !locked {
+        data::add_login_attempt(
+            &mysql,
+            tenant,
+            &form.admin_username,
+            distributor_user.as_ref().map(|u| u.id),
+            &ip,
+            pass_check,
+        )
+        .await;
+    }
+
+    if !pass_check || locked {
+        let mut entry = AuditEntry::for_tenant(
+            tenant,
+            &req,
+            "user.login_failed",
+            "distributoruser",
+            distributor_user.as_ref().map(|u| u.id).unwrap_or(0),
+        );
+        entry.after =
+            serde_json::json!({ "username": form.admin_username, "locked": locked }).to_string();
+        data::add_audit_entry(&mysql, &entry).await;
+
+        // Attempts during a lock don't extend it
+        if let Some(user) = &distributor_user {
+            if !locked && failed_attempts + 1 >= LOGIN_LOCK_AFTER {
+                data::lock_distributor_user(
+                    &mysql,
+                    user.id,
+                    Some(Utc::now() + Duration::minutes(LOGIN_LOCK_MINUTES)),
+                )
+                .await;
+
+                let mail_since = Utc::now() - Duration::hours(LOCK_MAIL_INTERVAL_HOURS);
+                if user.email != "" && data::claim_lock_mail(&mysql, user.id, mail_since).await {
+                    mail.send_account_locked_mail(user, LOGIN_LOCK_MINUTES, &ip)
+                        .await
+                        .unwrap_or_else(|e| println!("Error: {}", e));
+                }
+            }
+        }
+
+        return Ok(HttpResponse::Unauthorized()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
+    }
+
+    let distributor_user = distributor_user.unwrap();
+
+    // Only log in after the second step
+    if distributor_user.totp_enabled || distributor.require_2fa {
+        session.set("pending_2fa_user", distributor_user.id)?;
+
+        return Ok(HttpResponse::Found()
+            .header(http::header::LOCATION, "/admin/login/2fa")
+            .finish());
+    }
+
+    data::update_last_login(&mysql, distributor_user.id).await;
+    data::add_audit_entry(
+        &mysql,
+        &AuditEntry::new(
+            &distributor_user,
+            &req,
+            "user.login",
+            "distributoruser",
+            distributor_user.id,
+        ),
+    )
+    .await;
+    session.renew();
+    session.set("distributoruser", distributor_user)?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, "/admin/dashboard")
+        .finish())
+}
+
+// The user that passed the password check but not the second step yet
+async fn get_pending_2fa_user(
+    session: &Session,
+    mysql: &web::Data<MySQL>,
+    distributor: &Distributor,
+) -> Option<DistributorUser> {
+    let id = session.get::<u64>("pending_2fa_user").unwrap_or(None)?;
+
+    match DistributorUser::get_by_id(id, mysql).await {
+        Some(user) if user.distributor.id == distributor.id && user.active => Some(user),
+        _ => None,
+    }
+}
+
+async fn admin_login_2fa(
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    session: Session,
+) -> Result<HttpResponse> {
+    let distributor = match tenancy::current(&req) {
+        Some(d) => d,
+        None => return error404().await,
+    };
+
+    let distributor_user = get_pending_2fa_user(&session, &mysql, &distributor).await;
+    if distributor_user.is_none() {
+        return Ok(HttpResponse::Found()
+            .header(http::header::LOCATION, "/admin/login")
+            .finish());
+    }
+
+    let distributor_user = distributor_user.unwrap();
+
+    let mut s = AdminLogin2fa {
+        csrf_token: csrf::token(&req),
+        distributor_name: distributor.name.to_string(),
+        login_status: "".to_string(),
+        enroll: !distributor_user.totp_enabled,
+        secret: "".to_string(),
+        qr_code: "".to_string(),
+        recovery_codes: std::vec::Vec::new(),
+    };
+
+    // The distributor requires 2FA but this user didn't set it up yet
+    if s.enroll {
+        let secret = totp::generate_secret();
+        data::set_totp_pending_secret(&mysql, distributor_user.id, &secret).await;
+
+        s.qr_code = totp::qr_code_svg(&totp::provisioning_uri(
+            &format!("Kaddo {}", distributor.name),
+            &distributor_user.username,
+            &secret,
+        ));
+        s.secret = secret;
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(s.render().unwrap()))
+}
+
+async fn admin_login_2fa_form(
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<TwoFactorForm>,
+    session: Session,
+) -> Result<HttpResponse> {
+    let distributor = match tenancy::current(&req) {
+        Some(d) => d,
+        None => return error404().await,
+    };
+
+    let distributor_user = get_pending_2fa_user(&session, &mysql, &distributor).await;
+    if distributor_user.is_none() {
+        return Ok(HttpResponse::Found()
+            .header(http::header::LOCATION, "/admin/login")
+            .finish());
+    }
+
+    let mut distributor_user = distributor_user.unwrap();
+
+    let mut s = AdminLogin2fa {
+        csrf_token: csrf::token(&req),
+        distributor_name: distributor.name.to_string(),
+        login_status: "".to_string(),
+        enroll: !distributor_user.totp_enabled,
+        secret: "".to_string(),
+        qr_code: "".to_string(),
+        recovery_codes: std::vec::Vec::new(),
+    };
+
+    if !TWO_FACTOR_LIMITER
+        .check(&distributor_user.id.to_string())
+        .await
+    {
+        s.login_status = "Te veel pogingen, probeer het later opnieuw.".to_string();
+        return Ok(HttpResponse::TooManyRequests()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
+    }
+
+    if s.enroll {
+        let secret = data::get_totp_pending_secret(&mysql, distributor_user.id)
+            .await
+            .unwrap_or_default();
+
+        match totp::verify(&secret, &form.code, Utc::now().timestamp()) {
+            Some(step) if data::enable_totp(&mysql, distributor_user.id, &secret).await => {
+                data::use_totp_step(&mysql, distributor_user.id, step).await;
+
+                let recovery_codes = generate_recovery_codes();
+                data::set_recovery_codes(&mysql, distributor_user.id, &recovery_codes).await;
+
+                distributor_user.totp_enabled = true;
+                data::update_last_login(&mysql, distributor_user.id).await;
+
+                let mut entry = AuditEntry::new(
+                    &distributor_user,
+                    &req,
+                    "user.login",
+                    "distributoruser",
+                    distributor_user.id,
+                );
+                entry.reason = "2fa_enrolled".to_string();
+                data::add_audit_entry(&mysql, &entry).await;
+                session.remove("pending_2fa_user");
+                session.renew();
+                session.set("distributoruser", distributor_user)?;
+
+                // Recovery codes are only shown this once
+                s.recovery_codes = recovery_codes;
+                return Ok(HttpResponse::Ok()
+                    .content_type("text/html")
+                    .body(s.render().unwrap()));
+            }
+            _ => {
+                data::add_audit_entry(
+                    &mysql,
+                    &AuditEntry::new(
+                        &distributor_user,
+                        &req,
+                        "user.login_2fa_failed",
+                        "distributoruser",
+                        distributor_user.id,
+                    ),
+                )
+                .await;
+
+                s.login_status = "Code niet correct.".to_string();
+                s.qr_code = totp::qr_code_svg(&totp::provisioning_uri(
+                    &format!("Kaddo {}", distributor.name),
+                    &distributor_user.username,
+                    &secret,
+                ));
+                s.secret = secret;
+                return Ok(HttpResponse::Unauthorized()
+                    .content_type("text/html")
+                    .body(s.render().unwrap()));
+            }
+        }
+    }
+
+    match distributor_user
+        .verify_second_factor(&form.code, &mysql)
+        .await
+    {
+        true => {
+            data::update_last_login(&mysql, distributor_user.id).await;
+            data::add_audit_entry(
+                &mysql,
+                &AuditEntry::new(
+                    &distributor_user,
+                    &req,
+                    "user.login",
+                    "distributoruser",
+                    distributor_user.id,
+                ),
+            )
+            .await;
+            session.remove("pending_2fa_user");
+            session.renew();
+            session.set("distributoruser", distributor_user)?;
+
+            Ok(HttpResponse::Found()
+                .header(http::header::LOCATION, "/admin/dashboard")
+                .finish())
+        }
+        false => {
+            data::add_audit_entry(
+                &mysql,
+                &AuditEntry::new(
+                    &distributor_user,
+                    &req,
+                    "user.login_2fa_failed",
+                    "distributoruser",
+                    distributor_user.id,
+                ),
+            )
+            .await;
+
+            s.login_status = "Code niet correct.".to_string();
+            Ok(HttpResponse::Unauthorized()
+                .content_type("text/html")
+                .body(s.render().unwrap()))
+        }
+    }
+}
+
+async fn admin_dashboard_index(AdminUser(distributor_user): AdminUser) -> Result<HttpResponse> {
+    // Owners of a new shop continue where they left off
+    if distributor_user.distributor.status == DistributorStatus::Onboarding
+        && distributor_user.can(Permission::ManageBusiness)
+    {
+        return Ok(HttpResponse::Found()
+            .header(http::header::LOCATION, "/admin/dashboard/aan-de-slag")
+            .finish());
+    }
+
+    return Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, distributor_user.home())
+        .finish());
+}
+
+async fn admin_dashboard_aan_de_slag(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let distributor = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap();
+    if distributor.status != DistributorStatus::Onboarding {
+        return Ok(HttpResponse::Found()
+            .header(http::header::LOCATION, distributor_user.role.home())
+            .finish());
+    }
+
+    let s = AdminDashboardAanDeSlag {
+        csrf_token: csrf::token(&req),
+        storefront_url: distributor_url(&distributor),
+        has_vouchers: !data::get_active_distributor_vouchers_by_distributor(
+            &mysql,
+            distributor.tenant(),
+        )
+        .await
+        .unwrap_or_default()
+        .is_empty(),
+        distributor: distributor,
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+// Opens the storefront once the first vouchers are configured
+async fn admin_aan_de_slag_live(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    session: Session,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let distributor = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap();
+    if distributor.status != DistributorStatus::Onboarding {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("already_live"));
+    }
+    if data::get_active_distributor_vouchers_by_distributor(&mysql, distributor.tenant())
+        .await
+        .unwrap_or_default()
+        .is_empty()
+    {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("no_vouchers"));
+    }
+
+    if !data::update_distributor_status(&mysql, distributor.id, DistributorStatus::Active).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "distributor.go_live",
+        "distributor",
+        distributor.id,
+    );
+    entry.before = serde_json::json!({ "status": distributor.status }).to_string();
+    entry.after = serde_json::json!({ "status": DistributorStatus::Active }).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    // The session holds a copy of the distributor
+    let mut distributor_user = distributor_user;
+    distributor_user.distributor.status = DistributorStatus::Active;
+    session.set("distributoruser", distributor_user)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_dashboard_mijn_zaak(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let distributor = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap();
+
+    let mut s = AdminDashboardMijnZaak {
+        csrf_token: csrf::token(&req),
+        masked_bankaccountnr: distributor.masked_iban(),
+        distributor: distributor,
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn admin_update_mijn_zaak(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<MijnZaakUpdateForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    // The copy in the session can be outdated
+    let mut distributor = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap();
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "distributor.update",
+        "distributor",
+        distributor.id,
+    );
+    entry.before = distributor_audit_json(&distributor).to_string();
+
+    if form.email.trim().is_empty() || form.tel.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("invalid_input"));
+    }
+    if form.address.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("address_missing"));
+    }
+    let location =
+        match postcodes::resolve(&mysql, &form.country, &form.postalcode, &form.city).await {
+            Ok(location) => location,
+            Err(e) => {
+                return Ok(HttpResponse::BadRequest()
+                    .content_type("text/plain")
+                    .body(e))
+            }
+        };
+
+    distributor.description = form.description.to_string();
+    distributor.email = form.email.trim().to_string();
+    distributor.tel = form.tel.trim().to_string();
+    distributor.address = form.address.trim().to_string();
+    distributor.location = location;
+    entry.after = distributor_audit_json(&distributor).to_string();
+
+    match data::update_distributor(&mysql, &distributor).await {
+        false => Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed")),
+        true => {
+            data::add_audit_entry(&mysql, &entry).await;
+
+            Ok(HttpResponse::Ok()
+                .content_type("text/plain")
+                .body("update_succeeded"))
+        }
+    }
+}
+
+static REAUTHENTICATE_LIMITER: Lazy<ratelimit::RateLimiter> =
+    Lazy::new(|| ratelimit::RateLimiter::new(5, Duration::minutes(15)));
+
+// Asks the password again before showing or changing the bank account or changing the
+// password, a session left open on a shop computer isn't enough
+async fn reauthenticate(
+    mysql: &web::Data<MySQL>,
+    distributor_user: &DistributorUser,
+    password: &str,
+) -> std::result::Result<(), &'static str> {
+    if !REAUTHENTICATE_LIMITER
+        .check(&distributor_user.id.to_string())
+        .await
+    {
+        return Err("too_many_attempts");
+    }
+
+    // Check against the stored hash, not the copy in the session
+    match DistributorUser::get_by_id(distributor_user.id, mysql).await {
+        Some(user) if user.verify_password(password.as_bytes()).unwrap_or(false) => Ok(()),
+        _ => Err("wrong_password"),
+    }
+}
+
+async fn admin_bankgegevens_tonen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<ReauthenticateForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    if let Err(e) = reauthenticate(&mysql, &distributor_user, &form.password).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body(e));
+    }
+
+    let distributor = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap();
+    let entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "distributor.bankaccount_view",
+        "distributor",
+        distributor.id,
+    );
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "bankaccountnr": distributor.iban().map(|iban| bankdetails::format_iban(&iban)),
+    })))
+}
+
+async fn admin_update_bankgegevens(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<BankDetailsForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let (btw_nr, iban) = match check_registration_numbers(&form.btw_nr, &form.bankaccountnr) {
+        Ok(numbers) => numbers,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body(e))
+        }
+    };
+    if let Err(e) = reauthenticate(&mysql, &distributor_user, &form.password).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body(e));
+    }
+
+    let mut distributor = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap();
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "distributor.bankdetails",
+        "distributor",
+        distributor.id,
+    );
+    entry.before = serde_json::json!({ "bankaccountnr": distributor.masked_iban(), "btw_nr": distributor.btw_nr }).to_string();
+
+    distributor.bankaccountnr = bankdetails::encrypt(&iban);
+    distributor.btw_nr = btw_nr;
+    entry.after = serde_json::json!({ "bankaccountnr": distributor.masked_iban(), "btw_nr": distributor.btw_nr }).to_string();
+
+    match data::update_distributor(&mysql, &distributor).await {
+        false => Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed")),
+        true => {
+            data::add_audit_entry(&mysql, &entry).await;
+
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "bankaccountnr": distributor.masked_iban(),
+            })))
+        }
+    }
+}
+
+fn distributor_audit_json(distributor: &Distributor) -> serde_json::Value {
+    serde_json::json!({
+        "name": distributor.name,
+        "email": distributor.email,
+        "tel": distributor.tel,
+        "address": distributor.address,
+        "country": distributor.location.country,
+        "postalcode": distributor.location.postalcode,
+        "city": distributor.location.city,
+        "subdomain": distributor.subdomain,
+        "description": distributor.description,
+        "bankaccountnr": distributor.masked_iban(),
+        "btw_nr": distributor.btw_nr,
+    })
+}
+
+async fn admin_update_herinneringen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<RemindersUpdateForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let reminder_days_input = match form.reminder_days.trim() {
+        "" => DEFAULT_REMINDER_DAYS,
+        input => input,
+    };
+    let reminder_days = match parse_reminder_days(reminder_days_input) {
+        Some(days) if days.len() > 0 || !form.send_reminders => days,
+        _ => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body("invalid_reminder_days"))
+        }
+    };
+
+    let mut distributor = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap();
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "distributor.reminders",
+        "distributor",
+        distributor.id,
+    );
+    entry.before = serde_json::json!({ "send_reminders": distributor.send_reminders, "reminder_days": distributor.reminder_days }).to_string();
+
+    distributor.send_reminders = form.send_reminders;
+    distributor.reminder_days = reminder_days
+        .iter()
+        .map(|d| d.to_string())
+        .collect::<std::vec::Vec<String>>()
+        .join(",");
+
+    entry.after = serde_json::json!({ "send_reminders": distributor.send_reminders, "reminder_days": distributor.reminder_days }).to_string();
+
+    match data::update_distributor_reminders(&mysql, &distributor).await {
+        false => Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed")),
+        true => {
+            data::add_audit_entry(&mysql, &entry).await;
+
+            Ok(HttpResponse::Ok()
+                .content_type("text/plain")
+                .body("update_succeeded"))
+        }
+    }
+}
+
+async fn admin_dashboard_domeinen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let s = AdminDashboardDomeinen {
+        csrf_token: csrf::token(&req),
+        subdomain: distributor_user.distributor.subdomain.to_string(),
+        subdomain_host: format!(
+            "{}.{}",
+            distributor_user.distributor.subdomain, PLATFORM_DOMAIN
+        ),
+        platform_domain: PLATFORM_DOMAIN,
+        domains: data::get_distributor_domains(&mysql, distributor_user.tenant())
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|d| DomainRow {
+                id: d.id,
+                record_name: domains::verification_record_name(&d.domain),
+                record_value: domains::verification_record_value(&d.verification_token),
+                domain: d.domain,
+                verified: d.verified,
+                primary: d.primary,
+            })
+            .collect(),
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn admin_domein_toevoegen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<DomainForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let domain = match normalize_custom_domain(&form.domain) {
+        Ok(domain) => domain,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body(e))
+        }
+    };
+    if data::is_domain_taken(&mysql, distributor_user.tenant(), &domain).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("domain_taken"));
+    }
+
+    let id = data::add_distributor_domain(
+        &mysql,
+        distributor_user.tenant(),
+        &domain,
+        &generate_token()[..32],
+    )
+    .await;
+    if id == 0 {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "domain.add",
+        "distributordomain",
+        id,
+    );
+    entry.after = serde_json::json!({ "domain": domain }).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_domein_verifieren(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let domain = match data::get_distributor_domain(&mysql, distributor_user.tenant(), id).await {
+        Some(domain) => domain,
+        None => return error404().await,
+    };
+    if domain.verified {
+        return Ok(HttpResponse::Ok()
+            .content_type("text/plain")
+            .body("update_succeeded"));
+    }
+
+    if !domains::has_verification_record(&domain).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("record_not_found"));
+    }
+    if !data::verify_distributor_domain(&mysql, distributor_user.tenant(), id, &domain.domain).await
+    {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("domain_taken"));
+    }
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "domain.verify",
+        "distributordomain",
+        id,
+    );
+    entry.after = serde_json::json!({ "domain": domain.domain }).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_domein_primair(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    // Id 0 goes back to the subdomain
+    let domain = match id {
+        0 => None,
+        id => match data::get_distributor_domain(&mysql, distributor_user.tenant(), id).await {
+            Some(domain) if domain.verified => Some(domain),
+            Some(_) => {
+                return Ok(HttpResponse::BadRequest()
+                    .content_type("text/plain")
+                    .body("domain_not_verified"))
+            }
+            None => return error404().await,
+        },
+    };
+
+    if !data::set_primary_distributor_domain(
+        &mysql,
+        distributor_user.tenant(),
+        domain.as_ref().map(|d| d.id),
+    )
+    .await
+    {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "domain.primary",
+        "distributordomain",
+        id,
+    );
+    entry.before =
+        serde_json::json!({ "domain": distributor_user.distributor.primary_domain }).to_string();
+    entry.after = serde_json::json!({ "domain": domain.map(|d| d.domain) }).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_domein_verwijderen(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let domain = match data::get_distributor_domain(&mysql, distributor_user.tenant(), id).await {
+        Some(domain) => domain,
+        None => return error404().await,
+    };
+
+    if !data::delete_distributor_domain(&mysql, distributor_user.tenant(), id).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "domain.remove",
+        "distributordomain",
+        id,
+    );
+    entry.before =
+        serde_json::json!({ "domain": domain.domain, "primary": domain.primary }).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_dashboard_vestigingen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    use chrono::Datelike;
+
+    distributor_user.require(Permission::ViewOrders)?;
+    let tenant = distributor_user.tenant();
+
+    let filters = web::Query::<LocationReportParams>::from_query(req.query_string())
+        .map(|q| q.into_inner())
+        .unwrap_or_default();
+    let parse_date = |d: &Option<String>| {
+        d.as_deref()
+            .and_then(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+    };
+
+    // The current month by default
+    let today = Utc::now().naive_utc().date();
+    let min_date = parse_date(&filters.min_date).unwrap_or(chrono::NaiveDate::from_ymd(
+        today.year(),
+        today.month(),
+        1,
+    ));
+    let max_date = parse_date(&filters.max_date).unwrap_or(today);
+
+    let report = data::get_redemptions_per_location(
+        &mysql,
+        tenant,
+        Utc.from_utc_datetime(&min_date.and_hms(0, 0, 0)),
+        Utc.from_utc_datetime(&(max_date + Duration::days(1)).and_hms(0, 0, 0)),
+    )
+    .await
+    .unwrap_or_default();
+
+    let can_manage = distributor_user.can(Permission::ManageBusiness);
+    let mut devices = match can_manage {
+        true => data::get_scanner_devices(&mysql, tenant)
+            .await
+            .unwrap_or_default(),
+        false => std::vec::Vec::new(),
+    };
+    let hours_rows = |hours: &[String]| {
+        WEEKDAYS
+            .iter()
+            .enumerate()
+            .map(|(i, (label, field))| OpeningHoursRow {
+                label: *label,
+                field: *field,
+                value: hours.get(i).cloned().unwrap_or_default(),
+            })
+            .collect::<std::vec::Vec<OpeningHoursRow>>()
+    };
+
+    let s = AdminDashboardVestigingen {
+        csrf_token: csrf::token(&req),
+        locations: data::get_shop_locations(&mysql, tenant)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|location| ShopLocationRow {
+                hours: hours_rows(&location.opening_hours),
+                devices: {
+                    let (own, others): (
+                        std::vec::Vec<ScannerDevice>,
+                        std::vec::Vec<ScannerDevice>,
+                    ) = devices
+                        .drain(..)
+                        .partition(|d| d.shoplocation_id == location.id);
+                    devices = others;
+                    own
                 },
-                redirectUrl: "http://demok.kaddo.test:8080".to_string(),
-                webhookUrl:
-                    "https://b27b-2a02-1810-3807-8a00-2453-bf80-2799-ff4b.ngrok.io/payment/hook"
-                        .to_string(),
-                status: "".to_string(),
-                links: HashMap::new(),
-            };
+                location: location,
+            })
+            .collect(),
+        total_redemptions: report.iter().map(|r| r.redemptions).sum(),
+        total_amount: format!("{:.2}", report.iter().map(|r| r.amount).sum::<f64>())
+            .replace(".", ","),
+        report: report
+            .iter()
+            .map(|r| LocationReportRow {
+                name: match (&r.shoplocation_id, &r.name) {
+                    (Some(_), Some(name)) => name.to_string(),
+                    _ => "Onbekende vestiging".to_string(),
+                },
+                redemptions: r.redemptions,
+                amount: format!("{:.2}", r.amount).replace(".", ","),
+            })
+            .collect(),
+        min_date: min_date.format("%Y-%m-%d").to_string(),
+        max_date: max_date.format("%Y-%m-%d").to_string(),
+        can_manage: can_manage,
+        empty_hours: hours_rows(&[]),
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+// Checks the form, the id and distributor of the returned location still have to be set
+async fn shop_location_from_form(
+    mysql: &web::Data<MySQL>,
+    form: &ShopLocationForm,
+) -> std::result::Result<ShopLocation, &'static str> {
+    if form.name.trim().is_empty() {
+        return Err("invalid_input");
+    }
+    if form.address.trim().is_empty() {
+        return Err("address_missing");
+    }
+    let location = postcodes::resolve(mysql, &form.country, &form.postalcode, &form.city).await?;
+
+    let mut opening_hours: std::vec::Vec<String> = std::vec::Vec::new();
+    for day in form.opening_hours().iter() {
+        match parse_opening_hours(day) {
+            Some(hours) => opening_hours.push(hours),
+            None => return Err("invalid_opening_hours"),
+        }
+    }
+
+    Ok(ShopLocation {
+        id: 0,
+        distributor_id: 0,
+        name: form.name.trim().to_string(),
+        address: form.address.trim().to_string(),
+        location: location,
+        opening_hours: opening_hours,
+        active: true,
+    })
+}
+
+fn shop_location_audit_json(shop: &ShopLocation) -> serde_json::Value {
+    serde_json::json!({
+        "name": shop.name,
+        "address": shop.address,
+        "country": shop.location.country,
+        "postalcode": shop.location.postalcode,
+        "city": shop.location.city,
+        "opening_hours": shop.opening_hours,
+    })
+}
+
+async fn admin_vestiging_toevoegen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<ShopLocationForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let mut shop = match shop_location_from_form(&mysql, &form).await {
+        Ok(shop) => shop,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body(e))
+        }
+    };
+    shop.distributor_id = distributor_user.distributor.id;
+
+    shop.id = data::add_shop_location(&mysql, distributor_user.tenant(), &shop).await;
+    if shop.id == 0 {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "location.add",
+        "shoplocation",
+        shop.id,
+    );
+    entry.after = shop_location_audit_json(&shop).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_vestiging_update(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<ShopLocationForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let current = match data::get_shop_location(&mysql, distributor_user.tenant(), id).await {
+        Some(shop) => shop,
+        None => return error404().await,
+    };
+    let mut shop = match shop_location_from_form(&mysql, &form).await {
+        Ok(shop) => shop,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body(e))
+        }
+    };
+    shop.id = current.id;
+    shop.distributor_id = current.distributor_id;
+
+    if !data::update_shop_location(&mysql, distributor_user.tenant(), &shop).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "location.update",
+        "shoplocation",
+        id,
+    );
+    entry.before = shop_location_audit_json(&current).to_string();
+    entry.after = shop_location_audit_json(&shop).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_vestiging_sluiten(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let tenant = distributor_user.tenant();
+    let shop = match data::get_shop_location(&mysql, tenant, id).await {
+        Some(shop) => shop,
+        None => return error404().await,
+    };
+    // Scans need somewhere to go
+    if data::get_shop_locations(&mysql, tenant)
+        .await
+        .unwrap_or_default()
+        .len()
+        <= 1
+    {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("last_location"));
+    }
+
+    if !data::close_shop_location(&mysql, tenant, id).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "location.remove",
+        "shoplocation",
+        id,
+    );
+    entry.before = shop_location_audit_json(&shop).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+// The pairing link is only shown in this response, the token itself isn't stored
+async fn admin_apparaat_toevoegen(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<ScannerDeviceForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let tenant = distributor_user.tenant();
+    let shop = match data::get_shop_location(&mysql, tenant, id).await {
+        Some(shop) => shop,
+        None => return error404().await,
+    };
+    let name = form.name.trim();
+    if name.is_empty() || name.len() > 100 {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("invalid_input"));
+    }
+
+    let token = generate_token();
+    let device_id =
+        data::add_scanner_device(&mysql, tenant, shop.id, name, &hash_token(&token)).await;
+    if device_id == 0 {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "scanner.add",
+        "scannerdevice",
+        device_id,
+    );
+    entry.after = serde_json::json!({ "name": name, "location": shop.name }).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "url": format!(
+            "{}/scanner/koppelen/{}",
+            distributor_url(&distributor_user.distributor),
+            token
+        ),
+    })))
+}
+
+async fn admin_apparaat_intrekken(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let tenant = distributor_user.tenant();
+    let device = match data::get_scanner_devices(&mysql, tenant)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .find(|d| d.id == id)
+    {
+        Some(device) => device,
+        None => return error404().await,
+    };
+
+    if !data::revoke_scanner_device(&mysql, tenant, id).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "scanner.revoke",
+        "scannerdevice",
+        id,
+    );
+    entry.before = serde_json::json!({ "name": device.name }).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_dashboard_inhoud(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let s = AdminDashboardInhoud {
+        csrf_token: csrf::token(&req),
+        storefront_url: distributor_url(&distributor_user.distributor),
+        faq_entries: data::get_faq_entries(&mysql, distributor_user.tenant())
+            .await
+            .unwrap_or_default(),
+        pages: data::get_shop_pages(&mysql, distributor_user.tenant())
+            .await
+            .unwrap_or_default(),
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+// Sanitized rendering of the Markdown being edited, exactly as the storefront will show it
+async fn admin_inhoud_voorbeeld(
+    AdminUser(distributor_user): AdminUser,
+    form: web::Form<MarkdownPreviewForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    if form.markdown.len() > content::MAX_MARKDOWN_LENGTH {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("content_too_long"));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(content::render_markdown(&form.markdown)))
+}
+
+fn check_faq_entry_form(form: &FaqEntryForm) -> std::result::Result<(), &'static str> {
+    if form.question.trim().is_empty() || form.answer.trim().is_empty() {
+        return Err("invalid_input");
+    }
+    if form.question.len() > content::MAX_TITLE_LENGTH
+        || form.answer.len() > content::MAX_MARKDOWN_LENGTH
+    {
+        return Err("content_too_long");
+    }
+
+    Ok(())
+}
+
+async fn admin_faq_toevoegen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<FaqEntryForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    if let Err(e) = check_faq_entry_form(&form) {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body(e));
+    }
+
+    let mut entry = FaqEntry {
+        id: 0,
+        distributor_id: distributor_user.distributor.id,
+        question: form.question.trim().to_string(),
+        answer: form.answer.to_string(),
+        answer_html: content::render_markdown(&form.answer),
+        position: form.position,
+    };
+    entry.id = data::add_faq_entry(&mysql, distributor_user.tenant(), &entry).await;
+    if entry.id == 0 {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut audit = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "faq.add",
+        "distributorfaq",
+        entry.id,
+    );
+    audit.after = serde_json::json!({ "question": entry.question }).to_string();
+    data::add_audit_entry(&mysql, &audit).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_faq_update(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<FaqEntryForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let mut entry = match data::get_faq_entry(&mysql, distributor_user.tenant(), id).await {
+        Some(entry) => entry,
+        None => return error404().await,
+    };
+    if let Err(e) = check_faq_entry_form(&form) {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body(e));
+    }
+
+    let mut audit = AuditEntry::new(&distributor_user, &req, "faq.update", "distributorfaq", id);
+    audit.before =
+        serde_json::json!({ "question": entry.question, "answer": entry.answer }).to_string();
+
+    entry.question = form.question.trim().to_string();
+    entry.answer = form.answer.to_string();
+    entry.answer_html = content::render_markdown(&form.answer);
+    entry.position = form.position;
+    audit.after =
+        serde_json::json!({ "question": entry.question, "answer": entry.answer }).to_string();
+
+    if !data::update_faq_entry(&mysql, distributor_user.tenant(), &entry).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+    data::add_audit_entry(&mysql, &audit).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_faq_verwijderen(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let entry = match data::get_faq_entry(&mysql, distributor_user.tenant(), id).await {
+        Some(entry) => entry,
+        None => return error404().await,
+    };
+    if !data::delete_faq_entry(&mysql, distributor_user.tenant(), id).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut audit = AuditEntry::new(&distributor_user, &req, "faq.remove", "distributorfaq", id);
+    audit.before = serde_json::json!({ "question": entry.question }).to_string();
+    data::add_audit_entry(&mysql, &audit).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+// Checks the form and returns the slug to store, the terms and conditions don't have one
+async fn check_shop_page_form(
+    mysql: &web::Data<MySQL>,
+    tenant: data::Tenant,
+    form: &ShopPageForm,
+    kind: PageKind,
+    page_id: u64,
+) -> std::result::Result<String, &'static str> {
+    if form.title.trim().is_empty() {
+        return Err("invalid_input");
+    }
+    if form.title.len() > content::MAX_TITLE_LENGTH
+        || form.body.len() > content::MAX_MARKDOWN_LENGTH
+    {
+        return Err("content_too_long");
+    }
+    if kind == PageKind::Terms {
+        return Ok("voorwaarden".to_string());
+    }
+
+    let slug = form.slug.trim().to_lowercase();
+    content::check_slug(&slug)?;
+    if data::is_page_slug_taken(mysql, tenant, &slug, page_id).await {
+        return Err("slug_taken");
+    }
+
+    Ok(slug)
+}
+
+fn shop_page_audit_json(page: &ShopPage) -> serde_json::Value {
+    serde_json::json!({
+        "kind": page.kind,
+        "slug": page.slug,
+        "title": page.title,
+        "body": page.body,
+        "published": page.published,
+    })
+}
+
+// /admin/dashboard/inhoud/paginas/nieuw/{kind}, kind is "voorwaarden" or "pagina"
+async fn admin_pagina_toevoegen(
+    web::Path(kind): web::Path<String>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<ShopPageForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let kind = match &*kind {
+        "voorwaarden" => PageKind::Terms,
+        "pagina" => PageKind::Custom,
+        _ => return error404().await,
+    };
+    let tenant = distributor_user.tenant();
+
+    // One set of terms and conditions per distributor
+    if kind == PageKind::Terms
+        && data::get_shop_pages(&mysql, tenant)
+            .await
+            .unwrap_or_default()
+            .iter()
+            .any(|p| p.kind == PageKind::Terms)
+    {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("terms_exist"));
+    }
+
+    let slug = match check_shop_page_form(&mysql, tenant, &form, kind, 0).await {
+        Ok(slug) => slug,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body(e))
+        }
+    };
+
+    let mut page = ShopPage {
+        id: 0,
+        distributor_id: tenant.distributor_id,
+        kind: kind,
+        slug: slug,
+        title: form.title.trim().to_string(),
+        body: form.body.to_string(),
+        body_html: content::render_markdown(&form.body),
+        published: form.published,
+        update_date: None,
+    };
+    page.id = data::add_shop_page(&mysql, tenant, &page).await;
+    if page.id == 0 {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut audit = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "page.add",
+        "distributorpage",
+        page.id,
+    );
+    audit.after = shop_page_audit_json(&page).to_string();
+    data::add_audit_entry(&mysql, &audit).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_pagina_update(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<ShopPageForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let tenant = distributor_user.tenant();
+    let mut page = match data::get_shop_page(&mysql, tenant, id).await {
+        Some(page) => page,
+        None => return error404().await,
+    };
+    let slug = match check_shop_page_form(&mysql, tenant, &form, page.kind, page.id).await {
+        Ok(slug) => slug,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body(e))
+        }
+    };
+
+    let mut audit = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "page.update",
+        "distributorpage",
+        id,
+    );
+    audit.before = shop_page_audit_json(&page).to_string();
+
+    page.slug = slug;
+    page.title = form.title.trim().to_string();
+    page.body = form.body.to_string();
+    page.body_html = content::render_markdown(&form.body);
+    page.published = form.published;
+    audit.after = shop_page_audit_json(&page).to_string();
+
+    if !data::update_shop_page(&mysql, tenant, &page).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+    data::add_audit_entry(&mysql, &audit).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_pagina_verwijderen(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let page = match data::get_shop_page(&mysql, distributor_user.tenant(), id).await {
+        Some(page) => page,
+        None => return error404().await,
+    };
+    if !data::delete_shop_page(&mysql, distributor_user.tenant(), id).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut audit = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "page.remove",
+        "distributorpage",
+        id,
+    );
+    audit.before = shop_page_audit_json(&page).to_string();
+    data::add_audit_entry(&mysql, &audit).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_dashboard_huisstijl(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let distributor = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap();
+
+    let s = AdminDashboardHuisstijl {
+        csrf_token: csrf::token(&req),
+        storefront_url: distributor_url(&distributor),
+        branding: distributor.branding,
+        fonts: Font::all(),
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+fn branding_audit_json(branding: &Branding) -> serde_json::Value {
+    serde_json::json!({
+        "logo": branding.logo,
+        "cover": branding.cover,
+        "primary_color": branding.primary_color,
+        "accent_color": branding.accent_color,
+        "font": branding.font,
+    })
+}
+
+async fn admin_update_huisstijl(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<BrandingForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let (primary_color, accent_color) = match (
+        branding::check_color(&form.primary_color),
+        branding::check_color(&form.accent_color),
+    ) {
+        (Some(primary), Some(accent)) => (primary, accent),
+        _ => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body("invalid_color"))
+        }
+    };
+    let font = match Font::from_str(&form.font) {
+        Ok(font) => font,
+        Err(_) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body("invalid_font"))
+        }
+    };
+
+    let mut branding = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap()
+        .branding;
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "distributor.branding",
+        "distributor",
+        distributor_user.distributor.id,
+    );
+    entry.before = branding_audit_json(&branding).to_string();
+
+    branding.primary_color = primary_color;
+    branding.accent_color = accent_color;
+    branding.font = font;
+    entry.after = branding_audit_json(&branding).to_string();
+
+    if !data::update_distributor_branding(&mysql, distributor_user.tenant(), &branding).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+// The file is sent as the request body, with its type in the Content-Type header
+async fn admin_huisstijl_afbeelding(
+    web::Path(image): web::Path<String>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    mut payload: web::Payload,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let image = match branding::BrandingImage::from_path(&image) {
+        Some(image) => image,
+        None => return error404().await,
+    };
+
+    use futures::StreamExt;
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        body.extend_from_slice(&chunk?);
+        if body.len() > branding::MAX_UPLOAD_BYTES {
+            return Ok(HttpResponse::PayloadTooLarge()
+                .content_type("text/plain")
+                .body("file_too_large"));
+        }
+    }
+
+    let content_type = req
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("")
+        .to_lowercase();
+    let file_name = match branding::save_image(
+        distributor_user.tenant(),
+        image,
+        content_type,
+        body.freeze(),
+    )
+    .await
+    {
+        Ok(file_name) => file_name,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body(e))
+        }
+    };
+
+    let mut branding = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap()
+        .branding;
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "distributor.branding",
+        "distributor",
+        distributor_user.distributor.id,
+    );
+    entry.before = branding_audit_json(&branding).to_string();
+
+    let previous = match image {
+        branding::BrandingImage::Logo => branding.logo.replace(file_name.to_string()),
+        branding::BrandingImage::Cover => branding.cover.replace(file_name.to_string()),
+    };
+    entry.after = branding_audit_json(&branding).to_string();
+
+    if !data::update_distributor_branding(&mysql, distributor_user.tenant(), &branding).await {
+        branding::remove_image(file_name).await;
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+    data::add_audit_entry(&mysql, &entry).await;
+
+    if let Some(previous) = previous {
+        branding::remove_image(previous).await;
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_huisstijl_afbeelding_verwijderen(
+    web::Path(image): web::Path<String>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let image = match branding::BrandingImage::from_path(&image) {
+        Some(image) => image,
+        None => return error404().await,
+    };
+
+    let mut branding = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap()
+        .branding;
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "distributor.branding",
+        "distributor",
+        distributor_user.distributor.id,
+    );
+    entry.before = branding_audit_json(&branding).to_string();
+
+    let previous = match image {
+        branding::BrandingImage::Logo => branding.logo.take(),
+        branding::BrandingImage::Cover => branding.cover.take(),
+    };
+    entry.after = branding_audit_json(&branding).to_string();
+
+    if previous.is_some() {
+        if !data::update_distributor_branding(&mysql, distributor_user.tenant(), &branding).await {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body("update_failed"));
+        }
+        data::add_audit_entry(&mysql, &entry).await;
+        branding::remove_image(previous.unwrap()).await;
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+// The old subdomain keeps redirecting, the session stays behind on the old host
+async fn admin_subdomein_wijzigen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<SubdomainForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    let subdomain = form.subdomain.trim().to_lowercase();
+    if let Err(e) = check_subdomain(&subdomain) {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body(e));
+    }
+
+    let mut distributor = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap();
+    if distributor.subdomain == subdomain {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("subdomain_unchanged"));
+    }
+    if data::is_subdomain_taken(&mysql, &subdomain, distributor.id).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("subdomain_taken"));
+    }
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "distributor.subdomain",
+        "distributor",
+        distributor.id,
+    );
+    entry.before = serde_json::json!({ "subdomain": distributor.subdomain }).to_string();
+    entry.after = serde_json::json!({ "subdomain": subdomain }).to_string();
+
+    distributor.subdomain = subdomain;
+    if !data::update_distributor(&mysql, &distributor).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "url": format!("{}/admin/login", distributor_url(&distributor)),
+    })))
+}
+
+async fn admin_dashboard_tweestapsverificatie(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let distributor = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap();
+
+    // The session copy can be stale, e.g. 2FA was enabled in another tab
+    let totp_enabled = DistributorUser::get_by_id(distributor_user.id, &mysql)
+        .await
+        .map(|u| u.totp_enabled)
+        .unwrap_or(distributor_user.totp_enabled);
+
+    let mut s = AdminDashboardTweestapsverificatie {
+        csrf_token: csrf::token(&req),
+        totp_enabled: totp_enabled,
+        require_2fa: distributor.require_2fa,
+        secret: "".to_string(),
+        qr_code: "".to_string(),
+        recovery_codes_left: 0,
+    };
+
+    match totp_enabled {
+        true => {
+            s.recovery_codes_left = data::count_recovery_codes(&mysql, distributor_user.id).await;
+        }
+        false => {
+            // New secret on every visit until one gets confirmed with a code
+            let secret = totp::generate_secret();
+            data::set_totp_pending_secret(&mysql, distributor_user.id, &secret).await;
+
+            s.qr_code = totp::qr_code_svg(&totp::provisioning_uri(
+                &format!("Kaddo {}", distributor.name),
+                &distributor_user.username,
+                &secret,
+            ));
+            s.secret = secret;
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(s.render().unwrap()))
+}
+
+async fn admin_tweestapsverificatie_activeren(
+    session: Session,
+    AdminUser(mut distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    form: web::Form<TwoFactorForm>,
+) -> Result<HttpResponse> {
+    if distributor_user.totp_enabled {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("already_enabled"));
+    }
+    if !TWO_FACTOR_LIMITER
+        .check(&distributor_user.id.to_string())
+        .await
+    {
+        return Ok(HttpResponse::TooManyRequests()
+            .content_type("text/plain")
+            .body("too_many_attempts"));
+    }
+
+    let secret = data::get_totp_pending_secret(&mysql, distributor_user.id)
+        .await
+        .unwrap_or_default();
+    let step = match totp::verify(&secret, &form.code, Utc::now().timestamp()) {
+        Some(step) => step,
+        None => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body("invalid_code"))
+        }
+    };
+
+    if !data::enable_totp(&mysql, distributor_user.id, &secret).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+    data::use_totp_step(&mysql, distributor_user.id, step).await;
+
+    let recovery_codes = generate_recovery_codes();
+    data::set_recovery_codes(&mysql, distributor_user.id, &recovery_codes).await;
+
+    distributor_user.totp_enabled = true;
+    session.set("distributoruser", distributor_user)?;
+
+    Ok(HttpResponse::Ok().json(recovery_codes))
+}
+
+async fn admin_tweestapsverificatie_herstelcodes(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    form: web::Form<TwoFactorForm>,
+) -> Result<HttpResponse> {
+    if !distributor_user.totp_enabled {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("not_enabled"));
+    }
+    if !TWO_FACTOR_LIMITER
+        .check(&distributor_user.id.to_string())
+        .await
+    {
+        return Ok(HttpResponse::TooManyRequests()
+            .content_type("text/plain")
+            .body("too_many_attempts"));
+    }
+    if !distributor_user
+        .verify_second_factor(&form.code, &mysql)
+        .await
+    {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("invalid_code"));
+    }
+
+    let recovery_codes = generate_recovery_codes();
+    match data::set_recovery_codes(&mysql, distributor_user.id, &recovery_codes).await {
+        false => Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed")),
+        true => Ok(HttpResponse::Ok().json(recovery_codes)),
+    }
+}
+
+async fn admin_tweestapsverificatie_uitschakelen(
+    session: Session,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    form: web::Form<TwoFactorDisableForm>,
+) -> Result<HttpResponse> {
+    let mut distributor_user = DistributorUser::get_by_id(distributor_user.id, &mysql)
+        .await
+        .unwrap();
+
+    if distributor_user.distributor.require_2fa {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("2fa_required"));
+    }
+    if !TWO_FACTOR_LIMITER
+        .check(&distributor_user.id.to_string())
+        .await
+    {
+        return Ok(HttpResponse::TooManyRequests()
+            .content_type("text/plain")
+            .body("too_many_attempts"));
+    }
+    if !distributor_user
+        .verify_password(form.password.as_bytes())
+        .unwrap_or(false)
+    {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("wrong_password"));
+    }
+    if !distributor_user
+        .verify_second_factor(&form.code, &mysql)
+        .await
+    {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("invalid_code"));
+    }
+
+    if !data::update_totp(&mysql, distributor_user.id, None, false).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+    data::set_recovery_codes(&mysql, distributor_user.id, &std::vec::Vec::new()).await;
+
+    distributor_user.totp_enabled = false;
+    session.set("distributoruser", distributor_user)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
+
+async fn admin_update_tweestapsverificatie_verplicht(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<TwoFactorRequireForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageBusiness)?;
+
+    // Don't let an admin lock themselves out
+    if form.require_2fa && !distributor_user.totp_enabled {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("enable_2fa_first"));
+    }
+
+    let mut distributor = get_distributor(&mysql, distributor_user.distributor.id)
+        .await
+        .unwrap();
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "distributor.require_2fa",
+        "distributor",
+        distributor.id,
+    );
+    entry.before = serde_json::json!({ "require_2fa": distributor.require_2fa }).to_string();
+    entry.after = serde_json::json!({ "require_2fa": form.require_2fa }).to_string();
+
+    distributor.require_2fa = form.require_2fa;
+
+    match data::update_distributor_require_2fa(&mysql, &distributor).await {
+        false => Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed")),
+        true => {
+            data::add_audit_entry(&mysql, &entry).await;
+
+            Ok(HttpResponse::Ok()
+                .content_type("text/plain")
+                .body("update_succeeded"))
+        }
+    }
+}
+
+async fn admin_dashboard_cadeaubonnen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageVouchers)?;
+
+    let all_distributor_vouchers: Vec<DistributorVoucher> =
+        data::get_distributor_vouchers_by_distributor(&mysql, distributor_user.tenant())
+            .await
+            .unwrap();
+    let mut three_price_distributor_vouchers: Vec<&DistributorVoucher> = Vec::new();
+    let mut price_range_distributor_voucher: Option<&DistributorVoucher> = None;
+    let mut label_distributor_vouchers: Vec<LabelVoucherData> = Vec::new();
+    let mut currently_active: Option<VoucherType> = None;
+    for distributor_voucher in all_distributor_vouchers.iter() {
+        if distributor_voucher.voucher_type == VoucherType::ThreeOptionVoucher {
+            three_price_distributor_vouchers.push(distributor_voucher);
+
+            if distributor_voucher.active {
+                currently_active = Some(VoucherType::ThreeOptionVoucher);
+            }
+        }
+
+        if distributor_voucher.voucher_type == VoucherType::RangeVoucher {
+            price_range_distributor_voucher = Some(&distributor_voucher);
+
+            if distributor_voucher.active {
+                currently_active = Some(VoucherType::RangeVoucher);
+            }
+        }
+
+        if distributor_voucher.voucher_type == VoucherType::LabelVoucher {
+            label_distributor_vouchers.push(LabelVoucherData {
+                title: distributor_voucher.label.to_string(),
+                amount: distributor_voucher.amount as u64,
+                description: distributor_voucher.description.to_string(),
+                days_valid: distributor_voucher.days_valid,
+            });
+
+            if distributor_voucher.active {
+                currently_active = Some(VoucherType::LabelVoucher);
+            }
+        }
+    }
+
+    let mut s = AdminDashboardCadeaubonnen {
+        csrf_token: csrf::token(&req),
+        three_price_voucher_a: if three_price_distributor_vouchers.len() > 0 {
+            three_price_distributor_vouchers[0].amount
+        } else {
+            10 as f64
+        },
+        three_price_voucher_b: if three_price_distributor_vouchers.len() > 0 {
+            three_price_distributor_vouchers[1].amount
+        } else {
+            25 as f64
+        },
+        three_price_voucher_c: if three_price_distributor_vouchers.len() > 0 {
+            three_price_distributor_vouchers[2].amount
+        } else {
+            50 as f64
+        },
+        three_price_voucher_max_days: if three_price_distributor_vouchers.len() > 0 {
+            three_price_distributor_vouchers[0].days_valid
+        } else {
+            90
+        },
+        three_price_voucher_one_use_only: if three_price_distributor_vouchers.len() > 0 {
+            three_price_distributor_vouchers[0].one_use_only
+        } else {
+            false
+        },
 
-            let http = reqwest::Client::new();
-            let res = http
-                .post("https://api.mollie.com/v2/payments")
-                .json(&payment)
-                .header(
-                    "Authorization",
-                    "Bearer test_8v3dnuvdFp7hj6U9TCvtCpr8MsNeHx",
-                )
-                .send()
-                .await
-                .unwrap();
+        price_range_voucher_min: if price_range_distributor_voucher.is_some() {
+            price_range_distributor_voucher.unwrap().min_amount
+        } else {
+            10 as f64
+        },
+        price_range_voucher_max: if price_range_distributor_voucher.is_some() {
+            price_range_distributor_voucher.unwrap().max_amount
+        } else {
+            50 as f64
+        },
+        price_range_voucher_auto: if price_range_distributor_voucher.is_some() {
+            price_range_distributor_voucher.unwrap().amount
+        } else {
+            20 as f64
+        },
+        price_range_voucher_max_days: if price_range_distributor_voucher.is_some() {
+            price_range_distributor_voucher.unwrap().days_valid
+        } else {
+            90
+        },
+        price_range_voucher_one_use_only: if price_range_distributor_voucher.is_some() {
+            price_range_distributor_voucher.unwrap().one_use_only
+        } else {
+            false
+        },
 
-            let json: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        label_vouchers: &label_distributor_vouchers,
+        label_voucher_max_days: if label_distributor_vouchers.len() > 0 {
+            label_distributor_vouchers[0].days_valid
+        } else {
+            90
+        },
+
+        currently_active: currently_active,
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn admin_update_cadeaubonnen(
+    AdminUser(distributor_user): AdminUser,
+    req: HttpRequest,
+    mysql: web::Data<MySQL>,
+    json: web::Json<AdminVouchersUpdateJson>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageVouchers)?;
+
+    let voucher_type: VoucherType =
+        VoucherType::from_str(req.match_info().get("voucher_type").unwrap()).unwrap();
+
+    let mut vouchers_to_add: std::vec::Vec<DistributorVoucher> = Vec::new();
+    match voucher_type {
+        VoucherType::ThreeOptionVoucher => {
+            for distributor_voucher in &json.three_option_vouchers {
+                vouchers_to_add.push(DistributorVoucher {
+                    id: 0,
+                    distributor: distributor_user.distributor.clone(),
+                    voucher_type: VoucherType::ThreeOptionVoucher,
+                    amount: *distributor_voucher as f64,
+                    min_amount: 0 as f64,
+                    max_amount: 0 as f64,
+                    label: "".to_string(),
+                    description: "".to_string(),
+                    days_valid: json.days_valid as u16,
+                    active: true,
+                    one_use_only: json.one_use_only as bool,
+                    create_date: None,
+                });
+            }
+        }
+        VoucherType::RangeVoucher => {
+            vouchers_to_add.push(DistributorVoucher {
+                id: 0,
+                distributor: distributor_user.distributor.clone(),
+                voucher_type: VoucherType::RangeVoucher,
+                amount: json.price_range_voucher.auto_amount as f64,
+                min_amount: json.price_range_voucher.min_amount as f64,
+                max_amount: json.price_range_voucher.max_amount as f64,
+                label: "".to_string(),
+                description: "".to_string(),
+                days_valid: json.days_valid as u16,
+                active: true,
+                one_use_only: json.one_use_only as bool,
+                create_date: None,
+            });
+        }
+        VoucherType::LabelVoucher => {
+            for distributor_voucher in &json.label_vouchers {
+                vouchers_to_add.push(DistributorVoucher {
+                    id: 0,
+                    distributor: distributor_user.distributor.clone(),
+                    voucher_type: VoucherType::LabelVoucher,
+                    amount: distributor_voucher.amount as f64,
+                    min_amount: 0 as f64,
+                    max_amount: 0 as f64,
+                    label: distributor_voucher.title.to_string(),
+                    description: distributor_voucher.description.to_string(),
+                    days_valid: json.days_valid as u16,
+                    active: true,
+                    one_use_only: true,
+                    create_date: None,
+                });
+            }
+        }
+        _ => {
+            return Ok(HttpResponse::BadRequest().finish());
+        }
+    }
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "distributorvoucher.activate",
+        "distributor",
+        distributor_user.distributor.id,
+    );
+    entry.before = serde_json::Value::Array(
+        data::get_active_distributor_vouchers_by_distributor(&mysql, distributor_user.tenant())
+            .await
+            .unwrap_or_default()
+            .iter()
+            .map(distributor_voucher_audit_json)
+            .collect(),
+    )
+    .to_string();
+    entry.after = serde_json::Value::Array(
+        vouchers_to_add
+            .iter()
+            .map(distributor_voucher_audit_json)
+            .collect(),
+    )
+    .to_string();
+
+    if data::add_active_distributor_vouchers(&mysql, distributor_user.tenant(), vouchers_to_add)
+        .await
+    {
+        data::add_audit_entry(&mysql, &entry).await;
+    }
+
+    Ok(HttpResponse::Ok().content_type("text/plain").body(""))
+}
+
+fn distributor_voucher_audit_json(distributor_voucher: &DistributorVoucher) -> serde_json::Value {
+    serde_json::json!({
+        "voucher_type": distributor_voucher.voucher_type,
+        "amount": distributor_voucher.amount,
+        "min_amount": distributor_voucher.min_amount,
+        "max_amount": distributor_voucher.max_amount,
+        "label": distributor_voucher.label,
+        "days_valid": distributor_voucher.days_valid,
+        "one_use_only": distributor_voucher.one_use_only,
+    })
+}
+
+async fn admin_dashboard_bestellingen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ViewOrders)?;
+
+    let s = AdminDashboardBestellingen.render().unwrap();
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn admin_dashboard_bestelling(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ViewOrders)?;
+
+    let tenant = distributor_user.tenant();
+
+    let id = req.match_info().get("id").unwrap().parse::<u64>().unwrap();
+    let voucher = data::get_voucher(&mysql, tenant, data::Selector::ById(id)).await;
+    if voucher.is_none() {
+        return error404().await;
+    }
+
+    let voucher = voucher.unwrap();
+
+    let history = data::get_audit_entries_by_entity(&mysql, tenant, "voucher", voucher.id)
+        .await
+        .unwrap_or_default();
+
+    let s = AdminOrderData {
+        csrf_token: csrf::token(&req),
+        voucher: voucher,
+        history: history,
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+// Loads the voucher of the bestelling in the path, only if it belongs to the user's distributor
+async fn admin_get_voucher(
+    mysql: &web::Data<MySQL>,
+    req: &HttpRequest,
+    distributor_user: &DistributorUser,
+) -> Option<Voucher> {
+    let id = req.match_info().get("id")?.parse::<u64>().ok()?;
+
+    data::get_voucher(mysql, distributor_user.tenant(), data::Selector::ById(id)).await
+}
+
+async fn admin_save_voucher(
+    mysql: &web::Data<MySQL>,
+    tenant: data::Tenant,
+    voucher: &Voucher,
+    entry: &AuditEntry,
+) -> Result<HttpResponse> {
+    match data::update_voucher(mysql, tenant, voucher).await {
+        false => Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed")),
+        true => {
+            data::add_audit_entry(mysql, entry).await;
+
+            Ok(HttpResponse::Ok()
+                .content_type("text/plain")
+                .body("update_succeeded"))
+        }
+    }
+}
+
+async fn admin_bestelling_verlengen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<VoucherExpirationForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageOrders)?;
+
+    let voucher = admin_get_voucher(&mysql, &req, &distributor_user).await;
+    if voucher.is_none() {
+        return error404().await;
+    }
+
+    let mut v = voucher.unwrap();
+
+    let expiration_date = match chrono::NaiveDate::parse_from_str(&form.expiration_date, "%Y-%m-%d")
+    {
+        Ok(date) => Utc.from_utc_datetime(&date.and_hms(23, 59, 59)),
+        Err(_) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body("invalid_date"))
+        }
+    };
+
+    // Only extending is allowed, shortening would take away what the receiver paid for
+    if expiration_date <= v.expiration_date {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("invalid_date"));
+    }
+
+    let mut entry = AuditEntry::new(&distributor_user, &req, "voucher.extend", "voucher", v.id);
+    entry.before = serde_json::json!({ "expiration_date": v.expiration_date }).to_string();
+    entry.after = serde_json::json!({ "expiration_date": expiration_date }).to_string();
+
+    v.expiration_date = expiration_date;
+
+    admin_save_voucher(&mysql, distributor_user.tenant(), &v, &entry).await
+}
+
+async fn admin_bestelling_saldo(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<VoucherBalanceForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageOrders)?;
+
+    let voucher = admin_get_voucher(&mysql, &req, &distributor_user).await;
+    if voucher.is_none() {
+        return error404().await;
+    }
+
+    let mut v = voucher.unwrap();
+
+    // A correction can't give the voucher more than was paid for it
+    if form.balance < 0.0 || form.balance > v.sale.amount {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("invalid_balance"));
+    }
+    if form.reason.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("reason_required"));
+    }
+
+    let mut entry = AuditEntry::new(&distributor_user, &req, "voucher.balance", "voucher", v.id);
+    entry.before = serde_json::json!({ "balance": v.balance, "used": v.used }).to_string();
+    entry.reason = form.reason.trim().to_string();
+
+    v.balance = (form.balance * 100.0).round() / 100.0;
+    v.used = v.balance <= 0.0;
+
+    entry.after = serde_json::json!({ "balance": v.balance, "used": v.used }).to_string();
+
+    admin_save_voucher(&mysql, distributor_user.tenant(), &v, &entry).await
+}
+
+async fn admin_bestelling_blokkeren(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<VoucherBlockForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageOrders)?;
+
+    let voucher = admin_get_voucher(&mysql, &req, &distributor_user).await;
+    if voucher.is_none() {
+        return error404().await;
+    }
+
+    let mut v = voucher.unwrap();
+
+    let action = if form.blocked {
+        "voucher.block"
+    } else {
+        "voucher.unblock"
+    };
+    let mut entry = AuditEntry::new(&distributor_user, &req, action, "voucher", v.id);
+    entry.before = serde_json::json!({ "status": v.status }).to_string();
+    entry.reason = form.reason.trim().to_string();
+
+    // Unblocking hands the status back to derive_status
+    v.status = if form.blocked {
+        VoucherStatus::Blocked
+    } else {
+        VoucherStatus::Active
+    };
+    v.status = v.derive_status();
+
+    entry.after = serde_json::json!({ "status": v.status }).to_string();
+
+    admin_save_voucher(&mysql, distributor_user.tenant(), &v, &entry).await
+}
+
+async fn admin_bestelling_ontvanger(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<VoucherReceiverForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageOrders)?;
+
+    let voucher = admin_get_voucher(&mysql, &req, &distributor_user).await;
+    if voucher.is_none() {
+        return error404().await;
+    }
 
-            *payment_id = str::replace(&json["id"].to_string(), "\"", "");
+    let mut v = voucher.unwrap();
 
-            payment.redirectUrl = format!("http://demok.kaddo.test:8080/check/{}", payment_id);
+    if form.validate().is_err() {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("invalid_receiver"));
+    }
 
-            payment.id = payment_id.to_string();
-            self.update_payment(&payment).await;
+    let mut entry = AuditEntry::new(&distributor_user, &req, "voucher.receiver", "voucher", v.id);
+    entry.before = serde_json::json!({
+        "receiver_name": v.receiver_name,
+        "receiver_email": v.receiver_email,
+    })
+    .to_string();
 
-            payment
-        }
+    v.receiver_name = form.receiver_name.trim().to_string();
+    v.receiver_email = form.receiver_email.trim().to_string();
 
-        pub async fn update_payment(&self, payment: &Payment) -> bool {
-            #[derive(Serialize)]
-            struct Parameters {
-                redirectUrl: String,
-            }
+    entry.after = serde_json::json!({
+        "receiver_name": v.receiver_name,
+        "receiver_email": v.receiver_email,
+    })
+    .to_string();
 
-            let params = Parameters {
-                redirectUrl: (*payment.redirectUrl).to_string(),
-            };
+    admin_save_voucher(&mysql, distributor_user.tenant(), &v, &entry).await
+}
 
-            let http = reqwest::Client::new();
-            let res = http
-                .patch(&format!(
-                    "https://api.mollie.com/v2/payments/{}",
-                    payment.id
-                ))
-                .json(&params)
-                .header(
-                    "Authorization",
-                    "Bearer test_8v3dnuvdFp7hj6U9TCvtCpr8MsNeHx",
-                )
-                .send()
-                .await
-                .unwrap();
+async fn admin_bestelling_opnieuw_versturen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    mail: web::Data<mail::Mail>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageOrders)?;
 
-            true
-        }
+    let voucher = admin_get_voucher(&mysql, &req, &distributor_user).await;
+    if voucher.is_none() {
+        return error404().await;
+    }
 
-        pub async fn get_payment(&self, id: &String) -> Payment {
-            let http = reqwest::Client::new();
-            let res = http
-                .patch(&format!("https://api.mollie.com/v2/payments/{}", id))
-                .header(
-                    "Authorization",
-                    "Bearer test_8v3dnuvdFp7hj6U9TCvtCpr8MsNeHx",
-                )
-                .send()
-                .await
-                .unwrap();
+    let v = voucher.unwrap();
 
-            let payment: Payment = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    if !v.sale.paid {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("not_paid"));
+    }
 
-            payment
-        }
+    if let Err(e) = mail.send_voucher_mail(&v).await {
+        println!("Error: {}", e);
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("mail_failed"));
     }
+
+    let mut entry = AuditEntry::new(&distributor_user, &req, "voucher.resend", "voucher", v.id);
+    entry.after = serde_json::json!({ "receiver_email": v.receiver_email }).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("mail_sent"))
 }
 
-pub mod mail {
-    use crate::*;
-    use lettre::transport::smtp::authentication::Credentials;
-    use lettre::{Message, SmtpTransport, Transport};
-    use lettre_email::EmailBuilder;
+async fn admin_dashboard_get_bestellingen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ViewOrders)?;
 
-    #[derive(Clone)]
-    pub struct Creds {
-        pub username: String,
-        pub password: String,
+    let query_str = req.query_string().replace("%20", " ");
+    let filters = web::Query::<AdminOrderFilterParams>::from_query(&query_str)
+        .unwrap()
+        .into_inner();
+
+    let orders = data::get_all_orders(
+        &mysql,
+        distributor_user.tenant(),
+        filters.amount,
+        25,
+        filters,
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(&orders))
+}
+
+async fn admin_dashboard_wachtwoord(_: AdminUser, req: HttpRequest) -> Result<HttpResponse> {
+    let mut s = AdminDashboardWachtwoord {
+        csrf_token: csrf::token(&req),
     }
+    .render()
+    .unwrap();
 
-    #[derive(Clone)]
-    pub struct Mail {
-        pub creds: Creds,
-        pub smtp_server: String,
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn admin_update_wachtwoord(
+    session: Session,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<PasswordChangeForm>,
+) -> Result<HttpResponse> {
+    // Rate limited like the other password checks, a stolen session can't guess at it
+    if let Err(e) = reauthenticate(&mysql, &distributor_user, &form.current_password).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body(e));
     }
+    let mut distributor_user = DistributorUser::get_by_id(distributor_user.id, &mysql)
+        .await
+        .unwrap();
 
-    impl Mail {
-        pub async fn send_plain_mail(
-            &self,
-            from: String,
-            to: String,
-            subject: String,
-            text: String,
-        ) {
-            let email = Message::builder()
-                .from(from.parse().unwrap())
-                .reply_to(to.parse().unwrap())
-                .to(to.parse().unwrap())
-                .subject(subject)
-                .body(text)
-                .unwrap();
+    if form.new_password != form.new_password_repeat {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("passwords_dont_match"));
+    }
+    if let Err(e) = distributor_user.check_password_strength(&form.new_password) {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body(e));
+    }
 
-            let mailer = SmtpTransport::unencrypted_localhost();
+    match distributor_user
+        .update_password(&form.new_password, &mysql)
+        .await
+    {
+        false => Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed")),
+        true => {
+            // Other devices have to log in again with the new password
+            data::revoke_admin_sessions_by_user(
+                &mysql,
+                distributor_user.id,
+                sessions::current_session_id(&req),
+            )
+            .await;
+            session.set("distributoruser", distributor_user)?;
 
-            match mailer.send(&email) {
-                Ok(_) => println!("Email sent successfully!"),
-                Err(e) => panic!("Could not send email: {:?}", e),
-            }
+            Ok(HttpResponse::Ok()
+                .content_type("text/plain")
+                .body("update_succeeded"))
         }
     }
 }
 
-/* ROUTE FUNCTIONS */
+async fn admin_wachtwoord_vergeten(req: HttpRequest) -> Result<HttpResponse> {
+    let distributor = tenancy::current(&req);
 
-// Business routes
+    if distributor.is_none() {
+        return error404().await;
+    }
 
-async fn index(mysql: web::Data<MySQL>, req: HttpRequest) -> Result<HttpResponse> {
-    let distributor = data::get_distributor_by_subdomain(
-        &mysql,
-        get_subdomain_part(req.headers().get("Host").unwrap().to_str().unwrap()),
-    )
-    .await;
+    let s = AdminWachtwoordVergeten {
+        csrf_token: csrf::token(&req),
+        distributor_name: distributor.unwrap().name,
+        status: "".to_string(),
+    }
+    .render()
+    .unwrap();
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn admin_wachtwoord_vergeten_form(
+    mysql: web::Data<MySQL>,
+    mail: web::Data<mail::Mail>,
+    req: HttpRequest,
+    form: web::Form<PasswordForgotForm>,
+) -> Result<HttpResponse> {
+    let distributor = tenancy::current(&req);
 
     if distributor.is_none() {
         return error404().await;
     }
 
-    let d = distributor.unwrap();
+    let distributor = distributor.unwrap();
 
-    let s = Index {
-        distributor: &d,
-        distributor_vouchers: &data::get_active_distributor_vouchers_by_distributor(&mysql, d.id)
-            .await
-            .unwrap(),
+    // Same answer whether the user exists or not
+    let mut s = AdminWachtwoordVergeten {
+        csrf_token: csrf::token(&req),
+        distributor_name: distributor.name.to_string(),
+        status: "Als deze gebruiker bestaat, hebben we een mail gestuurd met een link om je wachtwoord te herstellen.".to_string(),
+    };
+
+    let ip = client_ip(&req);
+    if !PASSWORD_RESET_LIMITER.check(&ip).await {
+        s.status = "Te veel pogingen, probeer het later opnieuw.".to_string();
+        return Ok(HttpResponse::TooManyRequests()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
     }
-    .render()
-    .unwrap();
 
-    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+    let distributor_user =
+        DistributorUser::get_by_username(&form.admin_username, distributor.tenant(), &mysql).await;
+
+    if let Some(user) = distributor_user {
+        if user.active && user.email != "" {
+            let token = generate_token();
+            data::add_user_token(
+                &mysql,
+                user.id,
+                TokenPurpose::PasswordReset,
+                &token,
+                Utc::now() + Duration::minutes(PASSWORD_RESET_VALID_MINUTES),
+            )
+            .await;
+
+            // Same answer either way, a failing mail can't tell whether the user exists
+            mail.send_password_reset_mail(&user, &token, PASSWORD_RESET_VALID_MINUTES)
+                .await
+                .unwrap_or_else(|e| println!("Error: {}", e));
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(s.render().unwrap()))
 }
 
-async fn bestel(mysql: web::Data<MySQL>, req: HttpRequest) -> Result<HttpResponse> {
-    let distributor = data::get_distributor_by_subdomain(
-        &mysql,
-        get_subdomain_part(req.headers().get("Host").unwrap().to_str().unwrap()),
-    )
-    .await;
+async fn admin_wachtwoord_herstellen(
+    web::Path(token): web::Path<String>,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let distributor = tenancy::current(&req);
 
     if distributor.is_none() {
         return error404().await;
     }
 
-    let d = distributor.unwrap();
+    let mut s = AdminWachtwoordHerstellen {
+        csrf_token: csrf::token(&req),
+        distributor_name: distributor.unwrap().name,
+        token: token.to_string(),
+        status: "".to_string(),
+    };
 
-    let distributor_vouchers = &data::get_active_distributor_vouchers_by_distributor(&mysql, d.id)
+    if data::get_user_token_user(&mysql, TokenPurpose::PasswordReset, &token)
         .await
-        .unwrap();
-    let s = Bestel {
-        distributor_vouchers: &distributor_vouchers,
-        first_distributor_voucher: &distributor_vouchers[0],
+        .is_none()
+    {
+        s.token = "".to_string();
+        s.status = "Deze link is ongeldig of vervallen.".to_string();
+        return Ok(HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
     }
-    .render()
-    .unwrap();
 
-    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(s.render().unwrap()))
 }
 
-async fn faq() -> Result<HttpResponse> {
-    let s = Faq.render().unwrap();
+async fn admin_wachtwoord_herstellen_form(
+    web::Path(token): web::Path<String>,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<PasswordResetForm>,
+) -> Result<HttpResponse> {
+    let distributor = tenancy::current(&req);
+
+    if distributor.is_none() {
+        return error404().await;
+    }
+
+    let distributor = distributor.unwrap();
+
+    let mut s = AdminWachtwoordHerstellen {
+        csrf_token: csrf::token(&req),
+        distributor_name: distributor.name.to_string(),
+        token: token.to_string(),
+        status: "".to_string(),
+    };
+
+    let distributor_user =
+        match data::get_user_token_user(&mysql, TokenPurpose::PasswordReset, &token).await {
+            Some(id) => DistributorUser::get_by_id(id, &mysql).await,
+            None => None,
+        };
+    let mut distributor_user = match distributor_user {
+        Some(user) if user.distributor.id == distributor.id && user.active => user,
+        _ => {
+            s.token = "".to_string();
+            s.status = "Deze link is ongeldig of vervallen.".to_string();
+            return Ok(HttpResponse::NotFound()
+                .content_type("text/html")
+                .body(s.render().unwrap()));
+        }
+    };
+
+    if form.new_password != form.new_password_repeat {
+        s.status = "De wachtwoorden komen niet overeen.".to_string();
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
+    }
+    if distributor_user
+        .check_password_strength(&form.new_password)
+        .is_err()
+    {
+        s.status = "Kies een wachtwoord van minstens 10 tekens met letters en cijfers of symbolen, zonder je gebruikersnaam.".to_string();
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
+    }
+
+    // The link stays usable when saving fails, so the user can try again
+    if !distributor_user
+        .update_password(&form.new_password, &mysql)
+        .await
+    {
+        s.status = "Je wachtwoord kon niet aangepast worden, probeer het opnieuw.".to_string();
+        return Ok(HttpResponse::InternalServerError()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
+    }
+
+    // Consuming the token is what makes the link single-use
+    data::use_user_token(&mysql, TokenPurpose::PasswordReset, &token).await;
+    data::revoke_user_tokens(&mysql, distributor_user.id, TokenPurpose::PasswordReset).await;
+    data::lock_distributor_user(&mysql, distributor_user.id, None).await;
+    data::revoke_admin_sessions_by_user(&mysql, distributor_user.id, None).await;
+
+    let s = AdminLogin {
+        csrf_token: csrf::token(&req),
+        distributor_name: distributor.name,
+        login_status: "Je wachtwoord werd aangepast, je kan nu inloggen.".to_string(),
+    }
+    .render()
+    .unwrap();
     Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
 
-async fn order_form(
+async fn admin_dashboard_gebruikers(
+    AdminUser(distributor_user): AdminUser,
     mysql: web::Data<MySQL>,
-    mut form: web::Form<OrderForm>,
     req: HttpRequest,
-    mollie: web::Data<mollie::Mollie>,
-) -> String {
-    // Make client of this voucher
-    let mut client = Client {
-        id: 0,
-        firstname: form.firstname.to_string(),
-        lastname: form.lastname.to_string(),
-        email: form.from_email.to_string(),
-        tel: form.tel.to_string(),
-        saved_account: false,
-    };
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageUsers)?;
 
-    // Get distributor of this subpage and the voucher being purchased
-    let distributor = data::get_distributor_by_subdomain(
-        &mysql,
-        get_subdomain_part(req.headers().get("Host").unwrap().to_str().unwrap()),
-    )
-    .await
+    let s = AdminDashboardGebruikers {
+        csrf_token: csrf::token(&req),
+        users: data::get_distributor_users(&mysql, distributor_user.tenant())
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|u| AdminUserRow {
+                id: u.id,
+                is_self: u.id == distributor_user.id,
+                username: u.username,
+                display_name: u.display_name,
+                email: u.email,
+                role: u.role.to_string(),
+                active: u.active,
+                last_login: match u.last_login {
+                    Some(date) => date.format("%d/%m/%Y %H:%M").to_string(),
+                    None => "Nog niet ingelogd".to_string(),
+                },
+            })
+            .collect(),
+    }
+    .render()
     .unwrap();
-    let distributor_voucher = data::get_distributor_voucher(&mysql, form.voucher)
-        .await
-        .unwrap();
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
 
-    // Add client to database
-    let client_id = data::add_client(&mysql, &client).await;
-    client.id = client_id;
+async fn admin_gebruiker_uitnodigen(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    mail: web::Data<mail::Mail>,
+    req: HttpRequest,
+    form: web::Form<UserInviteForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageUsers)?;
 
-    // Check if amount is not altered on the client-side
-    if distributor_voucher.voucher_type != VoucherType::RangeVoucher
-        && form.amount != distributor_voucher.amount
+    if form.validate().is_err() {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("invalid_input"));
+    }
+    let role = match Role::from_str(&form.role) {
+        Ok(role) => role,
+        Err(_) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body("invalid_role"))
+        }
+    };
+    if DistributorUser::get_by_username(&form.username, distributor_user.tenant(), &mysql)
+        .await
+        .is_some()
     {
-        return "/niet-gelukt".to_string();
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("username_taken"));
     }
 
-    // Make Sale object with default values
-    let amount: f64 = form.amount;
-    let mut sale = Sale {
+    // Random password nobody knows, the invited user picks one through the link
+    let mut user = DistributorUser {
         id: 0,
-        client: client,
-        amount: amount,
-        payment_id: "".to_string(),
-        paid: false,
-        purchase_date: None,
+        username: form.username.to_string(),
+        password: generate_token(),
+        distributor: distributor_user.distributor.clone(),
+        display_name: form.display_name.to_string(),
+        email: form.email.to_string(),
+        totp_enabled: false,
+        role: role,
+        active: true,
+        last_login: None,
     };
+    user.id = DistributorUser::create(&mut user, &mysql).await;
+    if user.id == 0 {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
 
-    // Add sale to database and get ID of sale
-    let sale_id = data::add_sale(&mysql, &sale).await;
-    sale.id = sale_id;
-
-    // Generate voucher being purchased
-    let expiration_date = Utc::now()
-        .with_timezone(&chrono::Local)
-        .with_timezone(&chrono::Utc)
-        + Duration::days(distributor_voucher.days_valid.into());
-    let random_number: u16 = rand::thread_rng().gen();
-    let random_number_str = format!("{}-{}", client_id, random_number);
+    let token = generate_token();
+    data::add_user_token(
+        &mysql,
+        user.id,
+        TokenPurpose::Invitation,
+        &token,
+        Utc::now() + Duration::days(INVITATION_VALID_DAYS),
+    )
+    .await;
+    mail.send_invitation_mail(
+        &user,
+        &distributor_user.display_name,
+        &token,
+        INVITATION_VALID_DAYS,
+    )
+    .await
+    .unwrap_or_else(|e| println!("Error: {}", e));
+
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        "user.invite",
+        "distributoruser",
+        user.id,
+    );
+    entry.after =
+        serde_json::json!({ "username": user.username, "email": user.email, "role": user.role })
+            .to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
 
-    let mut sha256 = Sha256::new();
-    sha256.input_str(&random_number_str);
-    let hash = &sha256.result_str();
+async fn admin_gebruiker_rol(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<UserRoleForm>,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ManageUsers)?;
 
-    if form.to_email == "" {
-        form.to_email = form.from_email.to_string();
-    }
-    if form.to_firstname == "" {
-        form.to_firstname = form.firstname.to_string();
-    }
-    if form.to_lastname == "" {
-        form.to_lastname = form.lastname.to_string();
+    // An owner demoting themselves could leave the distributor without owner
+    if id == distributor_user.id {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("cannot_change_self"));
     }
-
-    let mut voucher = Voucher {
-        id: 0,
-        sale: sale.clone(),
-        receiver_email: form.to_email.to_string(),
-        receiver_name: format!("{} {}", form.to_firstname, form.to_lastname),
-        balance: form.amount,
-        distributorvoucher: distributor_voucher,
-        used: false,
-        expiration_date: expiration_date,
-        hash_code: hash.to_string(),
-        number_code: random_number_str,
-        version: 1,
+    let role = match Role::from_str(&form.role) {
+        Ok(role) => role,
+        Err(_) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body("invalid_role"))
+        }
+    };
+    let user = match data::get_distributor_user(&mysql, distributor_user.tenant(), id).await {
+        Some(user) => user,
+        None => return error404().await,
     };
 
-    // Add voucher to database
-    data::add_voucher(&mysql, &voucher).await;
+    if !data::update_distributor_user_role(&mysql, distributor_user.tenant(), id, role).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
 
-    // Make payment and retrieve payment_id
-    let mut payment_id = "".to_string(); // payment_id is passed by reference and gets a new value
-    mollie.make_payment(&sale, &mut payment_id).await;
-    sale.payment_id = payment_id;
+    // The role is kept in the session, so the user has to log in again
+    data::revoke_admin_sessions_by_user(&mysql, id, None).await;
 
-    // Update sale
-    data::update_sale(&mysql, &sale, data::Selector::ById(sale_id)).await;
+    let mut entry = AuditEntry::new(&distributor_user, &req, "user.role", "distributoruser", id);
+    entry.before = serde_json::json!({ "role": user.role }).to_string();
+    entry.after = serde_json::json!({ "role": role }).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
 
-    format!("/bevestig/{}", hash)
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
 }
 
-async fn payment_hook(
-    mollie: web::Data<mollie::Mollie>,
+async fn admin_gebruiker_actief(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
     mysql: web::Data<MySQL>,
-    mail: web::Data<mail::Mail>,
-    data: web::Form<PaymentHook>,
+    req: HttpRequest,
+    form: web::Form<UserActiveForm>,
 ) -> Result<HttpResponse> {
-    let payment = mollie.get_payment(&data.id).await;
+    distributor_user.require(Permission::ManageUsers)?;
 
-    if payment.status == "paid" {
-        let mut sale = data::get_sale(&mysql, data::Selector::ByPaymentId(data.id.clone()))
-            .await
-            .unwrap();
+    if id == distributor_user.id {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("cannot_change_self"));
+    }
+    let user = match data::get_distributor_user(&mysql, distributor_user.tenant(), id).await {
+        Some(user) => user,
+        None => return error404().await,
+    };
 
-        sale.paid = true;
-        data::update_sale(&mysql, &sale, data::Selector::ByPaymentId(data.id.clone())).await;
+    if !data::update_distributor_user_active(&mysql, distributor_user.tenant(), id, form.active)
+        .await
+    {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
 
-        mail.send_plain_mail(
-            "Kaddo. <noreply@kaddo.be>".to_string(),
-            format!(
-                "{} {} <{}>",
-                sale.client.firstname, sale.client.lastname, sale.client.email
-            ),
-            "Betaling ontvangen!".to_string(),
-            "Rofl".to_string(),
-        )
-        .await;
+    if !form.active {
+        data::revoke_admin_sessions_by_user(&mysql, id, None).await;
+        data::revoke_user_tokens(&mysql, id, TokenPurpose::Invitation).await;
+        data::revoke_user_tokens(&mysql, id, TokenPurpose::PasswordReset).await;
+    }
 
-        println!("Paid, updating ID {}", data.id.clone());
-        println!("{}", serde_json::to_string(&sale).unwrap());
+    let mut entry = AuditEntry::new(
+        &distributor_user,
+        &req,
+        match form.active {
+            true => "user.activate",
+            false => "user.deactivate",
+        },
+        "distributoruser",
+        id,
+    );
+    entry.before = serde_json::json!({ "active": user.active }).to_string();
+    entry.after = serde_json::json!({ "active": form.active }).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
 
-        return Ok(HttpResponse::Ok().finish());
-    } else {
-        return Ok(HttpResponse::Ok().finish());
-    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
 }
 
-async fn check(
-    mollie: web::Data<mollie::Mollie>,
-    web::Path(payment_id): web::Path<(String)>,
+async fn admin_uitnodiging(
+    web::Path(token): web::Path<String>,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
-    let payment = mollie.get_payment(&payment_id).await;
+    let distributor = tenancy::current(&req);
 
-    match payment.status == "paid" {
-        true => Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/succes/aankoop")
-            .finish()),
-        _ => Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/niet-gelukt")
-            .finish()),
+    if distributor.is_none() {
+        return error404().await;
+    }
+
+    let distributor = distributor.unwrap();
+
+    let mut s = AdminUitnodiging {
+        csrf_token: csrf::token(&req),
+        distributor_name: distributor.name.to_string(),
+        display_name: "".to_string(),
+        token: token.to_string(),
+        status: "".to_string(),
+    };
+
+    let distributor_user =
+        match data::get_user_token_user(&mysql, TokenPurpose::Invitation, &token).await {
+            Some(id) => data::get_distributor_user(&mysql, distributor.tenant(), id).await,
+            None => None,
+        };
+    match distributor_user {
+        Some(user) if user.active => {
+            s.display_name = user.display_name;
+        }
+        _ => {
+            s.token = "".to_string();
+            s.status = "Deze uitnodiging is ongeldig of vervallen.".to_string();
+            return Ok(HttpResponse::NotFound()
+                .content_type("text/html")
+                .body(s.render().unwrap()));
+        }
     }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(s.render().unwrap()))
 }
 
-async fn confirm_order(
-    web::Path(hash): web::Path<String>,
-    mollie: web::Data<mollie::Mollie>,
+async fn admin_uitnodiging_form(
+    web::Path(token): web::Path<String>,
     mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<PasswordResetForm>,
 ) -> Result<HttpResponse> {
-    let voucher_get = data::get_voucher(&mysql, data::Selector::ByHash(hash)).await;
-    let voucher: Voucher;
+    let distributor = tenancy::current(&req);
 
-    if voucher_get.is_none() {
-        return Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/niet-gelukt")
-            .finish());
-    } else {
-        voucher = voucher_get.unwrap();
+    if distributor.is_none() {
+        return error404().await;
     }
 
-    if voucher.sale.paid {
-        return Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/niet-gelukt")
-            .finish());
+    let distributor = distributor.unwrap();
+
+    let mut s = AdminUitnodiging {
+        csrf_token: csrf::token(&req),
+        distributor_name: distributor.name.to_string(),
+        display_name: "".to_string(),
+        token: token.to_string(),
+        status: "".to_string(),
+    };
+
+    let distributor_user =
+        match data::get_user_token_user(&mysql, TokenPurpose::Invitation, &token).await {
+            Some(id) => data::get_distributor_user(&mysql, distributor.tenant(), id).await,
+            None => None,
+        };
+    let mut distributor_user = match distributor_user {
+        Some(user) if user.active => user,
+        _ => {
+            s.token = "".to_string();
+            s.status = "Deze uitnodiging is ongeldig of vervallen.".to_string();
+            return Ok(HttpResponse::NotFound()
+                .content_type("text/html")
+                .body(s.render().unwrap()));
+        }
+    };
+    s.display_name = distributor_user.display_name.to_string();
+
+    if form.new_password != form.new_password_repeat {
+        s.status = "De wachtwoorden komen niet overeen.".to_string();
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
+    }
+    if distributor_user
+        .check_password_strength(&form.new_password)
+        .is_err()
+    {
+        s.status = "Kies een wachtwoord van minstens 10 tekens met letters en cijfers of symbolen, zonder je gebruikersnaam.".to_string();
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
     }
 
-    let payment_id = voucher.sale.payment_id;
-    let payment = mollie.get_payment(&payment_id).await;
+    if !distributor_user
+        .update_password(&form.new_password, &mysql)
+        .await
+    {
+        s.status = "Je wachtwoord kon niet opgeslagen worden, probeer het opnieuw.".to_string();
+        return Ok(HttpResponse::InternalServerError()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
+    }
+    data::use_user_token(&mysql, TokenPurpose::Invitation, &token).await;
 
-    let transaction_fee = 1.5;
-    let s = Bevestig {
-        payment_url: payment.links.get("checkout").unwrap().href.to_string(),
-        voucher_price: format!("{:.2}", voucher.sale.amount).replace(".", ","),
-        total: format!("{:.2}", voucher.sale.amount + transaction_fee).replace(".", ","),
-        from_str: format!(
-            "{} {} <{}>",
-            uppercase_first_letter(&voucher.sale.client.firstname),
-            uppercase_first_letter(&voucher.sale.client.lastname),
-            voucher.sale.client.email,
-        ),
-        to_str: format!(
-            "{} <{}>",
-            uppercase_first_letter(&voucher.receiver_name),
-            voucher.receiver_email
-        ),
+    let s = AdminLogin {
+        csrf_token: csrf::token(&req),
+        distributor_name: distributor.name,
+        login_status: "Je account is klaar, je kan nu inloggen.".to_string(),
     }
     .render()
     .unwrap();
-
     Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
 
-async fn success(web::Path(action): web::Path<String>) -> Result<HttpResponse> {
-    let (title, message) = match &*action {
-        "aankoop" => (
-            "Aankoop succesvol!",
-            "We hebben je aankoop goed ontvangen en gaan direct voor je aan de slag!",
-        ),
-        "registratie" => (
-            "Welkom bij Kaddo.",
-            "Je bent succesvol geregistreerd, welkom!",
-        ),
-        _ => ("Success", "Goed gedaan!"),
+const AUDIT_LOG_PAGE_SIZE: u64 = 50;
+const AUDIT_LOG_EXPORT_LIMIT: u64 = 10000;
+
+// Empty form fields mean "no filter"
+fn audit_log_filters(req: &HttpRequest) -> AuditLogFilterParams {
+    let filters = web::Query::<AuditLogFilterParams>::from_query(req.query_string())
+        .map(|q| q.into_inner())
+        .unwrap_or_default();
+    let non_empty = |v: Option<String>| v.filter(|v| v.trim() != "");
+
+    AuditLogFilterParams {
+        page: filters.page,
+        action: non_empty(filters.action),
+        entity: non_empty(filters.entity),
+        distributoruser: non_empty(filters.distributoruser),
+        min_date: non_empty(filters.min_date),
+        max_date: non_empty(filters.max_date),
+    }
+}
+
+async fn admin_dashboard_logboek(
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    distributor_user.require(Permission::ViewAuditLog)?;
+
+    let filters = audit_log_filters(&req);
+    let page = filters.page.unwrap_or(0);
+
+    let users = data::get_distributor_users(&mysql, distributor_user.tenant())
+        .await
+        .unwrap_or_default();
+    let user_name = |id: Option<u64>| match id {
+        Some(id) => users
+            .iter()
+            .find(|u| u.id == id)
+            .map(|u| u.display_name.to_string())
+            .unwrap_or(format!("#{}", id)),
+        None => "".to_string(),
     };
 
-    let s = Success {
-        title: title.to_string(),
-        message: message.to_string(),
+    // One extra row tells whether there's a next page
+    let mut entries = data::get_audit_entries(
+        &mysql,
+        distributor_user.tenant(),
+        &filters,
+        page * AUDIT_LOG_PAGE_SIZE,
+        AUDIT_LOG_PAGE_SIZE + 1,
+    )
+    .await
+    .unwrap_or_default();
+    let has_next_page = entries.len() as u64 > AUDIT_LOG_PAGE_SIZE;
+    entries.truncate(AUDIT_LOG_PAGE_SIZE as usize);
+
+    let s = AdminDashboardLogboek {
+        entries: entries
+            .into_iter()
+            .map(|e| AuditLogRow {
+                date: e
+                    .create_date
+                    .map(|d| d.format("%d/%m/%Y %H:%M:%S").to_string())
+                    .unwrap_or_default(),
+                user: match e.platformadmin_id {
+                    Some(_) => format!("{} (via Kaddo)", user_name(e.distributoruser_id)),
+                    None => user_name(e.distributoruser_id),
+                },
+                action: e.action,
+                entity: e.entity,
+                entity_id: e.entity_id,
+                before: e.before,
+                after: e.after,
+                reason: e.reason,
+                ip: e.ip,
+            })
+            .collect(),
+        users: users
+            .iter()
+            .map(|u| AuditLogUser {
+                id: u.id.to_string(),
+                display_name: u.display_name.to_string(),
+            })
+            .collect(),
+        action: filters.action.clone().unwrap_or_default(),
+        entity: filters.entity.clone().unwrap_or_default(),
+        distributoruser: filters.distributoruser.clone().unwrap_or_default(),
+        min_date: filters.min_date.clone().unwrap_or_default(),
+        max_date: filters.max_date.clone().unwrap_or_default(),
+        page: page,
+        has_next_page: has_next_page,
+        query: serde_urlencoded::to_string(&[
+            ("action", filters.action.unwrap_or_default()),
+            ("entity", filters.entity.unwrap_or_default()),
+            (
+                "distributoruser",
+                filters.distributoruser.unwrap_or_default(),
+            ),
+            ("min_date", filters.min_date.unwrap_or_default()),
+            ("max_date", filters.max_date.unwrap_or_default()),
+        ])
+        .unwrap_or_default(),
     }
     .render()
     .unwrap();
     Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
 
-async fn failed() -> Result<HttpResponse> {
-    let s = Failed.render().unwrap();
-    Ok(HttpResponse::Ok().content_type("text/html").body(s))
-}
-
-async fn voucher_desktop(
-    web::Path(hash): web::Path<String>,
+async fn admin_logboek_export(
+    AdminUser(distributor_user): AdminUser,
     mysql: web::Data<MySQL>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
-    let voucher = data::get_voucher(&mysql, data::Selector::ByHash(hash)).await;
+    distributor_user.require(Permission::ViewAuditLog)?;
+    distributor_user.require(Permission::Export)?;
 
-    if voucher.is_none() {
-        return Ok(HttpResponse::NotFound().finish());
+    let filters = audit_log_filters(&req);
+
+    let users = data::get_distributor_users(&mysql, distributor_user.tenant())
+        .await
+        .unwrap_or_default();
+    let entries = data::get_audit_entries(
+        &mysql,
+        distributor_user.tenant(),
+        &filters,
+        0,
+        AUDIT_LOG_EXPORT_LIMIT,
+    )
+    .await
+    .unwrap_or_default();
+
+    let mut csv = "datum;gebruiker;actie;object;object_id;voor;na;reden;ip\n".to_string();
+    for e in entries {
+        let mut user = match e.distributoruser_id {
+            Some(id) => users
+                .iter()
+                .find(|u| u.id == id)
+                .map(|u| u.username.to_string())
+                .unwrap_or(id.to_string()),
+            None => "".to_string(),
+        };
+        if e.platformadmin_id.is_some() {
+            user.push_str(" (via Kaddo)");
+        }
+
+        csv.push_str(
+            &[
+                csv_field(
+                    &e.create_date
+                        .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string())
+                        .unwrap_or_default(),
+                ),
+                csv_field(&user),
+                csv_field(&e.action),
+                csv_field(&e.entity),
+                e.entity_id.to_string(),
+                csv_field(&e.before),
+                csv_field(&e.after),
+                csv_field(&e.reason),
+                csv_field(&e.ip),
+            ]
+            .join(";"),
+        );
+        csv.push('\n');
     }
 
-    let v = voucher.unwrap();
+    data::add_audit_entry(
+        &mysql,
+        &AuditEntry::new(
+            &distributor_user,
+            &req,
+            "auditlog.export",
+            "distributor",
+            distributor_user.distributor.id,
+        ),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .header(
+            http::header::CONTENT_DISPOSITION,
+            "attachment; filename=\"logboek.csv\"",
+        )
+        .body(csv))
+}
+
+async fn admin_dashboard_help(_: AdminUser) -> Result<HttpResponse> {
+    let mut s = AdminDashboardHelp.render().unwrap();
 
-    let s = VoucherPageDesktop {
-        distributor_name: v.distributorvoucher.distributor.name,
-        balance: v.balance.to_string(),
-        number_code: v.number_code,
-        expiration_date: v.expiration_date.format("%d-%m-%Y").to_string(),
-        receiver_name: v.receiver_name,
-    }
-    .render()
-    .unwrap();
     Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
 
-async fn voucher_mobile(
-    web::Path(hash): web::Path<String>,
+async fn admin_dashboard_sessies(
+    AdminUser(distributor_user): AdminUser,
     mysql: web::Data<MySQL>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
-    let voucher = data::get_voucher(&mysql, data::Selector::ByHash(hash)).await;
-
-    if voucher.is_none() {
-        return Ok(HttpResponse::NotFound().finish());
-    }
-
-    let v = voucher.unwrap();
+    let current = sessions::current_session_id(&req);
 
-    let s = VoucherPageMobile {
-        distributor_name: v.distributorvoucher.distributor.name,
-        balance: v.balance.to_string(),
-        number_code: v.number_code,
-        expiration_date: v.expiration_date.format("%d-%m-%Y").to_string(),
-        receiver_name: v.receiver_name,
+    let s = AdminDashboardSessies {
+        csrf_token: csrf::token(&req),
+        sessions: data::get_admin_sessions_by_user(&mysql, distributor_user.id)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|s| AdminSessionRow {
+                id: s.id,
+                ip: s.ip,
+                user_agent: s.user_agent,
+                create_date: s.create_date.format("%d/%m/%Y %H:%M").to_string(),
+                last_seen: s.last_seen.format("%d/%m/%Y %H:%M").to_string(),
+                current: Some(s.id) == current,
+            })
+            .collect(),
     }
     .render()
     .unwrap();
     Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
 
-async fn scanner(mysql: web::Data<MySQL>) -> Result<HttpResponse> {
-    let s = Scanner.render().unwrap();
-    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+async fn admin_sessie_beeindigen(
+    web::Path(id): web::Path<u64>,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+) -> Result<HttpResponse> {
+    match data::revoke_admin_session_of_user(&mysql, distributor_user.id, id).await {
+        false => Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed")),
+        true => Ok(HttpResponse::Ok()
+            .content_type("text/plain")
+            .body("update_succeeded")),
+    }
 }
 
-async fn scanner_login(mysql: web::Data<MySQL>) -> Result<HttpResponse> {
-    let s = ScannerLogin.render().unwrap();
-    Ok(HttpResponse::Ok().content_type("text/html").body(s))
-}
-async fn scanner_login_authenticate(mysql: web::Data<MySQL>) -> Result<HttpResponse> {
-    unimplemented!()
+// Log out everywhere, including this session
+async fn admin_sessies_beeindigen(
+    session: Session,
+    AdminUser(distributor_user): AdminUser,
+    mysql: web::Data<MySQL>,
+) -> Result<HttpResponse> {
+    data::revoke_admin_sessions_by_user(&mysql, distributor_user.id, None).await;
+    session.purge();
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
 }
 
-async fn scan_get(
-    web::Path((method, identifier)): web::Path<(String, String)>,
+async fn admin_dashboard_logout(
+    session: Session,
     mysql: web::Data<MySQL>,
-) -> String {
-    let mut voucher: Option<Voucher> = None;
-
-    // = data::get_voucher(&mysql, data::Selector::ByHash(hash)).await;
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Some(distributor_user) = session.get::<DistributorUser>("distributoruser").unwrap() {
+        data::add_audit_entry(
+            &mysql,
+            &AuditEntry::new(
+                &distributor_user,
+                &req,
+                "user.logout",
+                "distributoruser",
+                distributor_user.id,
+            ),
+        )
+        .await;
 
-    match &*method {
-        "hash" => {
-            voucher = data::get_voucher(&mysql, data::Selector::ByHash(identifier)).await;
-        }
-        "number_code" => {
-            voucher = data::get_voucher(&mysql, data::Selector::ByNumberCode(identifier)).await;
-        }
-        _ => {
-            voucher = None;
-        }
+        session.purge();
     }
 
-    match voucher {
-        None => "{\"success\": false, \"msg\": \"Geen voucher gevonden\", \"voucher\": null}"
-            .to_string(),
-        Some(v) => {
-            format!(
-                "{{
-                \"success\": true,
-                \"msg\": \"\",
-                \"voucher\": {{
-                    \"id\": {},
-                    \"paid\": {},
-                    \"one_use_only\": {},
-                    \"used\": {},
-                    \"balance\": {},
-                    \"receiver_name\": \"{}\",
-                    \"hash_code\": \"{}\"
-                }}
-            }}",
-                v.id,
-                v.sale.paid,
-                v.distributorvoucher.one_use_only,
-                v.used,
-                v.balance,
-                v.receiver_name,
-                v.hash_code
-            )
-        }
-    }
-}
-
-async fn scan_update(mysql: web::Data<MySQL>, form: web::Form<VoucherUpdateForm>) -> String {
-    let mut voucher =
-        data::get_voucher(&mysql, data::Selector::ByHash(form.hash.to_string())).await;
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, "/admin/login")
+        .finish())
+}
 
-    match voucher {
-        None => "error".to_string(),
-        Some(mut v) => {
-            v.used = form.used;
-            v.balance = form.balance;
+/* REGISTRATION */
+static REGISTRATION_LIMITER: Lazy<ratelimit::RateLimiter> =
+    Lazy::new(|| ratelimit::RateLimiter::new(5, Duration::hours(1)));
+static SUBDOMAIN_CHECK_LIMITER: Lazy<ratelimit::RateLimiter> =
+    Lazy::new(|| ratelimit::RateLimiter::new(60, Duration::minutes(1)));
 
-            match data::update_voucher(&mysql, &v).await {
-                true => "success".to_string(),
-                false => "false".to_string(),
-            }
-        }
+const EMAIL_VERIFICATION_VALID_HOURS: i64 = 48;
+// A sign-up that isn't confirmed by then is removed, a new verification mail can be
+// requested until that time
+const UNVERIFIED_REGISTRATION_VALID_DAYS: i64 = 7;
+
+async fn registreren(req: HttpRequest) -> Result<HttpResponse> {
+    if !is_platform_host(&req) {
+        return error404().await;
     }
-}
 
-async fn test(mail: web::Data<mail::Mail>) -> Result<HttpResponse> {
-    //mail.send_plain_mail().await;
+    let s = Registreren {
+        csrf_token: csrf::token(&req),
+        domain: PLATFORM_DOMAIN.to_string(),
+    }
+    .render()
+    .unwrap();
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
 
-// Administrator routes
-
-async fn admin_login(
+// Used while typing, so the error codes are shown next to the field
+async fn registreren_subdomein(
     mysql: web::Data<MySQL>,
     req: HttpRequest,
-    session: Session,
+    query: web::Query<SubdomainCheckParams>,
 ) -> Result<HttpResponse> {
-    if session
-        .get::<DistributorUser>("distributoruser")
-        .unwrap()
-        .is_some()
-    {
-        return Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/admin/dashboard")
-            .finish());
+    let ip = client_ip(&req);
+    if !SUBDOMAIN_CHECK_LIMITER.check(&ip).await {
+        return Ok(HttpResponse::TooManyRequests().finish());
     }
 
-    let distributor = data::get_distributor_by_subdomain(
-        &mysql,
-        get_subdomain_part(req.headers().get("Host").unwrap().to_str().unwrap()),
-    )
-    .await
-    .unwrap();
+    let subdomain = query.subdomain.trim().to_lowercase();
+    let status = match check_subdomain(&subdomain) {
+        Err(e) => e,
+        Ok(_) if data::is_subdomain_taken(&mysql, &subdomain, 0).await => "subdomain_taken",
+        Ok(_) => "available",
+    };
 
-    let s = AdminLogin {
-        distributor_name: distributor.name,
-        login_status: "".to_string(),
-    }
-    .render()
-    .unwrap();
-    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "subdomain": subdomain,
+        "available": status == "available",
+        "status": status,
+    })))
 }
 
-async fn admin_login_form(
+async fn registreren_form(
     mysql: web::Data<MySQL>,
+    mail: web::Data<mail::Mail>,
     req: HttpRequest,
-    form: web::Form<AdminLoginForm>,
-    session: Session,
+    form: web::Form<RegistrationForm>,
 ) -> Result<HttpResponse> {
-    let distributor = data::get_distributor_by_subdomain(
-        &mysql,
-        get_subdomain_part(req.headers().get("Host").unwrap().to_str().unwrap()),
-    )
-    .await
-    .unwrap();
+    if !is_platform_host(&req) {
+        return error404().await;
+    }
 
-    let distributor_user = DistributorUser::get_by_username(&form.admin_username, &mysql).await;
+    let ip = client_ip(&req);
+    if !REGISTRATION_LIMITER.check(&ip).await {
+        return Ok(HttpResponse::TooManyRequests()
+            .content_type("text/plain")
+            .body("too_many_attempts"));
+    }
 
-    let mut s = AdminLogin {
-        distributor_name: distributor.name,
-        login_status: "".to_string(),
+    if form.validate().is_err() {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("invalid_input"));
+    }
+    if form.accept_terms.is_none() {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("terms_not_accepted"));
+    }
+    let (btw_nr, iban) = match check_registration_numbers(&form.btw_nr, &form.bankaccountnr) {
+        Ok(numbers) => numbers,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body(e))
+        }
     };
 
-    if distributor_user.is_none() {
-        s.login_status = format!("Gebruiker {} niet gevonden.", &form.admin_username);
-        return Ok(HttpResponse::NotFound()
-            .content_type("text/html")
-            .body(s.render().unwrap()));
+    let subdomain = form.subdomain.trim().to_lowercase();
+    if let Err(e) = check_subdomain(&subdomain) {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body(e));
     }
-    if distributor_user.clone().unwrap().distributor.id != distributor.id {
-        s.login_status = format!("Gebruiker {} niet gevonden.", &form.admin_username);
-        return Ok(HttpResponse::NotFound()
-            .content_type("text/html")
-            .body(s.render().unwrap()));
+    if data::is_subdomain_taken(&mysql, &subdomain, 0).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("subdomain_taken"));
     }
 
-    let pass_check = distributor_user
-        .clone()
-        .unwrap()
-        .verify_password(form.admin_password.as_bytes())
-        .unwrap();
+    let location =
+        match postcodes::resolve(&mysql, &form.country, &form.postalcode, &form.city).await {
+            Ok(location) => location,
+            Err(e) => {
+                return Ok(HttpResponse::BadRequest()
+                    .content_type("text/plain")
+                    .body(e))
+            }
+        };
 
-    match pass_check {
-        true => {
-            session.set("distributoruser", distributor_user.unwrap());
+    let mut distributor = Distributor {
+        id: 0,
+        name: form.name.trim().to_string(),
+        email: form.email.trim().to_string(),
+        tel: form.tel.trim().to_string(),
+        address: form.address.trim().to_string(),
+        location: location,
+        subdomain: subdomain,
+        description: "".to_string(),
+        description_html: "".to_string(),
+        bankaccountnr: bankdetails::encrypt(&iban),
+        btw_nr: btw_nr,
+        reminder_days: DEFAULT_REMINDER_DAYS.to_string(),
+        send_reminders: true,
+        require_2fa: false,
+        status: DistributorStatus::Unverified,
+        redeem_when_suspended: true,
+        primary_domain: None,
+        branding: Branding::default(),
+    };
 
-            Ok(HttpResponse::Found()
-                .header(http::header::LOCATION, "/admin/dashboard")
-                .finish())
-        }
-        false => {
-            s.login_status = format!("Passwoord niet correct.");
-            Ok(HttpResponse::Unauthorized()
-                .content_type("text/html")
-                .body(s.render().unwrap()))
+    let mut owner = DistributorUser {
+        id: 0,
+        username: form.username.trim().to_string(),
+        password: form.password.to_string(),
+        distributor: distributor.clone(),
+        display_name: form.owner_name.trim().to_string(),
+        email: form.email.trim().to_string(),
+        totp_enabled: false,
+        role: Role::Owner,
+        active: true,
+        last_login: None,
+    };
+    if form.password != form.password_repeat {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("passwords_not_equal"));
+    }
+    if let Err(e) = owner.check_password_strength(&form.password) {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body(e));
+    }
+
+    owner.hash_password().unwrap();
+    let token = generate_token();
+    let (distributor_id, owner_id) = match data::add_registration(
+        &mysql,
+        &distributor,
+        &owner,
+        TokenPurpose::EmailVerification,
+        &token,
+        Utc::now() + Duration::hours(EMAIL_VERIFICATION_VALID_HOURS),
+    )
+    .await
+    {
+        Some(ids) => ids,
+        None => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body("update_failed"))
         }
+    };
+    distributor.id = distributor_id;
+    owner.distributor = distributor;
+    owner.id = owner_id;
+
+    let mut entry = AuditEntry::new(
+        &owner,
+        &req,
+        "distributor.register",
+        "distributor",
+        owner.distributor.id,
+    );
+    entry.after = distributor_audit_json(&owner.distributor).to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    // The sign-up is kept, the mail can be sent again from the registration page
+    if let Err(e) = mail
+        .send_email_verification_mail(&owner, &token, EMAIL_VERIFICATION_VALID_HOURS)
+        .await
+    {
+        println!("Error: {}", e);
+        return Ok(HttpResponse::Ok()
+            .content_type("text/plain")
+            .body("verification_mail_failed"));
     }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
 }
 
-async fn admin_dashboard_index(session: Session) -> Result<HttpResponse> {
-    if session
-        .get::<DistributorUser>("distributoruser")
-        .unwrap()
-        .is_none()
-    {
-        return Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/admin/login")
-            .finish());
+// Sends a new link when the verification mail didn't arrive or expired
+async fn registreren_opnieuw_versturen(
+    mysql: web::Data<MySQL>,
+    mail: web::Data<mail::Mail>,
+    req: HttpRequest,
+    form: web::Form<ResendVerificationForm>,
+) -> Result<HttpResponse> {
+    if !is_platform_host(&req) {
+        return error404().await;
     }
 
-    return Ok(HttpResponse::Found()
-        .header(http::header::LOCATION, "/admin/dashboard/mijn-zaak")
-        .finish());
+    let ip = client_ip(&req);
+    if !REGISTRATION_LIMITER.check(&ip).await {
+        return Ok(HttpResponse::TooManyRequests()
+            .content_type("text/plain")
+            .body("too_many_attempts"));
+    }
+
+    if form.validate().is_err() {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("invalid_input"));
+    }
+
+    // Same answer whether or not there is a sign-up for this address
+    let owner = match data::get_unverified_owner_id(&mysql, form.email.trim()).await {
+        Some(id) => DistributorUser::get_by_id(id, &mysql).await,
+        None => None,
+    };
+    if let Some(owner) = owner {
+        data::revoke_user_tokens(&mysql, owner.id, TokenPurpose::EmailVerification).await;
+
+        let token = generate_token();
+        data::add_user_token(
+            &mysql,
+            owner.id,
+            TokenPurpose::EmailVerification,
+            &token,
+            Utc::now() + Duration::hours(EMAIL_VERIFICATION_VALID_HOURS),
+        )
+        .await;
+        mail.send_email_verification_mail(&owner, &token, EMAIL_VERIFICATION_VALID_HOURS)
+            .await
+            .unwrap_or_else(|e| println!("Error: {}", e));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
 }
 
-async fn admin_dashboard_mijn_zaak(
-    session: Session,
+// Link in the verification mail, continues on the host of the new shop
+async fn registreren_bevestigen(
+    web::Path(token): web::Path<String>,
     mysql: web::Data<MySQL>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
+    let user_id = match data::use_user_token(&mysql, TokenPurpose::EmailVerification, &token).await
+    {
+        Some(id) => id,
+        None => return error404().await,
+    };
+    let user = match DistributorUser::get_by_id(user_id, &mysql).await {
+        Some(user) => user,
+        None => return error404().await,
+    };
+
+    if user.distributor.status == DistributorStatus::Unverified {
+        data::update_distributor_status(&mysql, user.distributor.id, DistributorStatus::Onboarding)
+            .await;
+
+        let mut entry = AuditEntry::new(
+            &user,
+            &req,
+            "distributor.verify_email",
+            "distributor",
+            user.distributor.id,
+        );
+        entry.after = serde_json::json!({ "email": user.email }).to_string();
+        data::add_audit_entry(&mysql, &entry).await;
+    }
+
+    Ok(HttpResponse::Found()
+        .header(
+            http::header::LOCATION,
+            format!("{}/admin/login", distributor_url(&user.distributor)),
+        )
+        .finish())
+}
+
+/* PLATFORM */
+static PLATFORM_LOGIN_LIMITER: Lazy<ratelimit::RateLimiter> =
+    Lazy::new(|| ratelimit::RateLimiter::new(10, Duration::minutes(15)));
+
+// The link to the dashboard of a distributor is used right away
+const IMPERSONATION_VALID_MINUTES: i64 = 2;
+
+fn platform_audit_rows(
+    entries: std::vec::Vec<PlatformAuditEntry>,
+    admin_names: &HashMap<u64, String>,
+    distributor_names: &HashMap<u64, String>,
+) -> std::vec::Vec<PlatformAuditRow> {
+    entries
+        .into_iter()
+        .map(|e| PlatformAuditRow {
+            date: e
+                .create_date
+                .map(|d| d.format("%d/%m/%Y %H:%M:%S").to_string())
+                .unwrap_or_default(),
+            admin: admin_names
+                .get(&e.platformadmin_id)
+                .cloned()
+                .unwrap_or(format!("#{}", e.platformadmin_id)),
+            action: e.action,
+            distributor: match e.distributor_id {
+                Some(id) => distributor_names
+                    .get(&id)
+                    .cloned()
+                    .unwrap_or(format!("#{}", id)),
+                None => "".to_string(),
+            },
+            after: e.after,
+            reason: e.reason,
+        })
+        .collect()
+}
+
+async fn platform_login(req: HttpRequest, session: Session) -> Result<HttpResponse> {
+    if !is_platform_host(&req) {
+        return error404().await;
+    }
+
     if session
-        .get::<DistributorUser>("distributoruser")
+        .get::<PlatformAdmin>("platformadmin")
         .unwrap()
-        .is_none()
+        .is_some()
     {
         return Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/admin/login")
+            .header(http::header::LOCATION, "/platform")
             .finish());
     }
 
-    let mut s = AdminDashboardMijnZaak {
-        distributor: get_distributor(
-            &mysql,
-            session
-                .get::<DistributorUser>("distributoruser")?
-                .unwrap()
-                .distributor
-                .id,
-        )
-        .await
-        .unwrap(),
+    let s = PlatformLogin {
+        csrf_token: csrf::token(&req),
+        login_status: "".to_string(),
     }
     .render()
     .unwrap();
@@ -1833,167 +13683,233 @@ async fn admin_dashboard_mijn_zaak(
     Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
 
-async fn admin_update_mijn_zaak(
-    session: Session,
+async fn platform_login_form(
     mysql: web::Data<MySQL>,
-    form: web::Form<MijnZaakUpdateForm>,
+    req: HttpRequest,
+    form: web::Form<PlatformLoginForm>,
+    session: Session,
 ) -> Result<HttpResponse> {
-    if session
-        .get::<DistributorUser>("distributoruser")
-        .unwrap()
-        .is_none()
-    {
-        return Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/admin/login")
-            .finish());
+    if !is_platform_host(&req) {
+        return error404().await;
+    }
+
+    let ip = client_ip(&req);
+
+    let mut s = PlatformLogin {
+        csrf_token: csrf::token(&req),
+        login_status: "".to_string(),
+    };
+
+    if !PLATFORM_LOGIN_LIMITER.check(&ip).await {
+        s.login_status = "Te veel mislukte pogingen, probeer het later opnieuw.".to_string();
+        return Ok(HttpResponse::TooManyRequests()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
     }
 
-    let mut distributor = session
-        .get::<DistributorUser>("distributoruser")?
-        .unwrap()
-        .distributor;
-    distributor.description = form.description.to_string();
-    distributor.email = form.email.to_string();
-    distributor.tel = form.tel.to_string();
-    distributor.address = form.address.to_string();
-    distributor.location = Location {
-        id: 0,
-        postalcode: form.postalcode.to_string(),
-        city: form.city.to_string(),
+    let admin = match PlatformAdmin::get_by_username(&form.username, &mysql).await {
+        Some(admin) if admin.active => Some(admin),
+        _ => None,
+    };
+    let pass_check = match &admin {
+        Some(admin) => {
+            argon2::verify_encoded(&admin.password, form.password.as_bytes()).unwrap_or(false)
+        }
+        // Spend the same time on unknown users
+        None => {
+            argon2::verify_encoded(&DUMMY_PASSWORD_HASH, form.password.as_bytes()).ok();
+            false
+        }
     };
 
-    match data::update_distributor(&mysql, &distributor).await {
-        false => Ok(HttpResponse::BadRequest()
-            .content_type("text/plain")
-            .body("update_failed")),
-        true => Ok(HttpResponse::Ok()
-            .content_type("text/plain")
-            .body("update_succeeded")),
+    if !pass_check {
+        s.login_status = "Gebruikersnaam of wachtwoord niet correct.".to_string();
+        return Ok(HttpResponse::Unauthorized()
+            .content_type("text/html")
+            .body(s.render().unwrap()));
     }
+
+    let admin = admin.unwrap();
+    data::update_platform_admin_last_login(&mysql, admin.id).await;
+    data::add_platform_audit_entry(
+        &mysql,
+        &PlatformAuditEntry::new(
+            &admin,
+            &req,
+            "platform.login",
+            None,
+            "platformadmin",
+            admin.id,
+        ),
+    )
+    .await;
+
+    session.renew();
+    session.set("platformadmin", admin)?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, "/platform")
+        .finish())
 }
 
-async fn admin_dashboard_cadeaubonnen(
+async fn platform_logout(
     session: Session,
+    PlatformUser(admin): PlatformUser,
     mysql: web::Data<MySQL>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
-    if session
-        .get::<DistributorUser>("distributoruser")
-        .unwrap()
-        .is_none()
-    {
-        return Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/admin/login")
-            .finish());
-    }
-
-    let all_distributor_vouchers: Vec<DistributorVoucher> =
-        data::get_distributor_vouchers_by_distributor(
-            &mysql,
-            session
-                .get::<DistributorUser>("distributoruser")
-                .unwrap()
-                .unwrap()
-                .distributor
-                .id,
-        )
-        .await
-        .unwrap();
-    let mut three_price_distributor_vouchers: Vec<&DistributorVoucher> = Vec::new();
-    let mut price_range_distributor_voucher: Option<&DistributorVoucher> = None;
-    let mut label_distributor_vouchers: Vec<LabelVoucherData> = Vec::new();
-    let mut currently_active: Option<VoucherType> = None;
-    for distributor_voucher in all_distributor_vouchers.iter() {
-        if distributor_voucher.voucher_type == VoucherType::ThreeOptionVoucher {
-            three_price_distributor_vouchers.push(distributor_voucher);
+    data::add_platform_audit_entry(
+        &mysql,
+        &PlatformAuditEntry::new(
+            &admin,
+            &req,
+            "platform.logout",
+            None,
+            "platformadmin",
+            admin.id,
+        ),
+    )
+    .await;
+    session.purge();
 
-            if distributor_voucher.active {
-                currently_active = Some(VoucherType::ThreeOptionVoucher);
-            }
-        }
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, "/platform/login")
+        .finish())
+}
 
-        if distributor_voucher.voucher_type == VoucherType::RangeVoucher {
-            price_range_distributor_voucher = Some(&distributor_voucher);
+async fn platform_dashboard(
+    PlatformUser(admin): PlatformUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    use chrono::Datelike;
+
+    let filters = web::Query::<PlatformSalesFilterParams>::from_query(req.query_string())
+        .map(|q| q.into_inner())
+        .unwrap_or_default();
+    let parse_date = |d: &Option<String>| {
+        d.as_deref()
+            .and_then(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+    };
 
-            if distributor_voucher.active {
-                currently_active = Some(VoucherType::RangeVoucher);
-            }
-        }
+    // The current month by default
+    let today = Utc::now().naive_utc().date();
+    let min_date = parse_date(&filters.min_date).unwrap_or(chrono::NaiveDate::from_ymd(
+        today.year(),
+        today.month(),
+        1,
+    ));
+    let max_date = parse_date(&filters.max_date).unwrap_or(today);
 
-        if distributor_voucher.voucher_type == VoucherType::LabelVoucher {
-            label_distributor_vouchers.push(LabelVoucherData {
-                title: distributor_voucher.label.to_string(),
-                amount: distributor_voucher.amount as u64,
-                description: distributor_voucher.description.to_string(),
-                days_valid: distributor_voucher.days_valid,
-            });
+    let sales = data::get_sales_per_distributor(
+        &mysql,
+        Utc.from_utc_datetime(&min_date.and_hms(0, 0, 0)),
+        Utc.from_utc_datetime(&(max_date + Duration::days(1)).and_hms(0, 0, 0)),
+    )
+    .await
+    .unwrap_or_default();
+    let distributors = data::get_distributors(&mysql).await.unwrap_or_default();
+
+    let distributor_names: HashMap<u64, String> = distributors
+        .iter()
+        .map(|d| (d.id, d.name.to_string()))
+        .collect();
+    let audit = platform_audit_rows(
+        data::get_platform_audit_entries(&mysql, None, 25)
+            .await
+            .unwrap_or_default(),
+        &data::get_platform_admin_names(&mysql).await,
+        &distributor_names,
+    );
 
-            if distributor_voucher.active {
-                currently_active = Some(VoucherType::LabelVoucher);
-            }
-        }
+    // Deleted distributors are no longer listed but their sales still count
+    let total_sales: i64 = sales.iter().map(|s| s.sales).sum();
+    let total_amount: f64 = sales.iter().map(|s| s.amount).sum();
+
+    let s = PlatformDashboard {
+        csrf_token: csrf::token(&req),
+        admin_name: admin.display_name,
+        distributors: distributors
+            .iter()
+            .map(|d| {
+                let (count, amount) = sales
+                    .iter()
+                    .find(|s| s.distributor_id == d.id)
+                    .map(|s| (s.sales, s.amount))
+                    .unwrap_or((0, 0.0));
+
+                PlatformDistributorRow {
+                    id: d.id,
+                    name: d.name.to_string(),
+                    subdomain: d.subdomain.to_string(),
+                    url: distributor_url(d),
+                    status: d.status.to_string(),
+                    sales: count,
+                    amount: format!("{:.2}", amount).replace(".", ","),
+                    fees: format!("{:.2}", count as f64 * TRANSACTION_FEE).replace(".", ","),
+                }
+            })
+            .collect(),
+        min_date: min_date.format("%Y-%m-%d").to_string(),
+        max_date: max_date.format("%Y-%m-%d").to_string(),
+        total_sales: total_sales,
+        total_amount: format!("{:.2}", total_amount).replace(".", ","),
+        total_fees: format!("{:.2}", total_sales as f64 * TRANSACTION_FEE).replace(".", ","),
+        audit: audit,
     }
+    .render()
+    .unwrap();
 
-    let mut s = AdminDashboardCadeaubonnen {
-        three_price_voucher_a: if three_price_distributor_vouchers.len() > 0 {
-            three_price_distributor_vouchers[0].amount
-        } else {
-            10 as f64
-        },
-        three_price_voucher_b: if three_price_distributor_vouchers.len() > 0 {
-            three_price_distributor_vouchers[1].amount
-        } else {
-            25 as f64
-        },
-        three_price_voucher_c: if three_price_distributor_vouchers.len() > 0 {
-            three_price_distributor_vouchers[2].amount
-        } else {
-            50 as f64
-        },
-        three_price_voucher_max_days: if three_price_distributor_vouchers.len() > 0 {
-            three_price_distributor_vouchers[0].days_valid
-        } else {
-            90
-        },
-        three_price_voucher_one_use_only: if three_price_distributor_vouchers.len() > 0 {
-            three_price_distributor_vouchers[0].one_use_only
-        } else {
-            false
-        },
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
 
-        price_range_voucher_min: if price_range_distributor_voucher.is_some() {
-            price_range_distributor_voucher.unwrap().min_amount
-        } else {
-            10 as f64
-        },
-        price_range_voucher_max: if price_range_distributor_voucher.is_some() {
-            price_range_distributor_voucher.unwrap().max_amount
-        } else {
-            50 as f64
-        },
-        price_range_voucher_auto: if price_range_distributor_voucher.is_some() {
-            price_range_distributor_voucher.unwrap().amount
-        } else {
-            20 as f64
-        },
-        price_range_voucher_max_days: if price_range_distributor_voucher.is_some() {
-            price_range_distributor_voucher.unwrap().days_valid
-        } else {
-            90
-        },
-        price_range_voucher_one_use_only: if price_range_distributor_voucher.is_some() {
-            price_range_distributor_voucher.unwrap().one_use_only
-        } else {
-            false
-        },
+async fn platform_zaak(
+    web::Path(id): web::Path<u64>,
+    PlatformUser(_): PlatformUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let distributor = match get_distributor(&mysql, id).await {
+        Some(d) if d.status != DistributorStatus::Deleted => d,
+        _ => return error404().await,
+    };
 
-        label_vouchers: &label_distributor_vouchers,
-        label_voucher_max_days: if label_distributor_vouchers.len() > 0 {
-            label_distributor_vouchers[0].days_valid
-        } else {
-            90
-        },
+    let users = data::get_distributor_users(&mysql, distributor.tenant())
+        .await
+        .unwrap_or_default();
 
-        currently_active: currently_active,
+    let mut distributor_names: HashMap<u64, String> = HashMap::new();
+    distributor_names.insert(distributor.id, distributor.name.to_string());
+    let audit = platform_audit_rows(
+        data::get_platform_audit_entries(&mysql, Some(distributor.id), 50)
+            .await
+            .unwrap_or_default(),
+        &data::get_platform_admin_names(&mysql).await,
+        &distributor_names,
+    );
+
+    let s = PlatformZaak {
+        csrf_token: csrf::token(&req),
+        masked_bankaccountnr: distributor.masked_iban(),
+        status: distributor.status.to_string(),
+        distributor: distributor,
+        users: users
+            .into_iter()
+            .map(|u| AdminUserRow {
+                id: u.id,
+                is_self: false,
+                username: u.username,
+                display_name: u.display_name,
+                email: u.email,
+                role: u.role.to_string(),
+                active: u.active,
+                last_login: match u.last_login {
+                    Some(date) => date.format("%d/%m/%Y %H:%M").to_string(),
+                    None => "Nog niet ingelogd".to_string(),
+                },
+            })
+            .collect(),
+        audit: audit,
     }
     .render()
     .unwrap();
@@ -2001,225 +13917,803 @@ async fn admin_dashboard_cadeaubonnen(
     Ok(HttpResponse::Ok().content_type("text/html").body(s))
 }
 
-async fn admin_update_cadeaubonnen(
-    session: Session,
+async fn platform_zaak_nieuw(
+    PlatformUser(_): PlatformUser,
     req: HttpRequest,
+) -> Result<HttpResponse> {
+    let s = PlatformZaakNieuw {
+        csrf_token: csrf::token(&req),
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn platform_zaak_toevoegen(
+    PlatformUser(admin): PlatformUser,
     mysql: web::Data<MySQL>,
-    json: web::Json<AdminVouchersUpdateJson>,
+    mail: web::Data<mail::Mail>,
+    req: HttpRequest,
+    form: web::Form<PlatformDistributorForm>,
 ) -> Result<HttpResponse> {
-    if session
-        .get::<DistributorUser>("distributoruser")
-        .unwrap()
-        .is_none()
+    if form.validate().is_err()
+        || form.owner_username.len() < 3
+        || form.owner_username.len() > 64
+        || form.owner_name.trim() == ""
+        || !validator::validate_email(&form.owner_email)
     {
-        return Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/admin/login")
-            .finish());
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("invalid_input"));
     }
+    let subdomain = form.subdomain.trim().to_lowercase();
+    if let Err(e) = check_subdomain(&subdomain) {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body(e));
+    }
+    if data::is_subdomain_taken(&mysql, &subdomain, 0).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("subdomain_taken"));
+    }
+    let (btw_nr, iban) = match check_registration_numbers(&form.btw_nr, &form.bankaccountnr) {
+        Ok(numbers) => numbers,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body(e))
+        }
+    };
 
-    let voucher_type: VoucherType =
-        VoucherType::from_str(req.match_info().get("voucher_type").unwrap()).unwrap();
-
-    let mut vouchers_to_add: std::vec::Vec<DistributorVoucher> = Vec::new();
-    match voucher_type {
-        VoucherType::ThreeOptionVoucher => {
-            for distributor_voucher in &json.three_option_vouchers {
-                vouchers_to_add.push(DistributorVoucher {
-                    id: 0,
-                    distributor: session
-                        .get::<DistributorUser>("distributoruser")?
-                        .unwrap()
-                        .distributor,
-                    voucher_type: VoucherType::ThreeOptionVoucher,
-                    amount: *distributor_voucher as f64,
-                    min_amount: 0 as f64,
-                    max_amount: 0 as f64,
-                    label: "".to_string(),
-                    description: "".to_string(),
-                    days_valid: json.days_valid as u16,
-                    active: true,
-                    one_use_only: json.one_use_only as bool,
-                    create_date: None,
-                });
+    let location =
+        match postcodes::resolve(&mysql, &form.country, &form.postalcode, &form.city).await {
+            Ok(location) => location,
+            Err(e) => {
+                return Ok(HttpResponse::BadRequest()
+                    .content_type("text/plain")
+                    .body(e))
             }
-        }
-        VoucherType::RangeVoucher => {
-            vouchers_to_add.push(DistributorVoucher {
-                id: 0,
-                distributor: session
-                    .get::<DistributorUser>("distributoruser")?
-                    .unwrap()
-                    .distributor,
-                voucher_type: VoucherType::RangeVoucher,
-                amount: json.price_range_voucher.auto_amount as f64,
-                min_amount: json.price_range_voucher.min_amount as f64,
-                max_amount: json.price_range_voucher.max_amount as f64,
-                label: "".to_string(),
-                description: "".to_string(),
-                days_valid: json.days_valid as u16,
-                active: true,
-                one_use_only: json.one_use_only as bool,
-                create_date: None,
-            });
-        }
-        VoucherType::LabelVoucher => {
-            for distributor_voucher in &json.label_vouchers {
-                vouchers_to_add.push(DistributorVoucher {
-                    id: 0,
-                    distributor: session
-                        .get::<DistributorUser>("distributoruser")?
-                        .unwrap()
-                        .distributor,
-                    voucher_type: VoucherType::LabelVoucher,
-                    amount: distributor_voucher.amount as f64,
-                    min_amount: 0 as f64,
-                    max_amount: 0 as f64,
-                    label: distributor_voucher.title.to_string(),
-                    description: distributor_voucher.description.to_string(),
-                    days_valid: json.days_valid as u16,
-                    active: true,
-                    one_use_only: true,
-                    create_date: None,
-                });
+        };
+
+    let mut distributor = Distributor {
+        id: 0,
+        name: form.name.to_string(),
+        email: form.email.to_string(),
+        tel: form.tel.to_string(),
+        address: form.address.to_string(),
+        location: location,
+        subdomain: subdomain,
+        description: form.description.to_string(),
+        description_html: "".to_string(),
+        bankaccountnr: bankdetails::encrypt(&iban),
+        btw_nr: btw_nr,
+        reminder_days: DEFAULT_REMINDER_DAYS.to_string(),
+        send_reminders: true,
+        require_2fa: false,
+        status: DistributorStatus::Active,
+        redeem_when_suspended: true,
+        primary_domain: None,
+        branding: Branding::default(),
+    };
+    // Random password nobody knows, the owner picks one through the invitation
+    let mut owner = DistributorUser {
+        id: 0,
+        username: form.owner_username.to_string(),
+        password: generate_token(),
+        distributor: distributor.clone(),
+        display_name: form.owner_name.to_string(),
+        email: form.owner_email.to_string(),
+        totp_enabled: false,
+        role: Role::Owner,
+        active: true,
+        last_login: None,
+    };
+    owner.hash_password().unwrap();
+    let token = generate_token();
+    let (distributor_id, owner_id) = match data::add_registration(
+        &mysql,
+        &distributor,
+        &owner,
+        TokenPurpose::Invitation,
+        &token,
+        Utc::now() + Duration::days(INVITATION_VALID_DAYS),
+    )
+    .await
+    {
+        Some(ids) => ids,
+        None => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body("update_failed"))
+        }
+    };
+    distributor.id = distributor_id;
+    owner.distributor = distributor.clone();
+    owner.id = owner_id;
+
+    mail.send_invitation_mail(&owner, "Kaddo", &token, INVITATION_VALID_DAYS)
+        .await
+        .unwrap_or_else(|e| println!("Error: {}", e));
+
+    let mut entry = PlatformAuditEntry::new(
+        &admin,
+        &req,
+        "distributor.create",
+        Some(distributor.id),
+        "distributor",
+        distributor.id,
+    );
+    let mut after = distributor_audit_json(&distributor);
+    after["owner"] = serde_json::json!(owner.username);
+    entry.after = after.to_string();
+    data::add_platform_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": distributor.id })))
+}
+
+async fn platform_zaak_update(
+    web::Path(id): web::Path<u64>,
+    PlatformUser(admin): PlatformUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<PlatformDistributorForm>,
+) -> Result<HttpResponse> {
+    let mut distributor = match get_distributor(&mysql, id).await {
+        Some(d) if d.status != DistributorStatus::Deleted => d,
+        _ => return error404().await,
+    };
+
+    if form.validate().is_err() {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("invalid_input"));
+    }
+    let subdomain = form.subdomain.trim().to_lowercase();
+    if let Err(e) = check_subdomain(&subdomain) {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body(e));
+    }
+    if data::is_subdomain_taken(&mysql, &subdomain, distributor.id).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("subdomain_taken"));
+    }
+
+    let mut entry = PlatformAuditEntry::new(
+        &admin,
+        &req,
+        "distributor.update",
+        Some(distributor.id),
+        "distributor",
+        distributor.id,
+    );
+    entry.before = distributor_audit_json(&distributor).to_string();
+
+    distributor.name = form.name.to_string();
+    distributor.email = form.email.to_string();
+    distributor.tel = form.tel.to_string();
+    distributor.address = form.address.to_string();
+    distributor.location =
+        match postcodes::resolve(&mysql, &form.country, &form.postalcode, &form.city).await {
+            Ok(location) => location,
+            Err(e) => {
+                return Ok(HttpResponse::BadRequest()
+                    .content_type("text/plain")
+                    .body(e))
+            }
+        };
+    distributor.subdomain = subdomain;
+    distributor.description = form.description.to_string();
+    // The IBAN field is left empty to keep the current one, it's never shown in full
+    let current_iban = distributor.iban().unwrap_or_default();
+    let bankaccountnr = match form.bankaccountnr.trim() {
+        "" => current_iban.as_str(),
+        input => input,
+    };
+    match check_registration_numbers(&form.btw_nr, bankaccountnr) {
+        Ok((btw_nr, iban)) => {
+            distributor.btw_nr = btw_nr;
+            if iban != current_iban {
+                distributor.bankaccountnr = bankdetails::encrypt(&iban);
             }
         }
-        _ => {
-            return Ok(HttpResponse::BadRequest().finish());
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body(e))
         }
     }
+    entry.after = distributor_audit_json(&distributor).to_string();
 
-    data::add_active_distributor_vouchers(&mysql, vouchers_to_add).await;
+    match data::update_distributor(&mysql, &distributor).await {
+        false => Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed")),
+        true => {
+            data::add_platform_audit_entry(&mysql, &entry).await;
 
-    Ok(HttpResponse::Ok().content_type("text/plain").body(""))
+            Ok(HttpResponse::Ok()
+                .content_type("text/plain")
+                .body("update_succeeded"))
+        }
+    }
 }
 
-async fn admin_dashboard_bestellingen(
-    session: Session,
+// Logs out every user of a distributor, e.g. after suspending it
+async fn revoke_distributor_sessions(mysql: &web::Data<MySQL>, tenant: data::Tenant) {
+    for user in data::get_distributor_users(mysql, tenant)
+        .await
+        .unwrap_or_default()
+    {
+        data::revoke_admin_sessions_by_user(mysql, user.id, None).await;
+    }
+}
+
+async fn platform_zaak_status(
+    web::Path(id): web::Path<u64>,
+    PlatformUser(admin): PlatformUser,
     mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<PlatformStatusForm>,
 ) -> Result<HttpResponse> {
-    if session
-        .get::<DistributorUser>("distributoruser")
-        .unwrap()
-        .is_none()
+    let distributor = match get_distributor(&mysql, id).await {
+        Some(d) if d.status != DistributorStatus::Deleted => d,
+        _ => return error404().await,
+    };
+
+    // Deleting has its own route with extra checks
+    let status = match DistributorStatus::from_str(&form.status) {
+        Ok(status) if status != DistributorStatus::Deleted => status,
+        _ => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body("invalid_status"))
+        }
+    };
+    if form.reason.trim() == "" {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("reason_required"));
+    }
+
+    let redeem_when_suspended = match status {
+        DistributorStatus::Suspended => form.redeem_vouchers.is_some(),
+        _ => distributor.redeem_when_suspended,
+    };
+    if !data::update_distributor_status(&mysql, distributor.id, status).await
+        || !data::update_redeem_when_suspended(&mysql, distributor.id, redeem_when_suspended).await
     {
-        return Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/admin/login")
-            .finish());
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
     }
+    // Sessions hold a copy of the distributor, its status decides what users can do
+    revoke_distributor_sessions(&mysql, distributor.tenant()).await;
+
+    let mut entry = PlatformAuditEntry::new(
+        &admin,
+        &req,
+        match status {
+            DistributorStatus::Suspended => "distributor.suspend",
+            _ => "distributor.activate",
+        },
+        Some(distributor.id),
+        "distributor",
+        distributor.id,
+    );
+    entry.before = serde_json::json!({ "status": distributor.status, "redeem_when_suspended": distributor.redeem_when_suspended }).to_string();
+    entry.after =
+        serde_json::json!({ "status": status, "redeem_when_suspended": redeem_when_suspended })
+            .to_string();
+    entry.reason = form.reason.to_string();
+    data::add_platform_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
 
-    let s = AdminDashboardBestellingen.render().unwrap();
-    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+// Everything of a distributor as one JSON file, handed over before it's offboarded
+async fn platform_zaak_export(
+    web::Path(id): web::Path<u64>,
+    PlatformUser(admin): PlatformUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let distributor = match get_distributor(&mysql, id).await {
+        Some(d) if d.status != DistributorStatus::Deleted => d,
+        _ => return error404().await,
+    };
+    let tenant = distributor.tenant();
+
+    let mut details = distributor_audit_json(&distributor);
+    details["status"] = serde_json::json!(distributor.status);
+    let users: std::vec::Vec<serde_json::Value> = data::get_distributor_users(&mysql, tenant)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(|u| {
+            serde_json::json!({
+                "username": u.username,
+                "display_name": u.display_name,
+                "email": u.email,
+                "role": u.role,
+                "active": u.active,
+            })
+        })
+        .collect();
+
+    let export = serde_json::json!({
+        "exported_at": Utc::now(),
+        "distributor": details,
+        "users": users,
+        "vouchers": data::get_export_vouchers(&mysql, tenant).await.unwrap_or_default(),
+        "redemptions": data::get_export_redemptions(&mysql, tenant).await.unwrap_or_default(),
+        "settlements": data::get_settlements(&mysql, tenant).await.unwrap_or_default(),
+        "shop_locations": data::get_shop_locations(&mysql, tenant).await.unwrap_or_default(),
+        "domains": data::get_distributor_domains(&mysql, tenant).await.unwrap_or_default(),
+        "faq": data::get_faq_entries(&mysql, tenant).await.unwrap_or_default(),
+        "pages": data::get_shop_pages(&mysql, tenant).await.unwrap_or_default(),
+        "audit_log": data::get_audit_entries(&mysql, tenant, &AuditLogFilterParams::default(), 0, AUDIT_LOG_EXPORT_LIMIT)
+            .await
+            .unwrap_or_default(),
+    });
+
+    let entry = PlatformAuditEntry::new(
+        &admin,
+        &req,
+        "distributor.export",
+        Some(distributor.id),
+        "distributor",
+        distributor.id,
+    );
+    data::add_platform_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .header(
+            http::header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"kaddo-{}-{}.json\"",
+                distributor.subdomain,
+                Utc::now().format("%Y%m%d")
+            ),
+        )
+        .body(serde_json::to_string_pretty(&export).unwrap()))
 }
 
-async fn admin_dashboard_bestelling(
-    session: Session,
+// Refunds the open vouchers so the distributor can be deleted. Scanning has to be stopped
+// first, a voucher could otherwise be used and refunded at once.
+async fn platform_zaak_afrekenen(
+    web::Path(id): web::Path<u64>,
+    PlatformUser(admin): PlatformUser,
     mysql: web::Data<MySQL>,
     req: HttpRequest,
+    form: web::Form<PlatformReasonForm>,
 ) -> Result<HttpResponse> {
-    if session
-        .get::<DistributorUser>("distributoruser")
-        .unwrap()
-        .is_none()
-    {
-        return Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/admin/login")
-            .finish());
+    let distributor = match get_distributor(&mysql, id).await {
+        Some(d) if d.status != DistributorStatus::Deleted => d,
+        _ => return error404().await,
+    };
+
+    if form.reason.trim() == "" {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("reason_required"));
+    }
+    if distributor.status != DistributorStatus::Suspended || distributor.accepts_redemptions() {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("suspend_without_redemptions_first"));
     }
 
-    let id = req.match_info().get("id").unwrap().parse::<u64>().unwrap();
-    let voucher: Voucher = data::get_voucher(&mysql, data::Selector::ById(id))
+    let tenant = distributor.tenant();
+    let open_vouchers = data::count_open_vouchers(&mysql, tenant).await;
+    if !data::settle_open_vouchers(&mysql, tenant).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+    let settlements = data::get_settlements(&mysql, tenant)
         .await
-        .unwrap();
+        .unwrap_or_default();
+
+    let mut entry = PlatformAuditEntry::new(
+        &admin,
+        &req,
+        "distributor.settle",
+        Some(distributor.id),
+        "distributor",
+        distributor.id,
+    );
+    entry.after = serde_json::json!({
+        "vouchers": open_vouchers,
+        "total": settlements.iter().filter_map(|s| s["amount"].as_f64()).sum::<f64>(),
+    })
+    .to_string();
+    entry.reason = form.reason.to_string();
+    data::add_platform_audit_entry(&mysql, &entry).await;
 
-    let s = AdminOrderData { voucher: voucher }.render().unwrap();
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
+}
 
-    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+async fn platform_zaak_verwijderen(
+    web::Path(id): web::Path<u64>,
+    PlatformUser(admin): PlatformUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<PlatformReasonForm>,
+) -> Result<HttpResponse> {
+    let distributor = match get_distributor(&mysql, id).await {
+        Some(d) if d.status != DistributorStatus::Deleted => d,
+        _ => return error404().await,
+    };
+
+    if form.reason.trim() == "" {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("reason_required"));
+    }
+    // Customers still have vouchers of this shop, those have to be settled first
+    if data::count_open_vouchers(&mysql, distributor.tenant()).await > 0 {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("open_vouchers"));
+    }
+
+    revoke_distributor_sessions(&mysql, distributor.tenant()).await;
+    if !data::delete_distributor(&mysql, distributor.id, "offboarding").await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+
+    let mut entry = PlatformAuditEntry::new(
+        &admin,
+        &req,
+        "distributor.delete",
+        Some(distributor.id),
+        "distributor",
+        distributor.id,
+    );
+    // The entity id identifies the shop, the log shouldn't get a new copy of the wiped data
+    entry.before = serde_json::json!({ "status": distributor.status.to_string() }).to_string();
+    entry.reason = form.reason.to_string();
+    data::add_platform_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
 }
 
-async fn admin_dashboard_get_bestellingen(
-    session: Session,
+async fn platform_gebruiker_uitnodigen(
+    web::Path(id): web::Path<u64>,
+    PlatformUser(admin): PlatformUser,
     mysql: web::Data<MySQL>,
+    mail: web::Data<mail::Mail>,
     req: HttpRequest,
+    form: web::Form<UserInviteForm>,
 ) -> Result<HttpResponse> {
-    if session
-        .get::<DistributorUser>("distributoruser")
-        .unwrap()
-        .is_none()
+    let distributor = match get_distributor(&mysql, id).await {
+        Some(d) if d.status != DistributorStatus::Deleted => d,
+        _ => return error404().await,
+    };
+
+    if form.validate().is_err() {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("invalid_input"));
+    }
+    let role = match Role::from_str(&form.role) {
+        Ok(role) => role,
+        Err(_) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body("invalid_role"))
+        }
+    };
+    if DistributorUser::get_by_username(&form.username, distributor.tenant(), &mysql)
+        .await
+        .is_some()
     {
-        return Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/admin/login")
-            .finish());
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("username_taken"));
     }
 
-    let query_str = req.query_string().replace("%20", " ");
-    let filters = web::Query::<AdminOrderFilterParams>::from_query(&query_str)
-        .unwrap()
-        .into_inner();
+    let mut user = DistributorUser {
+        id: 0,
+        username: form.username.to_string(),
+        password: generate_token(),
+        distributor: distributor.clone(),
+        display_name: form.display_name.to_string(),
+        email: form.email.to_string(),
+        totp_enabled: false,
+        role: role,
+        active: true,
+        last_login: None,
+    };
+    user.id = DistributorUser::create(&mut user, &mysql).await;
+    if user.id == 0 {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
 
-    let orders = data::get_all_orders(
+    let token = generate_token();
+    data::add_user_token(
         &mysql,
-        filters.amount,
-        25,
-        filters,
-        session
-            .get::<DistributorUser>("distributoruser")
-            .unwrap()
-            .unwrap()
-            .distributor
-            .id,
+        user.id,
+        TokenPurpose::Invitation,
+        &token,
+        Utc::now() + Duration::days(INVITATION_VALID_DAYS),
     )
     .await;
-
-    Ok(HttpResponse::Ok().json(&orders))
+    mail.send_invitation_mail(&user, "Kaddo", &token, INVITATION_VALID_DAYS)
+        .await
+        .unwrap_or_else(|e| println!("Error: {}", e));
+
+    let mut entry = PlatformAuditEntry::new(
+        &admin,
+        &req,
+        "user.invite",
+        Some(distributor.id),
+        "distributoruser",
+        user.id,
+    );
+    entry.after =
+        serde_json::json!({ "username": user.username, "email": user.email, "role": user.role })
+            .to_string();
+    data::add_platform_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
 }
 
-async fn admin_dashboard_wachtwoord(session: Session) -> Result<HttpResponse> {
-    if session
-        .get::<DistributorUser>("distributoruser")
-        .unwrap()
-        .is_none()
+async fn platform_gebruiker_rol(
+    web::Path((id, user_id)): web::Path<(u64, u64)>,
+    PlatformUser(admin): PlatformUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<UserRoleForm>,
+) -> Result<HttpResponse> {
+    let distributor = match get_distributor(&mysql, id).await {
+        Some(d) if d.status != DistributorStatus::Deleted => d,
+        _ => return error404().await,
+    };
+    let tenant = distributor.tenant();
+    let user = match data::get_distributor_user(&mysql, tenant, user_id).await {
+        Some(user) => user,
+        None => return error404().await,
+    };
+    let role = match Role::from_str(&form.role) {
+        Ok(role) => role,
+        Err(_) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/plain")
+                .body("invalid_role"))
+        }
+    };
+    // Somebody has to be left who can manage the users
+    if user.role == Role::Owner
+        && user.active
+        && role != Role::Owner
+        && data::count_active_owners(&mysql, tenant).await <= 1
     {
-        return Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/admin/login")
-            .finish());
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("last_owner"));
     }
 
-    let mut s = AdminDashboardWachtwoord.render().unwrap();
+    if !data::update_distributor_user_role(&mysql, tenant, user_id, role).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
+    // The role is part of the session, log in again to get the new one
+    data::revoke_admin_sessions_by_user(&mysql, user_id, None).await;
+
+    let mut entry = PlatformAuditEntry::new(
+        &admin,
+        &req,
+        "user.role",
+        Some(id),
+        "distributoruser",
+        user_id,
+    );
+    entry.before = serde_json::json!({ "role": user.role }).to_string();
+    entry.after = serde_json::json!({ "role": role }).to_string();
+    data::add_platform_audit_entry(&mysql, &entry).await;
 
-    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
 }
 
-async fn admin_dashboard_help(session: Session) -> Result<HttpResponse> {
-    if session
-        .get::<DistributorUser>("distributoruser")
-        .unwrap()
-        .is_none()
+async fn platform_gebruiker_actief(
+    web::Path((id, user_id)): web::Path<(u64, u64)>,
+    PlatformUser(admin): PlatformUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<UserActiveForm>,
+) -> Result<HttpResponse> {
+    let distributor = match get_distributor(&mysql, id).await {
+        Some(d) if d.status != DistributorStatus::Deleted => d,
+        _ => return error404().await,
+    };
+    let tenant = distributor.tenant();
+    let user = match data::get_distributor_user(&mysql, tenant, user_id).await {
+        Some(user) => user,
+        None => return error404().await,
+    };
+    if user.role == Role::Owner
+        && user.active
+        && !form.active
+        && data::count_active_owners(&mysql, tenant).await <= 1
     {
-        return Ok(HttpResponse::Found()
-            .header(http::header::LOCATION, "/admin/login")
-            .finish());
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("last_owner"));
     }
 
-    let mut s = AdminDashboardHelp.render().unwrap();
+    if !data::update_distributor_user_active(&mysql, tenant, user_id, form.active).await {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("update_failed"));
+    }
 
-    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+    if !form.active {
+        data::revoke_admin_sessions_by_user(&mysql, user_id, None).await;
+        data::revoke_user_tokens(&mysql, user_id, TokenPurpose::Invitation).await;
+        data::revoke_user_tokens(&mysql, user_id, TokenPurpose::PasswordReset).await;
+    }
+
+    let mut entry = PlatformAuditEntry::new(
+        &admin,
+        &req,
+        match form.active {
+            true => "user.activate",
+            false => "user.deactivate",
+        },
+        Some(id),
+        "distributoruser",
+        user_id,
+    );
+    entry.before = serde_json::json!({ "active": user.active }).to_string();
+    entry.after = serde_json::json!({ "active": form.active }).to_string();
+    data::add_platform_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body("update_succeeded"))
 }
 
-async fn admin_dashboard_logout(session: Session) -> Result<HttpResponse> {
-    if session
-        .get::<DistributorUser>("distributoruser")
-        .unwrap()
-        .is_some()
-    {
-        session.remove("distributoruser");
+// Hands out a one-time link that logs in as the user on the host of the distributor.
+// It's recorded in the platform log and in the audit log the distributor sees.
+async fn platform_gebruiker_impersoneren(
+    web::Path((id, user_id)): web::Path<(u64, u64)>,
+    PlatformUser(admin): PlatformUser,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    form: web::Form<PlatformReasonForm>,
+) -> Result<HttpResponse> {
+    let distributor = match get_distributor(&mysql, id).await {
+        Some(d) if d.status != DistributorStatus::Deleted => d,
+        _ => return error404().await,
+    };
+    let user = match data::get_distributor_user(&mysql, distributor.tenant(), user_id).await {
+        Some(user) if user.active => user,
+        _ => return error404().await,
+    };
+
+    if form.reason.trim() == "" {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/plain")
+            .body("reason_required"));
     }
 
+    let token = generate_token();
+    data::add_impersonation_token(
+        &mysql,
+        user.id,
+        admin.id,
+        &token,
+        Utc::now() + Duration::minutes(IMPERSONATION_VALID_MINUTES),
+    )
+    .await;
+
+    let mut entry = PlatformAuditEntry::new(
+        &admin,
+        &req,
+        "user.impersonate",
+        Some(distributor.id),
+        "distributoruser",
+        user.id,
+    );
+    entry.after = serde_json::json!({ "username": user.username }).to_string();
+    entry.reason = form.reason.to_string();
+    data::add_platform_audit_entry(&mysql, &entry).await;
+
+    let mut entry = AuditEntry::for_tenant(
+        distributor.tenant(),
+        &req,
+        "user.impersonate",
+        "distributoruser",
+        user.id,
+    );
+    entry.after =
+        serde_json::json!({ "platformadmin": admin.display_name, "username": user.username })
+            .to_string();
+    entry.reason = form.reason.to_string();
+    data::add_audit_entry(&mysql, &entry).await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "url": format!("{}/admin/impersonatie/{}", distributor_url(&distributor), token)
+    })))
+}
+
+// Landing of the impersonation link on the host of the distributor
+async fn admin_impersonatie(
+    web::Path(token): web::Path<String>,
+    mysql: web::Data<MySQL>,
+    req: HttpRequest,
+    session: Session,
+) -> Result<HttpResponse> {
+    let distributor = match tenancy::current(&req) {
+        Some(d) => d,
+        None => return error404().await,
+    };
+
+    let admin_id = match data::get_impersonation_admin(&mysql, &token).await {
+        Some(id) => id,
+        None => return error404().await,
+    };
+    let user_id = match data::use_user_token(&mysql, TokenPurpose::Impersonation, &token).await {
+        Some(id) => id,
+        None => return error404().await,
+    };
+    let user = match data::get_distributor_user(&mysql, distributor.tenant(), user_id).await {
+        Some(user) if user.active => user,
+        _ => return error404().await,
+    };
+
+    // last_login stays untouched, it's not the user who logged in. Every audit entry of
+    // this session names the platform admin.
+    session.renew();
+    session.set("impersonated_by", admin_id)?;
+    session.set("distributoruser", &user)?;
+
     Ok(HttpResponse::Found()
-        .header(http::header::LOCATION, "/admin/login")
+        .header(http::header::LOCATION, user.home())
         .finish())
 }
 
 /* DEFAULT ROUTES AND ERRORS */
+// Suspended shops show that they're closed, shops that never opened don't exist yet
+async fn storefront_closed(distributor: &Distributor) -> Result<HttpResponse> {
+    if distributor.status != DistributorStatus::Suspended {
+        return error404().await;
+    }
+
+    let s = Gesloten {
+        branding: distributor.branding.clone(),
+        distributor_name: distributor.name.to_string(),
+        redeemable: distributor.accepts_redemptions(),
+    }
+    .render()
+    .unwrap();
+
+    Ok(HttpResponse::ServiceUnavailable()
+        .content_type("text/html")
+        .body(s))
+}
+
 async fn error404() -> Result<HttpResponse> {
     let s = Error404.render().unwrap();
     Ok(HttpResponse::NotFound().content_type("text/html").body(s))
@@ -2228,7 +14722,30 @@ async fn error404() -> Result<HttpResponse> {
 /* SEED FUNCTIONS */
 async fn seed(mysql: MySQL) {
     println!("[+] Started seeding...");
-    seed_distributor_user(mysql).await;
+    seed_distributor_user(mysql.clone()).await;
+    seed_platform_admin(mysql).await;
+}
+
+async fn seed_platform_admin(mysql: MySQL) {
+    let sql = "DELETE FROM platformadmin";
+    let mut result = sqlx::query(&sql).execute(&mysql.conn).await;
+
+    let mut admin = PlatformAdmin {
+        id: 0,
+        username: "platform_admin".to_string(),
+        password: "test123".to_string(),
+        display_name: "Kaddo".to_string(),
+        email: "platform@kaddo.be".to_string(),
+        active: true,
+        last_login: None,
+    };
+
+    admin.id = PlatformAdmin::create(&mut admin, &web::Data::new(mysql)).await;
+
+    println!(
+        "[+] Added admin {} (#{}) to platformadmin",
+        admin.username, admin.id
+    );
 }
 
 async fn seed_distributor_user(mysql: MySQL) {
@@ -2243,6 +14760,11 @@ async fn seed_distributor_user(mysql: MySQL) {
             .await
             .unwrap(),
         display_name: "Jouw Naam".to_string(),
+        email: "demok_admin@kaddo.be".to_string(),
+        totp_enabled: false,
+        role: Role::Owner,
+        active: true,
+        last_login: None,
     };
 
     user1.id = DistributorUser::create(&mut user1, &web::Data::new(mysql)).await;
@@ -2278,19 +14800,49 @@ async fn main() -> std::io::Result<()> {
         },
         smtp_server: "".to_string(),
     };
+    let signer =
+        sync::Signer::from_seed(&env::var("KADDO_SYNC_SEED").expect("KADDO_SYNC_SEED not set"));
+    bankdetails::init();
+
+    // Reference data for addresses, see postcodes::import
+    let args: Vec<String> = env::args().collect();
+    if let Some(i) = args.iter().position(|a| a == "import-postcodes") {
+        match (args.get(i + 1), args.get(i + 2)) {
+            (Some(country), Some(path)) => {
+                match postcodes::import(mysql.clone(), country, path).await {
+                    Ok((added, skipped)) => {
+                        println!("{} locations added, {} lines skipped", added, skipped)
+                    }
+                    Err(e) => println!("error: {}", e),
+                }
+            }
+            _ => println!("usage: import-postcodes <BE|NL|LU> <file.csv>"),
+        }
+        return Ok(());
+    }
 
     // Seed
     if find_arg(&"seed".to_string()).await {
         seed(mysql.clone()).await;
     }
 
+    content::render_missing_descriptions(mysql.clone()).await;
+    bankdetails::reencrypt(mysql.clone()).await;
+
+    // Background jobs
+    jobs::start(mysql.clone(), mail.clone());
+
     // start http server
     HttpServer::new(move || {
         App::new()
             .data(mysql.clone())
             .data(mollie.clone())
             .data(mail.clone())
-            .wrap(CookieSession::signed(&[0; 32]).secure(false))
+            .data(signer.clone())
+            .wrap(csrf::Csrf::new().secure(false))
+            .wrap(sessions::DbSession::new(mysql.clone()).secure(false))
+            .wrap(domains::CanonicalHost::new())
+            .wrap(tenancy::ResolveTenant::new(mysql.clone()))
             // Business services
             .service(web::resource("/").route(web::get().to(index)))
             .service(web::resource("/home").route(web::get().to(index)))
@@ -2298,13 +14850,24 @@ async fn main() -> std::io::Result<()> {
             .service(web::resource("/order_form").route(web::post().to(order_form)))
             .service(web::resource("/bevestig/{hash}").route(web::get().to(confirm_order)))
             .service(web::resource("/faq").route(web::get().to(faq)))
+            .service(web::resource("/voorwaarden").route(web::get().to(voorwaarden)))
+            .service(web::resource("/pagina/{slug}").route(web::get().to(pagina)))
             .service(web::resource("/payment/hook").route(web::post().to(payment_hook))) // Mollie hook
             .service(web::resource("/check/{payment_id}").route(web::get().to(check)))
             .service(web::resource("/succes/{action}").route(web::get().to(success)))
             .service(web::resource("/niet-gelukt").route(web::get().to(failed)))
             .service(web::resource("/bon/{hash}").route(web::get().to(voucher_desktop))) // Bon
             .service(web::resource("/mobile/bon/{hash}").route(web::get().to(voucher_mobile))) // Bon (Mobile)
+            .service(
+                web::resource("/saldo")
+                    .route(web::get().to(balance_check))
+                    .route(web::post().to(balance_check_form)),
+            )
             .service(web::resource("/scanner").route(web::get().to(scanner)))
+            .service(web::resource("/postcodes").route(web::get().to(postcodes_zoeken)))
+            .service(
+                web::resource("/scanner/koppelen/{token}").route(web::get().to(scanner_koppelen)),
+            )
             .service(
                 web::resource("/scanner/login")
                     .route(web::get().to(scanner_login))
@@ -2314,6 +14877,9 @@ async fn main() -> std::io::Result<()> {
                 web::resource("/scan/get/{method}/{identifier}").route(web::get().to(scan_get)),
             )
             .service(web::resource("/scan/update").route(web::post().to(scan_update)))
+            .service(web::resource("/scan/sync/key").route(web::get().to(scan_sync_key)))
+            .service(web::resource("/scan/sync/snapshot").route(web::get().to(scan_sync_snapshot)))
+            .service(web::resource("/scan/sync/upload").route(web::post().to(scan_sync_upload)))
             .service(web::resource("/test").route(web::get().to(test))) // TEST
             // Administrator services
             .service(
@@ -2321,18 +14887,172 @@ async fn main() -> std::io::Result<()> {
                     .route(web::get().to(admin_login))
                     .route(web::post().to(admin_login_form)),
             )
+            .service(
+                web::resource("/admin/login/2fa")
+                    .route(web::get().to(admin_login_2fa))
+                    .route(web::post().to(admin_login_2fa_form)),
+            )
+            .service(
+                web::resource("/admin/wachtwoord-vergeten")
+                    .route(web::get().to(admin_wachtwoord_vergeten))
+                    .route(web::post().to(admin_wachtwoord_vergeten_form)),
+            )
+            .service(
+                web::resource("/admin/uitnodiging/{token}")
+                    .route(web::get().to(admin_uitnodiging))
+                    .route(web::post().to(admin_uitnodiging_form)),
+            )
+            .service(
+                web::resource("/admin/impersonatie/{token}")
+                    .route(web::get().to(admin_impersonatie)),
+            )
+            .service(
+                web::resource("/admin/wachtwoord-herstellen/{token}")
+                    .route(web::get().to(admin_wachtwoord_herstellen))
+                    .route(web::post().to(admin_wachtwoord_herstellen_form)),
+            )
             .service(web::resource("/admin").route(web::get().to(admin_dashboard_index)))
             .service(web::resource("/admin/").route(web::get().to(admin_dashboard_index)))
             .service(web::resource("/admin/dashboard").route(web::get().to(admin_dashboard_index)))
             .service(web::resource("/admin/dashboard/").route(web::get().to(admin_dashboard_index)))
+            .service(
+                web::resource("/admin/dashboard/aan-de-slag")
+                    .route(web::get().to(admin_dashboard_aan_de_slag)),
+            )
+            .service(
+                web::resource("/admin/dashboard/aan-de-slag/live")
+                    .route(web::post().to(admin_aan_de_slag_live)),
+            )
             .service(
                 web::resource("/admin/dashboard/mijn-zaak")
                     .route(web::get().to(admin_dashboard_mijn_zaak)),
             )
+            .service(
+                web::resource("/admin/dashboard/mijn-zaak/bankgegevens")
+                    .route(web::post().to(admin_update_bankgegevens)),
+            )
+            .service(
+                web::resource("/admin/dashboard/mijn-zaak/bankgegevens/tonen")
+                    .route(web::post().to(admin_bankgegevens_tonen)),
+            )
             .service(
                 web::resource("/admin/dashboard/mijn-zaak/update")
                     .route(web::post().to(admin_update_mijn_zaak)),
             )
+            .service(
+                web::resource("/admin/dashboard/mijn-zaak/herinneringen")
+                    .route(web::post().to(admin_update_herinneringen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/mijn-zaak/tweestapsverificatie")
+                    .route(web::post().to(admin_update_tweestapsverificatie_verplicht)),
+            )
+            .service(
+                web::resource("/admin/dashboard/domeinen")
+                    .route(web::get().to(admin_dashboard_domeinen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/vestigingen")
+                    .route(web::get().to(admin_dashboard_vestigingen))
+                    .route(web::post().to(admin_vestiging_toevoegen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/vestigingen/{id}")
+                    .route(web::post().to(admin_vestiging_update)),
+            )
+            .service(
+                web::resource("/admin/dashboard/vestigingen/{id}/sluiten")
+                    .route(web::post().to(admin_vestiging_sluiten)),
+            )
+            .service(
+                web::resource("/admin/dashboard/vestigingen/{id}/apparaten")
+                    .route(web::post().to(admin_apparaat_toevoegen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/apparaten/{id}/intrekken")
+                    .route(web::post().to(admin_apparaat_intrekken)),
+            )
+            .service(
+                web::resource("/admin/dashboard/inhoud")
+                    .route(web::get().to(admin_dashboard_inhoud)),
+            )
+            .service(
+                web::resource("/admin/dashboard/inhoud/voorbeeld")
+                    .route(web::post().to(admin_inhoud_voorbeeld)),
+            )
+            .service(
+                web::resource("/admin/dashboard/inhoud/faq")
+                    .route(web::post().to(admin_faq_toevoegen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/inhoud/faq/{id}")
+                    .route(web::post().to(admin_faq_update)),
+            )
+            .service(
+                web::resource("/admin/dashboard/inhoud/faq/{id}/verwijderen")
+                    .route(web::post().to(admin_faq_verwijderen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/inhoud/paginas/nieuw/{kind}")
+                    .route(web::post().to(admin_pagina_toevoegen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/inhoud/paginas/{id}")
+                    .route(web::post().to(admin_pagina_update)),
+            )
+            .service(
+                web::resource("/admin/dashboard/inhoud/paginas/{id}/verwijderen")
+                    .route(web::post().to(admin_pagina_verwijderen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/huisstijl")
+                    .route(web::get().to(admin_dashboard_huisstijl))
+                    .route(web::post().to(admin_update_huisstijl)),
+            )
+            .service(
+                web::resource("/admin/dashboard/huisstijl/{image}")
+                    .route(web::post().to(admin_huisstijl_afbeelding)),
+            )
+            .service(
+                web::resource("/admin/dashboard/huisstijl/{image}/verwijderen")
+                    .route(web::post().to(admin_huisstijl_afbeelding_verwijderen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/domeinen/subdomein")
+                    .route(web::post().to(admin_subdomein_wijzigen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/domeinen/toevoegen")
+                    .route(web::post().to(admin_domein_toevoegen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/domeinen/{id}/verifieren")
+                    .route(web::post().to(admin_domein_verifieren)),
+            )
+            .service(
+                web::resource("/admin/dashboard/domeinen/{id}/primair")
+                    .route(web::post().to(admin_domein_primair)),
+            )
+            .service(
+                web::resource("/admin/dashboard/domeinen/{id}/verwijderen")
+                    .route(web::post().to(admin_domein_verwijderen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/tweestapsverificatie")
+                    .route(web::get().to(admin_dashboard_tweestapsverificatie)),
+            )
+            .service(
+                web::resource("/admin/dashboard/tweestapsverificatie/activeren")
+                    .route(web::post().to(admin_tweestapsverificatie_activeren)),
+            )
+            .service(
+                web::resource("/admin/dashboard/tweestapsverificatie/herstelcodes")
+                    .route(web::post().to(admin_tweestapsverificatie_herstelcodes)),
+            )
+            .service(
+                web::resource("/admin/dashboard/tweestapsverificatie/uitschakelen")
+                    .route(web::post().to(admin_tweestapsverificatie_uitschakelen)),
+            )
             .service(
                 web::resource("/admin/dashboard/cadeaubonnen")
                     .route(web::get().to(admin_dashboard_cadeaubonnen)),
@@ -2353,9 +15073,66 @@ async fn main() -> std::io::Result<()> {
                 web::resource("/admin/dashboard/bestellingen/{id}")
                     .route(web::get().to(admin_dashboard_bestelling)),
             )
+            .service(
+                web::resource("/admin/dashboard/bestellingen/{id}/verlengen")
+                    .route(web::post().to(admin_bestelling_verlengen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/bestellingen/{id}/saldo")
+                    .route(web::post().to(admin_bestelling_saldo)),
+            )
+            .service(
+                web::resource("/admin/dashboard/bestellingen/{id}/blokkeren")
+                    .route(web::post().to(admin_bestelling_blokkeren)),
+            )
+            .service(
+                web::resource("/admin/dashboard/bestellingen/{id}/ontvanger")
+                    .route(web::post().to(admin_bestelling_ontvanger)),
+            )
+            .service(
+                web::resource("/admin/dashboard/bestellingen/{id}/opnieuw-versturen")
+                    .route(web::post().to(admin_bestelling_opnieuw_versturen)),
+            )
             .service(
                 web::resource("/admin/dashboard/wachtwoord")
-                    .route(web::get().to(admin_dashboard_wachtwoord)),
+                    .route(web::get().to(admin_dashboard_wachtwoord))
+                    .route(web::post().to(admin_update_wachtwoord)),
+            )
+            .service(
+                web::resource("/admin/dashboard/gebruikers")
+                    .route(web::get().to(admin_dashboard_gebruikers)),
+            )
+            .service(
+                web::resource("/admin/dashboard/gebruikers/uitnodigen")
+                    .route(web::post().to(admin_gebruiker_uitnodigen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/gebruikers/{id}/rol")
+                    .route(web::post().to(admin_gebruiker_rol)),
+            )
+            .service(
+                web::resource("/admin/dashboard/gebruikers/{id}/actief")
+                    .route(web::post().to(admin_gebruiker_actief)),
+            )
+            .service(
+                web::resource("/admin/dashboard/logboek")
+                    .route(web::get().to(admin_dashboard_logboek)),
+            )
+            .service(
+                web::resource("/admin/dashboard/logboek/export")
+                    .route(web::get().to(admin_logboek_export)),
+            )
+            .service(
+                web::resource("/admin/dashboard/sessies")
+                    .route(web::get().to(admin_dashboard_sessies)),
+            )
+            .service(
+                web::resource("/admin/dashboard/sessies/alles-beeindigen")
+                    .route(web::post().to(admin_sessies_beeindigen)),
+            )
+            .service(
+                web::resource("/admin/dashboard/sessies/{id}/beeindigen")
+                    .route(web::post().to(admin_sessie_beeindigen)),
             )
             .service(
                 web::resource("/admin/dashboard/help").route(web::get().to(admin_dashboard_help)),
@@ -2366,6 +15143,74 @@ async fn main() -> std::io::Result<()> {
             )
             // General
             .service(Files::new("/assets", "./templates/assets").show_files_listing())
+            .service(Files::new("/uploads", branding::UPLOAD_DIR))
+            // Registration
+            .service(
+                web::resource("/registreren")
+                    .route(web::get().to(registreren))
+                    .route(web::post().to(registreren_form)),
+            )
+            .service(
+                web::resource("/registreren/subdomein").route(web::get().to(registreren_subdomein)),
+            )
+            .service(
+                web::resource("/registreren/bevestigen/{token}")
+                    .route(web::get().to(registreren_bevestigen)),
+            )
+            .service(
+                web::resource("/registreren/opnieuw-versturen")
+                    .route(web::post().to(registreren_opnieuw_versturen)),
+            )
+            // Platform
+            .service(web::resource("/platform").route(web::get().to(platform_dashboard)))
+            .service(
+                web::resource("/platform/login")
+                    .route(web::get().to(platform_login))
+                    .route(web::post().to(platform_login_form)),
+            )
+            .service(web::resource("/platform/uitloggen").route(web::get().to(platform_logout)))
+            .service(
+                web::resource("/platform/zaken/nieuw")
+                    .route(web::get().to(platform_zaak_nieuw))
+                    .route(web::post().to(platform_zaak_toevoegen)),
+            )
+            .service(web::resource("/platform/zaken/{id}").route(web::get().to(platform_zaak)))
+            .service(
+                web::resource("/platform/zaken/{id}/update")
+                    .route(web::post().to(platform_zaak_update)),
+            )
+            .service(
+                web::resource("/platform/zaken/{id}/status")
+                    .route(web::post().to(platform_zaak_status)),
+            )
+            .service(
+                web::resource("/platform/zaken/{id}/export")
+                    .route(web::get().to(platform_zaak_export)),
+            )
+            .service(
+                web::resource("/platform/zaken/{id}/afrekenen")
+                    .route(web::post().to(platform_zaak_afrekenen)),
+            )
+            .service(
+                web::resource("/platform/zaken/{id}/verwijderen")
+                    .route(web::post().to(platform_zaak_verwijderen)),
+            )
+            .service(
+                web::resource("/platform/zaken/{id}/gebruikers/uitnodigen")
+                    .route(web::post().to(platform_gebruiker_uitnodigen)),
+            )
+            .service(
+                web::resource("/platform/zaken/{id}/gebruikers/{user_id}/rol")
+                    .route(web::post().to(platform_gebruiker_rol)),
+            )
+            .service(
+                web::resource("/platform/zaken/{id}/gebruikers/{user_id}/actief")
+                    .route(web::post().to(platform_gebruiker_actief)),
+            )
+            .service(
+                web::resource("/platform/zaken/{id}/gebruikers/{user_id}/impersoneren")
+                    .route(web::post().to(platform_gebruiker_impersoneren)),
+            )
             .service(web::resource("/clear").route(web::get().to(clear)))
     })
     .bind("127.0.0.1:8080")?
@@ -2387,3 +15232,384 @@ fn uppercase_first_letter(s: &str) -> String {
         Some(f) => f.to_uppercase().collect::<String>() + c.as_str(),
     }
 }
+
+// The tests that use the database need one with the migrations applied, they add their own
+// rows inside a transaction that is rolled back:
+// KADDO_TEST_DATABASE=mysql://test@localhost/kaddo cargo test -- --ignored
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use sqlx::mysql::MySqlPoolOptions;
+
+    // A single connection, so the data functions run inside the transaction the test opened
+    async fn database() -> web::Data<MySQL> {
+        let conn_str = env::var("KADDO_TEST_DATABASE").expect("KADDO_TEST_DATABASE not set");
+        let pool = MySqlPoolOptions::new()
+            .max_connections(1)
+            .connect(&conn_str)
+            .await
+            .unwrap();
+        sqlx::Executor::execute(&pool, "START TRANSACTION")
+            .await
+            .unwrap();
+
+        web::Data::new(MySQL { conn: pool })
+    }
+
+    // A failing test never gets here, closing the connection rolls back as well
+    async fn rollback(mysql: &web::Data<MySQL>) {
+        sqlx::Executor::execute(&mysql.conn, "ROLLBACK")
+            .await
+            .unwrap();
+    }
+
+    async fn insert(
+        mysql: &web::Data<MySQL>,
+        query: sqlx::query::Query<'_, sqlx::MySql, sqlx::mysql::MySqlArguments>,
+    ) -> u64 {
+        query.execute(&mysql.conn).await.unwrap().last_insert_id()
+    }
+
+    async fn add_test_distributor(mysql: &web::Data<MySQL>, location: u64) -> u64 {
+        let subdomain = format!("test-{}", rand::thread_rng().gen::<u32>());
+
+        insert(
+            mysql,
+            sqlx::query("INSERT INTO distributor (name, email, tel, address, location, subdomain, description, description_html, bankaccountnr, btw_nr, reminder_days, send_reminders, require_2fa, status) VALUES (?,'','','',?,?,'','','','','',0,0,'Active')")
+                .bind(&subdomain)
+                .bind(&location)
+                .bind(&subdomain),
+        )
+        .await
+    }
+
+    // A sold voucher with the distributor it belongs to and a distributor it doesn't
+    struct Fixture {
+        voucher: u64,
+        sale: u64,
+        client: u64,
+        owner: data::Tenant,
+        other: data::Tenant,
+    }
+
+    async fn voucher_of_other_tenant(mysql: &web::Data<MySQL>) -> Fixture {
+        let code = rand::thread_rng().gen::<u32>();
+
+        let location = insert(
+            mysql,
+            sqlx::query("INSERT INTO location (country, postalcode, city, reference) VALUES ('BE', '0000', ?, 0)")
+                .bind(format!("Test {}", code)),
+        )
+        .await;
+        let owner = add_test_distributor(mysql, location).await;
+        let other = add_test_distributor(mysql, location).await;
+
+        let distributorvoucher = insert(
+            mysql,
+            sqlx::query("INSERT INTO distributorvoucher (distributor, voucher_type, amount, min_amount, max_amount, label, description, days_valid, active, one_use_only, most_recent_of_type) VALUES (?,'RangeVoucher',0,10,100,'','',365,1,0,1)")
+                .bind(&owner),
+        )
+        .await;
+        let client = insert(
+            mysql,
+            sqlx::query("INSERT INTO client (distributor, firstname, lastname, email, tel) VALUES (?,'Test','Klant','klant@example.com','')")
+                .bind(&owner),
+        )
+        .await;
+        let sale = insert(
+            mysql,
+            sqlx::query(
+                "INSERT INTO sale (distributor, client, amount, payment_id) VALUES (?,?,50,'')",
+            )
+            .bind(&owner)
+            .bind(&client),
+        )
+        .await;
+        let voucher = insert(
+            mysql,
+            sqlx::query("INSERT INTO voucher (sale, receiver_email, receiver_name, distributorvoucher, balance, used, expiration_date, hash_code, number_code, version, status) VALUES (?,'','Ontvanger',?,50,0,?,?,?,0,'Active')")
+                .bind(&sale)
+                .bind(&distributorvoucher)
+                .bind(&(Utc::now() + Duration::days(365)))
+                .bind(format!("test-{}", code))
+                .bind(format!("{:010}", code)),
+        )
+        .await;
+
+        Fixture {
+            voucher: voucher,
+            sale: sale,
+            client: client,
+            owner: data::Tenant::of(owner),
+            other: data::Tenant::of(other),
+        }
+    }
+
+    #[test]
+    #[ignore = "needs KADDO_TEST_DATABASE"]
+    fn other_tenant_cannot_read_vouchers_sales_or_clients() {
+        actix_web::rt::System::new("test").block_on(async {
+            let mysql = database().await;
+            let f = voucher_of_other_tenant(&mysql).await;
+
+            assert!(
+                data::get_voucher(&mysql, f.owner, data::Selector::ById(f.voucher))
+                    .await
+                    .is_some()
+            );
+            assert!(
+                data::get_voucher(&mysql, f.other, data::Selector::ById(f.voucher))
+                    .await
+                    .is_none()
+            );
+
+            assert!(
+                data::get_sale(&mysql, f.owner, data::Selector::ById(f.sale))
+                    .await
+                    .is_some()
+            );
+            assert!(
+                data::get_sale(&mysql, f.other, data::Selector::ById(f.sale))
+                    .await
+                    .is_none()
+            );
+
+            assert!(data::get_client(&mysql, f.owner, f.client).await.is_some());
+            assert!(data::get_client(&mysql, f.other, f.client).await.is_none());
+
+            rollback(&mysql).await;
+        });
+    }
+
+    #[test]
+    #[ignore = "needs KADDO_TEST_DATABASE"]
+    fn other_tenant_cannot_update_vouchers() {
+        actix_web::rt::System::new("test").block_on(async {
+            let mysql = database().await;
+            let f = voucher_of_other_tenant(&mysql).await;
+
+            let mut voucher = data::get_voucher(&mysql, f.owner, data::Selector::ById(f.voucher))
+                .await
+                .unwrap();
+            let version = voucher.version;
+            voucher.receiver_name = "Iemand anders".to_string();
+
+            assert!(!data::update_voucher(&mysql, f.other, &voucher).await);
+
+            let unchanged = data::get_voucher(&mysql, f.owner, data::Selector::ById(f.voucher))
+                .await
+                .unwrap();
+            assert_eq!(unchanged.version, version);
+            assert_eq!(unchanged.receiver_name, "Ontvanger");
+
+            rollback(&mysql).await;
+        });
+    }
+
+    #[test]
+    fn reminder_days_are_sorted_largest_first() {
+        assert_eq!(parse_reminder_days("30,7"), Some(vec![30, 7]));
+        assert_eq!(parse_reminder_days(" 7 , 30 "), Some(vec![30, 7]));
+        assert_eq!(parse_reminder_days("1,,14"), Some(vec![14, 1]));
+        assert_eq!(parse_reminder_days("7,7,30"), Some(vec![30, 7]));
+        assert_eq!(
+            parse_reminder_days(DEFAULT_REMINDER_DAYS),
+            Some(vec![30, 7])
+        );
+    }
+
+    #[test]
+    fn reminder_days_can_be_empty() {
+        assert_eq!(parse_reminder_days(""), Some(vec![]));
+        assert_eq!(parse_reminder_days(" , "), Some(vec![]));
+    }
+
+    #[test]
+    fn reminder_days_out_of_range_or_too_many() {
+        assert_eq!(parse_reminder_days("0"), None);
+        assert_eq!(parse_reminder_days("-7"), None);
+        assert_eq!(parse_reminder_days("365"), Some(vec![365]));
+        assert_eq!(parse_reminder_days("366"), None);
+        assert_eq!(parse_reminder_days("30;7"), None);
+        assert_eq!(parse_reminder_days("zeven"), None);
+        assert_eq!(parse_reminder_days("1,2,3,4,5"), Some(vec![5, 4, 3, 2, 1]));
+        assert_eq!(parse_reminder_days("1,2,3,4,5,6"), None);
+    }
+
+    #[test]
+    fn opening_hours_are_normalized() {
+        assert_eq!(
+            parse_opening_hours("9:00-12:00, 13:00 - 18:00"),
+            Some("09:00-12:00, 13:00-18:00".to_string())
+        );
+        assert_eq!(
+            parse_opening_hours("08:30-17:30"),
+            Some("08:30-17:30".to_string())
+        );
+        assert_eq!(
+            parse_opening_hours("9:00-12:00,,"),
+            Some("09:00-12:00".to_string())
+        );
+    }
+
+    #[test]
+    fn empty_opening_hours_mean_closed() {
+        assert_eq!(parse_opening_hours(""), Some("".to_string()));
+        assert_eq!(parse_opening_hours(" , "), Some("".to_string()));
+    }
+
+    #[test]
+    fn invalid_opening_hours() {
+        assert_eq!(parse_opening_hours("12:00-9:00"), None);
+        assert_eq!(parse_opening_hours("9:00-9:00"), None);
+        assert_eq!(parse_opening_hours("9:00"), None);
+        assert_eq!(parse_opening_hours("9-12"), None);
+        assert_eq!(parse_opening_hours("9:00-12:00-13:00"), None);
+        assert_eq!(parse_opening_hours("24:00-25:00"), None);
+        assert_eq!(parse_opening_hours("9:00-12:00, gesloten"), None);
+    }
+
+    #[test]
+    fn custom_domain_is_normalized() {
+        assert_eq!(
+            normalize_custom_domain("https://Bonnen.Bakkerij.be/"),
+            Ok("bonnen.bakkerij.be".to_string())
+        );
+        assert_eq!(
+            normalize_custom_domain(" http://bakkerij.be. "),
+            Ok("bakkerij.be".to_string())
+        );
+        assert_eq!(
+            normalize_custom_domain("xn--bcher-kva.be"),
+            Ok("xn--bcher-kva.be".to_string())
+        );
+        assert_eq!(
+            normalize_custom_domain("notkaddo.test"),
+            Ok("notkaddo.test".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_custom_domains() {
+        for domain in [
+            "",
+            "localhost",
+            "192.168.1.1",
+            "-bakkerij.be",
+            "bakkerij-.be",
+            "bak_kerij.be",
+            "bakkerij..be",
+            ".bakkerij.be",
+            "bakkerij.be:8080",
+            "bakkerij.be/bonnen",
+            "bücher.be",
+            "ftp://bakkerij.be",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                normalize_custom_domain(domain),
+                Err("domain_invalid"),
+                "{}",
+                domain
+            );
+        }
+    }
+
+    #[test]
+    fn custom_domain_length_limits() {
+        let label = "a".repeat(63);
+        assert!(normalize_custom_domain(&format!("{}.be", label)).is_ok());
+        assert_eq!(
+            normalize_custom_domain(&format!("a{}.be", label)),
+            Err("domain_invalid")
+        );
+        assert_eq!(
+            normalize_custom_domain(&vec![label.as_str(); 4].join(".")),
+            Err("domain_invalid")
+        );
+    }
+
+    #[test]
+    fn platform_domain_is_reserved() {
+        assert_eq!(
+            normalize_custom_domain("kaddo.test"),
+            Err("domain_reserved")
+        );
+        assert_eq!(
+            normalize_custom_domain("https://KADDO.test/"),
+            Err("domain_reserved")
+        );
+        assert_eq!(
+            normalize_custom_domain("bakkerij.kaddo.test"),
+            Err("domain_reserved")
+        );
+    }
+
+    #[test]
+    fn valid_subdomains() {
+        assert_eq!(check_subdomain("bakkerij"), Ok(()));
+        assert_eq!(check_subdomain("bakkerij-jansen"), Ok(()));
+        assert_eq!(check_subdomain("abc"), Ok(()));
+        assert_eq!(check_subdomain("zaak42"), Ok(()));
+        assert_eq!(check_subdomain(&"a".repeat(63)), Ok(()));
+        assert_eq!(check_subdomain("verwijderd"), Ok(()));
+    }
+
+    #[test]
+    fn subdomain_length() {
+        assert_eq!(check_subdomain(""), Err("subdomain_length"));
+        assert_eq!(check_subdomain("ab"), Err("subdomain_length"));
+        assert_eq!(check_subdomain(&"a".repeat(64)), Err("subdomain_length"));
+    }
+
+    #[test]
+    fn invalid_subdomains() {
+        assert_eq!(check_subdomain("Bakkerij"), Err("subdomain_invalid"));
+        assert_eq!(check_subdomain("-bakkerij"), Err("subdomain_invalid"));
+        assert_eq!(check_subdomain("bakkerij-"), Err("subdomain_invalid"));
+        assert_eq!(check_subdomain("bak.kerij"), Err("subdomain_invalid"));
+        assert_eq!(check_subdomain("bak_kerij"), Err("subdomain_invalid"));
+        assert_eq!(check_subdomain("bäkkerij"), Err("subdomain_invalid"));
+    }
+
+    #[test]
+    fn reserved_subdomains() {
+        for subdomain in RESERVED_SUBDOMAINS.iter() {
+            assert_eq!(check_subdomain(subdomain), Err("subdomain_reserved"));
+        }
+        assert_eq!(check_subdomain("verwijderd-12"), Err("subdomain_reserved"));
+        assert_eq!(
+            check_subdomain("verwijderd-bakkerij"),
+            Err("subdomain_reserved")
+        );
+    }
+
+    #[test]
+    fn csv_field_is_quoted() {
+        assert_eq!(csv_field("Jan"), "\"Jan\"");
+        assert_eq!(csv_field(""), "\"\"");
+        assert_eq!(csv_field("Jan \"Bakker\""), "\"Jan \"\"Bakker\"\"\"");
+        assert_eq!(csv_field("a;b,c"), "\"a;b,c\"");
+    }
+
+    #[test]
+    fn csv_field_keeps_formulas_as_text() {
+        assert_eq!(csv_field("=1+1"), "\"'=1+1\"");
+        assert_eq!(csv_field("+32 9 123 45 67"), "\"'+32 9 123 45 67\"");
+        assert_eq!(csv_field("-2+3"), "\"'-2+3\"");
+        assert_eq!(csv_field("@SUM(A1:A2)"), "\"'@SUM(A1:A2)\"");
+        assert_eq!(csv_field("\t=1"), "\"'\t=1\"");
+        assert_eq!(csv_field("\r=1"), "\"'\r=1\"");
+        assert_eq!(
+            csv_field("=HYPERLINK(\"http://x\")"),
+            "\"'=HYPERLINK(\"\"http://x\"\")\""
+        );
+    }
+
+    #[test]
+    fn csv_field_only_looks_at_the_first_character() {
+        assert_eq!(csv_field("1=1"), "\"1=1\"");
+        assert_eq!(csv_field("jan@bakkerij.be"), "\"jan@bakkerij.be\"");
+    }
+}
@@ -0,0 +1,15 @@
+CREATE TABLE redemption (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    distributor BIGINT UNSIGNED NOT NULL,
+    voucher BIGINT UNSIGNED NOT NULL,
+    client_id VARCHAR(64) NULL,
+    amount DOUBLE NOT NULL,
+    balance_after DOUBLE NOT NULL,
+    redeemed_at DATETIME NOT NULL,
+    synced_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    UNIQUE KEY redemption_client_id (distributor, client_id),
+    KEY redemption_voucher (voucher),
+    FOREIGN KEY (distributor) REFERENCES distributor (ID),
+    FOREIGN KEY (voucher) REFERENCES voucher (ID)
+);
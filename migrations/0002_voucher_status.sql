@@ -0,0 +1,11 @@
+ALTER TABLE voucher ADD status VARCHAR(32) NOT NULL DEFAULT 'PendingPayment';
+
+UPDATE voucher INNER JOIN sale ON sale.ID = voucher.sale SET voucher.status = CASE
+    WHEN sale.paid = 0 THEN 'PendingPayment'
+    WHEN voucher.used = 1 OR voucher.balance <= 0 THEN 'Used'
+    WHEN voucher.expiration_date < UTC_TIMESTAMP() THEN 'Expired'
+    WHEN voucher.balance < sale.amount THEN 'PartiallyUsed'
+    ELSE 'Active'
+END;
+
+CREATE INDEX voucher_status_expiration ON voucher (status, expiration_date);
@@ -0,0 +1,16 @@
+CREATE TABLE auditlog (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    distributor BIGINT UNSIGNED NOT NULL,
+    distributoruser BIGINT UNSIGNED NULL,
+    action VARCHAR(64) NOT NULL,
+    entity VARCHAR(64) NOT NULL,
+    entity_id BIGINT UNSIGNED NOT NULL,
+    before_value TEXT NOT NULL,
+    after_value TEXT NOT NULL,
+    reason VARCHAR(255) NOT NULL DEFAULT '',
+    ip VARCHAR(64) NOT NULL DEFAULT '',
+    create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    KEY auditlog_entity (distributor, entity, entity_id),
+    FOREIGN KEY (distributor) REFERENCES distributor (ID)
+);
@@ -0,0 +1,13 @@
+ALTER TABLE distributor
+    ADD reminder_days VARCHAR(64) NOT NULL DEFAULT '30,7',
+    ADD send_reminders TINYINT(1) NOT NULL DEFAULT 1;
+
+CREATE TABLE voucherreminder (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    voucher BIGINT UNSIGNED NOT NULL,
+    days_before INT NOT NULL,
+    sent_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    UNIQUE KEY voucherreminder_voucher_days (voucher, days_before),
+    FOREIGN KEY (voucher) REFERENCES voucher (ID)
+);
@@ -0,0 +1,13 @@
+ALTER TABLE sale ADD distributor BIGINT UNSIGNED NULL AFTER ID;
+ALTER TABLE client ADD distributor BIGINT UNSIGNED NULL AFTER ID;
+
+UPDATE sale
+    INNER JOIN voucher ON voucher.sale = sale.ID
+    INNER JOIN distributorvoucher ON distributorvoucher.ID = voucher.distributorvoucher
+SET sale.distributor = distributorvoucher.distributor;
+
+UPDATE client INNER JOIN sale ON sale.client = client.ID SET client.distributor = sale.distributor;
+
+-- Sales and clients of abandoned orders without a voucher can't be attributed and stay invisible
+ALTER TABLE sale ADD FOREIGN KEY (distributor) REFERENCES distributor (ID), ADD KEY sale_distributor (distributor, purchase_date);
+ALTER TABLE client ADD FOREIGN KEY (distributor) REFERENCES distributor (ID);
@@ -0,0 +1,19 @@
+ALTER TABLE distributoruser
+    ADD email VARCHAR(255) NOT NULL DEFAULT '';
+
+UPDATE distributoruser
+    JOIN distributor ON distributor.ID = distributoruser.distributor
+    SET distributoruser.email = distributor.email;
+
+CREATE TABLE usertoken (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    distributoruser BIGINT UNSIGNED NOT NULL,
+    purpose VARCHAR(32) NOT NULL,
+    token_hash CHAR(64) NOT NULL,
+    expires DATETIME NOT NULL,
+    used_date DATETIME NULL,
+    create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    UNIQUE KEY usertoken_token_hash (token_hash),
+    FOREIGN KEY (distributoruser) REFERENCES distributoruser (ID)
+);
@@ -0,0 +1,18 @@
+ALTER TABLE distributor
+    ADD require_2fa TINYINT(1) NOT NULL DEFAULT 0;
+
+ALTER TABLE distributoruser
+    ADD totp_secret VARCHAR(64) NULL,
+    ADD totp_enabled TINYINT(1) NOT NULL DEFAULT 0,
+    ADD totp_last_step BIGINT UNSIGNED NOT NULL DEFAULT 0;
+
+CREATE TABLE recoverycode (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    distributoruser BIGINT UNSIGNED NOT NULL,
+    code_hash CHAR(64) NOT NULL,
+    used_date DATETIME NULL,
+    create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    KEY recoverycode_distributoruser (distributoruser),
+    FOREIGN KEY (distributoruser) REFERENCES distributoruser (ID)
+);
@@ -0,0 +1,17 @@
+ALTER TABLE distributoruser
+    ADD locked_until DATETIME NULL;
+
+CREATE TABLE loginattempt (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    distributor BIGINT UNSIGNED NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    distributoruser BIGINT UNSIGNED NULL,
+    ip VARCHAR(64) NOT NULL,
+    success TINYINT(1) NOT NULL,
+    create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    KEY loginattempt_username (distributor, username, create_date),
+    KEY loginattempt_ip (ip, create_date),
+    FOREIGN KEY (distributor) REFERENCES distributor (ID),
+    FOREIGN KEY (distributoruser) REFERENCES distributoruser (ID)
+);
@@ -0,0 +1,16 @@
+CREATE TABLE adminsession (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    session_hash CHAR(64) NOT NULL,
+    distributoruser BIGINT UNSIGNED NULL,
+    state TEXT NOT NULL,
+    ip VARCHAR(64) NOT NULL,
+    user_agent VARCHAR(255) NOT NULL,
+    create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires DATETIME NOT NULL,
+    revoked_date DATETIME NULL,
+    PRIMARY KEY (ID),
+    UNIQUE KEY adminsession_session_hash (session_hash),
+    KEY adminsession_distributoruser (distributoruser),
+    FOREIGN KEY (distributoruser) REFERENCES distributoruser (ID)
+);
@@ -0,0 +1,3 @@
+-- Existing users keep full access
+ALTER TABLE distributoruser
+    ADD role VARCHAR(32) NOT NULL DEFAULT 'Owner';
@@ -0,0 +1,13 @@
+-- Usernames are unique within a distributor, two shops can both have an "admin". Older
+-- duplicates within one distributor get the ID appended.
+UPDATE distributoruser
+    JOIN distributoruser AS earlier
+        ON earlier.distributor = distributoruser.distributor
+        AND earlier.username = distributoruser.username
+        AND earlier.ID < distributoruser.ID
+    SET distributoruser.username = CONCAT(distributoruser.username, '-', distributoruser.ID);
+
+ALTER TABLE distributoruser
+    ADD active TINYINT(1) NOT NULL DEFAULT 1,
+    ADD last_login DATETIME NULL,
+    ADD UNIQUE KEY distributoruser_username (distributor, username);
@@ -0,0 +1,10 @@
+-- The audit log is append-only, corrections are new entries
+CREATE TRIGGER auditlog_no_update BEFORE UPDATE ON auditlog
+FOR EACH ROW SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'auditlog is append-only';
+
+CREATE TRIGGER auditlog_no_delete BEFORE DELETE ON auditlog
+FOR EACH ROW SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'auditlog is append-only';
+
+ALTER TABLE auditlog
+    ADD KEY auditlog_date (distributor, create_date),
+    ADD KEY auditlog_action (distributor, action, create_date);
@@ -0,0 +1,41 @@
+ALTER TABLE distributor
+    ADD status VARCHAR(16) NOT NULL DEFAULT 'Active',
+    ADD create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD UNIQUE KEY distributor_subdomain (subdomain);
+
+CREATE TABLE platformadmin (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    username VARCHAR(64) NOT NULL,
+    password VARCHAR(255) NOT NULL,
+    display_name VARCHAR(255) NOT NULL,
+    email VARCHAR(255) NOT NULL,
+    active TINYINT(1) NOT NULL DEFAULT 1,
+    last_login DATETIME NULL,
+    create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    UNIQUE KEY platformadmin_username (username)
+);
+
+CREATE TABLE platformauditlog (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    platformadmin BIGINT UNSIGNED NOT NULL,
+    distributor BIGINT UNSIGNED NULL,
+    action VARCHAR(64) NOT NULL,
+    entity VARCHAR(64) NOT NULL,
+    entity_id BIGINT UNSIGNED NOT NULL,
+    before_value TEXT NOT NULL,
+    after_value TEXT NOT NULL,
+    reason VARCHAR(255) NOT NULL DEFAULT '',
+    ip VARCHAR(64) NOT NULL DEFAULT '',
+    create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    KEY platformauditlog_distributor (distributor, create_date),
+    FOREIGN KEY (platformadmin) REFERENCES platformadmin (ID),
+    FOREIGN KEY (distributor) REFERENCES distributor (ID)
+);
+
+CREATE TRIGGER platformauditlog_no_update BEFORE UPDATE ON platformauditlog
+FOR EACH ROW SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'platformauditlog is append-only';
+
+CREATE TRIGGER platformauditlog_no_delete BEFORE DELETE ON platformauditlog
+FOR EACH ROW SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'platformauditlog is append-only';
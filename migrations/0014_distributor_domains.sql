@@ -0,0 +1,13 @@
+CREATE TABLE distributordomain (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    distributor BIGINT UNSIGNED NOT NULL,
+    domain VARCHAR(253) NOT NULL,
+    verification_token VARCHAR(64) NOT NULL,
+    verified_date DATETIME NULL,
+    is_primary TINYINT(1) NOT NULL DEFAULT 0,
+    create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    UNIQUE KEY distributordomain_domain (domain),
+    KEY distributordomain_distributor (distributor),
+    FOREIGN KEY (distributor) REFERENCES distributor (ID)
+);
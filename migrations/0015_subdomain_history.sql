@@ -0,0 +1,10 @@
+CREATE TABLE subdomainhistory (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    distributor BIGINT UNSIGNED NOT NULL,
+    subdomain VARCHAR(63) NOT NULL,
+    create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    UNIQUE KEY subdomainhistory_subdomain (subdomain),
+    KEY subdomainhistory_distributor (distributor),
+    FOREIGN KEY (distributor) REFERENCES distributor (ID)
+);
@@ -0,0 +1,11 @@
+CREATE TABLE distributorbranding (
+    distributor BIGINT UNSIGNED NOT NULL,
+    logo VARCHAR(255) NULL,
+    cover VARCHAR(255) NULL,
+    primary_color CHAR(7) NOT NULL DEFAULT '#1f3a5f',
+    accent_color CHAR(7) NOT NULL DEFAULT '#f2a541',
+    font VARCHAR(32) NOT NULL DEFAULT 'System',
+    update_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (distributor),
+    FOREIGN KEY (distributor) REFERENCES distributor (ID)
+);
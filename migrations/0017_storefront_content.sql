@@ -0,0 +1,31 @@
+-- Rendered by the application on save, existing descriptions are rendered at startup
+ALTER TABLE distributor ADD COLUMN description_html TEXT NOT NULL AFTER description;
+
+CREATE TABLE distributorfaq (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    distributor BIGINT UNSIGNED NOT NULL,
+    question VARCHAR(200) NOT NULL,
+    answer TEXT NOT NULL,
+    answer_html TEXT NOT NULL,
+    position INT NOT NULL DEFAULT 0,
+    create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    KEY distributorfaq_distributor (distributor, position),
+    FOREIGN KEY (distributor) REFERENCES distributor (ID)
+);
+
+CREATE TABLE distributorpage (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    distributor BIGINT UNSIGNED NOT NULL,
+    kind VARCHAR(16) NOT NULL DEFAULT 'Custom',
+    slug VARCHAR(60) NOT NULL,
+    title VARCHAR(200) NOT NULL,
+    body MEDIUMTEXT NOT NULL,
+    body_html MEDIUMTEXT NOT NULL,
+    is_published TINYINT(1) NOT NULL DEFAULT 0,
+    create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    update_date DATETIME NULL,
+    PRIMARY KEY (ID),
+    UNIQUE KEY distributorpage_slug (distributor, slug),
+    FOREIGN KEY (distributor) REFERENCES distributor (ID)
+);
@@ -0,0 +1,43 @@
+CREATE TABLE shoplocation (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    distributor BIGINT UNSIGNED NOT NULL,
+    name VARCHAR(100) NOT NULL,
+    address VARCHAR(200) NOT NULL,
+    location BIGINT UNSIGNED NOT NULL,
+    -- JSON array with one entry per weekday from Monday
+    opening_hours TEXT NOT NULL,
+    is_active TINYINT(1) NOT NULL DEFAULT 1,
+    create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    KEY shoplocation_distributor (distributor, is_active),
+    FOREIGN KEY (distributor) REFERENCES distributor (ID),
+    FOREIGN KEY (location) REFERENCES location (ID)
+);
+
+-- Existing distributors get one shop at their own address
+INSERT INTO shoplocation (distributor, name, address, location, opening_hours)
+SELECT ID, name, address, location, '["","","","","","",""]' FROM distributor;
+
+CREATE TABLE scannerdevice (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    distributor BIGINT UNSIGNED NOT NULL,
+    shoplocation BIGINT UNSIGNED NOT NULL,
+    name VARCHAR(100) NOT NULL,
+    token_hash CHAR(64) NOT NULL,
+    paired_date DATETIME NULL,
+    last_seen DATETIME NULL,
+    is_active TINYINT(1) NOT NULL DEFAULT 1,
+    create_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    UNIQUE KEY scannerdevice_token (token_hash),
+    FOREIGN KEY (distributor) REFERENCES distributor (ID),
+    FOREIGN KEY (shoplocation) REFERENCES shoplocation (ID)
+);
+
+-- NULL for redemptions from before shop locations
+ALTER TABLE redemption
+    ADD COLUMN shoplocation BIGINT UNSIGNED NULL,
+    ADD COLUMN scannerdevice BIGINT UNSIGNED NULL,
+    ADD KEY redemption_shoplocation (distributor, shoplocation, redeemed_at),
+    ADD FOREIGN KEY (shoplocation) REFERENCES shoplocation (ID),
+    ADD FOREIGN KEY (scannerdevice) REFERENCES scannerdevice (ID);
@@ -0,0 +1,6 @@
+-- Reference data is loaded with `kaddo import-postcodes <BE|NL|LU> <file.csv>`, the existing
+-- locations are all Belgian
+ALTER TABLE location
+    ADD COLUMN country CHAR(2) NOT NULL DEFAULT 'BE' AFTER ID,
+    ADD KEY location_postalcode (country, postalcode),
+    ADD KEY location_city (country, city);
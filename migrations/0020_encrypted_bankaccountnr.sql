@@ -0,0 +1,2 @@
+-- Holds the IBAN encrypted with KADDO_ENCRYPTION_KEY, existing numbers are encrypted at startup
+ALTER TABLE distributor MODIFY bankaccountnr VARCHAR(255) NOT NULL;
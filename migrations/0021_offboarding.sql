@@ -0,0 +1,14 @@
+ALTER TABLE distributor ADD COLUMN redeem_when_suspended TINYINT(1) NOT NULL DEFAULT 1 AFTER status;
+
+-- Remaining balances refunded when a distributor leaves, what it owes its customers
+CREATE TABLE settlement (
+    ID BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    distributor BIGINT UNSIGNED NOT NULL,
+    voucher BIGINT UNSIGNED NOT NULL,
+    amount DOUBLE NOT NULL,
+    settled_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ID),
+    UNIQUE KEY settlement_voucher (voucher),
+    FOREIGN KEY (distributor) REFERENCES distributor (ID),
+    FOREIGN KEY (voucher) REFERENCES voucher (ID)
+);
@@ -0,0 +1,5 @@
+-- Secret shown while setting up 2FA, it only becomes totp_secret once confirmed with a code
+ALTER TABLE distributoruser
+    ADD totp_pending_secret VARCHAR(64) NULL;
+
+UPDATE distributoruser SET totp_pending_secret = totp_secret, totp_secret = NULL WHERE totp_enabled = 0;
@@ -0,0 +1,8 @@
+-- Anyone can add a domain, only a verified claim reserves it. The first shop that passes the
+-- TXT check gets the domain, the unverified claims of others are removed at that time.
+ALTER TABLE distributordomain
+    DROP INDEX distributordomain_domain,
+    ADD verified_domain VARCHAR(253) AS (IF(verified_date IS NULL, NULL, domain)) STORED,
+    ADD UNIQUE KEY distributordomain_verified_domain (verified_domain),
+    ADD UNIQUE KEY distributordomain_distributor_domain (distributor, domain),
+    ADD KEY distributordomain_domain (domain);
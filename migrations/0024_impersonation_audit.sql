@@ -0,0 +1,6 @@
+-- Platform admin that handed out an impersonation link, and the one acting in an audit entry
+ALTER TABLE usertoken
+    ADD platformadmin BIGINT UNSIGNED NULL;
+
+ALTER TABLE auditlog
+    ADD platformadmin BIGINT UNSIGNED NULL;
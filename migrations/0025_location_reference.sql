@@ -0,0 +1,6 @@
+-- Locations from an imported postal code list. Countries without any are not validated yet,
+-- their addresses are stored as entered. The existing Belgian locations are the old list.
+ALTER TABLE location
+    ADD COLUMN reference TINYINT(1) NOT NULL DEFAULT 0;
+
+UPDATE location SET reference = 1 WHERE country = 'BE';
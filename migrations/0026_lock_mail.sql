@@ -0,0 +1,3 @@
+-- Last time the owner of an account was mailed about a lock, to not flood their mailbox
+ALTER TABLE distributoruser
+    ADD lock_mail_date DATETIME NULL;